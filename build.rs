@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // Compiled with protox rather than protoc, so no system install is needed.
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/transaction.proto");
+        let descriptors = protox::compile(["transaction.proto"], ["proto"])
+            .expect("proto/transaction.proto compiles");
+        prost_build::Config::new()
+            .compile_fds(descriptors)
+            .expect("protobuf types are generated");
+    }
+}
@@ -0,0 +1,61 @@
+#ifndef FICTIONAL_GUIDE_H
+#define FICTIONAL_GUIDE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ENGINE_OK 0
+#define ENGINE_NOT_FOUND 1
+/* The engine rejected the transaction; engine_last_rejection says why. */
+#define ENGINE_REJECTED 2
+#define ENGINE_INVALID_ARGUMENT -1
+/* The engine panicked; it may be inconsistent and should only be freed. */
+#define ENGINE_PANIC -2
+
+#define ENGINE_TX_DEPOSIT 0
+#define ENGINE_TX_WITHDRAWAL 1
+#define ENGINE_TX_DISPUTE 2
+#define ENGINE_TX_RESOLVE 3
+#define ENGINE_TX_CHARGEBACK 4
+#define ENGINE_TX_BONUS 5
+#define ENGINE_TX_APPROVE 6
+#define ENGINE_TX_REJECT 7
+
+typedef struct Engine Engine;
+
+typedef struct EngineAccount {
+    uint16_t client;
+    double available;
+    double held;
+    double total;
+    bool locked;
+} EngineAccount;
+
+/* Returns NULL on failure. */
+Engine *engine_new(void);
+void engine_free(Engine *engine);
+
+/* `amount` is ignored for disputes, resolves and chargebacks. */
+int engine_submit_tx(Engine *engine, uint32_t kind, uint16_t client, uint32_t tx, double amount);
+
+/* Why the last transaction submitted was rejected, e.g. "insufficient_funds",
+ * or NULL if it was not. Owned by the engine and valid until the next
+ * engine_submit_tx; do not free it. */
+const char *engine_last_rejection(const Engine *engine);
+
+/* Returns ENGINE_NOT_FOUND when the client has never been seen. */
+int engine_get_account(const Engine *engine, uint16_t client, EngineAccount *out);
+
+/* Returns NULL on failure; release the result with engine_string_free. */
+char *engine_snapshot_csv(const Engine *engine);
+void engine_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
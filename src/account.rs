@@ -1,33 +1,182 @@
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::rounding::{Rounding, PRECISION};
+use crate::transaction::Label;
+#[cfg(feature = "serde")]
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     InsufficientFunds,
     LockedAccount,
+    /// The amount is in another currency than the account.
+    CurrencyMismatch,
 }
 
-pub struct AccountsRepository {
-    accounts: HashMap<u16, Account>,
+/// Number of shards the accounts are spread over by client id.
+pub const SHARDS: usize = 16;
+
+type Map<M> = Arc<HashMap<u16, Account<M>>>;
+type Shard<M> = RwLock<Map<M>>;
+
+/// The accounts, spread over `SHARDS` maps behind a lock each. The engine
+/// is the one writer; `reader` hands out handles that other threads read
+/// balances through while it applies transactions, each waiting at most for
+/// the one account being changed in its shard.
+///
+/// The maps are copied on write: `snapshot` only takes another reference
+/// to each, and the writer copies a shard the first time it changes it
+/// while a snapshot still holds it.
+pub struct AccountsRepository<M = f64> {
+    shards: Arc<[Shard<M>]>,
+    rounding: Rounding,
+    currency: Option<Currency>,
+    locked_deposits: bool,
 }
 
-impl AccountsRepository {
-    pub fn new() -> AccountsRepository {
+impl<M: Money> AccountsRepository<M> {
+    pub fn new() -> AccountsRepository<M> {
+        AccountsRepository::with_rounding(Rounding::default())
+    }
+
+    /// A repository whose accounts round every balance with `rounding`.
+    pub fn with_rounding(rounding: Rounding) -> AccountsRepository<M> {
         AccountsRepository {
-            accounts: Default::default(),
+            shards: (0..SHARDS).map(|_| Default::default()).collect(),
+            rounding,
+            currency: None,
+            locked_deposits: false,
+        }
+    }
+
+    /// Opens new accounts in `currency` rather than leaving it to their
+    /// first transaction with a currency.
+    pub fn with_currency(mut self, currency: Option<Currency>) -> AccountsRepository<M> {
+        self.currency = currency;
+        self
+    }
+
+    /// Lets locked accounts, those already here and any opened or restored
+    /// later, still take deposits, e.g. refunds; withdrawals stay refused.
+    pub fn with_locked_deposits(mut self, allowed: bool) -> AccountsRepository<M> {
+        self.locked_deposits = allowed;
+        for shard in self.shards.iter() {
+            let mut shard = lock_write(shard);
+            if shard
+                .values()
+                .any(|account| account.locked_deposits != allowed)
+            {
+                for account in Arc::make_mut(&mut shard).values_mut() {
+                    account.locked_deposits = allowed;
+                }
+            }
         }
+        self
     }
 
-    pub fn get_or_create(&mut self, id: u16) -> &mut Account {
-        self.accounts.entry(id).or_insert_with(|| Account::new(id))
+    /// An empty repository opening accounts like this one does, with the
+    /// same rounding, currency and deposits to locked accounts.
+    pub fn configured_like(&self) -> AccountsRepository<M> {
+        AccountsRepository::with_rounding(self.rounding)
+            .with_currency(self.currency)
+            .with_locked_deposits(self.locked_deposits)
     }
 
-    pub fn display_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    /// The currency new accounts are opened in, if set.
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    /// A handle to read balances through from other threads.
+    pub fn reader(&self) -> AccountsReader<M> {
+        AccountsReader {
+            shards: Arc::clone(&self.shards),
+        }
+    }
 
-        let mut sorted: Vec<(&u16, &Account)> = self.accounts.iter().collect();
-        sorted.sort_by_key(|(_, c)| c.client_id());
-        for (_, client) in &sorted {
+    /// Adds an account rebuilt from persisted balances, replacing any
+    /// account with the same client id.
+    #[cfg(feature = "json")]
+    pub(crate) fn restore(&mut self, mut account: Account<M>) {
+        account.locked_deposits = self.locked_deposits;
+        Arc::make_mut(&mut write(&self.shards, account.client_id))
+            .insert(account.client_id, account);
+    }
+
+    pub fn get_or_create(&mut self, id: u16) -> AccountMut<'_, M> {
+        let (rounding, currency, locked_deposits) =
+            (self.rounding, self.currency, self.locked_deposits);
+        let mut shard = write(&self.shards, id);
+        if !shard.contains_key(&id) {
+            let account = Account {
+                currency,
+                locked_deposits,
+                ..Account::with_rounding(id, rounding)
+            };
+            Arc::make_mut(&mut shard).insert(id, account);
+        }
+        AccountMut { shard, id }
+    }
+
+    pub fn len(&self) -> usize {
+        len(&self.shards)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: u16) -> Option<AccountRef<'_, M>> {
+        let shard = read(&self.shards, id);
+        shard.contains_key(&id).then_some(AccountRef { shard, id })
+    }
+
+    /// Drops every account whose client id `keep` returns false for.
+    pub fn retain(&mut self, mut keep: impl FnMut(u16) -> bool) {
+        for shard in self.shards.iter() {
+            let mut shard = lock_write(shard);
+            if shard.keys().any(|&id| !keep(id)) {
+                Arc::make_mut(&mut shard).retain(|&id, _| keep(id));
+            }
+        }
+    }
+
+    #[cfg(feature = "csv")]
+    pub fn display_all(&mut self) -> Result<(), Box<dyn std::error::Error>>
+    where
+        M: Serialize,
+    {
+        self.write_csv(std::io::stdout())
+    }
+
+    /// A copy of all accounts ordered by client id.
+    pub fn sorted(&self) -> Vec<Account<M>> {
+        sorted(&self.shards)
+    }
+
+    /// The accounts as they are now, unaffected by later changes. Cheap to
+    /// take, whatever the number of accounts.
+    pub fn snapshot(&self) -> Snapshot<M> {
+        snapshot(&self.shards)
+    }
+
+    /// Writes every account as CSV, ordered by client id.
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn std::error::Error>>
+    where
+        M: Serialize,
+    {
+        let mut wtr = csv::Writer::from_writer(writer);
+
+        for client in self.sorted() {
             wtr.serialize(client)?;
         }
         wtr.flush()?;
@@ -36,47 +185,276 @@ impl AccountsRepository {
     }
 }
 
-impl Default for AccountsRepository {
+/// Reads the accounts of an `AccountsRepository` from another thread,
+/// seeing every transaction the writer has applied so far. Each account
+/// read is a copy, consistent on its own.
+#[derive(Clone)]
+pub struct AccountsReader<M = f64> {
+    shards: Arc<[Shard<M>]>,
+}
+
+impl<M: Money> AccountsReader<M> {
+    pub fn get(&self, id: u16) -> Option<Account<M>> {
+        read(&self.shards, id).get(&id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        len(&self.shards)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A copy of all accounts ordered by client id, taken shard by shard.
+    pub fn sorted(&self) -> Vec<Account<M>> {
+        sorted(&self.shards)
+    }
+
+    /// The accounts taken shard by shard, see `AccountsRepository::snapshot`.
+    /// Only one point in time while the writer is not applying anything.
+    pub fn snapshot(&self) -> Snapshot<M> {
+        snapshot(&self.shards)
+    }
+}
+
+/// The accounts of an `AccountsRepository` at one point in time, see
+/// `AccountsRepository::snapshot`.
+#[derive(Clone)]
+pub struct Snapshot<M = f64> {
+    shards: Vec<Map<M>>,
+}
+
+impl<M: Money> Snapshot<M> {
+    pub fn get(&self, id: u16) -> Option<&Account<M>> {
+        self.shards[usize::from(id) % self.shards.len()].get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All accounts ordered by client id.
+    pub fn sorted(&self) -> Vec<&Account<M>> {
+        let mut sorted: Vec<&Account<M>> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.values())
+            .collect();
+        sorted.sort_by_key(|c| c.client_id());
+        sorted
+    }
+}
+
+/// An account of an `AccountsRepository`, its shard locked for reading.
+pub struct AccountRef<'a, M = f64> {
+    shard: RwLockReadGuard<'a, Map<M>>,
+    id: u16,
+}
+
+impl<M> Deref for AccountRef<'_, M> {
+    type Target = Account<M>;
+
+    fn deref(&self) -> &Account<M> {
+        &self.shard[&self.id]
+    }
+}
+
+/// An account of an `AccountsRepository`, its shard locked for writing.
+pub struct AccountMut<'a, M = f64> {
+    shard: RwLockWriteGuard<'a, Map<M>>,
+    id: u16,
+}
+
+impl<M> Deref for AccountMut<'_, M> {
+    type Target = Account<M>;
+
+    fn deref(&self) -> &Account<M> {
+        &self.shard[&self.id]
+    }
+}
+
+impl<M: Clone> DerefMut for AccountMut<'_, M> {
+    fn deref_mut(&mut self) -> &mut Account<M> {
+        Arc::make_mut(&mut self.shard)
+            .get_mut(&self.id)
+            .expect("account is in its shard")
+    }
+}
+
+fn shard<M>(shards: &[Shard<M>], id: u16) -> &Shard<M> {
+    &shards[usize::from(id) % shards.len()]
+}
+
+// A panic while an account was being changed leaves at worst that one
+// transaction half applied, which readers may as well see.
+fn lock_read<M>(shard: &Shard<M>) -> RwLockReadGuard<'_, Map<M>> {
+    shard.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn lock_write<M>(shard: &Shard<M>) -> RwLockWriteGuard<'_, Map<M>> {
+    shard.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<M>(shards: &[Shard<M>], id: u16) -> RwLockReadGuard<'_, Map<M>> {
+    lock_read(shard(shards, id))
+}
+
+fn write<M>(shards: &[Shard<M>], id: u16) -> RwLockWriteGuard<'_, Map<M>> {
+    lock_write(shard(shards, id))
+}
+
+fn len<M>(shards: &[Shard<M>]) -> usize {
+    shards.iter().map(|shard| lock_read(shard).len()).sum()
+}
+
+fn snapshot<M>(shards: &[Shard<M>]) -> Snapshot<M> {
+    Snapshot {
+        shards: shards
+            .iter()
+            .map(|shard| lock_read(shard).clone())
+            .collect(),
+    }
+}
+
+fn sorted<M: Money>(shards: &[Shard<M>]) -> Vec<Account<M>> {
+    let mut sorted: Vec<Account<M>> = shards
+        .iter()
+        .flat_map(|shard| lock_read(shard).values().cloned().collect::<Vec<_>>())
+        .collect();
+    sorted.sort_by_key(|c| c.client_id());
+    sorted
+}
+
+impl<M: Money> Default for AccountsRepository<M> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-#[derive(Debug)]
-pub struct Account {
+/// Why an account is locked.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LockReason<M = f64> {
+    /// A chargeback of the transaction `tx`, which took `amount` off the
+    /// account.
+    Chargeback { tx: u32, amount: M },
+    /// Frozen by an operator.
+    AdminFreeze,
+    /// Frozen by the named risk rule.
+    RiskRule(Label),
+}
+
+impl<M> fmt::Display for LockReason<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::Chargeback { tx, .. } => write!(f, "chargeback:{}", tx),
+            LockReason::AdminFreeze => f.write_str("admin_freeze"),
+            LockReason::RiskRule(rule) => write!(f, "risk_rule:{}", rule.as_str()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<M> Serialize for LockReason<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A change of an account's lock, with the timestamp of the transaction
+/// that caused it, if known.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LockEvent<M = f64> {
+    Locked {
+        reason: LockReason<M>,
+        timestamp: Option<u64>,
+    },
+    Unlocked {
+        timestamp: Option<u64>,
+    },
+}
+
+/// How many lock changes an account remembers, the oldest being dropped
+/// first.
+pub const LOCK_HISTORY: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct Account<M = f64> {
     client_id: u16,
-    available_balance: f64,
-    held_balance: f64,
-    total_balance: f64,
+    available_balance: M,
+    held_balance: M,
+    total_balance: M,
     locked: bool,
+    rounding: Rounding,
+    /// Balances are kept at its exponent instead of `PRECISION` places.
+    currency: Option<Currency>,
+    /// Set whenever the account is locked and the reason is known.
+    lock_reason: Option<LockReason<M>>,
+    lock_history: VecDeque<LockEvent<M>>,
+    /// Deposits are taken even while locked.
+    locked_deposits: bool,
 }
 
-impl Serialize for Account {
+#[cfg(feature = "serde")]
+impl<M: Money + Serialize> Serialize for Account<M> {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let mut account = serializer.serialize_struct("Account", 5)?;
         account.serialize_field("client", &self.client_id)?;
-        account.serialize_field(
-            "available",
-            &((self.available_balance * 10000.0).round() / 10000.0),
-        )?;
-        account.serialize_field("held", &((self.held_balance * 10000.0).round() / 10000.0))?;
-        account.serialize_field("total", &((self.total_balance * 10000.0).round() / 10000.0))?;
+        account.serialize_field("available", &self.available_balance())?;
+        account.serialize_field("held", &self.held_balance())?;
+        account.serialize_field("total", &self.total_balance())?;
         account.serialize_field("locked", &self.locked)?;
         account.end()
     }
 }
 
-impl Account {
-    pub fn new(client_id: u16) -> Account {
+impl<M: Money> Account<M> {
+    pub fn new(client_id: u16) -> Account<M> {
+        Account::with_rounding(client_id, Rounding::default())
+    }
+
+    pub fn with_rounding(client_id: u16, rounding: Rounding) -> Account<M> {
         Account {
             client_id,
-            available_balance: 0.0,
-            held_balance: 0.0,
-            total_balance: 0.0,
+            available_balance: M::default(),
+            held_balance: M::default(),
+            total_balance: M::default(),
             locked: false,
+            rounding,
+            currency: None,
+            lock_reason: None,
+            lock_history: VecDeque::new(),
+            locked_deposits: false,
+        }
+    }
+
+    #[cfg(feature = "json")]
+    pub(crate) fn from_balances(
+        client_id: u16,
+        available: M,
+        held: M,
+        total: M,
+        locked: bool,
+        rounding: Rounding,
+    ) -> Account<M> {
+        Account {
+            client_id,
+            available_balance: available,
+            held_balance: held,
+            total_balance: total,
+            locked,
+            rounding,
+            currency: None,
+            lock_reason: None,
+            lock_history: VecDeque::new(),
+            locked_deposits: false,
         }
     }
 
@@ -84,6 +462,29 @@ impl Account {
         self.client_id
     }
 
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Takes on `currency` if the account has none yet, refusing amounts in
+    /// any other currency from then on.
+    pub fn adopt_currency(&mut self, currency: Currency) -> Result<(), Error> {
+        match self.currency {
+            Some(own) if own != currency => Err(Error::CurrencyMismatch),
+            _ => {
+                self.currency = Some(currency);
+                Ok(())
+            }
+        }
+    }
+
+    /// Places balances are kept at: the exponent of the account's currency,
+    /// `PRECISION` without one.
+    pub fn decimals(&self) -> i32 {
+        self.currency
+            .map_or(PRECISION, |currency| currency.decimals())
+    }
+
     fn is_locked(&self) -> Result<(), Error> {
         if self.locked {
             return Err(Error::LockedAccount);
@@ -92,7 +493,7 @@ impl Account {
         Ok(())
     }
 
-    fn has_sufficient_funds(&self, amount: f64) -> Result<(), Error> {
+    fn has_sufficient_funds(&self, amount: M) -> Result<(), Error> {
         if amount > self.available_balance {
             return Err(Error::InsufficientFunds);
         }
@@ -100,68 +501,244 @@ impl Account {
         Ok(())
     }
 
-    pub fn deposit(&mut self, amount: f64) -> Result<(), Error> {
-        self.is_locked()?;
-        self.available_balance += amount;
-        self.total_balance += amount;
+    /// Refuses deposits while locked, unless the repository lets locked
+    /// accounts take them.
+    fn takes_deposits(&self) -> Result<(), Error> {
+        match self.locked_deposits {
+            true => Ok(()),
+            false => self.is_locked(),
+        }
+    }
+
+    pub fn deposit(&mut self, amount: M) -> Result<(), Error> {
+        self.takes_deposits()?;
+        let amount = self.amount(amount);
+        self.available_balance = self.round(self.available_balance + amount);
+        self.total_balance = self.round(self.total_balance + amount);
         Ok(())
     }
 
-    pub fn withdrawal(&mut self, amount: f64) -> Result<(), Error> {
+    pub fn withdrawal(&mut self, amount: M) -> Result<(), Error> {
         self.is_locked()?;
+        let amount = self.amount(amount);
         self.has_sufficient_funds(amount)?;
-        self.available_balance -= amount;
-        self.total_balance -= amount;
+        self.available_balance = self.round(self.available_balance - amount);
+        self.total_balance = self.round(self.total_balance - amount);
         Ok(())
     }
 
-    pub fn dispute(&mut self, amount: f64) -> Result<(), Error> {
+    pub fn dispute(&mut self, amount: M) -> Result<(), Error> {
         self.is_locked()?;
+        self.hold(amount)
+    }
+
+    /// `dispute`, even while the account is locked.
+    pub(crate) fn hold(&mut self, amount: M) -> Result<(), Error> {
+        let amount = self.amount(amount);
         self.has_sufficient_funds(amount)?;
-        self.available_balance -= amount;
-        self.held_balance += amount;
+        self.available_balance = self.round(self.available_balance - amount);
+        self.held_balance = self.round(self.held_balance + amount);
         Ok(())
     }
 
-    fn has_sufficient_hold_balande(&self, amount: f64) -> Result<(), Error> {
+    fn has_sufficient_hold_balande(&self, amount: M) -> Result<(), Error> {
         if amount > self.held_balance {
             return Err(Error::InsufficientFunds);
         }
 
         Ok(())
     }
-    pub fn resolve(&mut self, amount: f64) -> Result<(), Error> {
+    pub fn resolve(&mut self, amount: M) -> Result<(), Error> {
         self.is_locked()?;
+        self.release(amount)
+    }
+
+    /// `resolve`, even while the account is locked.
+    pub(crate) fn release(&mut self, amount: M) -> Result<(), Error> {
+        let amount = self.amount(amount);
         self.has_sufficient_hold_balande(amount)?;
-        self.held_balance -= amount;
-        self.available_balance += amount;
+        self.held_balance = self.round(self.held_balance - amount);
+        self.available_balance = self.round(self.available_balance + amount);
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: f64) -> Result<(), Error> {
+    pub fn chargeback(&mut self, amount: M) -> Result<(), Error> {
         self.is_locked()?;
+        self.reverse(amount)
+    }
+
+    /// `chargeback`, even while the account is locked.
+    pub(crate) fn reverse(&mut self, amount: M) -> Result<(), Error> {
+        let amount = self.amount(amount);
         self.has_sufficient_hold_balande(amount)?;
-        self.held_balance -= amount;
-        self.total_balance -= amount;
+        self.held_balance = self.round(self.held_balance - amount);
+        self.total_balance = self.round(self.total_balance - amount);
         self.locked = true;
         Ok(())
     }
 
+    /// Takes in a deposit held for approval: into the held funds, not the
+    /// available ones.
+    pub fn hold_deposit(&mut self, amount: M) -> Result<(), Error> {
+        self.takes_deposits()?;
+        let amount = self.amount(amount);
+        self.held_balance = self.round(self.held_balance + amount);
+        self.total_balance = self.round(self.total_balance + amount);
+        Ok(())
+    }
+
+    /// Makes `amount` of a deposit held for approval available.
+    pub fn credit_held(&mut self, amount: M) -> Result<(), Error> {
+        self.takes_deposits()?;
+        self.release(amount)
+    }
+
+    /// Pays out `amount` held for a withdrawal that waited for approval.
+    pub fn pay_out(&mut self, amount: M) -> Result<(), Error> {
+        self.is_locked()?;
+        self.remove_held(amount)
+    }
+
+    /// `pay_out` of `amount` and `fee` on top from the available funds, all
+    /// or nothing: neither moves unless both are covered.
+    pub fn pay_out_with_fee(&mut self, amount: M, fee: M) -> Result<(), Error> {
+        self.is_locked()?;
+        self.has_sufficient_hold_balande(self.amount(amount))?;
+        self.has_sufficient_funds(self.amount(fee))?;
+        self.remove_held(amount)?;
+        self.charge_fee(fee);
+        Ok(())
+    }
+
+    /// `pay_out`, even while the account is locked, e.g. to send a rejected
+    /// deposit back.
+    pub(crate) fn remove_held(&mut self, amount: M) -> Result<(), Error> {
+        let amount = self.amount(amount);
+        self.has_sufficient_hold_balande(amount)?;
+        self.held_balance = self.round(self.held_balance - amount);
+        self.total_balance = self.round(self.total_balance - amount);
+        Ok(())
+    }
+
+    /// Moves the available and held funds by `available` and `held`, as a
+    /// plugin handling a custom type asks, refusing to take either below
+    /// zero.
+    #[cfg(feature = "plugins")]
+    pub(crate) fn adjust(&mut self, available: M, held: M) -> Result<(), Error> {
+        self.is_locked()?;
+        let zero = M::default();
+        let new_available = self.round(self.available_balance + self.amount(available));
+        let new_held = self.round(self.held_balance + self.amount(held));
+        if (available < zero && new_available < zero) || new_held < zero {
+            return Err(Error::InsufficientFunds);
+        }
+        self.total_balance = self.round(
+            self.total_balance
+                + (new_available - self.available_balance)
+                + (new_held - self.held_balance),
+        );
+        self.available_balance = new_available;
+        self.held_balance = new_held;
+        Ok(())
+    }
+
+    /// Takes a chargeback fee from the available funds, even while the
+    /// account is locked and even if they do not cover it.
+    pub(crate) fn charge_fee(&mut self, amount: M) {
+        let amount = self.amount(amount);
+        self.available_balance = self.round(self.available_balance - amount);
+        self.total_balance = self.round(self.total_balance - amount);
+    }
+
+    fn round(&self, amount: M) -> M {
+        match self.currency {
+            Some(_) => amount.round_to(self.rounding, self.decimals()),
+            None => amount.round(self.rounding),
+        }
+    }
+
+    /// `amount` as it is booked: at the currency's exponent, so that funds
+    /// are checked against what will actually move, and as given without a
+    /// currency.
+    fn amount(&self, amount: M) -> M {
+        match self.currency {
+            Some(_) => self.round(amount),
+            None => amount,
+        }
+    }
+
     pub fn locked(&self) -> bool {
         self.locked
     }
 
-    #[cfg(test)]
-    pub fn available_balance(&self) -> f64 {
-        (self.available_balance * 10000.0).round() / 10000.0
+    /// Why the account is locked. `None` while it is not, and for accounts
+    /// that were locked already when restored from a state file or from a
+    /// checkpoint that did not keep the reason.
+    pub fn lock_reason(&self) -> Option<&LockReason<M>> {
+        self.lock_reason.as_ref()
     }
-    #[cfg(test)]
-    pub fn held_balance(&self) -> f64 {
-        (self.held_balance * 10000.0).round() / 10000.0
+
+    /// The last `LOCK_HISTORY` lock changes, oldest first.
+    pub fn lock_history(&self) -> impl Iterator<Item = &LockEvent<M>> {
+        self.lock_history.iter()
+    }
+
+    /// Locks the account for `reason`. Does nothing if it is locked
+    /// already, keeping the reason it was locked for first.
+    pub fn lock(&mut self, reason: LockReason<M>, timestamp: Option<u64>) {
+        if !self.locked {
+            self.locked = true;
+            self.record_lock(reason, timestamp);
+        }
+    }
+
+    /// Lifts the lock, whatever its reason. Does nothing if the account is
+    /// not locked.
+    pub fn unlock(&mut self, timestamp: Option<u64>) {
+        if self.locked {
+            self.locked = false;
+            self.lock_reason = None;
+            self.record(LockEvent::Unlocked { timestamp });
+        }
+    }
+
+    /// Records why the account was just locked by `chargeback`, which
+    /// does not know the transaction itself.
+    pub(crate) fn record_lock(&mut self, reason: LockReason<M>, timestamp: Option<u64>) {
+        self.lock_reason = Some(reason);
+        self.record(LockEvent::Locked { reason, timestamp });
     }
-    #[cfg(test)]
-    pub fn total_balance(&self) -> f64 {
-        (self.total_balance * 10000.0).round() / 10000.0
+
+    /// Restores the reason and history of the lock as a checkpoint kept
+    /// them. The reason is dropped if the account is not locked.
+    #[cfg(feature = "json")]
+    pub(crate) fn restore_lock(
+        &mut self,
+        reason: Option<LockReason<M>>,
+        history: impl IntoIterator<Item = LockEvent<M>>,
+    ) {
+        self.lock_reason = reason.filter(|_| self.locked);
+        self.lock_history.clear();
+        for event in history {
+            self.record(event);
+        }
+    }
+
+    fn record(&mut self, event: LockEvent<M>) {
+        if self.lock_history.len() == LOCK_HISTORY {
+            self.lock_history.pop_front();
+        }
+        self.lock_history.push_back(event);
+    }
+
+    pub fn available_balance(&self) -> M {
+        self.round(self.available_balance)
+    }
+    pub fn held_balance(&self) -> M {
+        self.round(self.held_balance)
+    }
+    pub fn total_balance(&self) -> M {
+        self.round(self.total_balance)
     }
 }
 
@@ -180,6 +757,47 @@ mod test {
         acc
     }
 
+    #[test]
+    fn read_while_writing() {
+        let mut accounts = AccountsRepository::new();
+        let reader = accounts.reader();
+        let watcher = std::thread::spawn(move || {
+            let mut last = 0.0;
+            while last < 1000.0 {
+                if let Some(account) = reader.get(3) {
+                    assert!(account.available_balance() >= last);
+                    assert_eq!(account.available_balance(), account.total_balance());
+                    last = account.available_balance();
+                }
+            }
+            reader.len()
+        });
+        for client in 0..40 {
+            accounts.get_or_create(client);
+        }
+        for _ in 0..1000 {
+            accounts.get_or_create(3).deposit(1.0).unwrap();
+        }
+        assert_eq!(watcher.join().unwrap(), 40);
+        assert_eq!(accounts.sorted().len(), 40);
+        assert_eq!(accounts.sorted()[3].client_id(), 3);
+        accounts.retain(|client| client % 2 == 1);
+        assert_eq!(accounts.len(), 20);
+        assert!(accounts.get(4).is_none());
+    }
+
+    #[test]
+    fn half_even_rounding() {
+        let mut account = Account::with_rounding(1, Rounding::HalfEven);
+        assert!(account.deposit(1.00005).is_ok());
+        assert!(account.deposit(1.00015).is_ok());
+        assert_eq!(account.available_balance(), 2.0002);
+
+        let mut account = Account::new(1);
+        assert!(account.deposit(1.00005).is_ok());
+        assert_eq!(account.available_balance(), 1.0001);
+    }
+
     #[test]
     fn deposit() {
         let mut account = base_account();
@@ -280,4 +898,48 @@ mod test {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), Error::LockedAccount);
     }
+
+    #[test]
+    fn locked_deposits() {
+        let mut accounts = AccountsRepository::new();
+        accounts.get_or_create(1).deposit(20.0).unwrap();
+        accounts
+            .get_or_create(1)
+            .lock(LockReason::AdminFreeze, None);
+        let mut accounts = accounts.with_locked_deposits(true);
+        for id in [1, 2] {
+            let mut account = accounts.get_or_create(id);
+            account.lock(LockReason::AdminFreeze, None);
+            assert!(account.deposit(5.0).is_ok());
+            assert_eq!(account.withdrawal(1.0).unwrap_err(), Error::LockedAccount);
+        }
+        assert_eq!(accounts.get(1).unwrap().total_balance(), 25.0);
+        assert_eq!(accounts.get(2).unwrap().total_balance(), 5.0);
+    }
+
+    #[test]
+    fn lock_history() {
+        let mut account = base_account_with_funds(20.0);
+        let rule = LockReason::RiskRule(Label::new("velocity").unwrap());
+        account.lock(LockReason::AdminFreeze, Some(10));
+        account.lock(rule, Some(20));
+        assert_eq!(account.lock_reason(), Some(&LockReason::AdminFreeze));
+        assert_eq!(account.deposit(1.0).unwrap_err(), Error::LockedAccount);
+        account.unlock(Some(30));
+        assert_eq!(account.lock_reason(), None);
+        assert!(account.deposit(1.0).is_ok());
+        for day in 0..LOCK_HISTORY as u64 {
+            account.lock(rule, Some(day));
+            account.unlock(None);
+        }
+        assert_eq!(account.lock_history().count(), LOCK_HISTORY);
+        assert_eq!(
+            account.lock_history().next(),
+            Some(&LockEvent::Locked {
+                reason: rule,
+                timestamp: Some(4)
+            })
+        );
+        assert_eq!(rule.to_string(), "risk_rule:velocity");
+    }
 }
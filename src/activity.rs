@@ -0,0 +1,581 @@
+//! Per-client counters shown next to the balances in the extended
+//! snapshot, so that problematic accounts stand out without going through
+//! the logs or the rejects report, and the open disputes, pending
+//! withdrawals, locked, dormant and overdrawn accounts left at the end of a
+//! run.
+
+use crate::account::{AccountsRepository, LockEvent, LockReason};
+use crate::engine::{RejectReason, Rejection};
+use crate::money::Money;
+use crate::transaction::{Transaction, TransactionLedger, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ExtendedAccount {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+    /// Why the account is locked, when it is and the reason is known.
+    pub lock_reason: Option<LockReason>,
+    /// Withdrawals refused for any reason but a reused tx id.
+    pub rejected_withdrawals: u64,
+    /// Deposits and withdrawals ignored as repeats of an earlier one.
+    pub ignored_duplicates: u64,
+    /// Transactions of the client currently under dispute.
+    pub open_disputes: u64,
+    /// Chargebacks applied to the client's transactions.
+    pub chargebacks: u64,
+    /// Chargebacks per applied deposit, to be held against the thresholds
+    /// of the card networks, e.g. 0.009 for 0.9%. Rounded to 4 places and 0
+    /// without deposits.
+    pub chargeback_ratio: f64,
+}
+
+/// Every account with its counters, ordered by client id. `transactions`
+/// is the input the ledger and the rejections came from.
+pub fn extended(
+    accounts: &AccountsRepository,
+    tx_ledger: &TransactionLedger,
+    transactions: &[Transaction],
+    rejections: &[Rejection],
+) -> Vec<ExtendedAccount> {
+    let mut counters: HashMap<u16, ExtendedAccount> = HashMap::new();
+    let mut deposits: HashMap<u16, u64> = HashMap::new();
+    for tx in transactions {
+        match tx.r#type() {
+            Type::Deposit => *deposits.entry(tx.account_id()).or_default() += 1,
+            Type::Chargeback => counters.entry(tx.account_id()).or_default().chargebacks += 1,
+            _ => {}
+        }
+    }
+    for rejection in rejections {
+        match rejection.r#type {
+            Type::Deposit => {
+                let deposits = deposits.entry(rejection.client).or_default();
+                *deposits = deposits.saturating_sub(1);
+            }
+            Type::Chargeback => {
+                let counter = counters.entry(rejection.client).or_default();
+                counter.chargebacks = counter.chargebacks.saturating_sub(1);
+            }
+            _ => {}
+        }
+        let counter = counters.entry(rejection.client).or_default();
+        match (rejection.r#type, rejection.reason) {
+            (_, RejectReason::DuplicateTx) => counter.ignored_duplicates += 1,
+            (_, RejectReason::ConflictingTx) => {}
+            (Type::Withdrawal, _) => counter.rejected_withdrawals += 1,
+            _ => {}
+        }
+    }
+    for tx in tx_ledger.iter().filter(|tx| tx.is_dispute()) {
+        counters.entry(tx.account_id()).or_default().open_disputes += 1;
+    }
+    for (client, counter) in &mut counters {
+        if let Some(&deposits) = deposits.get(client).filter(|&&deposits| deposits > 0) {
+            let ratio = counter.chargebacks as f64 / deposits as f64;
+            counter.chargeback_ratio = (ratio * 10_000.0).round() / 10_000.0;
+        }
+    }
+    accounts
+        .sorted()
+        .into_iter()
+        .map(|account| {
+            let client = account.client_id();
+            ExtendedAccount {
+                client,
+                available: account.available_balance(),
+                held: account.held_balance(),
+                total: account.total_balance(),
+                locked: account.locked(),
+                lock_reason: account.lock_reason().copied(),
+                ..counters.get(&client).copied().unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+/// A transaction still under dispute, as a worklist entry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct OpenDispute {
+    pub client: u16,
+    pub tx: u32,
+    pub amount: f64,
+    /// Seconds since the Unix epoch the dispute was raised at, when the
+    /// dispute row has a timestamp.
+    pub disputed_at: Option<u64>,
+    /// Whole days from `disputed_at` to the latest timestamp of the input.
+    pub age_days: Option<u64>,
+}
+
+/// Every transaction of `tx_ledger` still under dispute, ordered by client
+/// and tx id. The time a dispute was raised is that of the last dispute row
+/// for it among `transactions`, the input the ledger was built from.
+pub fn open_disputes(
+    tx_ledger: &TransactionLedger,
+    transactions: &[Transaction],
+) -> Vec<OpenDispute> {
+    let mut raised: HashMap<u32, u64> = HashMap::new();
+    for tx in transactions
+        .iter()
+        .filter(|tx| tx.r#type() == Type::Dispute)
+    {
+        if let Some(timestamp) = tx.timestamp() {
+            raised.insert(tx.id(), timestamp);
+        }
+    }
+    let as_of = transactions.iter().filter_map(Transaction::timestamp).max();
+    let mut disputes: Vec<OpenDispute> = tx_ledger
+        .iter()
+        .filter(|tx| tx.is_dispute())
+        .map(|tx| {
+            let disputed_at = raised.get(&tx.id()).copied();
+            OpenDispute {
+                client: tx.account_id(),
+                tx: tx.id(),
+                amount: tx.amount(),
+                disputed_at,
+                age_days: disputed_at
+                    .zip(as_of)
+                    .map(|(disputed_at, as_of)| as_of.saturating_sub(disputed_at) / 86_400),
+            }
+        })
+        .collect();
+    disputes.sort_by_key(|dispute| (dispute.client, dispute.tx));
+    disputes
+}
+
+/// What an open item waits for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OpenItemKind {
+    /// A resolve or chargeback.
+    Dispute,
+    /// An approve or reject of a held deposit.
+    PendingDeposit,
+    /// An approve or reject of a held withdrawal.
+    PendingWithdrawal,
+}
+
+/// A transaction still waiting for a decision, as a worklist entry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct OpenItem {
+    pub client: u16,
+    pub tx: u32,
+    pub item: OpenItemKind,
+    pub amount: f64,
+    /// Seconds since the Unix epoch the dispute was raised or the withdrawal
+    /// requested at, when its row has a timestamp.
+    pub opened_at: Option<u64>,
+    /// Whole days from `opened_at` to the latest timestamp of the input.
+    pub age_days: Option<u64>,
+}
+
+/// The open disputes of `tx_ledger`, as `open_disputes` finds them, and its
+/// deposits and withdrawals pending approval, ordered by client and tx id.
+pub fn open_items(tx_ledger: &TransactionLedger, transactions: &[Transaction]) -> Vec<OpenItem> {
+    let as_of = transactions.iter().filter_map(Transaction::timestamp).max();
+    let disputes = open_disputes(tx_ledger, transactions)
+        .into_iter()
+        .map(|dispute| OpenItem {
+            client: dispute.client,
+            tx: dispute.tx,
+            item: OpenItemKind::Dispute,
+            amount: dispute.amount,
+            opened_at: dispute.disputed_at,
+            age_days: dispute.age_days,
+        });
+    let pending = tx_ledger
+        .iter()
+        .filter(|tx| tx.is_pending())
+        .map(|tx| OpenItem {
+            client: tx.account_id(),
+            tx: tx.id(),
+            item: match tx.r#type() {
+                Type::Deposit => OpenItemKind::PendingDeposit,
+                _ => OpenItemKind::PendingWithdrawal,
+            },
+            amount: tx.amount(),
+            opened_at: tx.timestamp(),
+            age_days: tx
+                .timestamp()
+                .zip(as_of)
+                .map(|(opened_at, as_of)| as_of.saturating_sub(opened_at) / 86_400),
+        });
+    let mut items: Vec<OpenItem> = disputes.chain(pending).collect();
+    items.sort_by_key(|item| (item.client, item.tx));
+    items
+}
+
+/// A locked account with what locked it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LockedAccount {
+    pub client: u16,
+    /// Empty when the reason is not known.
+    pub reason: Option<LockReason>,
+    /// The tx id of the chargeback, for accounts locked by one.
+    pub tx: Option<u32>,
+    pub amount: Option<f64>,
+    /// Seconds since the Unix epoch of the transaction that locked the
+    /// account, if it had a timestamp.
+    pub locked_at: Option<u64>,
+}
+
+/// Every locked account, ordered by client id.
+pub fn locked_accounts(accounts: &AccountsRepository) -> Vec<LockedAccount> {
+    accounts
+        .sorted()
+        .into_iter()
+        .filter(|account| account.locked())
+        .map(|account| {
+            let reason = account.lock_reason().copied();
+            let (tx, amount) = match reason {
+                Some(LockReason::Chargeback { tx, amount }) => {
+                    (Some(tx), Some(Money::round(amount, accounts.rounding())))
+                }
+                _ => (None, None),
+            };
+            let locked_at = account
+                .lock_history()
+                .filter_map(|event| match event {
+                    LockEvent::Locked { timestamp, .. } => Some(*timestamp),
+                    LockEvent::Unlocked { .. } => None,
+                })
+                .last()
+                .flatten();
+            LockedAccount {
+                client: account.client_id(),
+                reason,
+                tx,
+                amount,
+                locked_at,
+            }
+        })
+        .collect()
+}
+
+/// An account without activity for at least the dormancy period.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DormantAccount {
+    pub client: u16,
+    /// Seconds since the Unix epoch of the client's latest transaction.
+    pub last_activity: u64,
+    /// Whole days from there to the latest timestamp of the input.
+    pub idle_days: u64,
+}
+
+/// Every account of `accounts` idle for `days` days or more, ordered by
+/// client id. Any transaction of the client in `transactions` counts as
+/// activity, applied or not, and idleness is measured up to the latest
+/// timestamp there. Clients none of whose transactions has a timestamp are
+/// left out.
+pub fn dormant_accounts(
+    accounts: &AccountsRepository,
+    transactions: &[Transaction],
+    days: u64,
+) -> Vec<DormantAccount> {
+    let mut last_activity: HashMap<u16, u64> = HashMap::new();
+    for tx in transactions {
+        if let Some(timestamp) = tx.timestamp() {
+            let last = last_activity.entry(tx.account_id()).or_default();
+            *last = timestamp.max(*last);
+        }
+    }
+    let Some(as_of) = last_activity.values().max().copied() else {
+        return Vec::new();
+    };
+    accounts
+        .sorted()
+        .into_iter()
+        .filter_map(|account| {
+            let client = account.client_id();
+            let last_activity = *last_activity.get(&client)?;
+            let idle_days = (as_of - last_activity) / 86_400;
+            (idle_days >= days).then_some(DormantAccount {
+                client,
+                last_activity,
+                idle_days,
+            })
+        })
+        .collect()
+}
+
+/// An account whose available balance is negative.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NegativeAccount {
+    pub client: u16,
+    pub available: f64,
+    pub total: f64,
+    /// What the client owes, the available balance without its sign.
+    pub deficit: f64,
+    /// The deficits of this and every account listed before it, so the
+    /// last one holds the total exposure.
+    pub cumulative_deficit: f64,
+}
+
+/// Every account with a negative available balance, largest deficit first
+/// and then by client id.
+pub fn negative_exposure(accounts: &AccountsRepository) -> Vec<NegativeAccount> {
+    let mut negative: Vec<NegativeAccount> = accounts
+        .sorted()
+        .into_iter()
+        .filter(|account| account.available_balance() < 0.0)
+        .map(|account| NegativeAccount {
+            client: account.client_id(),
+            available: account.available_balance(),
+            total: account.total_balance(),
+            deficit: -account.available_balance(),
+            cumulative_deficit: 0.0,
+        })
+        .collect();
+    negative.sort_by(|a, b| b.deficit.total_cmp(&a.deficit));
+    let mut cumulative = 0.0;
+    for account in &mut negative {
+        cumulative = Money::round(cumulative + account.deficit, accounts.rounding());
+        account.cumulative_deficit = cumulative;
+    }
+    negative
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn counters() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts);
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(3, Type::Withdrawal, 1, 9.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(4, Type::Withdrawal, 1, 6.0),
+            Transaction::new(5, Type::Deposit, 2, 1.0),
+            Transaction::new(6, Type::Deposit, 3, 2.0),
+            Transaction::new(7, Type::Deposit, 3, 1.0),
+            Transaction::new(8, Type::Deposit, 3, 1.0),
+            Transaction::new(6, Type::Dispute, 3, 0.0),
+            Transaction::new(6, Type::Chargeback, 3, 0.0),
+            Transaction::new(7, Type::Chargeback, 3, 0.0),
+        ];
+        engine.process(&transactions);
+        let rejections = engine.rejections().to_vec();
+        let extended = extended(&accounts, &tx_ledger, &transactions, &rejections);
+        assert_eq!(
+            extended,
+            [
+                ExtendedAccount {
+                    client: 1,
+                    available: 5.0,
+                    held: 3.0,
+                    total: 8.0,
+                    locked: false,
+                    rejected_withdrawals: 2,
+                    ignored_duplicates: 1,
+                    open_disputes: 1,
+                    ..Default::default()
+                },
+                ExtendedAccount {
+                    client: 2,
+                    available: 1.0,
+                    total: 1.0,
+                    ..Default::default()
+                },
+                ExtendedAccount {
+                    client: 3,
+                    available: 2.0,
+                    total: 2.0,
+                    locked: true,
+                    chargebacks: 1,
+                    lock_reason: Some(LockReason::Chargeback { tx: 6, amount: 2.0 }),
+                    chargeback_ratio: 0.3333,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn open_dispute_worklist() {
+        let day = 86_400;
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 2, 5.0).with_timestamp(Some(0)),
+            Transaction::new(2, Type::Deposit, 1, 3.0).with_timestamp(Some(0)),
+            Transaction::new(3, Type::Deposit, 1, 1.5),
+            Transaction::new(1, Type::Dispute, 2, 0.0).with_timestamp(Some(day)),
+            Transaction::new(2, Type::Dispute, 1, 0.0).with_timestamp(Some(day)),
+            Transaction::new(2, Type::Resolve, 1, 0.0).with_timestamp(Some(2 * day)),
+            Transaction::new(2, Type::Dispute, 1, 0.0).with_timestamp(Some(3 * day)),
+            Transaction::new(3, Type::Dispute, 1, 0.0),
+            Transaction::new(4, Type::Deposit, 3, 1.0).with_timestamp(Some(10 * day + 5)),
+        ];
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut accounts).process(&transactions);
+        assert_eq!(
+            open_disputes(&tx_ledger, &transactions),
+            [
+                OpenDispute {
+                    client: 1,
+                    tx: 2,
+                    amount: 3.0,
+                    disputed_at: Some(3 * day),
+                    age_days: Some(7),
+                },
+                OpenDispute {
+                    client: 1,
+                    tx: 3,
+                    amount: 1.5,
+                    disputed_at: None,
+                    age_days: None,
+                },
+                OpenDispute {
+                    client: 2,
+                    tx: 1,
+                    amount: 5.0,
+                    disputed_at: Some(day),
+                    age_days: Some(9),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn open_items_include_pending_withdrawals() {
+        let day = 86_400;
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 100.0).with_timestamp(Some(0)),
+            Transaction::new(2, Type::Withdrawal, 1, 60.0).with_timestamp(Some(day)),
+            Transaction::new(3, Type::Withdrawal, 1, 20.0).with_timestamp(Some(day)),
+            Transaction::new(4, Type::Withdrawal, 1, 55.0),
+            Transaction::new(5, Type::Deposit, 2, 1.0).with_timestamp(Some(4 * day)),
+            Transaction::new(5, Type::Dispute, 2, 0.0).with_timestamp(Some(4 * day)),
+        ];
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut accounts)
+            .with_withdrawal_approval(50.0)
+            .process(&transactions);
+        let account = accounts.get(1).unwrap();
+        assert_eq!(
+            (account.available_balance(), account.held_balance()),
+            (20.0, 60.0)
+        );
+        drop(account);
+        let items: Vec<(u32, OpenItemKind, Option<u64>)> = open_items(&tx_ledger, &transactions)
+            .iter()
+            .map(|item| (item.tx, item.item, item.age_days))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (2, OpenItemKind::PendingWithdrawal, Some(3)),
+                (5, OpenItemKind::Dispute, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lock_causes() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut accounts).process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 2, 3.25),
+            Transaction::new(3, Type::Deposit, 2, 1.0),
+            Transaction::new(2, Type::Chargeback, 2, 0.0).with_timestamp(Some(50)),
+            Transaction::new(2, Type::Dispute, 2, 0.0),
+            Transaction::new(3, Type::Dispute, 2, 0.0),
+            Transaction::new(2, Type::Chargeback, 2, 0.0).with_timestamp(Some(100)),
+            Transaction::new(3, Type::Chargeback, 2, 0.0).with_timestamp(Some(200)),
+        ]);
+        assert_eq!(
+            locked_accounts(&accounts),
+            [LockedAccount {
+                client: 2,
+                reason: Some(LockReason::Chargeback {
+                    tx: 2,
+                    amount: 3.25
+                }),
+                tx: Some(2),
+                amount: Some(3.25),
+                locked_at: Some(100),
+            }]
+        );
+    }
+
+    #[test]
+    fn dormancy() {
+        let day = 86_400;
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0).with_timestamp(Some(0)),
+            Transaction::new(2, Type::Deposit, 2, 5.0).with_timestamp(Some(day)),
+            Transaction::new(3, Type::Withdrawal, 2, 9.0).with_timestamp(Some(40 * day)),
+            Transaction::new(4, Type::Deposit, 3, 5.0),
+            Transaction::new(5, Type::Deposit, 4, 5.0).with_timestamp(Some(10 * day)),
+            Transaction::new(6, Type::Deposit, 5, 5.0).with_timestamp(Some(100 * day)),
+        ];
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut accounts).process(&transactions);
+        assert_eq!(
+            dormant_accounts(&accounts, &transactions, 90),
+            [
+                DormantAccount {
+                    client: 1,
+                    last_activity: 0,
+                    idle_days: 100,
+                },
+                DormantAccount {
+                    client: 4,
+                    last_activity: 10 * day,
+                    idle_days: 90,
+                },
+            ]
+        );
+        assert!(dormant_accounts(&accounts, &transactions[3..4], 0).is_empty());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn exposure() {
+        use crate::account::Account;
+
+        let mut accounts = AccountsRepository::new();
+        for (client, available, held) in [
+            (1, -2.5, 0.0),
+            (2, 4.0, 0.0),
+            (3, -7.25, 3.0),
+            (4, -2.5, 0.0),
+        ] {
+            accounts.restore(Account::from_balances(
+                client,
+                available,
+                held,
+                available + held,
+                false,
+                accounts.rounding(),
+            ));
+        }
+        let exposure: Vec<(u16, f64, f64)> = negative_exposure(&accounts)
+            .iter()
+            .map(|account| (account.client, account.deficit, account.cumulative_deficit))
+            .collect();
+        assert_eq!(exposure, [(3, 7.25, 7.25), (1, 2.5, 9.75), (4, 2.5, 12.25)]);
+    }
+}
@@ -0,0 +1,497 @@
+//! Per-client actors for the streaming server.
+//!
+//! Every client gets an actor the first time one of its transactions
+//! arrives: a bounded mailbox that a `Router` puts the client's
+//! transactions into. The actors are run by a fixed pool of workers. Each
+//! worker owns the accounts and the share of the ledger of the clients
+//! whose id falls to it, so clients of different workers are applied in
+//! parallel and nothing is locked while applying. A worker takes turns
+//! between its clients with transactions waiting, applying up to `BATCH`
+//! of one before moving on, so a busy client does not hold up the others.
+//! A client whose mailbox is full slows the router down rather than
+//! buffering without end, and an actor whose mailbox stayed empty for
+//! `REAP_AFTER` is dropped; the client's account and transactions stay with
+//! the worker.
+//!
+//! The router remembers which client every tx id belongs to. A deposit or
+//! withdrawal reusing another client's id is rejected as `ConflictingTx`,
+//! and a dispute, resolve or chargeback of another client's transaction as
+//! `ClientMismatch`, so that the outcome is that of a single engine. Both
+//! are rejected before the referenced transaction is looked at, which
+//! makes them win over a reason a single engine would have checked first,
+//! such as `AlreadyDisputed`.
+
+use crate::account::{AccountsReader, AccountsRepository, Snapshot};
+use crate::dead_letter::{DeadLetter, DeadLetters};
+use crate::engine::{Engine, RejectReason, Rejection};
+use crate::expiry::HoldExpiry;
+use crate::metrics::EngineMetrics;
+use crate::transaction::{Transaction, TransactionLedger, Type};
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Most transactions a worker applies for one client before it turns to
+/// the next one waiting.
+const BATCH: usize = 64;
+
+/// How often an idle worker checks its holds for expiry, and any worker
+/// reaps idle actors.
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+/// How long the mailbox of an actor stays empty before the actor is
+/// dropped.
+const REAP_AFTER: Duration = Duration::from_secs(60);
+
+enum Message {
+    Apply(Transaction),
+    /// Refused by the router. The worker records it like one it refused
+    /// itself, opening the account as an engine would.
+    Reject(Transaction, RejectReason),
+}
+
+/// The mailbox of a client.
+struct Actor {
+    messages: VecDeque<Message>,
+    /// Whether the client is waiting in `Queues::ready` or being applied.
+    scheduled: bool,
+    /// When `messages` last ran empty.
+    idle_since: Instant,
+}
+
+/// What a worker shares with the router.
+#[derive(Default)]
+struct Queues {
+    actors: HashMap<u16, Actor>,
+    /// Clients with transactions waiting, in turn.
+    ready: VecDeque<u16>,
+    /// Set while the worker applies transactions it took out.
+    busy: bool,
+    closed: bool,
+    hold_expiry: Option<HoldExpiry>,
+    dead_letters: Option<DeadLetters>,
+}
+
+impl Queues {
+    /// Drops the actors whose mailbox has been empty for `REAP_AFTER`.
+    fn reap(&mut self, now: Instant) {
+        self.actors.retain(|_, actor| {
+            actor.scheduled || now.duration_since(actor.idle_since) < REAP_AFTER
+        });
+    }
+}
+
+#[derive(Default)]
+struct Inbox {
+    queues: Mutex<Queues>,
+    /// Signalled when a client gets transactions or the router closes.
+    work: Condvar,
+    /// Signalled when the worker took transactions out or is done with them.
+    taken: Condvar,
+}
+
+/// What a worker ends with.
+struct Finished {
+    tx_ledger: TransactionLedger,
+    accounts: AccountsRepository,
+    rejections: Vec<Rejection>,
+    metrics: EngineMetrics,
+}
+
+struct Worker {
+    inbox: Arc<Inbox>,
+    accounts: AccountsReader,
+    handle: JoinHandle<Finished>,
+}
+
+/// Everything the actors applied, gathered by `Router::finish`.
+pub struct Outcome {
+    pub tx_ledger: TransactionLedger,
+    pub accounts: AccountsRepository,
+    /// Every rejection, worker by worker, each worker's in processing
+    /// order.
+    pub rejections: Vec<Rejection>,
+    pub metrics: EngineMetrics,
+}
+
+/// Dispatches transactions to the actor of their client.
+pub struct Router {
+    mailbox: usize,
+    workers: Vec<Worker>,
+    /// Rounding, currency and locked deposits of every worker's accounts.
+    template: AccountsRepository,
+    owners: HashMap<u32, u16>,
+}
+
+impl Router {
+    /// A router whose actors queue up to `mailbox` transactions each, run
+    /// by `workers` threads. Accounts are opened like those of `accounts`,
+    /// with its rounding, currency and whether locked ones take deposits.
+    pub fn new(mailbox: usize, workers: usize, accounts: &AccountsRepository) -> Router {
+        let template = accounts.configured_like();
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let inbox = Arc::new(Inbox::default());
+                let accounts = template.configured_like();
+                let reader = accounts.reader();
+                let handle = {
+                    let inbox = Arc::clone(&inbox);
+                    thread::spawn(move || work(&inbox, accounts))
+                };
+                Worker {
+                    inbox,
+                    accounts: reader,
+                    handle,
+                }
+            })
+            .collect();
+        Router {
+            mailbox: mailbox.max(1),
+            workers,
+            template,
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Closes disputes that stay open longer than `policy` allows, see
+    /// `Engine::with_hold_expiry`.
+    pub fn set_hold_expiry(&mut self, policy: Option<HoldExpiry>) {
+        for worker in &self.workers {
+            worker.inbox.queues.lock().unwrap().hold_expiry = policy;
+        }
+    }
+
+    /// Sends every transaction the actors reject to `dead_letters`.
+    pub fn set_dead_letters(&mut self, dead_letters: Option<DeadLetters>) {
+        for worker in &self.workers {
+            worker.inbox.queues.lock().unwrap().dead_letters = dead_letters.clone();
+        }
+    }
+
+    /// Number of clients with an actor, not counting those reaped.
+    pub fn clients(&self) -> usize {
+        self.workers
+            .iter()
+            .map(|worker| worker.inbox.queues.lock().unwrap().actors.len())
+            .sum()
+    }
+
+    /// Number of accounts over all workers.
+    pub fn accounts(&self) -> usize {
+        self.workers
+            .iter()
+            .map(|worker| worker.accounts.len())
+            .sum()
+    }
+
+    /// Number of distinct tx ids dispatched.
+    pub fn transactions(&self) -> usize {
+        self.owners.len()
+    }
+
+    /// Handles to read the accounts of every worker through, see
+    /// `AccountsRepository::reader`.
+    pub fn readers(&self) -> Vec<AccountsReader> {
+        self.workers
+            .iter()
+            .map(|worker| worker.accounts.clone())
+            .collect()
+    }
+
+    /// The accounts of every worker as they are now. Call `flush` first
+    /// for them to include everything dispatched.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.workers
+            .iter()
+            .map(|worker| worker.accounts.snapshot())
+            .collect()
+    }
+
+    /// Hands `tx` to its client's actor, starting one if needed. Blocks
+    /// while that actor's mailbox is full.
+    pub fn dispatch(&mut self, tx: Transaction) {
+        let client = tx.account_id();
+        let message = match self.owners.entry(tx.id()) {
+            Entry::Vacant(entry) => {
+                entry.insert(client);
+                Message::Apply(tx)
+            }
+            Entry::Occupied(entry) if *entry.get() == client => Message::Apply(tx),
+            Entry::Occupied(..) => match tx.r#type() {
+                Type::Deposit | Type::Withdrawal | Type::Bonus | Type::Fee | Type::Custom(_) => {
+                    Message::Reject(tx, RejectReason::ConflictingTx)
+                }
+                Type::Dispute | Type::Resolve | Type::Chargeback | Type::Approve | Type::Reject => {
+                    Message::Reject(tx, RejectReason::ClientMismatch)
+                }
+            },
+        };
+        let worker = &self.workers[usize::from(client) % self.workers.len()];
+        let mut queues = worker.inbox.queues.lock().unwrap();
+        while queues
+            .actors
+            .get(&client)
+            .is_some_and(|actor| actor.messages.len() >= self.mailbox)
+        {
+            if worker.handle.is_finished() {
+                log::warn!(
+                    "worker of client {} is gone, dropping tx {}",
+                    client,
+                    tx.id()
+                );
+                return;
+            }
+            queues = worker
+                .inbox
+                .taken
+                .wait_timeout(queues, IDLE_CHECK)
+                .unwrap()
+                .0;
+        }
+        let queues = &mut *queues;
+        let actor = queues.actors.entry(client).or_insert_with(|| Actor {
+            messages: VecDeque::new(),
+            scheduled: false,
+            idle_since: Instant::now(),
+        });
+        actor.messages.push_back(message);
+        if !actor.scheduled {
+            actor.scheduled = true;
+            queues.ready.push_back(client);
+            worker.inbox.work.notify_one();
+        }
+    }
+
+    /// Waits for every actor to apply what it was sent so far.
+    pub fn flush(&self) {
+        for worker in &self.workers {
+            let mut queues = worker.inbox.queues.lock().unwrap();
+            while (queues.busy || !queues.ready.is_empty()) && !worker.handle.is_finished() {
+                queues = worker
+                    .inbox
+                    .taken
+                    .wait_timeout(queues, IDLE_CHECK)
+                    .unwrap()
+                    .0;
+            }
+        }
+    }
+
+    /// Waits for every actor to apply what it was sent, stops the workers
+    /// and gathers their ledgers, accounts, rejections and metrics.
+    pub fn finish(self) -> Outcome {
+        let mut outcome = Outcome {
+            tx_ledger: TransactionLedger::new(),
+            accounts: self.template,
+            rejections: Vec::new(),
+            metrics: EngineMetrics::default(),
+        };
+        for worker in &self.workers {
+            worker.inbox.queues.lock().unwrap().closed = true;
+            worker.inbox.work.notify_one();
+        }
+        for (index, worker) in self.workers.into_iter().enumerate() {
+            let Ok(finished) = worker.handle.join() else {
+                log::error!("actor worker {} panicked, its state is lost", index);
+                continue;
+            };
+            for tx in finished.tx_ledger.iter() {
+                outcome.tx_ledger.append(tx);
+                match finished.tx_ledger.disputed_at(tx.id()) {
+                    Some(at) => outcome.tx_ledger.dispute_tx_at(tx.id(), at),
+                    None if tx.is_dispute() => outcome.tx_ledger.dispute_tx(tx.id()),
+                    None if tx.is_charged_back() => outcome.tx_ledger.charge_back_tx(tx.id()),
+                    None => {}
+                }
+                if let Some(approval) = tx.approval() {
+                    outcome.tx_ledger.set_approval(tx.id(), approval);
+                }
+            }
+            for account in finished.accounts.sorted() {
+                outcome.accounts.restore(account);
+            }
+            outcome.rejections.extend(finished.rejections);
+            outcome.metrics.merge(&finished.metrics);
+        }
+        outcome
+    }
+}
+
+/// Applies what the mailboxes of `inbox` hold until the router closes it
+/// and they are empty.
+fn work(inbox: &Inbox, mut accounts: AccountsRepository) -> Finished {
+    let mut tx_ledger = TransactionLedger::new();
+    let mut rejections = Vec::new();
+    let mut metrics = EngineMetrics::default();
+    let mut batch = Vec::with_capacity(BATCH);
+    let mut reaped_at = Instant::now();
+    loop {
+        let mut queues = inbox.queues.lock().unwrap();
+        if queues.ready.is_empty() && !queues.closed {
+            queues = inbox.work.wait_timeout(queues, IDLE_CHECK).unwrap().0;
+        }
+        let client = queues.ready.pop_front();
+        match client {
+            Some(client) => {
+                let actor = queues.actors.get_mut(&client).expect("ready without actor");
+                let taken = actor.messages.len().min(BATCH);
+                batch.extend(actor.messages.drain(..taken));
+                queues.busy = true;
+                inbox.taken.notify_all();
+            }
+            None if queues.closed => break,
+            None => {}
+        }
+        if reaped_at.elapsed() >= IDLE_CHECK {
+            reaped_at = Instant::now();
+            queues.reap(reaped_at);
+        }
+        let (hold_expiry, dead_letters) = (queues.hold_expiry, queues.dead_letters.clone());
+        drop(queues);
+
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts);
+        if let Some(policy) = hold_expiry {
+            engine = engine.with_hold_expiry(policy);
+        }
+        if client.is_none() {
+            engine.expire_holds();
+        }
+        for message in batch.drain(..) {
+            let (tx, reason) = match message {
+                Message::Apply(tx) => match engine.apply(tx) {
+                    Ok(..) => continue,
+                    Err(reason) => (tx, reason),
+                },
+                Message::Reject(tx, reason) => {
+                    if engine.accounts.get(tx.account_id()).is_none() {
+                        engine.accounts.get_or_create(tx.account_id());
+                        metrics.accounts_created += 1;
+                    }
+                    metrics.record(tx.r#type(), Err(reason));
+                    (tx, reason)
+                }
+            };
+            if let Some(dead_letters) = &dead_letters {
+                dead_letters.send(DeadLetter::rejected(&tx, reason));
+            }
+            rejections.push(Rejection::new(&tx, reason));
+        }
+        metrics.merge(engine.metrics());
+        drop(engine);
+
+        if let Some(client) = client {
+            let mut queues = inbox.queues.lock().unwrap();
+            let queues = &mut *queues;
+            queues.busy = false;
+            let actor = queues
+                .actors
+                .get_mut(&client)
+                .expect("applied without actor");
+            if actor.messages.is_empty() {
+                actor.scheduled = false;
+                actor.idle_since = Instant::now();
+            } else {
+                queues.ready.push_back(client);
+            }
+            inbox.taken.notify_all();
+        }
+    }
+    Finished {
+        tx_ledger,
+        accounts,
+        rejections,
+        metrics,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn same_outcome_as_one_engine() {
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 2, 3.0),
+            Transaction::new(1, Type::Deposit, 2, 9.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(3, Type::Withdrawal, 1, 1.0),
+            Transaction::new(2, Type::Dispute, 2, 0.0),
+            Transaction::new(2, Type::Chargeback, 2, 0.0),
+            Transaction::new(4, Type::Deposit, 2, 1.0),
+        ];
+        let mut router = Router::new(2, 3, &AccountsRepository::new());
+        for tx in transactions {
+            router.dispatch(tx);
+        }
+        assert_eq!(router.clients(), 2);
+        assert_eq!(router.transactions(), 4);
+        router.flush();
+        assert_eq!(router.accounts(), 2);
+        let outcome = router.finish();
+
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts);
+        engine.process(&transactions);
+        let mut rejections = engine.rejections().to_vec();
+        let metrics = engine.metrics().clone();
+        drop(engine);
+
+        let balances = |accounts: &AccountsRepository| {
+            accounts
+                .sorted()
+                .iter()
+                .map(|a| {
+                    (
+                        a.client_id(),
+                        a.available_balance(),
+                        a.held_balance(),
+                        a.locked(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(balances(&outcome.accounts), balances(&accounts));
+        assert_eq!(outcome.tx_ledger.len(), tx_ledger.len());
+        assert!(outcome.tx_ledger.get(2).unwrap().is_charged_back());
+        let mut outcome_rejections = outcome.rejections;
+        outcome_rejections.sort_by_key(|r| (r.client, r.tx));
+        rejections.sort_by_key(|r| (r.client, r.tx));
+        assert_eq!(outcome_rejections, rejections);
+        assert_eq!(outcome.metrics, metrics);
+    }
+
+    #[test]
+    fn configured_and_reaped() {
+        let accounts = AccountsRepository::new().with_locked_deposits(true);
+        let mut router = Router::new(1, 2, &accounts);
+        for tx in [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+            Transaction::new(2, Type::Deposit, 1, 2.0),
+        ] {
+            router.dispatch(tx);
+        }
+        router.flush();
+        let account = router.readers()[1].get(1).unwrap();
+        assert!(account.locked());
+        assert_eq!(account.available_balance(), 2.0);
+
+        let mut queues = router.workers[1].inbox.queues.lock().unwrap();
+        queues.reap(Instant::now());
+        assert_eq!(queues.actors.len(), 1);
+        queues.reap(Instant::now() + REAP_AFTER);
+        assert!(queues.actors.is_empty());
+        drop(queues);
+        assert_eq!(router.clients(), 0);
+        assert_eq!(
+            router.finish().accounts.get(1).unwrap().available_balance(),
+            2.0
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Alerts on account balances.
+//!
+//! A `Threshold` names a balance, `available`, `held` or `total`, and a
+//! limit it should stay above or below, e.g. `available<100` or
+//! `held>5000`. With `Engine::with_alert` the engine checks the account of
+//! every applied transaction against each threshold, and raises an `Alert`
+//! when the account starts breaching one: it is logged, recorded in
+//! `Engine::alerts` and sent to subscribers as a `threshold_crossed` event
+//! after the balances it follows from.
+//!
+//! An account raises one alert per crossing, not one per transaction while
+//! it stays beyond the limit; once it is back within, the next crossing
+//! raises another. Which accounts breach is not kept in checkpoints, so a
+//! resumed run alerts again for those still beyond a limit when they next
+//! change.
+
+use crate::account::Account;
+use crate::money::Money;
+use crate::transaction::Transaction;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// The balance of an account a threshold watches.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Balance {
+    Available,
+    Held,
+    Total,
+}
+
+impl Balance {
+    pub fn of<M: Money>(self, account: &Account<M>) -> M {
+        match self {
+            Balance::Available => account.available_balance(),
+            Balance::Held => account.held_balance(),
+            Balance::Total => account.total_balance(),
+        }
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Balance::Available => "available",
+            Balance::Held => "held",
+            Balance::Total => "total",
+        })
+    }
+}
+
+/// Which side of its limit breaches a threshold.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Comparison {
+    Below,
+    Above,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Threshold {
+    pub balance: Balance,
+    pub comparison: Comparison,
+    pub limit: f64,
+}
+
+impl Threshold {
+    /// Whether `value` of the watched balance is beyond the limit.
+    pub fn breached_by(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Below => value < self.limit,
+            Comparison::Above => value > self.limit,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    /// A balance, `<` or `>` and the limit, e.g. `available<100`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid threshold: {} (expected e.g. available<100, held>5000 or total>100000)",
+                s
+            )
+        };
+        let at = s.find(['<', '>']).ok_or_else(invalid)?;
+        let comparison = match s.as_bytes()[at] {
+            b'<' => Comparison::Below,
+            _ => Comparison::Above,
+        };
+        let balance = match s[..at].trim() {
+            "available" => Balance::Available,
+            "held" => Balance::Held,
+            "total" => Balance::Total,
+            _ => return Err(invalid()),
+        };
+        let limit: f64 = s[at + 1..].trim().parse().map_err(|_| invalid())?;
+        if !limit.is_finite() {
+            return Err(invalid());
+        }
+        Ok(Threshold {
+            balance,
+            comparison,
+            limit,
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.comparison {
+            Comparison::Below => '<',
+            Comparison::Above => '>',
+        };
+        write!(f, "{}{}{}", self.balance, sign, self.limit)
+    }
+}
+
+/// Record of an account that started breaching a threshold.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Alert {
+    /// The transaction that took the account beyond the limit.
+    pub tx: u32,
+    pub client: u16,
+    pub balance: Balance,
+    pub comparison: Comparison,
+    pub limit: f64,
+    /// The balance after the transaction.
+    pub value: f64,
+    /// Timestamp of the transaction, if it has one.
+    pub timestamp: Option<u64>,
+}
+
+impl Alert {
+    pub fn new<M: Money>(tx: &Transaction<M>, threshold: Threshold, value: M) -> Alert {
+        Alert {
+            tx: tx.id(),
+            client: tx.account_id(),
+            balance: threshold.balance,
+            comparison: threshold.comparison,
+            limit: threshold.limit,
+            value: value.to_f64(),
+            timestamp: tx.timestamp(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{AccountEvent, Engine};
+    use crate::transaction::{TransactionLedger, Type};
+
+    #[test]
+    fn parse_thresholds() {
+        let threshold: Threshold = "held > 5000".parse().unwrap();
+        assert_eq!(
+            threshold,
+            Threshold {
+                balance: Balance::Held,
+                comparison: Comparison::Above,
+                limit: 5000.0,
+            }
+        );
+        assert_eq!(threshold.to_string(), "held>5000");
+        assert_eq!("available<-10".parse::<Threshold>().unwrap().limit, -10.0);
+        assert!("pending>1".parse::<Threshold>().is_err());
+        assert!("total=1".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn alerts_once_per_crossing() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_alert("available<10".parse().unwrap())
+            .with_alert("held>50".parse().unwrap());
+        let events = engine.subscribe();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 100.0),
+            Transaction::new(2, Type::Withdrawal, 1, 95.0).with_timestamp(Some(5)),
+            Transaction::new(3, Type::Withdrawal, 1, 1.0),
+            Transaction::new(4, Type::Deposit, 1, 60.0),
+            Transaction::new(4, Type::Dispute, 1, 0.0),
+            Transaction::new(5, Type::Deposit, 2, 1.0),
+        ]);
+        let alerts: Vec<(u32, u16, Balance, f64)> = engine
+            .alerts()
+            .iter()
+            .map(|alert| (alert.tx, alert.client, alert.balance, alert.value))
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                (2, 1, Balance::Available, 5.0),
+                (4, 1, Balance::Available, 4.0),
+                (4, 1, Balance::Held, 60.0),
+                (5, 2, Balance::Available, 1.0),
+            ]
+        );
+        assert_eq!(engine.alerts()[0].timestamp, Some(5));
+        let crossed = events
+            .try_iter()
+            .filter(|event| matches!(event, AccountEvent::ThresholdCrossed { .. }))
+            .count();
+        assert_eq!(crossed, 4);
+    }
+}
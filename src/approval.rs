@@ -0,0 +1,120 @@
+//! Deposits and withdrawals held for approval.
+//!
+//! A withdrawal above the threshold of `Engine::with_withdrawal_approval`,
+//! and a deposit or withdrawal a `hold` rule of `Engine::with_rules` applies
+//! to, is not applied right away. Its funds are held instead, taken from the
+//! available ones for a withdrawal and added to the held ones for a deposit,
+//! and the transaction stays `Approval::Pending` in the ledger until an
+//! `approve` or `reject` of its tx id and client arrives:
+//!
+//! - `approve` completes it: a withdrawal's funds are paid out, a deposit's
+//!   become available, and its fee is taken then;
+//! - `reject` undoes the hold: a withdrawal's funds become available again,
+//!   a deposit's are sent back.
+//!
+//! Either is final; a second `approve` or `reject`, or one of a transaction
+//! that was never held, is rejected as `not_pending`. A pending or rejected
+//! transaction cannot be disputed. Every decision is recorded as a
+//! `Decision`, its postings land in the journal like those of any other
+//! transaction, and the transactions still pending are in
+//! `activity::open_items`.
+
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Audit record of an `approve` or `reject`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Decision {
+    pub tx: u32,
+    pub client: u16,
+    /// Type of the held transaction.
+    pub r#type: Type,
+    pub amount: f64,
+    /// `approve` or `reject`.
+    pub decision: Type,
+    /// Timestamp of the held transaction, if it has one.
+    pub requested_at: Option<u64>,
+    /// Timestamp of the `approve` or `reject`, if it has one.
+    pub decided_at: Option<u64>,
+}
+
+impl Decision {
+    pub fn new<M: Money>(held: &Transaction<M>, decision: &Transaction<M>) -> Decision {
+        Decision {
+            tx: held.id(),
+            client: held.account_id(),
+            r#type: held.r#type(),
+            amount: held.amount().to_f64(),
+            decision: decision.r#type(),
+            requested_at: held.timestamp(),
+            decided_at: decision.timestamp(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{Engine, RejectReason};
+    use crate::journal::Book;
+    use crate::rules::Rules;
+    use crate::transaction::{Approval, TransactionLedger};
+
+    #[test]
+    fn rules_hold_deposits_and_withdrawals() {
+        let rules = Rules::parse(
+            "hold review: amount > 100\n\
+             hold dispute: type == \"dispute\"",
+        )
+        .unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_rules(&rules)
+            .with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 500.0).with_timestamp(Some(10)),
+            Transaction::new(2, Type::Deposit, 1, 50.0),
+            Transaction::new(3, Type::Deposit, 1, 200.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Approve, 1, 0.0).with_timestamp(Some(20)),
+            Transaction::new(4, Type::Withdrawal, 1, 150.0),
+            Transaction::new(3, Type::Reject, 1, 0.0),
+            Transaction::new(4, Type::Reject, 1, 0.0),
+            Transaction::new(3, Type::Approve, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::NotPending]);
+        let account = engine.accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 500.0);
+        assert_eq!(account.held_balance(), 50.0);
+        assert_eq!(account.total_balance(), 550.0);
+        drop(account);
+        assert_eq!(
+            engine.tx_ledger.get(3).unwrap().approval(),
+            Some(Approval::Rejected)
+        );
+        let balances = engine.journal().unwrap().balances();
+        assert_eq!(balances[&Book::CashIn], -550.0);
+
+        let decisions: Vec<(u32, Type, Type)> = engine
+            .decisions()
+            .iter()
+            .map(|decision| (decision.tx, decision.r#type, decision.decision))
+            .collect();
+        assert_eq!(
+            decisions,
+            [
+                (1, Type::Deposit, Type::Approve),
+                (3, Type::Deposit, Type::Reject),
+                (4, Type::Withdrawal, Type::Reject),
+            ]
+        );
+        let first = engine.decisions()[0];
+        assert_eq!((first.requested_at, first.decided_at), (Some(10), Some(20)));
+    }
+}
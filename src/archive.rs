@@ -0,0 +1,375 @@
+//! Cold storage for the settled part of a ledger.
+//!
+//! The ledger keeps every transaction so that a later dispute can still
+//! find it, though most are never disputed. `ColdStorage::archive` moves
+//! the settled ones, those neither under dispute nor pending approval, out
+//! of the ledger except for the most recent, into a gzip-compressed segment
+//! of JSON lines in its directory. Only which segment holds which id stays in memory.
+//!
+//! Segments are never rewritten. `rehydrate` reads a transaction back from
+//! its segment into the ledger, e.g. when a dispute refers to it, and the
+//! next `archive` puts it into a new segment once it is settled again.
+//! Opening a directory rebuilds the index from its segments, so a later run
+//! still finds what earlier ones archived.
+//!
+//! Every segment starts with a `{"version":N}` line. Segments without one
+//! were written before it existed and are read as version 1, which has the
+//! same records; `migrate` stamps them. A segment of a newer version than
+//! this release knows is refused rather than misread.
+
+use crate::currency::Currency;
+use crate::money::Money;
+use crate::transaction::{Approval, Label, Transaction, TransactionLedger, Type};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Format version written into new segments.
+pub const VERSION: u32 = 2;
+const PREFIX: &str = "segment-";
+const SUFFIX: &str = ".jsonl.gz";
+
+/// First line of a segment.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    r#type: Type,
+    client: u16,
+    tx: u32,
+    amount: Option<f64>,
+    #[serde(default)]
+    charged_back: bool,
+    #[serde(default)]
+    merchant: Option<Label>,
+    #[serde(default)]
+    category: Option<Label>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    approval: Option<Approval>,
+}
+
+pub struct ColdStorage {
+    dir: PathBuf,
+    /// Segment number by archived tx id.
+    index: HashMap<u32, u32>,
+    next_segment: u32,
+}
+
+impl ColdStorage {
+    /// Archives into `dir`, creating it if needed, and indexes the segments
+    /// already in it.
+    pub fn open(dir: &Path) -> io::Result<ColdStorage> {
+        fs::create_dir_all(dir)?;
+        let mut storage = ColdStorage {
+            dir: dir.to_path_buf(),
+            index: HashMap::new(),
+            next_segment: 0,
+        };
+        for segment in numbers(dir)? {
+            for record in storage.read(segment)? {
+                storage.index.insert(record.tx, segment);
+            }
+            storage.next_segment = segment + 1;
+        }
+        Ok(storage)
+    }
+
+    /// Number of archived transactions.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: u32) -> bool {
+        self.index.contains_key(&tx_id)
+    }
+
+    /// Moves every settled transaction of `ledger` but the `keep` with the
+    /// highest ids into a new segment, returning how many were moved.
+    pub fn archive<M: Money>(
+        &mut self,
+        ledger: &mut TransactionLedger<M>,
+        keep: usize,
+    ) -> io::Result<usize> {
+        let mut ids: Vec<u32> = ledger.iter().map(Transaction::id).collect();
+        ids.sort_unstable();
+        ids.truncate(ids.len().saturating_sub(keep));
+        ids.retain(|id| {
+            ledger
+                .get(*id)
+                .is_some_and(|tx| !tx.is_dispute() && !tx.is_pending())
+        });
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let segment = self.next_segment;
+        let path = self.path(segment);
+        // Written in full before anything leaves the ledger.
+        let temporary = path.with_extension("tmp");
+        let mut writer = GzEncoder::new(
+            BufWriter::new(File::create(&temporary)?),
+            Compression::default(),
+        );
+        serde_json::to_writer(&mut writer, &Header { version: VERSION })?;
+        writer.write_all(b"\n")?;
+        for id in &ids {
+            let tx = ledger.get(*id).expect("ids come from the ledger");
+            let record = Record {
+                r#type: tx.r#type(),
+                client: tx.account_id(),
+                tx: tx.id(),
+                amount: tx.optional_amount().map(M::to_f64),
+                charged_back: tx.is_charged_back(),
+                merchant: tx.merchant(),
+                category: tx.category(),
+                timestamp: tx.timestamp(),
+                currency: tx.currency(),
+                approval: tx.approval(),
+            };
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.finish()?.into_inner()?.sync_all()?;
+        fs::rename(&temporary, &path)?;
+
+        for id in &ids {
+            ledger.remove(*id);
+            self.index.insert(*id, segment);
+        }
+        self.next_segment += 1;
+        log::info!("archived {} transactions to {}", ids.len(), path.display());
+        Ok(ids.len())
+    }
+
+    /// Puts the archived `tx_id` back into `ledger`, returning whether it
+    /// was archived.
+    pub fn rehydrate<M: Money>(
+        &mut self,
+        ledger: &mut TransactionLedger<M>,
+        tx_id: u32,
+    ) -> io::Result<bool> {
+        let Some(&segment) = self.index.get(&tx_id) else {
+            return Ok(false);
+        };
+        let record = self
+            .read(segment)?
+            .into_iter()
+            .find(|record| record.tx == tx_id)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("tx {} is missing from segment {}", tx_id, segment),
+                )
+            })?;
+        let mut tx = Transaction::new(record.tx, record.r#type, record.client, M::default())
+            .with_merchant(record.merchant)
+            .with_category(record.category)
+            .with_timestamp(record.timestamp)
+            .with_currency(record.currency);
+        tx.amount = record.amount.map(M::from_f64);
+        ledger.append(&tx);
+        if record.charged_back {
+            ledger.charge_back_tx(tx_id);
+        }
+        if let Some(approval) = record.approval {
+            ledger.set_approval(tx_id, approval);
+        }
+        self.index.remove(&tx_id);
+        Ok(true)
+    }
+
+    fn path(&self, segment: u32) -> PathBuf {
+        segment_path(&self.dir, segment)
+    }
+
+    fn read(&self, segment: u32) -> io::Result<Vec<Record>> {
+        Ok(read_segment(&self.path(segment))?.1)
+    }
+}
+
+fn segment_path(dir: &Path, segment: u32) -> PathBuf {
+    dir.join(format!("{}{:010}{}", PREFIX, segment, SUFFIX))
+}
+
+/// Numbers of the segments in `dir`, in order.
+fn numbers(dir: &Path) -> io::Result<Vec<u32>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number: Option<u32> = name.to_str().and_then(|name| {
+            name.strip_prefix(PREFIX)?
+                .strip_suffix(SUFFIX)?
+                .parse()
+                .ok()
+        });
+        segments.extend(number);
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Every segment in `dir`, in order.
+pub fn segments(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(numbers(dir)?
+        .into_iter()
+        .map(|segment| segment_path(dir, segment))
+        .collect())
+}
+
+/// The format version and records of the segment at `path`.
+fn read_segment(path: &Path) -> io::Result<(u32, Vec<Record>)> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut lines = BufReader::new(GzDecoder::new(File::open(path)?)).lines();
+    let mut records = Vec::new();
+    let version = match lines.next().transpose()? {
+        None => 1,
+        Some(line) => match serde_json::from_str::<Header>(&line) {
+            Ok(Header { version }) if (1..=VERSION).contains(&version) => version,
+            Ok(Header { version }) => {
+                return Err(invalid(format!(
+                    "archive segment version {} is newer than this release supports ({})",
+                    version, VERSION
+                )))
+            }
+            Err(_) => {
+                records.push(serde_json::from_str(&line)?);
+                1
+            }
+        },
+    };
+    for line in lines {
+        records.push(serde_json::from_str(&line?)?);
+    }
+    Ok((version, records))
+}
+
+/// Stamps the segment at `path` with the current header, returning the
+/// version it had. Segments already at `VERSION` are left alone.
+pub fn migrate(path: &Path) -> io::Result<u32> {
+    let (from, records) = read_segment(path)?;
+    if from < VERSION {
+        let temporary = path.with_extension("tmp");
+        let mut writer = GzEncoder::new(
+            BufWriter::new(File::create(&temporary)?),
+            Compression::default(),
+        );
+        serde_json::to_writer(&mut writer, &Header { version: VERSION })?;
+        writer.write_all(b"\n")?;
+        for record in &records {
+            serde_json::to_writer(&mut writer, record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.finish()?.into_inner()?.sync_all()?;
+        fs::rename(&temporary, path)?;
+    }
+    Ok(from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn archive_and_rehydrate() {
+        let dir = std::env::temp_dir().join(format!("fg-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut ledger = TransactionLedger::new();
+        for id in 1..=5 {
+            ledger.append(&Transaction::new(id, Type::Deposit, 1, f64::from(id)));
+        }
+        ledger.dispute_tx(1);
+        ledger.charge_back_tx(1);
+        ledger.dispute_tx(2);
+
+        let mut storage = ColdStorage::open(&dir).unwrap();
+        assert_eq!(storage.archive(&mut ledger, 2).unwrap(), 2);
+        let mut hot: Vec<u32> = ledger.iter().map(Transaction::id).collect();
+        hot.sort_unstable();
+        assert_eq!(hot, [2, 4, 5]);
+        assert_eq!(storage.archive(&mut ledger, 2).unwrap(), 0);
+
+        let mut storage = ColdStorage::open(&dir).unwrap();
+        assert_eq!(storage.len(), 2);
+        assert!(storage.rehydrate(&mut ledger, 1).unwrap());
+        assert!(!storage.rehydrate(&mut ledger, 2).unwrap());
+        let tx = ledger.get(1).unwrap();
+        assert_eq!(tx.amount(), 1.0);
+        assert!(tx.is_charged_back());
+        assert!(!storage.contains(1) && storage.contains(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn versions() {
+        let dir = std::env::temp_dir().join(format!("fg-archive-versions-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let mut writer = GzEncoder::new(
+                File::create(dir.join(name)).unwrap(),
+                Compression::default(),
+            );
+            writer.write_all(contents.as_bytes()).unwrap();
+            writer.finish().unwrap();
+        };
+        let record = r#"{"type":"deposit","client":1,"tx":7,"amount":2.0}"#;
+        write("segment-0000000000.jsonl.gz", &format!("{}\n", record));
+        let storage = ColdStorage::open(&dir).unwrap();
+        assert!(storage.contains(7));
+
+        let old = segments(&dir).unwrap().remove(0);
+        assert_eq!(migrate(&old).unwrap(), 1);
+        assert_eq!(migrate(&old).unwrap(), VERSION);
+        assert_eq!(read_segment(&old).unwrap().1.len(), 1);
+
+        let newer = format!("{{\"version\":{}}}\n{}\n", VERSION + 1, record);
+        write("segment-0000000001.jsonl.gz", &newer);
+        let err = ColdStorage::open(&dir).err().unwrap();
+        assert!(err.to_string().contains("newer than this release"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn engine_rehydrates_disputed() {
+        use crate::account::AccountsRepository;
+        use crate::engine::{Engine, RejectReason};
+
+        let dir = std::env::temp_dir().join(format!("fg-archive-engine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut storage = ColdStorage::open(&dir).unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut ledger, &mut accounts).with_cold_storage(&mut storage);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, Type::Deposit, 1, 5.0),
+        ]);
+        assert_eq!(engine.archive_settled(1).unwrap(), 1);
+        assert!(engine.tx_ledger.get(1).is_none());
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::DuplicateTx]);
+        assert_eq!(accounts.get(1).unwrap().held_balance(), 10.0);
+        assert!(storage.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
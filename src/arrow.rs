@@ -0,0 +1,230 @@
+//! Apache Arrow IPC streams: transaction batches as input and the account
+//! snapshot as a record batch, so that Polars or DataFusion pipelines can
+//! hand data over without going through text.
+//!
+//! Input batches carry the CSV columns by name, in any Arrow type that
+//! prints as the CSV value would: integers or decimals for numbers, and
+//! strings, dictionaries or categoricals for text. Timestamp and date
+//! columns are read as unix seconds. Every row then goes through the same
+//! checks as a CSV row, and warnings give its position in the stream,
+//! counting from 1.
+
+use crate::account::Account;
+use crate::money::Money;
+use crate::parser::{Parser, Tenants, Warning};
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, UInt16Array};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_ipc::reader::StreamReader;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use csv::{Position, StringRecord};
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// Reads every batch of the IPC stream `reader`, split by tenant like
+/// `Parser::parse_tenants_with_warnings`.
+pub fn parse_tenants_with_warnings<R: Read>(
+    reader: R,
+) -> Result<(Tenants, Vec<Warning>), ArrowError> {
+    let stream = StreamReader::try_new(reader, None)?;
+    let headers: StringRecord = stream
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    let mut line = 0;
+    let mut records = Vec::new();
+    for batch in stream {
+        for mut record in records_of(&batch?)? {
+            line += 1;
+            let mut position = Position::new();
+            position.set_line(line);
+            record.set_position(Some(position));
+            records.push(Ok(record));
+        }
+    }
+    Ok(Parser::read_tenants(&headers, records))
+}
+
+/// The rows of `batch` as they would read in CSV, with nulls left empty.
+fn records_of(batch: &RecordBatch) -> Result<Vec<StringRecord>, ArrowError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .map(seconds)
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = FormatOptions::default();
+    let formatters = columns
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()?;
+    (0..batch.num_rows())
+        .map(|row| {
+            formatters
+                .iter()
+                .map(|formatter| Ok(formatter.value(row).try_to_string()?.trim().to_string()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Timestamp and date columns as unix seconds, other columns unchanged.
+fn seconds(column: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    match column.data_type() {
+        DataType::Timestamp(..) | DataType::Date32 | DataType::Date64 => {
+            let column = arrow_cast::cast(column, &DataType::Timestamp(TimeUnit::Second, None))?;
+            arrow_cast::cast(&column, &DataType::Int64)
+        }
+        _ => Ok(Arc::clone(column)),
+    }
+}
+
+/// The schema of account snapshots: the CSV columns, with balances rounded
+/// as they are there.
+pub fn accounts_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", DataType::Float64, false),
+        Field::new("held", DataType::Float64, false),
+        Field::new("total", DataType::Float64, false),
+        Field::new("locked", DataType::Boolean, false),
+    ])
+}
+
+/// `accounts` as one record batch, in the order given.
+pub fn accounts_batch<M: Money>(accounts: &[Account<M>]) -> RecordBatch {
+    let balance = |balance: fn(&Account<M>) -> M| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(
+            accounts.iter().map(|account| balance(account).to_f64()),
+        ))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            accounts.iter().map(|account| account.client_id()),
+        )),
+        balance(|account| account.available_balance()),
+        balance(|account| account.held_balance()),
+        balance(|account| account.total_balance()),
+        Arc::new(BooleanArray::from_iter(
+            accounts.iter().map(|account| Some(account.locked())),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(accounts_schema()), columns)
+        .expect("columns match the accounts schema")
+}
+
+/// Writes `accounts` to `writer` as an IPC stream of a single record batch.
+pub fn write_accounts<M: Money, W: Write>(
+    accounts: &[Account<M>],
+    writer: W,
+) -> Result<(), ArrowError> {
+    let mut writer = StreamWriter::try_new(writer, &accounts_schema())?;
+    writer.write(&accounts_batch(accounts))?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::parser::WarningKind;
+    use crate::transaction::Type;
+    use arrow_array::{Float64Array, StringArray, TimestampMillisecondArray, UInt32Array};
+
+    fn stream(batches: &[RecordBatch]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut writer = StreamWriter::try_new(&mut bytes, &batches[0].schema()).unwrap();
+        for batch in batches {
+            writer.write(batch).unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        bytes
+    }
+
+    #[test]
+    fn transaction_batches() {
+        let batch = |types: Vec<&str>, txs: Vec<u32>, amounts: Vec<Option<f64>>| {
+            let rows = types.len();
+            RecordBatch::try_from_iter_with_nullable([
+                (
+                    "type",
+                    Arc::new(StringArray::from(types)) as ArrayRef,
+                    false,
+                ),
+                ("client", Arc::new(UInt32Array::from(vec![1; rows])), false),
+                ("tx", Arc::new(UInt32Array::from(txs)), false),
+                ("amount", Arc::new(Float64Array::from(amounts)), true),
+                (
+                    "timestamp",
+                    Arc::new(TimestampMillisecondArray::from(vec![
+                        1_717_200_000_000;
+                        rows
+                    ])),
+                    false,
+                ),
+            ])
+            .unwrap()
+        };
+        let input = stream(&[
+            batch(
+                vec!["deposit", "withdrawal"],
+                vec![1, 2],
+                vec![Some(5.5), Some(1.25)],
+            ),
+            batch(
+                vec!["deposit", "refund", "dispute"],
+                vec![3, 4, 1],
+                vec![None, Some(1.0), None],
+            ),
+        ]);
+
+        let (tenants, warnings) = parse_tenants_with_warnings(input.as_slice()).unwrap();
+        let parsed: Vec<(Type, u32, Option<f64>, Option<u64>)> = tenants[&None]
+            .iter()
+            .map(|tx| (tx.r#type(), tx.id(), tx.optional_amount(), tx.timestamp()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 1, Some(5.5), Some(1_717_200_000)),
+                (Type::Withdrawal, 2, Some(1.25), Some(1_717_200_000)),
+                (Type::Dispute, 1, None, Some(1_717_200_000)),
+            ]
+        );
+        let found: Vec<(u64, &WarningKind)> = warnings
+            .iter()
+            .map(|warning| (warning.line, &warning.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, &WarningKind::EmptyAmount),
+                (4, &WarningKind::UnknownType("refund".to_string())),
+            ]
+        );
+
+        assert!(parse_tenants_with_warnings(&input[..input.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn account_snapshot() {
+        let mut accounts = AccountsRepository::<f64>::new();
+        accounts.get_or_create(2).deposit(1.5).unwrap();
+        accounts.get_or_create(1).deposit(3.0).unwrap();
+        let mut bytes = Vec::new();
+        write_accounts(&accounts.sorted(), &mut bytes).unwrap();
+
+        let batches: Vec<RecordBatch> = StreamReader::try_new(bytes.as_slice(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].schema().as_ref(), &accounts_schema());
+        let clients = batches[0].column(0).as_any().downcast_ref::<UInt16Array>();
+        assert_eq!(clients.unwrap().values(), &[1, 2]);
+        let totals = batches[0].column(3).as_any().downcast_ref::<Float64Array>();
+        assert_eq!(totals.unwrap().values(), &[3.0, 1.5]);
+    }
+}
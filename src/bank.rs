@@ -0,0 +1,126 @@
+//! Pieces shared by the bank formats (`iso20022`, `ofx`, `qif`): which
+//! client an account belongs to, and the tx id of a bank reference.
+
+use crate::transaction::Label;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+/// Matches bank account ids, IBAN or other, to clients.
+#[derive(Clone, Debug, Default)]
+pub struct AccountMap {
+    clients: HashMap<String, u16>,
+    /// The client of accounts not in `clients`, from a `*` entry.
+    fallback: Option<u16>,
+}
+
+impl AccountMap {
+    pub fn new(clients: HashMap<String, u16>) -> AccountMap {
+        let fallback = clients.get("*").copied();
+        AccountMap { clients, fallback }
+    }
+
+    /// Reads `account,client` pairs, one per line. An account of `*`
+    /// matches every account not listed. Blank lines and lines starting
+    /// with `#` are skipped, as is a leading `account,client` header.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<AccountMap> {
+        let mut clients = HashMap::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (index == 0 && line == "account,client")
+            {
+                continue;
+            }
+            let pair = line.split_once(',').and_then(|(account, client)| {
+                Some((account.trim().to_string(), client.trim().parse().ok()?))
+            });
+            let (account, client) = pair.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "invalid account,client pair on line {}: {:?}",
+                        index + 1,
+                        line
+                    ),
+                )
+            })?;
+            clients.insert(account, client);
+        }
+        Ok(AccountMap::new(clients))
+    }
+
+    /// The client of `account`: the mapped one, the `*` one, or the account
+    /// id itself when it is a client number.
+    pub fn client(&self, account: &str) -> Result<u16, String> {
+        match self.clients.get(account).or(self.fallback.as_ref()) {
+            Some(&client) => Ok(client),
+            None => account
+                .parse()
+                .map_err(|_| format!("no client for account {}", account)),
+        }
+    }
+}
+
+/// The tx id of a bank transaction with `reference`: the reference itself
+/// when it is a number, a 32-bit FNV-1a hash of it otherwise. Disputes of
+/// the transaction use the same id.
+pub fn tx_id(reference: &str) -> u32 {
+    reference.parse().unwrap_or_else(|_| {
+        reference.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        })
+    })
+}
+
+/// `name` cut to fit a label, with commas dropped and control characters
+/// such as tabs turned into spaces; `None` if nothing is left of it.
+pub fn label(name: &str) -> Option<Label> {
+    let mut name: String = name
+        .trim()
+        .chars()
+        .filter(|&c| c != ',')
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    while name.len() > Label::CAPACITY {
+        name.pop();
+    }
+    Label::new(name.trim_end())
+        .ok()
+        .filter(|label| !label.as_str().is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn account_map() {
+        let input = "account,client\n# savings\nDE89370400440532013000, 1\n";
+        let map = AccountMap::read(input.as_bytes()).unwrap();
+        assert_eq!(map.client("DE89370400440532013000"), Ok(1));
+        assert_eq!(map.client("7"), Ok(7));
+        assert!(map.client("GB29NWBK60161331926819").is_err());
+
+        let map = AccountMap::read("*,3\n".as_bytes()).unwrap();
+        assert_eq!(map.client("GB29NWBK60161331926819"), Ok(3));
+        assert!(AccountMap::read("DE89;1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn labels_from_free_text() {
+        let payee = label("ACME,\tInc.\r\n").unwrap();
+        assert_eq!(payee.as_str(), "ACME Inc.");
+        assert_eq!(
+            label("Acme Corporation Europe, GmbH").unwrap().as_str(),
+            "Acme Corporation Europe"
+        );
+        assert_eq!(label(" \t,"), None);
+    }
+
+    #[test]
+    fn reference_ids() {
+        assert_eq!(tx_id("42"), 42);
+        assert_eq!(tx_id("INV-2024-17"), tx_id("INV-2024-17"));
+        assert_ne!(tx_id("INV-2024-17"), tx_id("INV-2024-18"));
+    }
+}
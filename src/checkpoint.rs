@@ -0,0 +1,793 @@
+//! Durable snapshots of a `State`.
+//!
+//! A checkpoint holds the accounts, the ledger and the offset (number of
+//! transactions applied) as JSON, named after that offset so the newest one
+//! sorts last. Files are written to a temporary name and renamed into place,
+//! so a crash mid-write never leaves a truncated checkpoint behind; a file
+//! that fails to load anyway is skipped in favour of the next older one.
+//!
+//! Every checkpoint records the format `VERSION` it was written with. One
+//! from an older release is upgraded in memory when loaded, and `migrate`
+//! rewrites it on disk; one from a newer release is refused.
+//!
+//! Checkpoints of shards that each processed their own clients can be
+//! combined into one with `merge`.
+
+use crate::account::{Account, AccountsRepository, LockEvent, LockReason};
+use crate::currency::Currency;
+use crate::expiry::unix_seconds;
+use crate::rounding::Rounding;
+use crate::state::State;
+use crate::transaction::{Approval, Label, Transaction, TransactionLedger, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Format version written into new checkpoints.
+pub const VERSION: u32 = 3;
+
+/// Upgrade steps between formats: entry `n` turns a checkpoint of version
+/// `n + 1` into version `n + 2`.
+const UPGRADES: [fn(&mut Value); VERSION as usize - 1] = [add_currencies, add_locks];
+const PREFIX: &str = "checkpoint-";
+const SUFFIX: &str = ".json";
+
+/// Checkpoints kept in the directory after a new one is written.
+pub const RETAINED: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    version: u32,
+    offset: u64,
+    last_tx_id: Option<u32>,
+    /// Fingerprint of the batch input, see `Progress::input`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+    accounts: Vec<AccountRecord>,
+    ledger: Vec<TxRecord>,
+    /// Ids purged after their dispute was settled, still taken.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    purged: Vec<u32>,
+    /// Chargeback fees, see `TransactionLedger::fees`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fees: Vec<FeeRecord>,
+    /// Where each replayable source resumes, see `State::positions`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    positions: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountRecord {
+    client: u16,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    /// The currency the account keeps its balances in, see
+    /// `Account::currency`.
+    #[serde(default)]
+    currency: Option<Currency>,
+    /// Why the account is locked, see `Account::lock_reason`.
+    #[serde(default)]
+    lock_reason: Option<LockRecord>,
+    #[serde(default)]
+    lock_history: Vec<LockEventRecord>,
+}
+
+/// A `LockReason`, tagged with its `kind`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum LockRecord {
+    Chargeback { tx: u32, amount: f64 },
+    AdminFreeze,
+    RiskRule { rule: Label },
+}
+
+impl From<&LockReason> for LockRecord {
+    fn from(reason: &LockReason) -> LockRecord {
+        match *reason {
+            LockReason::Chargeback { tx, amount } => LockRecord::Chargeback { tx, amount },
+            LockReason::AdminFreeze => LockRecord::AdminFreeze,
+            LockReason::RiskRule(rule) => LockRecord::RiskRule { rule },
+        }
+    }
+}
+
+impl From<LockRecord> for LockReason {
+    fn from(record: LockRecord) -> LockReason {
+        match record {
+            LockRecord::Chargeback { tx, amount } => LockReason::Chargeback { tx, amount },
+            LockRecord::AdminFreeze => LockReason::AdminFreeze,
+            LockRecord::RiskRule { rule } => LockReason::RiskRule(rule),
+        }
+    }
+}
+
+/// A `LockEvent`, tagged with the `event`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum LockEventRecord {
+    Locked {
+        reason: LockRecord,
+        timestamp: Option<u64>,
+    },
+    Unlocked {
+        timestamp: Option<u64>,
+    },
+}
+
+impl From<&LockEvent> for LockEventRecord {
+    fn from(event: &LockEvent) -> LockEventRecord {
+        match event {
+            LockEvent::Locked { reason, timestamp } => LockEventRecord::Locked {
+                reason: reason.into(),
+                timestamp: *timestamp,
+            },
+            LockEvent::Unlocked { timestamp } => LockEventRecord::Unlocked {
+                timestamp: *timestamp,
+            },
+        }
+    }
+}
+
+impl From<LockEventRecord> for LockEvent {
+    fn from(record: LockEventRecord) -> LockEvent {
+        match record {
+            LockEventRecord::Locked { reason, timestamp } => LockEvent::Locked {
+                reason: reason.into(),
+                timestamp,
+            },
+            LockEventRecord::Unlocked { timestamp } => LockEvent::Unlocked { timestamp },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TxRecord {
+    r#type: Type,
+    client: u16,
+    tx: u32,
+    amount: Option<f64>,
+    disputed: bool,
+    #[serde(default)]
+    charged_back: bool,
+    #[serde(default)]
+    merchant: Option<Label>,
+    #[serde(default)]
+    category: Option<Label>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    /// Seconds since the Unix epoch an open dispute was raised at, if known.
+    #[serde(default)]
+    disputed_at: Option<u64>,
+    /// Seconds since the Unix epoch a settled dispute was settled at, if
+    /// known.
+    #[serde(default)]
+    settled_at: Option<u64>,
+    /// Where a withdrawal held for approval stands.
+    #[serde(default)]
+    approval: Option<Approval>,
+    #[serde(default)]
+    currency: Option<Currency>,
+}
+
+/// A chargeback fee, under the id of its chargeback.
+#[derive(Serialize, Deserialize)]
+struct FeeRecord {
+    client: u16,
+    tx: u32,
+    amount: f64,
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+/// How far a run got, for checkpoints of a ledger and accounts that are
+/// not kept in a `State`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    /// Number of transactions applied.
+    pub offset: u64,
+    pub last_tx_id: Option<u32>,
+    /// A fingerprint of the input the transactions came from, such as a
+    /// hash of the file, so that a run resumes only on the same input.
+    pub input: Option<String>,
+    /// Where each replayable source resumes, see `State::positions`.
+    pub positions: BTreeMap<String, u64>,
+}
+
+/// Writes a checkpoint of `state` into `dir` and prunes all but the newest
+/// `RETAINED` ones.
+pub fn write(dir: &Path, state: &State) -> io::Result<PathBuf> {
+    let progress = Progress {
+        offset: state.offset(),
+        last_tx_id: state.last_tx_id(),
+        input: state.input().map(str::to_string),
+        positions: state.positions().clone(),
+    };
+    write_progress(dir, &state.tx_ledger, &state.accounts, &progress)
+}
+
+/// Like `write`, for a ledger and accounts that `progress` was made on.
+pub fn write_progress(
+    dir: &Path,
+    tx_ledger: &TransactionLedger,
+    accounts: &AccountsRepository,
+    progress: &Progress,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let mut ledger: Vec<TxRecord> = tx_ledger
+        .iter()
+        .map(|tx| TxRecord {
+            r#type: tx.r#type(),
+            client: tx.account_id(),
+            tx: tx.id(),
+            amount: tx.optional_amount(),
+            disputed: tx.is_dispute(),
+            charged_back: tx.is_charged_back(),
+            merchant: tx.merchant(),
+            category: tx.category(),
+            timestamp: tx.timestamp(),
+            disputed_at: tx_ledger.disputed_at(tx.id()).map(unix_seconds),
+            settled_at: tx_ledger.settled_at(tx.id()).map(unix_seconds),
+            approval: tx.approval(),
+            currency: tx.currency(),
+        })
+        .collect();
+    ledger.sort_by_key(|tx| tx.tx);
+    let mut purged: Vec<u32> = tx_ledger.purged().collect();
+    purged.sort_unstable();
+    let fees = tx_ledger
+        .fees()
+        .iter()
+        .map(|fee| FeeRecord {
+            client: fee.account_id(),
+            tx: fee.id(),
+            amount: fee.amount(),
+            timestamp: fee.timestamp(),
+        })
+        .collect();
+    let checkpoint = Checkpoint {
+        version: VERSION,
+        offset: progress.offset,
+        last_tx_id: progress.last_tx_id,
+        input: progress.input.clone(),
+        accounts: accounts
+            .sorted()
+            .into_iter()
+            .map(|account| AccountRecord {
+                client: account.client_id(),
+                available: account.available_balance(),
+                held: account.held_balance(),
+                total: account.total_balance(),
+                locked: account.locked(),
+                currency: account.currency(),
+                lock_reason: account.lock_reason().map(LockRecord::from),
+                lock_history: account.lock_history().map(LockEventRecord::from).collect(),
+            })
+            .collect(),
+        ledger,
+        purged,
+        fees,
+        positions: progress.positions.clone(),
+    };
+
+    let path = dir.join(format!("{}{:020}{}", PREFIX, progress.offset, SUFFIX));
+    write_atomically(&path, &checkpoint)?;
+    prune(dir)?;
+    Ok(path)
+}
+
+/// A client or transaction id found in more than one of the checkpoints
+/// given to `merge`, with every checkpoint holding it.
+#[derive(Debug, PartialEq)]
+pub enum Collision {
+    Client { client: u16, shards: Vec<PathBuf> },
+    Tx { tx: u32, shards: Vec<PathBuf> },
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, shards) = match self {
+            Collision::Client { client, shards } => (format!("client {}", client), shards),
+            Collision::Tx { tx, shards } => (format!("tx {}", tx), shards),
+        };
+        let shards: Vec<String> = shards
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        write!(f, "{} is in {}", what, shards.join(", "))
+    }
+}
+
+/// Merges the checkpoints of shards into a single checkpoint at `output`,
+/// with every account and ledger entry of all of them. Offsets add up, and
+/// the last transaction is the one of the last shard.
+///
+/// Shards must not share clients or transaction ids. Every id that is in
+/// more than one shard is returned, and nothing is written then.
+pub fn merge(shards: &[PathBuf], output: &Path) -> io::Result<Vec<Collision>> {
+    let mut clients: BTreeMap<u16, Vec<PathBuf>> = BTreeMap::new();
+    let mut txs: BTreeMap<u32, Vec<PathBuf>> = BTreeMap::new();
+    let mut merged = Checkpoint {
+        version: VERSION,
+        offset: 0,
+        last_tx_id: None,
+        input: None,
+        accounts: Vec::new(),
+        ledger: Vec::new(),
+        purged: Vec::new(),
+        fees: Vec::new(),
+        positions: BTreeMap::new(),
+    };
+    for path in shards {
+        let mut checkpoint: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        upgrade(&mut checkpoint)?;
+        let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+        for account in &checkpoint.accounts {
+            clients
+                .entry(account.client)
+                .or_default()
+                .push(path.clone());
+        }
+        for tx in &checkpoint.ledger {
+            txs.entry(tx.tx).or_default().push(path.clone());
+        }
+        merged.offset += checkpoint.offset;
+        merged.last_tx_id = checkpoint.last_tx_id.or(merged.last_tx_id);
+        merged.accounts.extend(checkpoint.accounts);
+        merged.ledger.extend(checkpoint.ledger);
+        merged.purged.extend(checkpoint.purged);
+        merged.fees.extend(checkpoint.fees);
+        merged.positions.extend(checkpoint.positions);
+    }
+
+    let collisions: Vec<Collision> = clients
+        .into_iter()
+        .filter(|(_, shards)| shards.len() > 1)
+        .map(|(client, shards)| Collision::Client { client, shards })
+        .chain(
+            txs.into_iter()
+                .filter(|(_, shards)| shards.len() > 1)
+                .map(|(tx, shards)| Collision::Tx { tx, shards }),
+        )
+        .collect();
+    if collisions.is_empty() {
+        merged.accounts.sort_by_key(|account| account.client);
+        merged.ledger.sort_by_key(|tx| tx.tx);
+        merged.purged.sort_unstable();
+        write_atomically(output, &merged)?;
+    }
+    Ok(collisions)
+}
+
+fn write_atomically<T: Serialize>(path: &Path, checkpoint: &T) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, checkpoint)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Brings a checkpoint read as JSON up to `VERSION`, returning the version
+/// it had.
+fn upgrade(checkpoint: &mut Value) -> io::Result<u32> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let version = checkpoint
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid("checkpoint has no version".to_string()))?;
+    let from = match u32::try_from(version) {
+        Ok(from) if (1..=VERSION).contains(&from) => from,
+        _ => {
+            return Err(invalid(format!(
+                "unsupported checkpoint version {} (this release writes {})",
+                version, VERSION
+            )))
+        }
+    };
+    for upgrade in &UPGRADES[from as usize - 1..] {
+        upgrade(checkpoint);
+    }
+    checkpoint["version"] = VERSION.into();
+    Ok(from)
+}
+
+/// Version 2 records the currency of accounts and transactions. Earlier
+/// checkpoints had none kept, so they get none.
+fn add_currencies(checkpoint: &mut Value) {
+    for key in ["accounts", "ledger"] {
+        let records = checkpoint.get_mut(key).and_then(Value::as_array_mut);
+        for record in records.into_iter().flatten() {
+            if let Some(record) = record.as_object_mut() {
+                record.entry("currency").or_insert(Value::Null);
+            }
+        }
+    }
+}
+
+/// Version 3 records why accounts are locked and their lock history, which
+/// earlier checkpoints lost.
+fn add_locks(checkpoint: &mut Value) {
+    let accounts = checkpoint.get_mut("accounts").and_then(Value::as_array_mut);
+    for account in accounts.into_iter().flatten() {
+        if let Some(account) = account.as_object_mut() {
+            account.entry("lock_reason").or_insert(Value::Null);
+            account
+                .entry("lock_history")
+                .or_insert_with(|| Value::Array(Vec::new()));
+        }
+    }
+}
+
+/// Rewrites the checkpoint at `path` in the current format, returning the
+/// version it had. Checkpoints already at `VERSION` are left alone.
+pub fn migrate(path: &Path) -> io::Result<u32> {
+    let mut checkpoint: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let from = upgrade(&mut checkpoint)?;
+    if from < VERSION {
+        write_atomically(path, &checkpoint)?;
+    }
+    Ok(from)
+}
+
+/// Loads the newest checkpoint in `dir` that can be read back, if any.
+pub fn load_latest(dir: &Path, rounding: Rounding) -> io::Result<Option<State>> {
+    for path in list(dir)?.into_iter().rev() {
+        match load(&path, rounding) {
+            Ok(state) => {
+                log::info!("restored checkpoint {}", path.display());
+                return Ok(Some(state));
+            }
+            Err(err) => log::warn!("skipping checkpoint {}: {}", path.display(), err),
+        }
+    }
+    Ok(None)
+}
+
+pub fn load(path: &Path, rounding: Rounding) -> io::Result<State> {
+    let mut checkpoint: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    upgrade(&mut checkpoint)?;
+    let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+
+    let mut accounts = AccountsRepository::with_rounding(rounding);
+    for record in checkpoint.accounts {
+        let mut account = Account::from_balances(
+            record.client,
+            record.available,
+            record.held,
+            record.total,
+            record.locked,
+            rounding,
+        );
+        if let Some(currency) = record.currency {
+            account
+                .adopt_currency(currency)
+                .expect("restored accounts have no currency yet");
+        }
+        account.restore_lock(
+            record.lock_reason.map(LockReason::from),
+            record.lock_history.into_iter().map(LockEvent::from),
+        );
+        accounts.restore(account);
+    }
+    let mut tx_ledger = TransactionLedger::new();
+    for record in checkpoint.ledger {
+        let mut tx = Transaction::new(record.tx, record.r#type, record.client, 0.0)
+            .with_merchant(record.merchant)
+            .with_category(record.category)
+            .with_timestamp(record.timestamp)
+            .with_currency(record.currency);
+        tx.amount = record.amount;
+        tx_ledger.append(&tx);
+        match (record.disputed, record.disputed_at) {
+            (true, Some(secs)) => tx_ledger.dispute_tx_at(
+                record.tx,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            ),
+            (true, None) => tx_ledger.dispute_tx(record.tx),
+            (false, _) => {}
+        }
+        if record.charged_back {
+            tx_ledger.charge_back_tx(record.tx);
+        }
+        if let Some(secs) = record.settled_at {
+            tx_ledger.settle_tx_at(
+                record.tx,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            );
+        }
+        if let Some(approval) = record.approval {
+            tx_ledger.set_approval(record.tx, approval);
+        }
+    }
+    for tx_id in checkpoint.purged {
+        tx_ledger.purge(tx_id);
+    }
+    for record in checkpoint.fees {
+        let fee = Transaction::new(record.tx, Type::Fee, record.client, record.amount)
+            .with_timestamp(record.timestamp);
+        tx_ledger.append_fee(fee);
+    }
+    let mut state = State::restored(
+        tx_ledger,
+        accounts,
+        checkpoint.offset,
+        checkpoint.last_tx_id,
+        checkpoint.input,
+    );
+    for (part, next) in &checkpoint.positions {
+        state.set_position(part, *next);
+    }
+    Ok(state)
+}
+
+/// Checkpoint files in `dir`, oldest first.
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        if name.is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX)) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn prune(dir: &Path) -> io::Result<()> {
+    let paths = list(dir)?;
+    for path in &paths[..paths.len().saturating_sub(RETAINED)] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::RejectReason;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fg-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dir = dir("checkpoint-roundtrip");
+        let mut state = State::new();
+        let merchant = Label::new("acme").ok();
+        state.apply(&Transaction::new(1, Type::Deposit, 1, 5.0).with_merchant(merchant));
+        state.apply(&Transaction::new(2, Type::Deposit, 1, 2.5));
+        state.apply(&Transaction::new(1, Type::Dispute, 1, 0.0));
+        let disputed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(42);
+        state.tx_ledger.dispute_tx_at(1, disputed_at);
+        let settled_at = SystemTime::UNIX_EPOCH + Duration::from_secs(7);
+        state.tx_ledger.settle_tx_at(2, settled_at);
+        state.tx_ledger.set_approval(2, Approval::Rejected);
+        state.apply(&Transaction::new(3, Type::Deposit, 2, 1.0));
+        state.tx_ledger.purge(3);
+        let fee = Transaction::new(2, Type::Fee, 1, 15.0).with_timestamp(Some(9));
+        state.tx_ledger.append_fee(fee);
+        write(&dir, &state).unwrap();
+
+        let mut restored = load_latest(&dir, Rounding::HalfUp).unwrap().unwrap();
+        assert_eq!(restored.offset(), 4);
+        assert!(restored.tx_ledger.is_purged(3));
+        assert_eq!(restored.last_tx_id(), Some(3));
+        assert!(restored.tx_ledger.get(1).unwrap().is_dispute());
+        assert_eq!(restored.tx_ledger.get(1).unwrap().merchant(), merchant);
+        assert_eq!(restored.tx_ledger.disputed_at(1), Some(disputed_at));
+        assert_eq!(restored.tx_ledger.settled_at(2), Some(settled_at));
+        let approval = restored.tx_ledger.get(2).unwrap().approval();
+        assert_eq!(approval, Some(Approval::Rejected));
+        let fees = restored.tx_ledger.fees();
+        assert_eq!(fees.len(), 1);
+        assert_eq!((fees[0].r#type(), fees[0].amount()), (Type::Fee, 15.0));
+        assert_eq!(fees[0].timestamp(), Some(9));
+        let account = restored.accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 2.5);
+        assert_eq!(account.held_balance(), 5.0);
+        drop(account);
+
+        restored.apply(&Transaction::new(1, Type::Resolve, 1, 0.0));
+        assert_eq!(restored.accounts.get(1).unwrap().available_balance(), 7.5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn currencies() {
+        let dir = dir("checkpoint-currencies");
+        let (jpy, usd) = (Some("JPY".parse().unwrap()), Some("USD".parse().unwrap()));
+        let mut state = State::new();
+        state.apply(&Transaction::new(1, Type::Deposit, 1, 1234.5).with_currency(jpy));
+        write(&dir, &state).unwrap();
+
+        let mut restored = load_latest(&dir, Rounding::HalfUp).unwrap().unwrap();
+        assert_eq!(restored.accounts.get(1).unwrap().currency(), jpy);
+        assert_eq!(restored.tx_ledger.get(1).unwrap().currency(), jpy);
+        let deposit = Transaction::new(2, Type::Deposit, 1, 10.0).with_currency(usd);
+        assert_eq!(
+            restored.try_apply(&deposit),
+            Err(RejectReason::CurrencyMismatch)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn locks() {
+        let dir = dir("checkpoint-locks");
+        let mut state = State::new();
+        state.apply(&Transaction::new(1, Type::Deposit, 1, 5.0));
+        state.apply(&Transaction::new(1, Type::Dispute, 1, 0.0).with_timestamp(Some(3)));
+        state.apply(&Transaction::new(1, Type::Chargeback, 1, 0.0).with_timestamp(Some(4)));
+        state.apply(&Transaction::new(2, Type::Deposit, 2, 1.0));
+        let rule = Label::new("velocity").unwrap();
+        let mut account = state.accounts.get_or_create(2);
+        account.lock(LockReason::RiskRule(rule), Some(5));
+        account.unlock(Some(6));
+        drop(account);
+        write(&dir, &state).unwrap();
+
+        let restored = load_latest(&dir, Rounding::HalfUp).unwrap().unwrap();
+        let account = restored.accounts.get(1).unwrap();
+        let reason = LockReason::Chargeback { tx: 1, amount: 5.0 };
+        assert_eq!(account.lock_reason(), Some(&reason));
+        let history: Vec<_> = account.lock_history().copied().collect();
+        let locked = LockEvent::Locked {
+            reason,
+            timestamp: Some(4),
+        };
+        assert_eq!(history, [locked]);
+        drop(account);
+        let account = restored.accounts.get(2).unwrap();
+        assert_eq!(account.lock_reason(), None);
+        let history: Vec<_> = account.lock_history().copied().collect();
+        let locked = LockEvent::Locked {
+            reason: LockReason::RiskRule(rule),
+            timestamp: Some(5),
+        };
+        assert_eq!(
+            history,
+            [locked, LockEvent::Unlocked { timestamp: Some(6) }]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn progress() {
+        let dir = dir("checkpoint-progress");
+        let mut state = State::new();
+        state.apply(&Transaction::new(1, Type::Deposit, 1, 5.0));
+        let progress = Progress {
+            offset: 7,
+            last_tx_id: Some(1),
+            input: Some("sha256:00ff".to_string()),
+            positions: BTreeMap::from([("kafka:payments:0".to_string(), 42)]),
+        };
+        write_progress(&dir, &state.tx_ledger, &state.accounts, &progress).unwrap();
+        let restored = load_latest(&dir, Rounding::HalfUp).unwrap().unwrap();
+        assert_eq!(restored.offset(), 7);
+        assert_eq!(restored.input(), Some("sha256:00ff"));
+        assert_eq!(restored.positions(), &progress.positions);
+        assert_eq!(restored.accounts.get(1).unwrap().total_balance(), 5.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newest_valid_wins_and_old_ones_are_pruned() {
+        let dir = dir("checkpoint-newest");
+        let mut state = State::new();
+        for id in 1..=5 {
+            state.apply(&Transaction::new(id, Type::Deposit, 1, 1.0));
+            write(&dir, &state).unwrap();
+        }
+        assert_eq!(list(&dir).unwrap().len(), RETAINED);
+
+        let newest = list(&dir).unwrap().pop().unwrap();
+        fs::write(&newest, b"{\"version\":1,").unwrap();
+        let restored = load_latest(&dir, Rounding::HalfUp).unwrap().unwrap();
+        assert_eq!(restored.offset(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn versions() {
+        let dir = dir("checkpoint-versions");
+        let path = write(&dir, &State::new()).unwrap();
+        assert_eq!(migrate(&path).unwrap(), VERSION);
+
+        let v1 = concat!(
+            "{\"version\":1,\"offset\":1,\"last_tx_id\":1,",
+            "\"accounts\":[{\"client\":1,\"available\":2.0,\"held\":0.0,\"total\":2.0,\"locked\":false}],",
+            "\"ledger\":[{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.0,\"disputed\":false}]}"
+        );
+        fs::write(&path, v1).unwrap();
+        assert_eq!(migrate(&path).unwrap(), 1);
+        let migrated: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(migrated["version"], VERSION);
+        assert!(migrated["accounts"][0]["currency"].is_null());
+        assert_eq!(
+            migrated["accounts"][0]["lock_history"],
+            serde_json::json!([])
+        );
+        let restored = load(&path, Rounding::HalfUp).unwrap();
+        assert_eq!(restored.accounts.get(1).unwrap().currency(), None);
+
+        let newer = format!(
+            "{{\"version\":{},\"offset\":0,\"last_tx_id\":null,\"accounts\":[],\"ledger\":[]}}",
+            VERSION + 1
+        );
+        fs::write(&path, newer).unwrap();
+        let err = load(&path, Rounding::HalfUp).err().unwrap();
+        assert!(err.to_string().contains("unsupported checkpoint version"));
+        assert!(migrate(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merged_shards() {
+        let dir = dir("checkpoint-merge");
+        let shard = |name: &str, txs: &[(u32, u16)]| {
+            let mut state = State::new();
+            for &(id, client) in txs {
+                state.apply(&Transaction::new(id, Type::Deposit, client, 1.0));
+            }
+            let path = write(&dir.join(name), &state).unwrap();
+            fs::rename(&path, dir.join(format!("{}.json", name))).unwrap();
+            dir.join(format!("{}.json", name))
+        };
+        let shards = [
+            shard("shard0", &[(1, 1), (3, 1)]),
+            shard("shard1", &[(2, 2), (4, 3)]),
+        ];
+        let output = dir.join("full.json");
+        assert_eq!(merge(&shards, &output).unwrap(), []);
+        let merged = load(&output, Rounding::HalfUp).unwrap();
+        assert_eq!(merged.offset(), 4);
+        assert_eq!(merged.last_tx_id(), Some(4));
+        assert_eq!(merged.accounts.len(), 3);
+        assert_eq!(merged.accounts.get(1).unwrap().total_balance(), 2.0);
+        assert!(merged.tx_ledger.get(4).is_some());
+        merged.verify().unwrap();
+
+        let clashing = shard("shard2", &[(5, 3), (3, 4)]);
+        let collisions = merge(
+            &[shards[1].clone(), clashing.clone()],
+            &dir.join("bad.json"),
+        );
+        let collisions = collisions.unwrap();
+        assert_eq!(
+            collisions,
+            [Collision::Client {
+                client: 3,
+                shards: vec![shards[1].clone(), clashing.clone()],
+            },]
+        );
+        assert!(collisions[0].to_string().starts_with("client 3 is in "));
+        let collisions = merge(&[shards[0].clone(), clashing], &dir.join("bad.json")).unwrap();
+        assert!(matches!(collisions[..], [Collision::Tx { tx: 3, .. }]));
+        assert!(!dir.join("bad.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_dir() {
+        assert!(load_latest(&dir("checkpoint-missing"), Rounding::HalfUp)
+            .unwrap()
+            .is_none());
+    }
+}
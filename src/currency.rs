@@ -0,0 +1,125 @@
+//! ISO 4217 currency codes and the number of decimal places (the exponent)
+//! amounts in each currency are kept at.
+
+use crate::rounding::PRECISION;
+use std::fmt;
+use std::str::FromStr;
+
+/// A three-letter ISO 4217 code such as `EUR`, stored inline so that
+/// transactions stay `Copy`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+/// Codes whose minor unit is not the usual hundredth, with their exponent.
+const EXPONENTS: &[(&str, u8)] = &[
+    ("BHD", 3),
+    ("BIF", 0),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("TND", 3),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("UYW", 4),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+];
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    /// The official number of decimal places, 2 for codes without an
+    /// exception in ISO 4217.
+    pub fn exponent(&self) -> u8 {
+        EXPONENTS
+            .binary_search_by(|(code, _)| code.cmp(&self.as_str()))
+            .map_or(2, |index| EXPONENTS[index].1)
+    }
+
+    /// The exponent, but never more places than balances are kept at.
+    pub fn decimals(&self) -> i32 {
+        i32::from(self.exponent()).min(PRECISION)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Currency, String> {
+        match s.trim().as_bytes() {
+            &[a, b, c] if [a, b, c].iter().all(u8::is_ascii_alphabetic) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(format!("invalid currency code {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Currency {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exponents() {
+        let exponent = |code: &str| code.parse::<Currency>().unwrap().exponent();
+        assert_eq!(exponent("JPY"), 0);
+        assert_eq!(exponent("usd"), 2);
+        assert_eq!(exponent("BHD"), 3);
+        assert_eq!(exponent("CLF"), 4);
+        assert!(EXPONENTS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!("eur".parse::<Currency>().unwrap().to_string(), "EUR");
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+        assert!("".parse::<Currency>().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Dead letters: what the streaming server could not apply, kept with why
+//! so that it can be looked into and sent again rather than only logged.
+//!
+//! A rejected transaction becomes a JSON object with the fields of the JSON
+//! line protocol and its `reason`, so a file of dead letters can be fed
+//! back to the server once the cause is fixed. A line that could not be
+//! parsed keeps its text under `line`, with the reason `unparseable`.
+
+use crate::currency::Currency;
+use crate::engine::RejectReason;
+use crate::transaction::{Label, Transaction, Type};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// A transaction or line the server did not apply.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DeadLetter {
+    Rejected {
+        r#type: Type,
+        client: u16,
+        tx: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        amount: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        merchant: Option<Label>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        category: Option<Label>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        reason: RejectReason,
+    },
+    Unparseable {
+        line: String,
+        /// Always `unparseable`.
+        reason: &'static str,
+    },
+}
+
+impl DeadLetter {
+    pub fn rejected(tx: &Transaction, reason: RejectReason) -> DeadLetter {
+        DeadLetter::Rejected {
+            r#type: tx.r#type(),
+            client: tx.account_id(),
+            tx: tx.id(),
+            amount: tx.optional_amount(),
+            merchant: tx.merchant(),
+            category: tx.category(),
+            timestamp: tx.timestamp(),
+            currency: tx.currency(),
+            reason,
+        }
+    }
+
+    pub fn unparseable(line: &str) -> DeadLetter {
+        DeadLetter::Unparseable {
+            line: line.to_string(),
+            reason: "unparseable",
+        }
+    }
+}
+
+/// Where dead letters go.
+pub trait DeadLetterSink: Send {
+    fn send(&mut self, letter: &DeadLetter) -> io::Result<()>;
+}
+
+/// Appends dead letters to a file, one JSON object per line.
+pub struct DeadLetterFile {
+    file: File,
+}
+
+impl DeadLetterFile {
+    pub fn open(path: &Path) -> io::Result<DeadLetterFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(DeadLetterFile { file })
+    }
+}
+
+impl DeadLetterSink for DeadLetterFile {
+    fn send(&mut self, letter: &DeadLetter) -> io::Result<()> {
+        let mut line = serde_json::to_vec(letter)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Queues dead letters for a consumer in the same process.
+impl DeadLetterSink for Sender<DeadLetter> {
+    fn send(&mut self, letter: &DeadLetter) -> io::Result<()> {
+        Sender::send(self, letter.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "dead letter queue is gone"))
+    }
+}
+
+/// A sink shared by everything that rejects transactions, such as the
+/// server and its actors.
+#[derive(Clone)]
+pub struct DeadLetters(Arc<Mutex<dyn DeadLetterSink>>);
+
+impl DeadLetters {
+    pub fn new<S: DeadLetterSink + 'static>(sink: S) -> DeadLetters {
+        DeadLetters(Arc::new(Mutex::new(sink)))
+    }
+
+    /// Hands `letter` to the sink. A sink that fails only gets the failure
+    /// logged, the same as a rejection without a sink.
+    pub fn send(&self, letter: DeadLetter) {
+        let mut sink = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = sink.send(&letter) {
+            log::warn!("could not send dead letter {:?}: {}", letter, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use std::sync::mpsc;
+
+    #[test]
+    fn replayable() {
+        let (sender, receiver) = mpsc::channel();
+        let letters = DeadLetters::new(sender);
+        let tx = Transaction::new(7, Type::Withdrawal, 2, 1.5).with_timestamp(Some(100));
+        letters.send(DeadLetter::rejected(&tx, RejectReason::InsufficientFunds));
+        letters.send(DeadLetter::unparseable("deposit,x"));
+
+        let letters: Vec<String> = receiver
+            .try_iter()
+            .map(|letter| serde_json::to_string(&letter).unwrap())
+            .collect();
+        assert_eq!(
+            letters,
+            [
+                r#"{"type":"withdrawal","client":2,"tx":7,"amount":1.5,"timestamp":100,"reason":"insufficient_funds"}"#,
+                r#"{"line":"deposit,x","reason":"unparseable"}"#,
+            ]
+        );
+        let replayed = Parser::parse_line(&letters[0]).unwrap();
+        assert_eq!(
+            (
+                replayed.id(),
+                replayed.optional_amount(),
+                replayed.timestamp()
+            ),
+            (7, Some(1.5), Some(100))
+        );
+    }
+}
@@ -1,101 +1,1343 @@
-use crate::account::AccountsRepository;
-use crate::transaction::{Transaction, TransactionLedger, Type};
+use crate::account::{self, Account, AccountsRepository, LockReason};
+use crate::alerts::{Alert, Threshold};
+use crate::approval::Decision;
+#[cfg(feature = "archive")]
+use crate::archive::ColdStorage;
+use crate::clock::{Clock, SystemClock};
+use crate::expiry::{self, AutoReason, Expiration, ExpiryAction, HoldExpiry};
+use crate::fees::FeeSchedule;
+use crate::fx::Rates;
+use crate::journal::Journal;
+use crate::metrics::EngineMetrics;
+use crate::money::Money;
+use crate::retention::{AuditLog, Purged, Retention};
+use crate::rules::{RuleHit, Rules, Verdict};
+use crate::screening::Screening;
+#[cfg(feature = "rhai")]
+use crate::script::Script;
+use crate::seen::SeenIds;
+use crate::transaction::{Approval, Label, Transaction, TransactionLedger, Type};
+use crate::validators::Validator;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
 
-pub struct Engine<'a> {
-    pub tx_ledger: &'a mut TransactionLedger,
-    pub accounts: &'a mut AccountsRepository,
+/// Why a transaction was not applied. The serialized names are part of the
+/// rejects report format and must stay stable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RejectReason {
+    InsufficientFunds,
+    LockedAccount,
+    DuplicateTx,
+    TxNotFound,
+    ClientMismatch,
+    AlreadyDisputed,
+    NotDisputed,
+    BlockedClient,
+    NotDisputable,
+    /// Reuses the id of a transaction with a different type, client or
+    /// amount, rather than retrying it.
+    ConflictingTx,
+    /// In another currency than the client's account.
+    CurrencyMismatch,
+    /// Refused by `validators::AmountBounds`.
+    AmountOutOfBounds,
+    /// Refused by `validators::Precision`.
+    TooPrecise,
+    /// Refused by `validators::KnownTypes`, or a fee given as input: only
+    /// the engine posts those.
+    TypeNotAllowed,
+    /// Refused by `validators::AllowedClients`.
+    ClientNotAllowed,
+    /// Refused by a validator of the library user's own.
+    Invalid,
+    /// Over the server's rate limit, and not applied.
+    RateLimited,
+    /// Denied by a rule of `Engine::with_rules`.
+    RuleDenied,
+    /// Of a custom type the engine has no handler for.
+    UnhandledType,
+    /// In another currency than the client's account, with no rate of
+    /// `Engine::with_rates` valid at its timestamp.
+    NoFxRate,
+    /// An approve or reject of a transaction that is not pending approval.
+    NotPending,
 }
 
-impl Engine<'_> {
+impl From<account::Error> for RejectReason {
+    fn from(err: account::Error) -> Self {
+        match err {
+            account::Error::InsufficientFunds => RejectReason::InsufficientFunds,
+            account::Error::LockedAccount => RejectReason::LockedAccount,
+            account::Error::CurrencyMismatch => RejectReason::CurrencyMismatch,
+        }
+    }
+}
+
+/// Applies the transactions of a custom type, see `Engine::with_handler`.
+pub trait Handler<M: Money = f64> {
+    /// Changes the `account` of the client of `tx` as `tx` requires, or
+    /// says why it cannot. Runs after screening, the validator and the rules.
+    fn apply(&self, tx: &Transaction<M>, account: &mut Account<M>) -> Result<(), RejectReason>;
+}
+
+impl<M: Money, F> Handler<M> for F
+where
+    F: Fn(&Transaction<M>, &mut Account<M>) -> Result<(), RejectReason>,
+{
+    fn apply(&self, tx: &Transaction<M>, account: &mut Account<M>) -> Result<(), RejectReason> {
+        self(tx, account)
+    }
+}
+
+/// A transaction the engine refused, as it appeared in the input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Rejection {
+    pub r#type: Type,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<f64>,
+    pub reason: RejectReason,
+}
+
+impl Rejection {
+    pub fn new<M: Money>(tx: &Transaction<M>, reason: RejectReason) -> Rejection {
+        Rejection {
+            r#type: tx.r#type(),
+            client: tx.account_id(),
+            tx: tx.id(),
+            amount: tx.optional_amount().map(M::to_f64),
+            reason,
+        }
+    }
+}
+
+/// A transaction `Engine::apply` accepted, with the balances of its
+/// client's account right after it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Applied<M = f64> {
+    pub r#type: Type,
+    pub client: u16,
+    pub tx: u32,
+    pub available: M,
+    pub held: M,
+    pub total: M,
+    pub locked: bool,
+}
+
+/// A change to an account, as sent to the receivers of `Engine::subscribe`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "event", rename_all = "snake_case"))]
+pub enum AccountEvent<M = f64> {
+    /// The balances after an applied transaction.
+    BalanceChanged {
+        client: u16,
+        tx: u32,
+        available: M,
+        held: M,
+        total: M,
+    },
+    DisputeOpened {
+        client: u16,
+        tx: u32,
+    },
+    Locked {
+        client: u16,
+        tx: u32,
+    },
+    Unlocked {
+        client: u16,
+        tx: u32,
+    },
+    /// The engine resolved a dispute on its own, see `expiry`. Sent before
+    /// the balances it led to.
+    AutoResolve {
+        client: u16,
+        tx: u32,
+        reason: AutoReason,
+    },
+    /// The engine charged a dispute back on its own.
+    AutoChargeback {
+        client: u16,
+        tx: u32,
+        reason: AutoReason,
+    },
+    /// A balance went beyond the limit of a threshold, see `alerts`. Sent
+    /// after the balances.
+    ThresholdCrossed {
+        client: u16,
+        tx: u32,
+        threshold: Threshold,
+        value: M,
+    },
+}
+
+/// Which of a dispute, resolve and chargeback may still be applied to an
+/// account once it is locked, see `Engine::with_locked_disputes`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LockedDisputes {
+    /// None of them: they are rejected as `LockedAccount`.
+    #[default]
+    Deny,
+    /// Disputes and chargebacks, so that further fraudulent deposits can be
+    /// charged back, but no resolve, which would free funds to a client
+    /// that was locked for fraud.
+    AllowChargebackOnly,
+    /// All of them.
+    Allow,
+}
+
+impl FromStr for LockedDisputes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny" => Ok(LockedDisputes::Deny),
+            "allow-chargeback-only" => Ok(LockedDisputes::AllowChargebackOnly),
+            "allow" => Ok(LockedDisputes::Allow),
+            _ => Err(format!(
+                "invalid policy: {} (expected deny, allow-chargeback-only or allow)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LockedDisputes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockedDisputes::Deny => "deny",
+            LockedDisputes::AllowChargebackOnly => "allow-chargeback-only",
+            LockedDisputes::Allow => "allow",
+        })
+    }
+}
+
+pub struct Engine<'a, M = f64> {
+    pub tx_ledger: &'a mut TransactionLedger<M>,
+    pub accounts: &'a mut AccountsRepository<M>,
+    rejections: Vec<Rejection>,
+    metrics: EngineMetrics,
+    journal: Option<Journal>,
+    client_label: Option<&'a dyn Fn(u16) -> String>,
+    screening: Option<&'a dyn Screening>,
+    validator: Option<&'a dyn Validator<M>>,
+    rules: Option<&'a Rules>,
+    #[cfg(feature = "rhai")]
+    script: Option<&'a Script>,
+    rates: Option<&'a Rates>,
+    rule_hits: Vec<RuleHit>,
+    handlers: HashMap<Label, &'a dyn Handler<M>>,
+    clock: &'a dyn Clock,
+    hold_expiry: Option<HoldExpiry>,
+    expirations: Vec<Expiration>,
+    decisions: Vec<Decision>,
+    thresholds: Vec<Threshold>,
+    /// Client and index of every threshold an account is beyond.
+    breaching: HashSet<(u16, usize)>,
+    alerts: Vec<Alert>,
+    retention: Option<(Retention, &'a mut dyn AuditLog)>,
+    fees: Option<FeeSchedule>,
+    disputable_bonuses: bool,
+    direct_chargebacks: bool,
+    auto_unlock: bool,
+    locked_disputes: LockedDisputes,
+    withdrawal_approval: Option<M>,
+    seen_ids: Option<&'a mut SeenIds>,
+    #[cfg(feature = "archive")]
+    cold_storage: Option<&'a mut ColdStorage>,
+    subscribers: Vec<Sender<AccountEvent<M>>>,
+}
+
+impl<'a, M: Money> Engine<'a, M> {
+    /// Shows clients in trace spans under the given label instead of their
+    /// id, e.g. a pseudonym.
+    pub fn with_client_label(mut self, label: &'a dyn Fn(u16) -> String) -> Self {
+        self.client_label = Some(label);
+        self
+    }
+
+    /// Rejects every transaction of a client `screening` blocks, before its
+    /// account is looked at.
+    pub fn with_screening(mut self, screening: &'a dyn Screening) -> Self {
+        self.screening = Some(screening);
+        self
+    }
+
+    /// Rejects every transaction `validator` refuses, with its reason,
+    /// after screening and before the account is looked at. Several can be
+    /// combined into a `validators::Chain`.
+    pub fn with_validator(mut self, validator: &'a dyn Validator<M>) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Runs `rules` over every transaction that passed screening and the
+    /// validator, with its client's account as it is before the
+    /// transaction. One a `deny` rule applies to is rejected as
+    /// `RuleDenied`; both those and the ones a `flag` rule applies to are
+    /// listed by `rule_hits`.
+    pub fn with_rules(mut self, rules: &'a Rules) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// Runs `script` over every transaction no rule of `with_rules`
+    /// decided, see `script`. Its verdicts count like those of a rule named
+    /// `script`; one it fails on is denied.
+    #[cfg(feature = "rhai")]
+    pub fn with_script(mut self, script: &'a Script) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Applies the transactions of the custom `r#type` with `handler`,
+    /// replacing any handler given for it before. Like deposits, they are
+    /// rejected when their id is taken, and they cannot be disputed. Those
+    /// of custom types without a handler are rejected as `UnhandledType`.
+    /// Built-in types cannot be handled this way.
+    pub fn with_handler(
+        mut self,
+        r#type: Type,
+        handler: &'a dyn Handler<M>,
+    ) -> Result<Self, String> {
+        let Type::Custom(name) = r#type else {
+            return Err(format!("{} is a built-in transaction type", r#type));
+        };
+        self.handlers.insert(name, handler);
+        Ok(self)
+    }
+
+    /// Also rejects as duplicates the deposits and withdrawals whose ids are
+    /// in `seen`, e.g. from earlier runs, and records the ids of those
+    /// applied from now on in it.
+    pub fn with_seen_ids(mut self, seen: &'a mut SeenIds) -> Self {
+        self.seen_ids = Some(seen);
+        self
+    }
+
+    /// Brings transactions back from `storage` when one refers to or
+    /// repeats an archived id, see `archive_settled`.
+    #[cfg(feature = "archive")]
+    pub fn with_cold_storage(mut self, storage: &'a mut ColdStorage) -> Self {
+        self.cold_storage = Some(storage);
+        self
+    }
+
+    /// Converts deposits and withdrawals in another currency than their
+    /// client's account at the dated `rates`, see `fx`.
+    pub fn with_rates(mut self, rates: &'a Rates) -> Self {
+        self.rates = Some(rates);
+        self
+    }
+
+    /// Purges transactions whose dispute was settled longer ago than
+    /// `policy` allows, after exporting them to `audit_log`, see `retention`.
+    pub fn with_retention(mut self, policy: Retention, audit_log: &'a mut dyn AuditLog) -> Self {
+        self.retention = Some((policy, audit_log));
+        self
+    }
+
+    /// Reads the current time for time-dependent rules from `clock` instead
+    /// of the system clock.
+    pub fn with_clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &'a dyn Clock {
+        self.clock
+    }
+}
+
+impl<M: Money> Engine<'_, M> {
     pub fn new<'a>(
-        tx_ledger: &'a mut TransactionLedger,
-        accounts: &'a mut AccountsRepository,
-    ) -> Engine<'a> {
+        tx_ledger: &'a mut TransactionLedger<M>,
+        accounts: &'a mut AccountsRepository<M>,
+    ) -> Engine<'a, M> {
         Engine {
             tx_ledger,
             accounts,
+            rejections: Vec::new(),
+            metrics: EngineMetrics::default(),
+            journal: None,
+            client_label: None,
+            screening: None,
+            validator: None,
+            rules: None,
+            #[cfg(feature = "rhai")]
+            script: None,
+            rates: None,
+            rule_hits: Vec::new(),
+            handlers: HashMap::new(),
+            clock: &SystemClock,
+            hold_expiry: None,
+            expirations: Vec::new(),
+            decisions: Vec::new(),
+            thresholds: Vec::new(),
+            breaching: HashSet::new(),
+            alerts: Vec::new(),
+            retention: None,
+            fees: None,
+            disputable_bonuses: false,
+            direct_chargebacks: false,
+            auto_unlock: false,
+            locked_disputes: LockedDisputes::Deny,
+            withdrawal_approval: None,
+            seen_ids: None,
+            #[cfg(feature = "archive")]
+            cold_storage: None,
+            subscribers: Vec::new(),
         }
     }
 
-    fn deposit(&mut self, tx: &Transaction) {
-        let account = self.accounts.get_or_create(tx.account_id());
-        if self.tx_ledger.get(tx.id()).is_some() {
+    /// Records every applied movement as a double-entry posting.
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Some(Journal::new());
+        self
+    }
+
+    /// Closes disputes that stay open longer than `policy` allows, see
+    /// `expiry`.
+    pub fn with_hold_expiry(mut self, policy: HoldExpiry) -> Self {
+        self.hold_expiry = Some(policy);
+        self
+    }
+
+    /// Charges fees on deposits and withdrawals according to `schedule`.
+    pub fn with_fees(mut self, schedule: FeeSchedule) -> Self {
+        self.fees = Some(schedule);
+        self
+    }
+
+    /// Lets bonuses be disputed like deposits; by default a dispute of a
+    /// bonus is rejected as `NotDisputable`.
+    pub fn with_disputable_bonuses(mut self) -> Self {
+        self.disputable_bonuses = true;
+        self
+    }
+
+    /// Lets a chargeback of an undisputed transaction first open the dispute
+    /// it skipped, holding the funds, instead of being rejected as
+    /// `NotDisputed`.
+    pub fn with_direct_chargebacks(mut self) -> Self {
+        self.direct_chargebacks = true;
+        self
+    }
+
+    /// Treats a lock by chargeback as temporary: the account's other
+    /// disputes can still be resolved or charged back, and the lock is lifted
+    /// as soon as none is left open and the total balance is not negative.
+    pub fn with_auto_unlock(mut self) -> Self {
+        self.auto_unlock = true;
+        self
+    }
+
+    /// Raises an `Alert` whenever an account goes beyond `threshold`, see
+    /// `alerts`. May be given several times.
+    pub fn with_alert(mut self, threshold: Threshold) -> Self {
+        self.thresholds.push(threshold);
+        self
+    }
+
+    /// Lets disputes, and depending on `policy` resolves and chargebacks,
+    /// through to accounts that are locked; by default they are rejected as
+    /// `LockedAccount`. The account stays locked either way.
+    pub fn with_locked_disputes(mut self, policy: LockedDisputes) -> Self {
+        self.locked_disputes = policy;
+        self
+    }
+
+    /// Holds withdrawals above `threshold` for approval: their funds move
+    /// from available to held, and only leave the account with an `approve`
+    /// of the same tx id, while a `reject` makes them available again. Fees
+    /// are taken on approval. See `approval`.
+    pub fn with_withdrawal_approval(mut self, threshold: M) -> Self {
+        self.withdrawal_approval = Some(threshold);
+        self
+    }
+
+    /// A channel receiving an `AccountEvent` for every change to an account
+    /// from now on: new balances after each applied transaction, then any
+    /// dispute it opened and any lock it set or lifted. Events stop for a
+    /// receiver once it is dropped.
+    pub fn subscribe(&mut self) -> Receiver<AccountEvent<M>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Sends `event` to every subscriber, dropping those that are gone.
+    fn announce(&mut self, event: AccountEvent<M>) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+    }
+
+    /// Counts the account of `client` in `EngineMetrics::accounts_locked` if
+    /// it was not locked before.
+    fn count_lock(&mut self, client: u16, was_locked: bool) {
+        let locked = self
+            .accounts
+            .get(client)
+            .is_some_and(|account| account.locked());
+        if locked && !was_locked {
+            self.metrics.accounts_locked += 1;
+        }
+    }
+
+    /// Sends the events of the applied `tx` to every subscriber.
+    fn publish(&mut self, tx: &Transaction<M>, was_locked: bool) {
+        if self.subscribers.is_empty() {
             return;
         }
-        if let Err(err) = account.deposit(tx.amount()) {
-            log::warn!("could not deposit money: {:?}", err)
+        let (client, id) = (tx.account_id(), tx.id());
+        let account = self.accounts.get_or_create(client);
+        let mut events = vec![AccountEvent::BalanceChanged {
+            client,
+            tx: id,
+            available: account.available_balance(),
+            held: account.held_balance(),
+            total: account.total_balance(),
+        }];
+        if tx.r#type() == Type::Dispute {
+            events.push(AccountEvent::DisputeOpened { client, tx: id });
+        }
+        match (was_locked, account.locked()) {
+            (false, true) => events.push(AccountEvent::Locked { client, tx: id }),
+            (true, false) => events.push(AccountEvent::Unlocked { client, tx: id }),
+            _ => {}
         }
+        self.subscribers
+            .retain(|subscriber| events.iter().all(|event| subscriber.send(*event).is_ok()));
     }
 
-    fn withdrawal(&mut self, tx: &Transaction) {
-        let account = self.accounts.get_or_create(tx.account_id());
-        if self.tx_ledger.get(tx.id()).is_some() {
+    /// Raises an alert for every threshold the account of the applied `tx`
+    /// went beyond, and rearms those it is back within.
+    fn watch(&mut self, tx: &Transaction<M>) {
+        if self.thresholds.is_empty() {
             return;
         }
-        if let Err(err) = account.withdrawal(tx.amount()) {
-            log::warn!("could not withdrawal money: {:?}", err)
+        let client = tx.account_id();
+        let Some(account) = self.accounts.get(client) else {
+            return;
+        };
+        let mut crossed = Vec::new();
+        for (index, threshold) in self.thresholds.iter().enumerate() {
+            let value = threshold.balance.of(&account);
+            if !threshold.breached_by(value.to_f64()) {
+                self.breaching.remove(&(client, index));
+            } else if self.breaching.insert((client, index)) {
+                crossed.push((*threshold, value));
+            }
+        }
+        drop(account);
+        for (threshold, value) in crossed {
+            log::warn!(
+                "client {} crossed {} at tx {}: {}",
+                client,
+                threshold,
+                tx.id(),
+                value.to_f64()
+            );
+            self.alerts.push(Alert::new(tx, threshold, value));
+            self.announce(AccountEvent::ThresholdCrossed {
+                client,
+                tx: tx.id(),
+                threshold,
+                value,
+            });
         }
     }
 
-    fn dispute(&mut self, tx: &Transaction) {
-        let account = self.accounts.get_or_create(tx.account_id());
-        if let Some(old_tx) = self.tx_ledger.get(tx.id()) {
-            if old_tx.is_dispute() || account.client_id() != old_tx.account_id() {
-                return;
+    /// Moves the settled transactions of the ledger but the `keep` with the
+    /// highest ids to cold storage, if the engine has one, returning how
+    /// many were moved.
+    #[cfg(feature = "archive")]
+    pub fn archive_settled(&mut self, keep: usize) -> std::io::Result<usize> {
+        match &mut self.cold_storage {
+            Some(storage) => storage.archive(self.tx_ledger, keep),
+            None => Ok(0),
+        }
+    }
+
+    /// Puts the archived transaction with the id of `tx` back into the
+    /// ledger, so that a dispute finds it and a repeat is seen as one.
+    #[cfg(feature = "archive")]
+    fn rehydrate(&mut self, tx: &Transaction<M>) {
+        let Some(storage) = &mut self.cold_storage else {
+            return;
+        };
+        if !storage.contains(tx.id()) || self.tx_ledger.get(tx.id()).is_some() {
+            return;
+        }
+        match storage.rehydrate(self.tx_ledger, tx.id()) {
+            Ok(_) => log::info!("rehydrated tx {} from cold storage", tx.id()),
+            Err(err) => log::warn!("could not rehydrate tx {}: {}", tx.id(), err),
+        }
+    }
+
+    /// Every dispute closed by the hold expiry policy so far.
+    pub fn expirations(&self) -> &[Expiration] {
+        &self.expirations
+    }
+
+    /// Every approve and reject applied so far, see `approval`.
+    pub fn decisions(&self) -> &[Decision] {
+        &self.decisions
+    }
+
+    /// Every alert raised so far, see `alerts`.
+    pub fn alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    /// Every transaction a `flag`, `hold` or `deny` rule applied to so far.
+    pub fn rule_hits(&self) -> &[RuleHit] {
+        &self.rule_hits
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Every transaction refused so far, in processing order.
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    /// The rounded fee `tx` triggers under the fee schedule, if any.
+    fn fee(&self, tx: &Transaction<M>) -> M {
+        self.fees.map_or(M::default(), |schedule| {
+            let origin = self.tx_ledger.get(tx.id());
+            let (r#type, amount) = match tx.r#type() {
+                Type::Chargeback => (Type::Chargeback, origin.map(|origin| origin.amount())),
+                // Transactions held for approval are charged once approved.
+                Type::Deposit | Type::Withdrawal if origin.is_some_and(Transaction::is_pending) => {
+                    return M::default()
+                }
+                Type::Approve => match origin {
+                    Some(origin) => (origin.r#type(), Some(origin.amount())),
+                    None => (Type::Approve, None),
+                },
+                r#type => (r#type, tx.optional_amount()),
+            };
+            let amount = amount.map_or(0.0, M::to_f64);
+            M::from_f64(schedule.fee(r#type, amount)).round(self.accounts.rounding())
+        })
+    }
+
+    /// The verdict of the first of `rules` that applies to `tx`, recording
+    /// it unless it allows `tx`.
+    fn judge(&mut self, rules: &Rules, tx: &Transaction<M>) -> Option<Verdict> {
+        let account = self.accounts.get(tx.account_id());
+        let rule = rules.evaluate(tx, account.as_deref())?;
+        drop(account);
+        self.note_verdict(tx, &rule.name, rule.verdict);
+        Some(rule.verdict)
+    }
+
+    /// The verdict of `script` on `tx`, `Deny` if the script fails.
+    #[cfg(feature = "rhai")]
+    fn run_script(&mut self, script: &Script, tx: &Transaction<M>) -> Option<Verdict> {
+        let account = self.accounts.get(tx.account_id());
+        let verdict = script
+            .evaluate(tx, account.as_deref())
+            .unwrap_or_else(|err| {
+                log::warn!("script failed on tx {}: {}", tx.id(), err);
+                Some(Verdict::Deny)
+            })?;
+        drop(account);
+        self.note_verdict(tx, "script", verdict);
+        Some(verdict)
+    }
+
+    /// Logs and lists in `rule_hits` a verdict other than `Allow` of the
+    /// rule `name` on `tx`.
+    fn note_verdict(&mut self, tx: &Transaction<M>, name: &str, verdict: Verdict) {
+        if verdict != Verdict::Allow {
+            log::info!("rule {} applies to tx {}: {:?}", name, tx.id(), verdict);
+            self.rule_hits.push(RuleHit {
+                r#type: tx.r#type(),
+                client: tx.account_id(),
+                tx: tx.id(),
+                rule: name.to_string(),
+                verdict,
+            });
+        }
+    }
+
+    /// Refuses a deposit or withdrawal whose id is taken: as a duplicate when
+    /// it repeats the stored transaction, as a conflict when it differs.
+    fn check_duplicate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        match self.tx_ledger.get(tx.id()) {
+            None if self.tx_ledger.is_purged(tx.id())
+                || self
+                    .seen_ids
+                    .as_ref()
+                    .is_some_and(|seen| seen.contains(tx.id())) =>
+            {
+                Err(RejectReason::DuplicateTx)
             }
-            if let Err(err) = account.dispute(old_tx.amount()) {
-                log::warn!("could not dispute transaction: {:?}", err);
-                return;
+            None => Ok(()),
+            Some(old)
+                if old.r#type() == tx.r#type()
+                    && old.account_id() == tx.account_id()
+                    && old.optional_amount() == tx.optional_amount() =>
+            {
+                Err(RejectReason::DuplicateTx)
             }
-            self.tx_ledger.dispute_tx(tx.id())
+            Some(_) => Err(RejectReason::ConflictingTx),
         }
     }
 
-    fn resolve(&mut self, tx: &Transaction) {
-        let account = self.accounts.get_or_create(tx.account_id());
+    /// `tx` with its amount in the currency of its client's account, if it
+    /// names another one and rates are known.
+    fn convert(&self, tx: &Transaction<M>) -> Result<Transaction<M>, RejectReason> {
+        let (Some(rates), Some(from), Type::Deposit | Type::Withdrawal | Type::Bonus) =
+            (self.rates, tx.currency(), tx.r#type())
+        else {
+            return Ok(*tx);
+        };
+        let to = match self.accounts.get(tx.account_id()) {
+            Some(account) => account.currency(),
+            None => self.accounts.currency(),
+        };
+        let Some(to) = to.filter(|to| *to != from) else {
+            return Ok(*tx);
+        };
+        let at = tx
+            .timestamp()
+            .unwrap_or_else(|| expiry::unix_seconds(self.clock.now()));
+        let rate = rates.rate(from, to, at).ok_or(RejectReason::NoFxRate)?;
+        let mut converted = tx.with_currency(Some(to));
+        converted.amount = Some(M::from_f64(tx.amount().to_f64() * rate));
+        log::debug!(
+            "converted tx {} from {} to {} at {}",
+            tx.id(),
+            from,
+            to,
+            rate
+        );
+        Ok(converted)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn deposit(&mut self, tx: &Transaction<M>, hold: bool) -> Result<(), RejectReason> {
+        let fee = self.fee(tx);
+        let duplicate = self.check_duplicate(tx);
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        duplicate?;
+        if let Some(currency) = tx.currency() {
+            account.adopt_currency(currency)?;
+        }
+        if hold {
+            account.hold_deposit(tx.amount())?;
+            drop(account);
+            self.hold_for_approval(tx);
+            return Ok(());
+        }
+        account.deposit(tx.amount())?;
+        if fee > M::default() {
+            // Never more than the deposit itself, so always covered.
+            account.charge_fee(fee);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn withdrawal(&mut self, tx: &Transaction<M>, hold: bool) -> Result<(), RejectReason> {
+        let fee = self.fee(tx);
+        let duplicate = self.check_duplicate(tx);
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        duplicate?;
+        if let Some(currency) = tx.currency() {
+            account.adopt_currency(currency)?;
+        }
+        let over_threshold = self
+            .withdrawal_approval
+            .is_some_and(|threshold| tx.amount() > threshold);
+        if !hold && !over_threshold {
+            return Ok(account.withdrawal(tx.amount() + fee)?);
+        }
+        // Held like disputed funds until approved or rejected.
+        account.dispute(tx.amount())?;
+        drop(account);
+        self.hold_for_approval(tx);
+        Ok(())
+    }
+
+    /// Stores `tx`, whose funds were just held, as pending approval.
+    fn hold_for_approval(&mut self, tx: &Transaction<M>) {
+        self.tx_ledger.append(tx);
+        self.tx_ledger.set_approval(tx.id(), Approval::Pending);
+        log::info!("holding {} {} for approval", tx.r#type(), tx.id());
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn dispute(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        let old_tx = self
+            .tx_ledger
+            .get(tx.id())
+            .ok_or(RejectReason::TxNotFound)?;
+        if old_tx.is_dispute() || old_tx.is_charged_back() {
+            return Err(RejectReason::AlreadyDisputed);
+        }
+        if account.client_id() != old_tx.account_id() {
+            return Err(RejectReason::ClientMismatch);
+        }
+        match old_tx.r#type() {
+            Type::Bonus if !self.disputable_bonuses => return Err(RejectReason::NotDisputable),
+            Type::Custom(_) => return Err(RejectReason::NotDisputable),
+            _ => {}
+        }
+        if matches!(
+            old_tx.approval(),
+            Some(Approval::Pending | Approval::Rejected)
+        ) {
+            return Err(RejectReason::NotDisputable);
+        }
+        match account.locked() && self.locked_disputes != LockedDisputes::Deny {
+            true => account.hold(old_tx.amount())?,
+            false => account.dispute(old_tx.amount())?,
+        }
+        match self.hold_expiry {
+            Some(_) => self.tx_ledger.dispute_tx_at(tx.id(), self.clock.now()),
+            None => self.tx_ledger.dispute_tx(tx.id()),
+        }
+        Ok(())
+    }
+
+    /// Hands `tx`, of the custom type `name`, to its handler.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn custom(&mut self, tx: &Transaction<M>, name: Label) -> Result<(), RejectReason> {
+        let handler = *self
+            .handlers
+            .get(&name)
+            .ok_or(RejectReason::UnhandledType)?;
+        let duplicate = self.check_duplicate(tx);
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        duplicate?;
+        handler.apply(tx, &mut account)
+    }
+
+    /// Looks up the disputed transaction a resolve or chargeback refers to.
+    fn disputed(&self, tx: &Transaction<M>) -> Result<Transaction<M>, RejectReason> {
+        let old_tx = self
+            .tx_ledger
+            .get(tx.id())
+            .ok_or(RejectReason::TxNotFound)?;
+        if !old_tx.is_dispute() {
+            return Err(RejectReason::NotDisputed);
+        }
+        if old_tx.account_id() != tx.account_id() {
+            return Err(RejectReason::ClientMismatch);
+        }
+        Ok(*old_tx)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn resolve(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        let old_tx = self.disputed(tx);
+        let temporary_lock = self.temporarily_locked(tx.account_id());
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        let amount = old_tx?.amount();
+        let allowed = account.locked() && self.locked_disputes == LockedDisputes::Allow;
+        match temporary_lock || allowed {
+            true => account.release(amount)?,
+            false => account.resolve(amount)?,
+        }
+        drop(account);
+        self.tx_ledger.undispute_tx(tx.id());
+        self.note_settlement(tx);
+        self.unlock_if_settled(tx);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn chargeback(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        if self.direct_chargebacks {
+            self.dispute_implicitly(tx)?;
+        }
+        let old_tx = self.disputed(tx);
+        let fee = self.fee(tx);
+        let temporary_lock = self.temporarily_locked(tx.account_id());
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        let amount = old_tx?.amount();
+        let allowed = account.locked() && self.locked_disputes != LockedDisputes::Deny;
+        match temporary_lock || allowed {
+            true => account.reverse(amount)?,
+            false => {
+                account.chargeback(amount)?;
+                account.record_lock(
+                    LockReason::Chargeback {
+                        tx: tx.id(),
+                        amount,
+                    },
+                    tx.timestamp(),
+                );
+            }
+        }
+        if fee > M::default() {
+            account.charge_fee(fee);
+        }
+        drop(account);
+        self.tx_ledger.charge_back_tx(tx.id());
+        if fee > M::default() {
+            let fee = Transaction::new(tx.id(), Type::Fee, tx.account_id(), fee)
+                .with_timestamp(tx.timestamp());
+            self.tx_ledger.append_fee(fee);
+        }
+        self.note_settlement(tx);
+        self.unlock_if_settled(tx);
+        Ok(())
+    }
+
+    /// Looks up the transaction pending approval an approve or reject
+    /// refers to.
+    fn pending(&self, tx: &Transaction<M>) -> Result<Transaction<M>, RejectReason> {
+        let old_tx = self
+            .tx_ledger
+            .get(tx.id())
+            .ok_or(RejectReason::TxNotFound)?;
+        if !old_tx.is_pending() {
+            return Err(RejectReason::NotPending);
+        }
+        if old_tx.account_id() != tx.account_id() {
+            return Err(RejectReason::ClientMismatch);
+        }
+        Ok(*old_tx)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn approve(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        let old_tx = self.pending(tx);
+        let fee = self.fee(tx);
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        let old_tx = old_tx?;
+        if old_tx.r#type() == Type::Deposit {
+            account.credit_held(old_tx.amount())?;
+            if fee > M::default() {
+                // Never more than the deposit itself, so always covered.
+                account.charge_fee(fee);
+            }
+        } else {
+            account.pay_out_with_fee(old_tx.amount(), fee)?;
+        }
+        drop(account);
+        self.decide(&old_tx, tx, Approval::Approved);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn reject(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        let old_tx = self.pending(tx);
+        let mut account = self.accounts.get_or_create(tx.account_id());
+        let old_tx = old_tx?;
+        // Sends the funds back where they came from, even while locked.
+        match old_tx.r#type() {
+            Type::Deposit => account.remove_held(old_tx.amount())?,
+            _ => account.release(old_tx.amount())?,
+        }
+        drop(account);
+        self.decide(&old_tx, tx, Approval::Rejected);
+        Ok(())
+    }
+
+    /// Records the `approval` of the held `old_tx` by `tx`.
+    fn decide(&mut self, old_tx: &Transaction<M>, tx: &Transaction<M>, approval: Approval) {
+        self.tx_ledger.set_approval(tx.id(), approval);
+        log::info!(
+            "{} {} {} of client {}",
+            tx.r#type(),
+            old_tx.r#type(),
+            old_tx.id(),
+            old_tx.account_id()
+        );
+        self.decisions.push(Decision::new(old_tx, tx));
+    }
+
+    /// Notes when the dispute of `tx` was settled, if settled disputes are
+    /// purged after a while.
+    fn note_settlement(&mut self, tx: &Transaction<M>) {
+        if self.retention.is_some() {
+            self.tx_ledger.settle_tx_at(tx.id(), self.clock.now());
+        }
+    }
+
+    /// Whether `client` is locked by a chargeback that auto-unlock may lift.
+    fn temporarily_locked(&self, client: u16) -> bool {
+        self.auto_unlock
+            && self.accounts.get(client).is_some_and(|account| {
+                matches!(account.lock_reason(), Some(LockReason::Chargeback { .. }))
+            })
+    }
+
+    /// Lifts a temporary lock of the client of `tx` once its disputes are
+    /// settled. Looks through the whole ledger, but only for locked accounts.
+    fn unlock_if_settled(&mut self, tx: &Transaction<M>) {
+        let client = tx.account_id();
+        if !self.temporarily_locked(client) {
+            return;
+        }
+        let open_disputes = self
+            .tx_ledger
+            .iter()
+            .any(|old_tx| old_tx.is_dispute() && old_tx.account_id() == client);
+        let mut account = self.accounts.get_or_create(client);
+        if !open_disputes && account.total_balance() >= M::default() {
+            account.unlock(tx.timestamp());
+            log::info!("unlocked client {} after tx {}", client, tx.id());
+        }
+    }
+
+    /// Opens the dispute a direct chargeback skipped, posting it like one
+    /// from the input. Does nothing unless `tx` refers to an undisputed
+    /// transaction of the same client.
+    fn dispute_implicitly(&mut self, tx: &Transaction<M>) -> Result<(), RejectReason> {
         match self.tx_ledger.get(tx.id()) {
-            None => (),
-            Some(old_tx) => {
-                if old_tx.is_dispute() && old_tx.account_id() == account.client_id() {
-                    if let Err(err) = account.resolve(old_tx.amount()) {
-                        log::warn!("could not resolve: {:?}", err);
-                        return;
+            Some(old_tx) if !old_tx.is_dispute() && old_tx.account_id() == tx.account_id() => {}
+            _ => return Ok(()),
+        }
+        let mut dispute = Transaction::new(tx.id(), Type::Dispute, tx.account_id(), M::default());
+        dispute.amount = None;
+        self.dispute(&dispute)?;
+        log::info!("opened dispute of tx {} for a direct chargeback", tx.id());
+        self.post(&dispute);
+        Ok(())
+    }
+
+    /// Applies the policy's resolve or chargeback to every dispute that has
+    /// been open for too long. Runs before every transaction; embedders whose
+    /// input can go quiet may call it on a timer as well.
+    pub fn expire_holds(&mut self) {
+        let Some(policy) = self.hold_expiry else {
+            return;
+        };
+        let now = self.clock.now();
+        let Some(deadline) = now.checked_sub(policy.after) else {
+            return;
+        };
+        for id in self.tx_ledger.disputed_since(deadline) {
+            let (Some(origin), Some(disputed_at)) = (
+                self.tx_ledger.get(id).copied(),
+                self.tx_ledger.disputed_at(id),
+            ) else {
+                continue;
+            };
+            let mut tx = Transaction::new(
+                id,
+                policy.action.r#type(),
+                origin.account_id(),
+                M::default(),
+            );
+            tx.amount = None;
+            let was_locked = self
+                .accounts
+                .get(origin.account_id())
+                .is_some_and(|account| account.locked());
+            let result = match policy.action {
+                ExpiryAction::Resolve => self.resolve(&tx),
+                ExpiryAction::Chargeback => self.chargeback(&tx),
+            };
+            self.metrics.record(tx.r#type(), result);
+            self.count_lock(origin.account_id(), was_locked);
+            match result {
+                Ok(()) => {
+                    log::info!("dispute of tx {} expired: {}", id, policy.action);
+                    let reason = AutoReason::HoldExpired;
+                    let posted = self
+                        .journal
+                        .as_ref()
+                        .map_or(0, |journal| journal.entries().len());
+                    self.post(&tx);
+                    if let Some(journal) = &mut self.journal {
+                        journal.attribute(posted, policy.action.event(), reason);
                     }
-                    self.tx_ledger.undispute_tx(tx.id());
+                    let (client, tx_id) = (origin.account_id(), id);
+                    self.announce(match policy.action {
+                        ExpiryAction::Resolve => AccountEvent::AutoResolve {
+                            client,
+                            tx: tx_id,
+                            reason,
+                        },
+                        ExpiryAction::Chargeback => AccountEvent::AutoChargeback {
+                            client,
+                            tx: tx_id,
+                            reason,
+                        },
+                    });
+                    self.publish(&tx, was_locked);
+                    self.watch(&tx);
+                    self.expirations.push(Expiration {
+                        tx: id,
+                        client: origin.account_id(),
+                        action: tx.r#type(),
+                        amount: origin.amount().round(self.accounts.rounding()).to_f64(),
+                        disputed_at: expiry::unix_seconds(disputed_at),
+                        expired_at: expiry::unix_seconds(now),
+                    });
+                }
+                Err(reason) => {
+                    log::warn!("could not expire dispute of tx {}: {:?}", id, reason);
+                    self.tx_ledger.forget_dispute_time(id);
+                    self.rejections.push(Rejection::new(&tx, reason));
                 }
             }
         }
     }
 
-    fn chargeback(&mut self, tx: &Transaction) {
-        let account = self.accounts.get_or_create(tx.account_id());
-        match self.tx_ledger.get(tx.id()) {
-            None => {}
-            Some(tx) => {
-                if tx.is_dispute() && tx.account_id() == account.client_id() {
-                    if let Err(err) = account.chargeback(tx.amount()) {
-                        log::warn!("could not chargeback money: {:?}", err)
-                    }
+    /// Exports and drops every transaction whose dispute was settled longer
+    /// ago than the retention policy allows. Runs before every transaction,
+    /// like `expire_holds`.
+    pub fn purge_settled(&mut self) {
+        let Some((policy, audit_log)) = &mut self.retention else {
+            return;
+        };
+        let now = self.clock.now();
+        let Some(deadline) = now.checked_sub(policy.after) else {
+            return;
+        };
+        for id in self.tx_ledger.settled_since(deadline) {
+            let (Some(tx), Some(settled_at)) =
+                (self.tx_ledger.get(id), self.tx_ledger.settled_at(id))
+            else {
+                continue;
+            };
+            if let Err(err) = audit_log.record(&Purged::new(tx, settled_at, now)) {
+                log::warn!(
+                    "could not export tx {} to the audit log, keeping it: {}",
+                    id,
+                    err
+                );
+                return;
+            }
+            self.tx_ledger.purge(id);
+            log::info!("purged tx {} after its dispute was settled", id);
+        }
+    }
+
+    fn post(&mut self, tx: &Transaction<M>) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        let origin = match tx.r#type() {
+            Type::Bonus => Some(tx),
+            // The stored one says whether it was held for approval.
+            Type::Deposit | Type::Withdrawal => self.tx_ledger.get(tx.id()).or(Some(tx)),
+            Type::Custom(_) => None,
+            _ => self.tx_ledger.get(tx.id()),
+        };
+        if let Some(origin) = origin {
+            // At the places the account booked it at.
+            let rounding = self.accounts.rounding();
+            let amount = match self.accounts.get(origin.account_id()) {
+                Some(account) if account.currency().is_some() => {
+                    origin.amount().round_to(rounding, account.decimals())
                 }
+                _ => origin.amount().round(rounding),
+            };
+            journal.post(tx, origin, amount.to_f64());
+        }
+        let fee = self.fee(tx);
+        if fee > M::default() {
+            log::info!(
+                "charged fee of {} on {} of tx {}",
+                fee.to_f64(),
+                tx.r#type(),
+                tx.id()
+            );
+            if let Some(journal) = &mut self.journal {
+                journal.post_fee(tx, fee.to_f64());
             }
         }
     }
 
-    pub fn process(&mut self, input_tx: &[Transaction]) {
+    #[tracing::instrument(skip_all, fields(batch_size = input_tx.len()))]
+    pub fn process(&mut self, input_tx: &[Transaction<M>]) {
         for tx in input_tx {
-            match tx.r#type() {
-                Type::Deposit => self.deposit(tx),
-                Type::Withdrawal => self.withdrawal(tx),
-                Type::Dispute => self.dispute(tx),
-                Type::Resolve => self.resolve(tx),
-                Type::Chargeback => self.chargeback(tx),
+            let _ = self.apply(*tx);
+        }
+    }
+
+    /// Applies a single transaction, for callers that need its outcome right
+    /// away. A rejection is recorded in `rejections` as with `process`.
+    pub fn apply(&mut self, tx: Transaction<M>) -> Result<Applied<M>, RejectReason> {
+        let tx = &tx;
+        let span = tracing::debug_span!(
+            "transaction",
+            tx = tx.id(),
+            client = tracing::field::Empty,
+            kind = ?tx.r#type()
+        );
+        if !span.is_disabled() {
+            match &self.client_label {
+                Some(label) => span.record("client", label(tx.account_id())),
+                None => span.record("client", tx.account_id()),
+            };
+        }
+        let _entered = span.enter();
+        self.expire_holds();
+        self.purge_settled();
+        #[cfg(feature = "archive")]
+        self.rehydrate(tx);
+
+        let conversion = self.convert(tx);
+        let tx = &conversion.unwrap_or(*tx);
+        let known_accounts = self.accounts.len();
+        let was_locked = self
+            .accounts
+            .get(tx.account_id())
+            .is_some_and(|account| account.locked());
+        let blocked = self
+            .screening
+            .is_some_and(|screening| screening.is_blocked(tx.account_id()));
+        let validated = match self.validator {
+            Some(validator) if !blocked => validator.validate(tx),
+            _ => Ok(()),
+        };
+        let verdict = match self.rules {
+            Some(rules) if !blocked && validated.is_ok() => self.judge(rules, tx),
+            _ => None,
+        };
+        #[cfg(feature = "rhai")]
+        let verdict = match self.script {
+            Some(script) if !blocked && validated.is_ok() && verdict.is_none() => {
+                self.run_script(script, tx)
+            }
+            _ => verdict,
+        };
+        let hold = verdict == Some(Verdict::Hold);
+        let result = match tx.r#type() {
+            _ if blocked => Err(RejectReason::BlockedClient),
+            _ if validated.is_err() => validated,
+            _ if verdict == Some(Verdict::Deny) => Err(RejectReason::RuleDenied),
+            _ if conversion.is_err() => conversion.map(drop),
+            Type::Deposit => self.deposit(tx, hold),
+            Type::Bonus => self.deposit(tx, false),
+            Type::Withdrawal => self.withdrawal(tx, hold),
+            Type::Dispute => self.dispute(tx),
+            Type::Resolve => self.resolve(tx),
+            Type::Chargeback => self.chargeback(tx),
+            Type::Approve => self.approve(tx),
+            Type::Reject => self.reject(tx),
+            Type::Fee => Err(RejectReason::TypeNotAllowed),
+            Type::Custom(name) => self.custom(tx, name),
+        };
+        self.metrics.record(tx.r#type(), result);
+        self.count_lock(tx.account_id(), was_locked);
+        if self.accounts.len() > known_accounts {
+            self.metrics.accounts_created += 1;
+        }
+        match result {
+            Ok(()) => {
+                self.post(tx);
+                self.publish(tx, was_locked);
+                self.watch(tx);
+                if let (
+                    Type::Deposit | Type::Withdrawal | Type::Bonus | Type::Custom(_),
+                    Some(seen),
+                ) = (tx.r#type(), &mut self.seen_ids)
+                {
+                    seen.insert(tx.id());
+                }
+            }
+            Err(reason) => {
+                log::warn!("rejected {:?} of tx {}: {:?}", tx.r#type(), tx.id(), reason);
+                self.rejections.push(Rejection::new(tx, reason));
             }
+        }
 
-            self.tx_ledger.append(tx)
+        self.tx_ledger.append(tx);
+        result?;
+        let account = self.accounts.get_or_create(tx.account_id());
+        Ok(Applied {
+            r#type: tx.r#type(),
+            client: tx.account_id(),
+            tx: tx.id(),
+            available: account.available_balance(),
+            held: account.held_balance(),
+            total: account.total_balance(),
+            locked: account.locked(),
+        })
+    }
+}
+
+/// Upper bound on the transactions applied between two yield points.
+#[cfg(feature = "async")]
+pub const STREAM_BATCH_SIZE: usize = 1024;
+
+#[cfg(feature = "async")]
+impl<M: Money> Engine<'_, M> {
+    /// Applies transactions as they become available, in batches of whatever
+    /// the stream has ready (at most `STREAM_BATCH_SIZE`), yielding to the
+    /// executor after every batch so a long stream does not starve other tasks.
+    pub async fn process_stream<S>(&mut self, stream: S)
+    where
+        S: futures::Stream<Item = Transaction<M>>,
+    {
+        let Ok(()) = self
+            .process_stream_with(stream, STREAM_BATCH_SIZE, |_| {
+                std::future::ready(Ok::<(), std::convert::Infallible>(()))
+            })
+            .await;
+    }
+
+    /// Like `process_stream`, with batches of at most `capacity` handed to
+    /// `persist` once applied. The stream is not read any further until
+    /// `persist` is done with a batch, so a slow persistence layer slows
+    /// down ingestion instead of letting transactions pile up in memory.
+    /// Stops at the first batch `persist` fails on.
+    pub async fn process_stream_with<S, F, Fut, E>(
+        &mut self,
+        stream: S,
+        capacity: usize,
+        mut persist: F,
+    ) -> Result<(), E>
+    where
+        S: futures::Stream<Item = Transaction<M>>,
+        F: FnMut(Vec<Transaction<M>>) -> Fut,
+        Fut: std::future::Future<Output = Result<(), E>>,
+    {
+        use futures::StreamExt;
+
+        let mut batches = std::pin::pin!(stream.ready_chunks(capacity.max(1)));
+        while let Some(batch) = batches.next().await {
+            self.process(&batch);
+            persist(batch).await?;
+            yield_now().await;
         }
+        Ok(())
     }
 }
 
+/// Returns `Pending` exactly once, rescheduling the task immediately.
+#[cfg(feature = "async")]
+async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return std::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    })
+    .await
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -112,6 +1354,69 @@ mod test {
         assert_eq!(account.available_balance(), 5.0);
     }
 
+    #[test]
+    fn apply_one_by_one() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let applied = engine.apply(Transaction::new(1, Type::Deposit, 1, 5.0));
+        assert_eq!(
+            applied,
+            Ok(Applied {
+                r#type: Type::Deposit,
+                client: 1,
+                tx: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+            })
+        );
+        let applied = engine
+            .apply(Transaction::new(1, Type::Dispute, 1, 0.0))
+            .unwrap();
+        assert_eq!((applied.available, applied.held), (0.0, 5.0));
+        assert_eq!(
+            engine.apply(Transaction::new(2, Type::Withdrawal, 1, 1.0)),
+            Err(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(engine.rejections().len(), 1);
+    }
+
+    #[test]
+    fn subscribe() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let events = engine.subscribe();
+        let dropped = engine.subscribe();
+        drop(dropped);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Withdrawal, 1, 9.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+        ]);
+        let balance = |tx, available, held, total| AccountEvent::BalanceChanged {
+            client: 1,
+            tx,
+            available,
+            held,
+            total,
+        };
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                balance(1, 5.0, 0.0, 5.0),
+                balance(1, 0.0, 5.0, 5.0),
+                AccountEvent::DisputeOpened { client: 1, tx: 1 },
+                balance(1, 0.0, 0.0, 0.0),
+                AccountEvent::Locked { client: 1, tx: 1 },
+            ]
+        );
+        assert_eq!(engine.subscribers.len(), 1);
+    }
+
     #[test]
     fn withdrawal() {
         let mut acc_repo = AccountsRepository::new();
@@ -215,6 +1520,174 @@ mod test {
         assert!(account.locked());
     }
 
+    #[test]
+    fn auto_unlock() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_auto_unlock();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Chargeback, 1, 0.0),
+            Transaction::new(3, Type::Deposit, 1, 1.0),
+            Transaction::new(1, Type::Resolve, 1, 0.0).with_timestamp(Some(60)),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Chargeback, 1, 0.0),
+            Transaction::new(4, Type::Deposit, 1, 1.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine
+            .rejections()
+            .iter()
+            .map(|rejection| rejection.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::LockedAccount,
+                RejectReason::AlreadyDisputed,
+                RejectReason::NotDisputed,
+            ]
+        );
+        let account = acc_repo.get(1).unwrap();
+        assert!(!account.locked());
+        assert_eq!(account.total_balance(), 6.0);
+        assert_eq!(account.lock_history().count(), 2);
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_auto_unlock()
+            .process(&[
+                Transaction::new(1, Type::Deposit, 1, 5.0),
+                Transaction::new(2, Type::Deposit, 1, 3.0),
+                Transaction::new(1, Type::Dispute, 1, 0.0),
+                Transaction::new(2, Type::Dispute, 1, 0.0),
+                Transaction::new(1, Type::Chargeback, 1, 0.0),
+            ]);
+        assert!(acc_repo.get(1).unwrap().locked());
+    }
+
+    #[test]
+    fn locked_disputes() {
+        let run = |policy: LockedDisputes| {
+            let mut acc_repo = AccountsRepository::new();
+            let mut tx_ledger = TransactionLedger::new();
+            let mut engine =
+                Engine::new(&mut tx_ledger, &mut acc_repo).with_locked_disputes(policy);
+            engine.process(&[
+                Transaction::new(1, Type::Deposit, 1, 5.0),
+                Transaction::new(2, Type::Deposit, 1, 3.0),
+                Transaction::new(3, Type::Deposit, 1, 2.0),
+                Transaction::new(1, Type::Dispute, 1, 0.0),
+                Transaction::new(1, Type::Chargeback, 1, 0.0),
+                Transaction::new(2, Type::Dispute, 1, 0.0),
+                Transaction::new(2, Type::Chargeback, 1, 0.0),
+                Transaction::new(3, Type::Dispute, 1, 0.0),
+                Transaction::new(3, Type::Resolve, 1, 0.0),
+            ]);
+            let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+            let account = acc_repo.get(1).unwrap();
+            assert!(account.locked());
+            (reasons, account.available_balance(), account.held_balance())
+        };
+        use RejectReason::{LockedAccount, NotDisputed};
+        assert_eq!(
+            run(LockedDisputes::Deny),
+            (
+                vec![LockedAccount, NotDisputed, LockedAccount, NotDisputed],
+                5.0,
+                0.0
+            )
+        );
+        assert_eq!(
+            run(LockedDisputes::AllowChargebackOnly),
+            (vec![LockedAccount], 0.0, 2.0)
+        );
+        assert_eq!(run(LockedDisputes::Allow), (vec![], 2.0, 0.0));
+        assert_eq!(
+            "allow-chargeback-only".parse(),
+            Ok(LockedDisputes::AllowChargebackOnly)
+        );
+    }
+
+    #[test]
+    fn withdrawals_held_for_approval() {
+        use crate::journal::Book;
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_withdrawal_approval(50.0)
+            .with_fees("withdrawal=1".parse().unwrap())
+            .with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 200.0),
+            Transaction::new(2, Type::Withdrawal, 1, 80.0),
+            Transaction::new(3, Type::Withdrawal, 1, 60.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+        ]);
+        let account = engine.accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 60.0);
+        assert_eq!(account.held_balance(), 140.0);
+        assert_eq!(account.total_balance(), 200.0);
+        drop(account);
+        assert!(engine.tx_ledger.get(2).unwrap().is_pending());
+
+        engine.process(&[
+            Transaction::new(2, Type::Approve, 1, 0.0),
+            Transaction::new(3, Type::Reject, 1, 0.0),
+            Transaction::new(2, Type::Approve, 1, 0.0),
+            Transaction::new(3, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Reject, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::NotDisputable,
+                RejectReason::NotPending,
+                RejectReason::NotDisputable,
+                RejectReason::NotPending,
+            ]
+        );
+        let account = engine.accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 119.0);
+        assert_eq!(account.held_balance(), 0.0);
+        assert_eq!(account.total_balance(), 119.0);
+        drop(account);
+        assert_eq!(
+            engine.tx_ledger.get(3).unwrap().approval(),
+            Some(Approval::Rejected)
+        );
+        let balances = engine.journal().unwrap().balances();
+        assert_eq!(balances[&Book::ClientHeld(1)], 0.0);
+        assert_eq!(balances[&Book::CashIn], -120.0);
+        assert_eq!(balances[&Book::FeeIncome], 1.0);
+    }
+
+    #[test]
+    fn approval_needs_funds_for_the_fee() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_withdrawal_approval(50.0)
+            .with_fees("withdrawal=1".parse().unwrap());
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 60.0),
+            Transaction::new(2, Type::Withdrawal, 1, 60.0),
+            Transaction::new(2, Type::Approve, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::InsufficientFunds]);
+        let account = engine.accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 0.0);
+        assert_eq!(account.held_balance(), 60.0);
+        drop(account);
+        assert!(engine.tx_ledger.get(2).unwrap().is_pending());
+    }
+
     #[test]
     fn dispute_with_different_account_id() {
         let mut acc_repo = AccountsRepository::new();
@@ -346,6 +1819,450 @@ mod test {
         assert!(tx.is_dispute());
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn process_stream() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let transactions = (1..=3000).map(|id| Transaction::new(id, Type::Deposit, 1, 1.0));
+        futures::executor::block_on(engine.process_stream(futures::stream::iter(transactions)));
+        let account = acc_repo.get_or_create(1);
+        assert_eq!(account.available_balance(), 3000.0);
+        assert_eq!(tx_ledger.len(), 3000);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn process_stream_with_backpressure() {
+        use futures::StreamExt;
+        use std::cell::Cell;
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let read = Cell::new(0);
+        let transactions = futures::stream::iter(1..=100)
+            .map(|id| Transaction::new(id, Type::Deposit, 1, 1.0))
+            .inspect(|_| read.set(read.get() + 1));
+        let mut persisted = 0;
+        let result =
+            futures::executor::block_on(engine.process_stream_with(transactions, 16, |batch| {
+                assert!(batch.len() <= 16);
+                assert_eq!(read.get(), persisted + batch.len());
+                persisted += batch.len();
+                std::future::ready(if persisted < 64 {
+                    Ok(())
+                } else {
+                    Err(persisted)
+                })
+            }));
+        assert_eq!(result, Err(64));
+        assert_eq!(read.get(), 64);
+        assert_eq!(acc_repo.get_or_create(1).available_balance(), 64.0);
+    }
+
+    #[test]
+    fn rejections() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Withdrawal, 1, 6.0),
+            Transaction::new(3, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Dispute, 2, 0.0),
+            Transaction::new(1, Type::Resolve, 1, 0.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+            Transaction::new(4, Type::Deposit, 1, 1.0),
+        ];
+        engine.process(&transactions);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::DuplicateTx,
+                RejectReason::InsufficientFunds,
+                RejectReason::TxNotFound,
+                RejectReason::ClientMismatch,
+                RejectReason::NotDisputed,
+                RejectReason::AlreadyDisputed,
+                RejectReason::LockedAccount,
+            ]
+        );
+        assert_eq!(engine.rejections()[0].tx, 1);
+        assert_eq!(engine.rejections()[2].amount, Some(0.0));
+    }
+
+    #[test]
+    fn metrics() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 2, 1.0),
+            Transaction::new(3, Type::Withdrawal, 2, 6.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+        ];
+        engine.process(&transactions);
+        let metrics = engine.metrics();
+        assert_eq!(metrics.deposit.applied, 2);
+        assert_eq!(metrics.deposit.ignored, 1);
+        assert_eq!(metrics.withdrawal.rejected, 1);
+        assert_eq!(metrics.dispute.applied, 1);
+        assert_eq!(metrics.chargeback.applied, 1);
+        assert_eq!(metrics.accounts_created, 2);
+        assert_eq!(metrics.accounts_locked, 1);
+
+        // A chargeback on an account locked already does not lock it again.
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine =
+            Engine::new(&mut tx_ledger, &mut acc_repo).with_locked_disputes(LockedDisputes::Allow);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+            Transaction::new(2, Type::Chargeback, 1, 0.0),
+        ]);
+        assert_eq!(engine.metrics().chargeback.applied, 2);
+        assert_eq!(engine.metrics().accounts_locked, 1);
+    }
+
+    #[test]
+    fn journal_mirrors_accounts() {
+        use crate::journal::Book;
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_journal();
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(3, Type::Withdrawal, 1, 9.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+        ];
+        engine.process(&transactions);
+        let journal = engine.journal().unwrap();
+        assert_eq!(journal.entries().len(), 3);
+        let balances = journal.balances();
+        assert_eq!(balances.values().sum::<f64>(), 0.0);
+
+        let account = acc_repo.get_or_create(1);
+        assert_eq!(
+            balances[&Book::ClientAvailable(1)],
+            account.available_balance()
+        );
+        assert_eq!(balances[&Book::ClientHeld(1)], account.held_balance());
+    }
+
+    #[test]
+    fn conflicting_duplicates() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Deposit, 1, 6.0),
+            Transaction::new(1, Type::Deposit, 2, 5.0),
+            Transaction::new(1, Type::Withdrawal, 1, 5.0),
+        ];
+        engine.process(&transactions);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::DuplicateTx,
+                RejectReason::ConflictingTx,
+                RejectReason::ConflictingTx,
+                RejectReason::ConflictingTx,
+            ]
+        );
+        assert_eq!(acc_repo.get(1).unwrap().available_balance(), 5.0);
+    }
+
+    #[test]
+    fn currency_exponents() {
+        let jpy = Some("JPY".parse().unwrap());
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 1234.5).with_currency(jpy),
+            Transaction::new(2, Type::Withdrawal, 1, 1234.6).with_currency(jpy),
+            Transaction::new(3, Type::Deposit, 1, 10.0).with_currency(Some("USD".parse().unwrap())),
+            Transaction::new(4, Type::Deposit, 2, 1.00049),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::CurrencyMismatch]);
+        let balances = engine.journal().unwrap().balances();
+        assert_eq!(balances[&crate::journal::Book::ClientAvailable(1)], 0.0);
+        let account = acc_repo.get(1).unwrap();
+        assert_eq!(account.currency(), jpy);
+        assert_eq!(account.total_balance(), 0.0);
+        assert_eq!(acc_repo.get(2).unwrap().total_balance(), 1.0005);
+
+        let mut acc_repo = AccountsRepository::new().with_currency(Some("BHD".parse().unwrap()));
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut acc_repo).process(&[Transaction::new(
+            1,
+            Type::Deposit,
+            2,
+            1.00049,
+        )]);
+        assert_eq!(acc_repo.get(2).unwrap().total_balance(), 1.0);
+    }
+
+    #[test]
+    fn direct_chargebacks() {
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(2, Type::Chargeback, 1, 0.0),
+        ];
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        engine.process(&transactions);
+        assert_eq!(engine.rejections()[0].reason, RejectReason::NotDisputed);
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_direct_chargebacks()
+            .with_journal();
+        engine.process(&transactions);
+        assert!(engine.rejections().is_empty());
+        assert_eq!(engine.metrics().chargeback.applied, 1);
+        let types: Vec<Type> = engine
+            .journal()
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|entry| entry.r#type)
+            .collect();
+        assert_eq!(
+            types,
+            [
+                Type::Deposit,
+                Type::Deposit,
+                Type::Dispute,
+                Type::Chargeback
+            ]
+        );
+        let account = acc_repo.get(1).unwrap();
+        assert!(account.locked());
+        assert_eq!(account.total_balance(), 5.0);
+        assert_eq!(account.held_balance(), 0.0);
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::with_window(2);
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let transactions = [
+            Transaction::new(1, Type::Deposit, 1, 1.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Deposit, 1, 2.0),
+            Transaction::new(3, Type::Deposit, 1, 3.0),
+            Transaction::new(4, Type::Deposit, 1, 4.0),
+            // 2 fell out of the window: no longer a duplicate.
+            Transaction::new(2, Type::Deposit, 1, 2.0),
+            Transaction::new(2, Type::Deposit, 1, 2.0),
+            // 1 stayed because it is disputed.
+            Transaction::new(1, Type::Resolve, 1, 0.0),
+        ];
+        engine.process(&transactions);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::DuplicateTx]);
+        assert_eq!(acc_repo.get(1).unwrap().available_balance(), 12.0);
+        assert_eq!(tx_ledger.len(), 2);
+    }
+
+    #[test]
+    fn dedup_window_keeps_settled_disputes_oldest() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::with_window(2);
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 1.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(2, Type::Deposit, 1, 2.0),
+            Transaction::new(3, Type::Deposit, 1, 3.0),
+            Transaction::new(1, Type::Resolve, 1, 0.0),
+            // 1 is the oldest again once resolved, so it goes before 3.
+            Transaction::new(4, Type::Deposit, 1, 4.0),
+            Transaction::new(3, Type::Deposit, 1, 3.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::DuplicateTx]);
+        let mut ids: Vec<u32> = tx_ledger.iter().map(|tx| tx.id()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [3, 4]);
+    }
+
+    #[test]
+    fn dedup_max_age() {
+        const DAY: u64 = 86_400;
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        tx_ledger.set_max_age(Some(std::time::Duration::from_secs(2 * DAY)));
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo);
+        let at = |tx: Transaction, day: u64| tx.with_timestamp(Some(day * DAY));
+        engine.process(&[
+            at(Transaction::new(1, Type::Deposit, 1, 1.0), 0),
+            at(Transaction::new(2, Type::Deposit, 1, 2.0), 1),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            // Counts as added on day 1, the newest timestamp then.
+            Transaction::new(3, Type::Deposit, 1, 3.0),
+            at(Transaction::new(4, Type::Deposit, 1, 4.0), 3),
+            // 1 is more than two days older than 4; 2 is disputed.
+            at(Transaction::new(1, Type::Deposit, 1, 1.0), 3),
+            at(Transaction::new(3, Type::Deposit, 1, 3.0), 3),
+            Transaction::new(2, Type::Resolve, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::DuplicateTx]);
+        assert_eq!(acc_repo.get(1).unwrap().available_balance(), 11.0);
+    }
+
+    #[test]
+    fn seen_ids() {
+        let path = std::env::temp_dir().join(format!("fg-engine-seen-{}", std::process::id()));
+        let mut seen = SeenIds::open(&path).unwrap();
+        seen.insert(1);
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_seen_ids(&mut seen);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(3, Type::Withdrawal, 1, 9.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [RejectReason::DuplicateTx, RejectReason::InsufficientFunds]
+        );
+        assert_eq!(acc_repo.get(1).unwrap().held_balance(), 3.0);
+        assert!(seen.contains(2) && !seen.contains(3));
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn rules() {
+        let rules = Rules::parse(
+            "deny overdraw: type == \"withdrawal\" and amount > available / 2\n\
+             flag big: amount >= 100",
+        )
+        .unwrap();
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_rules(&rules);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 100.0),
+            Transaction::new(2, Type::Withdrawal, 1, 60.0),
+            Transaction::new(3, Type::Withdrawal, 1, 40.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::RuleDenied]);
+        let hits: Vec<(u32, &str, Verdict)> = engine
+            .rule_hits()
+            .iter()
+            .map(|hit| (hit.tx, hit.rule.as_str(), hit.verdict))
+            .collect();
+        assert_eq!(
+            hits,
+            [(1, "big", Verdict::Flag), (2, "overdraw", Verdict::Deny)]
+        );
+        assert_eq!(engine.metrics().withdrawal.rejected, 1);
+        assert_eq!(acc_repo.get(1).unwrap().available_balance(), 60.0);
+    }
+
+    #[test]
+    fn bonus() {
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_journal();
+        let transactions = [
+            Transaction::new(1, Type::Bonus, 1, 2.0),
+            Transaction::new(2, Type::Deposit, 1, 3.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Bonus, 1, 2.0),
+        ];
+        engine.process(&transactions);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [RejectReason::NotDisputable, RejectReason::DuplicateTx]
+        );
+        assert_eq!(engine.metrics().bonus.applied, 1);
+        let balances = engine.journal().unwrap().balances();
+        assert_eq!(balances[&crate::journal::Book::Promotions], -2.0);
+        let account = acc_repo.get(1).unwrap();
+        assert_eq!(account.available_balance(), 5.0);
+        assert_eq!(account.held_balance(), 0.0);
+
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo).with_disputable_bonuses();
+        engine.process(&transactions[..3]);
+        assert!(engine.rejections().is_empty());
+        assert_eq!(acc_repo.get(1).unwrap().held_balance(), 2.0);
+    }
+
+    #[test]
+    fn custom_types() {
+        let cashout = Type::custom("cashout").unwrap();
+        let refund = Type::custom("refund_fee").unwrap();
+        assert_eq!(Type::custom("cashout"), Ok(cashout));
+        assert!("cashout".parse::<Type>().is_err());
+        assert!(Type::custom("deposit").is_err());
+        let handler =
+            |tx: &Transaction, account: &mut Account| Ok(account.withdrawal(tx.amount() * 2.0)?);
+        let mut acc_repo = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        assert!(Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_handler(Type::Deposit, &handler)
+            .is_err());
+        let mut engine = Engine::new(&mut tx_ledger, &mut acc_repo)
+            .with_handler(cashout, &handler)
+            .unwrap();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, cashout, 1, 3.0),
+            Transaction::new(3, cashout, 1, 3.0),
+            Transaction::new(2, cashout, 1, 3.0),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+            Transaction::new(4, refund, 1, 1.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::InsufficientFunds,
+                RejectReason::DuplicateTx,
+                RejectReason::NotDisputable,
+                RejectReason::UnhandledType,
+            ]
+        );
+        let counts = engine.metrics().for_type(cashout);
+        assert_eq!((counts.applied, counts.rejected, counts.ignored), (1, 1, 1));
+        assert_eq!(engine.metrics().for_type(refund).rejected, 1);
+        assert_eq!(acc_repo.get(1).unwrap().available_balance(), 4.0);
+    }
+
     #[test]
     fn chargeback_the_same_tx_with_diff_acc() {
         let mut acc_repo = AccountsRepository::new();
@@ -0,0 +1,230 @@
+//! Automatic expiration of disputes left open for too long.
+//!
+//! With a `HoldExpiry` the engine notes, by its `Clock`, when each dispute is
+//! raised. Before every transaction it closes the disputes older than the
+//! policy allows with a synthetic resolve or chargeback, which is applied
+//! and counted like one from the input and recorded as an `Expiration`.
+//! Its journal postings name it an `auto_resolve` or `auto_chargeback`
+//! with the reason, and subscribers receive an event of that name before
+//! the new balances, so that the audit trail explains the movement.
+
+use crate::transaction::Type;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExpiryAction {
+    /// Release the held funds back to the client.
+    #[default]
+    Resolve,
+    /// Charge the held funds back and lock the account.
+    Chargeback,
+}
+
+impl ExpiryAction {
+    pub fn r#type(self) -> Type {
+        match self {
+            ExpiryAction::Resolve => Type::Resolve,
+            ExpiryAction::Chargeback => Type::Chargeback,
+        }
+    }
+
+    pub fn event(self) -> AutoEvent {
+        match self {
+            ExpiryAction::Resolve => AutoEvent::AutoResolve,
+            ExpiryAction::Chargeback => AutoEvent::AutoChargeback,
+        }
+    }
+}
+
+/// A resolve or chargeback the engine made on its own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AutoEvent {
+    AutoResolve,
+    AutoChargeback,
+}
+
+/// Why the engine made an `AutoEvent`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AutoReason {
+    /// The dispute stayed open longer than the `HoldExpiry` allows.
+    HoldExpired,
+}
+
+impl FromStr for ExpiryAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(ExpiryAction::Resolve),
+            "chargeback" => Ok(ExpiryAction::Chargeback),
+            _ => Err(format!(
+                "invalid expiry action: {} (expected resolve or chargeback)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExpiryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExpiryAction::Resolve => "resolve",
+            ExpiryAction::Chargeback => "chargeback",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HoldExpiry {
+    /// How long a dispute may stay open.
+    pub after: Duration,
+    pub action: ExpiryAction,
+}
+
+impl HoldExpiry {
+    pub fn days(days: u64, action: ExpiryAction) -> HoldExpiry {
+        HoldExpiry {
+            after: Duration::from_secs(days * 86_400),
+            action,
+        }
+    }
+}
+
+/// Audit record of a dispute the engine closed on its own.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Expiration {
+    pub tx: u32,
+    pub client: u16,
+    /// `resolve` or `chargeback`.
+    pub action: Type,
+    pub amount: f64,
+    /// Seconds since the Unix epoch the dispute was raised at.
+    pub disputed_at: u64,
+    /// Seconds since the Unix epoch the dispute was closed at.
+    pub expired_at: u64,
+}
+
+pub(crate) fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::clock::ManualClock;
+    use crate::engine::{AccountEvent, Engine};
+    use crate::transaction::{Transaction, TransactionLedger};
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn run(action: ExpiryAction) -> (AccountsRepository, Vec<Expiration>, usize) {
+        let clock = ManualClock::default();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_clock(&clock)
+            .with_hold_expiry(HoldExpiry::days(30, action))
+            .with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+        ]);
+        clock.advance(10 * DAY);
+        engine.process(&[Transaction::new(2, Type::Dispute, 1, 0.0)]);
+        clock.advance(20 * DAY);
+        engine.process(&[Transaction::new(3, Type::Deposit, 1, 1.0)]);
+        let expirations = engine.expirations().to_vec();
+        let entries = engine.journal().unwrap().entries().len();
+        (accounts, expirations, entries)
+    }
+
+    #[test]
+    fn expired_disputes_resolve() {
+        let (accounts, expirations, entries) = run(ExpiryAction::Resolve);
+        assert_eq!(entries, 6);
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 11.0);
+        assert_eq!(account.held_balance(), 5.0);
+        assert_eq!(
+            expirations,
+            [Expiration {
+                tx: 1,
+                client: 1,
+                action: Type::Resolve,
+                amount: 10.0,
+                disputed_at: 0,
+                expired_at: 30 * 86_400,
+            }]
+        );
+    }
+
+    #[test]
+    fn expired_disputes_are_named() {
+        let clock = ManualClock::default();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_clock(&clock)
+            .with_hold_expiry(HoldExpiry::days(1, ExpiryAction::Resolve))
+            .with_journal();
+        let events = engine.subscribe();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+        ]);
+        clock.advance(2 * DAY);
+        engine.expire_holds();
+
+        let entries = engine.journal().unwrap().entries();
+        let named: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.event, entry.reason))
+            .collect();
+        assert_eq!(
+            named,
+            [
+                (None, None),
+                (None, None),
+                (Some(AutoEvent::AutoResolve), Some(AutoReason::HoldExpired))
+            ]
+        );
+        drop(engine);
+        let events: Vec<AccountEvent> = events.try_iter().collect();
+        assert_eq!(
+            events[events.len() - 2],
+            AccountEvent::AutoResolve {
+                client: 1,
+                tx: 1,
+                reason: AutoReason::HoldExpired
+            }
+        );
+    }
+
+    #[test]
+    fn expired_disputes_charge_back() {
+        let (accounts, expirations, _) = run(ExpiryAction::Chargeback);
+        let account = accounts.get(1).unwrap();
+        assert!(account.locked());
+        assert_eq!(account.total_balance(), 5.0);
+        assert_eq!(expirations.len(), 1);
+        assert_eq!(expirations[0].action, Type::Chargeback);
+    }
+
+    #[test]
+    fn parse_action() {
+        assert_eq!("chargeback".parse(), Ok(ExpiryAction::Chargeback));
+        assert!("refund".parse::<ExpiryAction>().is_err());
+    }
+}
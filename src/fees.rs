@@ -0,0 +1,180 @@
+//! Fees charged on deposits and withdrawals, and passed on for chargebacks.
+//!
+//! A fee is taken from the client's available funds together with the
+//! transaction that triggers it and journaled as a separate posting to the
+//! fee income book. A withdrawal whose amount plus fee is not covered is
+//! rejected as a whole.
+//!
+//! A chargeback fee passes on what the card network charges for each
+//! chargeback. It is posted to a book of its own, so that it is not mixed
+//! with fee income, and taken even though the chargeback locks the account
+//! and even if that leaves the available funds negative: the client owes it
+//! either way. It is also recorded in the ledger as a transaction of type
+//! `fee` under the id of the chargeback, see `TransactionLedger::fees`, so
+//! that checkpoints keep it for audits.
+
+use crate::transaction::Type;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    /// Flat fee on every withdrawal.
+    pub withdrawal: f64,
+    /// Percentage of the part of a deposit or withdrawal above `above`.
+    pub percent: f64,
+    pub above: f64,
+    /// Flat fee on every chargeback.
+    pub chargeback: f64,
+    /// Percentage of the charged-back amount.
+    pub chargeback_percent: f64,
+}
+
+impl FeeSchedule {
+    /// The fee `r#type` of `amount` triggers, before rounding. The amount of
+    /// a chargeback is that of the transaction it charges back.
+    pub fn fee(&self, r#type: Type, amount: f64) -> f64 {
+        let flat = match r#type {
+            Type::Withdrawal => self.withdrawal,
+            Type::Chargeback => self.chargeback,
+            _ => 0.0,
+        };
+        let percentage = match r#type {
+            Type::Deposit | Type::Withdrawal if amount > self.above => {
+                (amount - self.above) * self.percent / 100.0
+            }
+            Type::Chargeback => amount * self.chargeback_percent / 100.0,
+            _ => 0.0,
+        };
+        flat + percentage
+    }
+}
+
+impl FromStr for FeeSchedule {
+    type Err = String;
+
+    /// Comma-separated `withdrawal=FLAT`, `percent=P`, `above=THRESHOLD`,
+    /// `chargeback=FLAT` and `chargeback_percent=P`, e.g.
+    /// `withdrawal=0.5,percent=1.5,above=1000`; left-out parts are zero.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut schedule = FeeSchedule::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let invalid = || format!("invalid fee schedule entry: {}", part);
+            let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+            let value: f64 = value.trim().parse().map_err(|_| invalid())?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(invalid());
+            }
+            match key.trim() {
+                "withdrawal" => schedule.withdrawal = value,
+                "percent" if value <= 100.0 => schedule.percent = value,
+                "above" => schedule.above = value,
+                "chargeback" => schedule.chargeback = value,
+                "chargeback_percent" if value <= 100.0 => schedule.chargeback_percent = value,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for FeeSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "withdrawal={},percent={},above={},chargeback={},chargeback_percent={}",
+            self.withdrawal, self.percent, self.above, self.chargeback, self.chargeback_percent
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{Engine, RejectReason};
+    use crate::journal::Book;
+    use crate::transaction::{Transaction, TransactionLedger};
+
+    #[test]
+    fn schedule() {
+        let schedule: FeeSchedule = "withdrawal=0.5, percent=2,above=100".parse().unwrap();
+        assert_eq!(schedule.fee(Type::Withdrawal, 50.0), 0.5);
+        assert_eq!(schedule.fee(Type::Withdrawal, 150.0), 1.5);
+        assert_eq!(schedule.fee(Type::Deposit, 150.0), 1.0);
+        assert_eq!(schedule.fee(Type::Dispute, 150.0), 0.0);
+        assert_eq!(schedule.fee(Type::Chargeback, 150.0), 0.0);
+        assert_eq!(schedule.to_string().parse(), Ok(schedule));
+        let schedule: FeeSchedule = "chargeback=15,chargeback_percent=1".parse().unwrap();
+        assert_eq!(schedule.fee(Type::Chargeback, 200.0), 17.0);
+        assert_eq!(schedule.to_string().parse(), Ok(schedule));
+        assert!("percent=150".parse::<FeeSchedule>().is_err());
+        assert!("withdrawal=-1".parse::<FeeSchedule>().is_err());
+        assert!("monthly=5".parse::<FeeSchedule>().is_err());
+    }
+
+    #[test]
+    fn fees_are_charged_and_journaled() {
+        let schedule = "withdrawal=1,percent=10,above=100".parse().unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_fees(schedule)
+            .with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 200.0),
+            Transaction::new(2, Type::Withdrawal, 1, 10.0),
+            // The 179 available cover 178.5 but not its fee of 8.85 on top.
+            Transaction::new(3, Type::Withdrawal, 1, 178.5),
+        ]);
+        assert_eq!(
+            engine.rejections()[0].reason,
+            RejectReason::InsufficientFunds
+        );
+
+        let journal = engine.journal().unwrap();
+        let fees: Vec<f64> = journal
+            .entries()
+            .iter()
+            .filter(|entry| entry.is_fee())
+            .map(|entry| entry.amount)
+            .collect();
+        assert_eq!(fees, [10.0, 1.0]);
+        let balances = journal.balances();
+        assert_eq!(balances[&Book::FeeIncome], 11.0);
+        assert_eq!(balances[&Book::ClientAvailable(1)], 179.0);
+        assert_eq!(accounts.get(1).unwrap().available_balance(), 179.0);
+    }
+
+    #[test]
+    fn chargeback_fees_are_passed_on() {
+        let schedule = "chargeback=15,chargeback_percent=10".parse().unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_fees(schedule)
+            .with_journal();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 100.0),
+            Transaction::new(2, Type::Deposit, 1, 10.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+        ]);
+        assert!(engine.rejections().is_empty());
+
+        let balances = engine.journal().unwrap().balances();
+        assert_eq!(balances[&Book::ChargebackFees], 25.0);
+        assert!(!balances.contains_key(&Book::FeeIncome));
+        assert_eq!(balances[&Book::ClientAvailable(1)], -15.0);
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), -15.0);
+        assert_eq!(account.total_balance(), -15.0);
+        assert!(account.locked());
+        let fees: Vec<(u32, Type, f64)> = tx_ledger
+            .fees()
+            .iter()
+            .map(|fee| (fee.id(), fee.r#type(), fee.amount()))
+            .collect();
+        assert_eq!(fees, [(1, Type::Fee, 25.0)]);
+    }
+}
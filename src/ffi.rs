@@ -0,0 +1,262 @@
+//! C API for embedding the engine, see `include/fictional_guide.h`.
+//!
+//! Every handle returned by `engine_new` must be released with `engine_free`
+//! and every string returned by the engine with `engine_string_free`.
+//!
+//! A rejected transaction makes `engine_submit_tx` return `ENGINE_REJECTED`,
+//! and `engine_last_rejection` says why.
+//!
+//! No panic crosses into the caller, where it would abort the whole
+//! process: each function catches it and fails instead, with
+//! `ENGINE_PANIC` or null. The engine it happened in may be left halfway
+//! through a transaction and should only be freed.
+
+use crate::engine::RejectReason;
+use crate::state::State;
+use crate::transaction::{Transaction, Type};
+use std::ffi::{c_char, c_int, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+pub const ENGINE_OK: c_int = 0;
+pub const ENGINE_NOT_FOUND: c_int = 1;
+pub const ENGINE_REJECTED: c_int = 2;
+pub const ENGINE_INVALID_ARGUMENT: c_int = -1;
+pub const ENGINE_PANIC: c_int = -2;
+
+/// What the handles of `engine_new` point to.
+#[derive(Default)]
+pub struct Engine {
+    state: State,
+    /// Why the last transaction submitted was rejected, if it was.
+    rejection: Option<CString>,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EngineAccount {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+fn tx_type(kind: u32) -> Option<Type> {
+    match kind {
+        0 => Some(Type::Deposit),
+        1 => Some(Type::Withdrawal),
+        2 => Some(Type::Dispute),
+        3 => Some(Type::Resolve),
+        4 => Some(Type::Chargeback),
+        5 => Some(Type::Bonus),
+        6 => Some(Type::Approve),
+        7 => Some(Type::Reject),
+        _ => None,
+    }
+}
+
+/// The name of `reason`, as in the CSV reports.
+fn reason_name(reason: RejectReason) -> Option<CString> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(reason).ok()?;
+    let mut name = writer.into_inner().ok()?;
+    name.pop();
+    CString::new(name).ok()
+}
+
+/// Runs `body`, returning `on_panic` if it panics.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+#[no_mangle]
+pub extern "C" fn engine_new() -> *mut Engine {
+    guard(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// # Safety
+///
+/// `engine` must be null or a handle returned by `engine_new` that has not
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn engine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        guard((), || drop(Box::from_raw(engine)));
+    }
+}
+
+/// Applies a single transaction. `kind` is one of the `ENGINE_TX_*` constants;
+/// `amount` is ignored for disputes, resolves and chargebacks. Returns
+/// `ENGINE_REJECTED` if the engine rejected it, see `engine_last_rejection`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle returned by `engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn engine_submit_tx(
+    engine: *mut Engine,
+    kind: u32,
+    client: u16,
+    tx: u32,
+    amount: f64,
+) -> c_int {
+    let (Some(engine), Some(kind)) = (engine.as_mut(), tx_type(kind)) else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    guard(ENGINE_PANIC, || {
+        let result = engine
+            .state
+            .try_apply(&Transaction::new(tx, kind, client, amount));
+        engine.rejection = result.err().and_then(reason_name);
+        match engine.rejection {
+            Some(_) => ENGINE_REJECTED,
+            None => ENGINE_OK,
+        }
+    })
+}
+
+/// Why the last transaction given to `engine_submit_tx` was rejected, such
+/// as `insufficient_funds`, or null if it was not. The string belongs to
+/// the engine and stays valid until the next call to `engine_submit_tx`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle returned by `engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn engine_last_rejection(engine: *const Engine) -> *const c_char {
+    let Some(engine) = engine.as_ref() else {
+        return std::ptr::null();
+    };
+    engine
+        .rejection
+        .as_ref()
+        .map_or(std::ptr::null(), |reason| reason.as_ptr())
+}
+
+/// Copies the balances of `client` into `out`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle returned by `engine_new`, and `out`
+/// must be null or point to writable memory for one `EngineAccount`.
+#[no_mangle]
+pub unsafe extern "C" fn engine_get_account(
+    engine: *const Engine,
+    client: u16,
+    out: *mut EngineAccount,
+) -> c_int {
+    let (Some(engine), Some(out)) = (engine.as_ref(), out.as_mut()) else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    guard(ENGINE_PANIC, || match engine.state.accounts.get(client) {
+        Some(account) => {
+            *out = EngineAccount {
+                client: account.client_id(),
+                available: account.available_balance(),
+                held: account.held_balance(),
+                total: account.total_balance(),
+                locked: account.locked(),
+            };
+            ENGINE_OK
+        }
+        None => ENGINE_NOT_FOUND,
+    })
+}
+
+/// Renders all accounts in the same CSV format as the CLI. Returns null on
+/// failure; otherwise the string must be released with `engine_string_free`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle returned by `engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn engine_snapshot_csv(engine: *const Engine) -> *mut c_char {
+    let Some(engine) = engine.as_ref() else {
+        return std::ptr::null_mut();
+    };
+    guard(std::ptr::null_mut(), || {
+        let mut csv = Vec::new();
+        if engine.state.accounts.write_csv(&mut csv).is_err() {
+            return std::ptr::null_mut();
+        }
+        match CString::new(csv) {
+            Ok(csv) => csv.into_raw(),
+            Err(..) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+///
+/// `s` must be null or a string returned by this library that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn engine_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        guard((), || drop(CString::from_raw(s)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn roundtrip() {
+        unsafe {
+            let engine = engine_new();
+            assert_eq!(engine_submit_tx(engine, 0, 1, 1, 5.0), ENGINE_OK);
+            assert_eq!(engine_submit_tx(engine, 1, 1, 2, 2.0), ENGINE_OK);
+            assert!(engine_last_rejection(engine).is_null());
+            assert_eq!(engine_submit_tx(engine, 1, 1, 3, 9.0), ENGINE_REJECTED);
+            assert_eq!(
+                CStr::from_ptr(engine_last_rejection(engine)).to_str(),
+                Ok("insufficient_funds")
+            );
+            assert_eq!(
+                engine_submit_tx(engine, 9, 1, 4, 2.0),
+                ENGINE_INVALID_ARGUMENT
+            );
+
+            let mut account = EngineAccount::default();
+            assert_eq!(engine_get_account(engine, 1, &mut account), ENGINE_OK);
+            assert_eq!(account.available, 3.0);
+            assert_eq!(account.total, 3.0);
+            assert_eq!(
+                engine_get_account(engine, 2, &mut account),
+                ENGINE_NOT_FOUND
+            );
+
+            let csv = engine_snapshot_csv(engine);
+            assert_eq!(
+                CStr::from_ptr(csv).to_str().unwrap(),
+                "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n"
+            );
+            engine_string_free(csv);
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn null_handles() {
+        unsafe {
+            assert_eq!(
+                engine_submit_tx(std::ptr::null_mut(), 0, 1, 1, 1.0),
+                ENGINE_INVALID_ARGUMENT
+            );
+            assert!(engine_snapshot_csv(std::ptr::null()).is_null());
+            assert!(engine_last_rejection(std::ptr::null()).is_null());
+            engine_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        assert_eq!(guard(ENGINE_PANIC, || panic!("bug")), ENGINE_PANIC);
+        assert_eq!(guard(ENGINE_PANIC, || ENGINE_OK), ENGINE_OK);
+        assert!(guard(std::ptr::null_mut::<c_char>(), || panic!("bug")).is_null());
+    }
+}
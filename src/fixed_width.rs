@@ -0,0 +1,203 @@
+//! Fixed-width text records, as sent by mainframe systems.
+//!
+//! A `Layout` names the columns of a record and where each one sits on the
+//! line. Every line is cut into those columns and read exactly like a CSV
+//! row with the same header, so the optional columns, skipped rows and
+//! warnings all work as described in `parser`.
+
+use crate::parser::{Parser, Tenants, Warning};
+use crate::transaction::Transaction;
+use csv::{Position, StringRecord};
+use std::io::BufRead;
+use std::{fmt, str::FromStr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    /// Character the field starts at, counting from 0.
+    pub offset: usize,
+    /// Characters the field spans.
+    pub width: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    fields: Vec<Field>,
+}
+
+impl Layout {
+    /// Fails when a field is empty, named twice, or one of `type`, `client`
+    /// and `tx` is missing.
+    pub fn new(fields: Vec<Field>) -> Result<Layout, String> {
+        for (index, field) in fields.iter().enumerate() {
+            if field.width == 0 {
+                return Err(format!("field {} has no width", field.name));
+            }
+            if fields[..index].iter().any(|other| other.name == field.name) {
+                return Err(format!("field {} is defined twice", field.name));
+            }
+        }
+        for name in ["type", "client", "tx"] {
+            if !fields.iter().any(|field| field.name == name) {
+                return Err(format!("layout has no {} field", name));
+            }
+        }
+        Ok(Layout { fields })
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Reads every non-blank line as a record, skipping malformed ones.
+    pub fn parse<R: BufRead>(&self, reader: R) -> Result<Vec<Transaction>, csv::Error> {
+        Ok(self
+            .parse_tenants_with_warnings(reader)?
+            .0
+            .into_values()
+            .flatten()
+            .collect())
+    }
+
+    /// Like `Parser::parse_tenants_with_warnings`, where line 1 is the first
+    /// record since there is no header.
+    pub fn parse_tenants_with_warnings<R: BufRead>(
+        &self,
+        reader: R,
+    ) -> Result<(Tenants, Vec<Warning>), csv::Error> {
+        let headers: StringRecord = self.fields.iter().map(|field| &field.name).collect();
+        let mut error = None;
+        let records = reader
+            .lines()
+            .enumerate()
+            .map_while(|(index, line)| match line {
+                Ok(line) => Some((index as u64 + 1, line)),
+                Err(err) => {
+                    error = Some(err);
+                    None
+                }
+            })
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| Ok(self.split(number, &line)));
+        let parsed = Parser::read_tenants(&headers, records);
+        match error {
+            Some(err) => Err(err.into()),
+            None => Ok(parsed),
+        }
+    }
+
+    /// Cuts `line` into the layout's fields, trimmed. Fields past the end of
+    /// a short line are empty.
+    fn split(&self, number: u64, line: &str) -> StringRecord {
+        let chars: Vec<char> = line.chars().collect();
+        let mut record: StringRecord = self
+            .fields
+            .iter()
+            .map(|field| {
+                let start = field.offset.min(chars.len());
+                let end = (field.offset + field.width).min(chars.len());
+                chars[start..end]
+                    .iter()
+                    .collect::<String>()
+                    .trim()
+                    .to_string()
+            })
+            .collect();
+        let mut position = Position::new();
+        position.set_line(number);
+        record.set_position(Some(position));
+        record
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    /// Comma-separated `name=OFFSET:WIDTH` fields, e.g.
+    /// `type=0:10,client=10:5,tx=15:8,amount=23:12`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let invalid = || format!("invalid layout field: {}", part);
+            let (name, span) = part.split_once('=').ok_or_else(invalid)?;
+            let (offset, width) = span.split_once(':').ok_or_else(invalid)?;
+            fields.push(Field {
+                name: name.trim().to_string(),
+                offset: offset.trim().parse().map_err(|_| invalid())?,
+                width: width.trim().parse().map_err(|_| invalid())?,
+            });
+        }
+        Layout::new(fields)
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, field) in self.fields.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}:{}", field.name, field.offset, field.width)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::WarningKind;
+    use crate::transaction::Type;
+
+    #[test]
+    fn layout_spec() {
+        let layout: Layout = "type=0:10, client=10:5,tx=15:8,amount=23:12"
+            .parse()
+            .unwrap();
+        assert_eq!(layout.fields().len(), 4);
+        assert_eq!(
+            layout.to_string(),
+            "type=0:10,client=10:5,tx=15:8,amount=23:12"
+        );
+        assert!("type=0:10,client=10:5".parse::<Layout>().is_err());
+        assert!("type=0:10,client=10:5,tx=15:0".parse::<Layout>().is_err());
+        assert!("type=0:10,client=10:5,tx=15:8,tx=23:8"
+            .parse::<Layout>()
+            .is_err());
+        assert!("type=0,client=10:5,tx=15:8".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn records() {
+        let layout: Layout = "type=0:10,client=10:5,tx=15:8,amount=23:13"
+            .parse()
+            .unwrap();
+        let input = "deposit       1       1         5.5\n\
+                     \n\
+                     withdrawal    1       2         1.25\n\
+                     dispute       1       1\n\
+                     refund        1       3         1.0\n";
+        let (tenants, warnings) = layout
+            .parse_tenants_with_warnings(input.as_bytes())
+            .unwrap();
+        let txs = &tenants[&None];
+        let parsed: Vec<(Type, u16, u32, Option<f64>)> = txs
+            .iter()
+            .map(|tx| (tx.r#type(), tx.account_id(), tx.id(), tx.optional_amount()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 1, 1, Some(5.5)),
+                (Type::Withdrawal, 1, 2, Some(1.25)),
+                (Type::Dispute, 1, 1, None),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 5);
+        assert_eq!(
+            warnings[0].kind,
+            WarningKind::UnknownType("refund".to_string())
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Exchange rates for deposits and withdrawals in another currency than
+//! their client's account.
+//!
+//! A rates file has one rate per line after a header, as CSV:
+//!
+//! ```text
+//! from,to,rate,valid_from,valid_to
+//! EUR,USD,1.08,2024-06-01,2024-07-01
+//! EUR,USD,1.07,2024-07-01,
+//! ```
+//!
+//! An amount of `from` times `rate` is the amount of `to`; the rate is used
+//! the other way round too, divided by, when no line names the currencies
+//! in that order. A rate is valid from `valid_from` up to, but not
+//! including, `valid_to`, or with no end when that is empty. Both are Unix
+//! seconds, dates or UTC dates and times as in transaction timestamps.
+//! Where validities overlap, the rate valid from later wins.
+//!
+//! With `Engine::with_rates` a deposit or withdrawal naming another currency
+//! than its client's account is converted at the rate valid at its
+//! timestamp, or at the engine's clock when it has none, and booked and kept
+//! in the ledger in the account's currency. One no rate is valid for is
+//! rejected as `no_fx_rate`.
+
+use crate::currency::Currency;
+use crate::timestamp;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rate {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: f64,
+    /// Unix seconds the rate is valid from.
+    pub valid_from: u64,
+    /// Unix seconds the rate is valid until, exclusive.
+    pub valid_to: Option<u64>,
+}
+
+impl Rate {
+    fn valid_at(&self, at: u64) -> bool {
+        self.valid_from <= at && self.valid_to.is_none_or(|to| at < to)
+    }
+}
+
+/// A rates file that could not be read.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// 1-based line of the rate.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Default)]
+pub struct Rates {
+    rates: Vec<Rate>,
+}
+
+impl Rates {
+    pub fn parse(source: &str) -> Result<Rates, ParseError> {
+        let mut rates = Vec::new();
+        for (index, line) in source.lines().enumerate().skip(1) {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| ParseError {
+                line: index + 1,
+                message,
+            };
+            let [from, to, rate, valid_from, valid_to] =
+                line.split(',').map(str::trim).collect::<Vec<_>>()[..]
+            else {
+                return Err(error(
+                    "expected from,to,rate,valid_from,valid_to".to_string(),
+                ));
+            };
+            let time =
+                |s: &str| timestamp::parse(s).ok_or_else(|| error(format!("invalid time: {}", s)));
+            let rate = Rate {
+                from: from.parse().map_err(error)?,
+                to: to.parse().map_err(error)?,
+                rate: rate
+                    .parse()
+                    .ok()
+                    .filter(|rate: &f64| rate.is_finite() && *rate > 0.0)
+                    .ok_or_else(|| error(format!("invalid rate: {}", rate)))?,
+                valid_from: time(valid_from)?,
+                valid_to: match valid_to {
+                    "" => None,
+                    valid_to => Some(time(valid_to)?),
+                },
+            };
+            if rate.valid_to.is_some_and(|to| to <= rate.valid_from) {
+                return Err(error("valid_to is not after valid_from".to_string()));
+            }
+            rates.push(rate);
+        }
+        Ok(Rates { rates })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// What one unit of `from` is worth in `to` at `at`, Unix seconds.
+    pub fn rate(&self, from: Currency, to: Currency, at: u64) -> Option<f64> {
+        let latest = |from: Currency, to: Currency| {
+            self.rates
+                .iter()
+                .filter(|rate| rate.from == from && rate.to == to && rate.valid_at(at))
+                .max_by_key(|rate| rate.valid_from)
+                .map(|rate| rate.rate)
+        };
+        latest(from, to).or_else(|| latest(to, from).map(|rate| 1.0 / rate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{Engine, RejectReason};
+    use crate::transaction::{Transaction, TransactionLedger, Type};
+
+    const RATES: &str = "from,to,rate,valid_from,valid_to\n\
+        EUR,USD,1.25,2024-06-01,2024-07-01\n\
+        EUR,USD,1.5,2024-07-01,\n";
+
+    #[test]
+    fn dated_rates() {
+        let rates = Rates::parse(RATES).unwrap();
+        let (eur, usd) = ("EUR".parse().unwrap(), "USD".parse().unwrap());
+        let june = timestamp::parse("2024-06-15").unwrap();
+        let july = timestamp::parse("2024-07-01").unwrap();
+        assert_eq!(rates.rate(eur, usd, june), Some(1.25));
+        assert_eq!(rates.rate(eur, usd, july), Some(1.5));
+        assert_eq!(rates.rate(usd, eur, june), Some(0.8));
+        assert_eq!(rates.rate(eur, usd, june - 31 * 86_400), None);
+        assert_eq!(
+            Rates::parse("from,to,rate,valid_from,valid_to\nEUR,USD,-1,2024-06-01,\n")
+                .unwrap_err()
+                .to_string(),
+            "line 2: invalid rate: -1"
+        );
+    }
+
+    #[test]
+    fn engine_converts() {
+        let rates = Rates::parse(RATES).unwrap();
+        let (eur, usd) = (Some("EUR".parse().unwrap()), Some("USD".parse().unwrap()));
+        let june = timestamp::parse("2024-06-15");
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts).with_rates(&rates);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0).with_currency(usd),
+            Transaction::new(2, Type::Deposit, 1, 4.0)
+                .with_currency(eur)
+                .with_timestamp(june),
+            Transaction::new(3, Type::Withdrawal, 1, 1.0)
+                .with_currency(eur)
+                .with_timestamp(timestamp::parse("2024-05-01")),
+            Transaction::new(2, Type::Dispute, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::NoFxRate]);
+        assert_eq!(engine.tx_ledger.get(2).unwrap().amount(), 5.0);
+        assert_eq!(engine.tx_ledger.get(2).unwrap().currency(), usd);
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.total_balance(), 15.0);
+        assert_eq!(account.held_balance(), 5.0);
+    }
+}
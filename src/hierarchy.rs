@@ -0,0 +1,207 @@
+//! Parent/child account hierarchies.
+//!
+//! Corporate programs issue many sub-cards, each processed as an account of
+//! its own. A `Hierarchy` maps those child clients to their parent so that
+//! balances can be reported per parent with `rollup`; it never changes how
+//! transactions are applied.
+
+use crate::account::AccountsRepository;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead};
+
+#[derive(Clone, Debug, Default)]
+pub struct Hierarchy {
+    parents: HashMap<u16, u16>,
+}
+
+impl Hierarchy {
+    /// From `(child, parent)` pairs. Fails when a child has two parents or
+    /// the pairs form a cycle.
+    pub fn new(pairs: impl IntoIterator<Item = (u16, u16)>) -> Result<Hierarchy, String> {
+        let mut parents = HashMap::new();
+        for (child, parent) in pairs {
+            match parents.insert(child, parent) {
+                Some(previous) if previous != parent => {
+                    return Err(format!(
+                        "client {} has two parents: {} and {}",
+                        child, previous, parent
+                    ))
+                }
+                _ => {}
+            }
+        }
+        let hierarchy = Hierarchy { parents };
+        for &child in hierarchy.parents.keys() {
+            if hierarchy.ancestors(child).any(|ancestor| ancestor == child) {
+                return Err(format!("client {} is its own ancestor", child));
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    /// Reads `child,parent` pairs, one per line. Blank lines and lines
+    /// starting with `#` are skipped, as is a leading `child,parent` header.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Hierarchy> {
+        let mut pairs = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (index == 0 && line == "child,parent") {
+                continue;
+            }
+            let pair = line.split_once(',').and_then(|(child, parent)| {
+                Some((child.trim().parse().ok()?, parent.trim().parse().ok()?))
+            });
+            let pair = pair.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "invalid child,parent pair on line {}: {:?}",
+                        index + 1,
+                        line
+                    ),
+                )
+            })?;
+            pairs.push(pair);
+        }
+        Hierarchy::new(pairs).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// The direct parent of `client`, if it has one.
+    pub fn parent(&self, client: u16) -> Option<u16> {
+        self.parents.get(&client).copied()
+    }
+
+    /// The top of `client`'s hierarchy: `client` itself when it has no
+    /// parent.
+    pub fn root(&self, client: u16) -> u16 {
+        self.ancestors(client).last().unwrap_or(client)
+    }
+
+    /// Parent, grandparent and so on, stopping early on a cycle.
+    fn ancestors(&self, client: u16) -> impl Iterator<Item = u16> + '_ {
+        let mut next = self.parent(client);
+        let mut remaining = self.parents.len();
+        std::iter::from_fn(move || {
+            let current = next.filter(|_| remaining > 0)?;
+            remaining -= 1;
+            next = self.parent(current);
+            Some(current)
+        })
+    }
+}
+
+/// The summed balances of a top-level client and every account below it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Rollup {
+    pub parent: u16,
+    /// Accounts rolled up, the parent's own included when it has one.
+    pub accounts: u64,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    /// How many of those accounts are locked.
+    pub locked: u64,
+}
+
+/// One rollup per top-level client, ordered by client. Clients outside the
+/// hierarchy are their own top level.
+pub fn rollup(accounts: &AccountsRepository, hierarchy: &Hierarchy) -> Vec<Rollup> {
+    let mut rollups: BTreeMap<u16, Rollup> = BTreeMap::new();
+    for account in accounts.sorted() {
+        let parent = hierarchy.root(account.client_id());
+        let rollup = rollups.entry(parent).or_insert(Rollup {
+            parent,
+            accounts: 0,
+            available: 0.0,
+            held: 0.0,
+            total: 0.0,
+            locked: 0,
+        });
+        rollup.accounts += 1;
+        rollup.available += account.available_balance();
+        rollup.held += account.held_balance();
+        rollup.total += account.total_balance();
+        rollup.locked += u64::from(account.locked());
+    }
+    let rounding = accounts.rounding();
+    rollups
+        .into_values()
+        .map(|rollup| Rollup {
+            available: rounding.round(rollup.available),
+            held: rounding.round(rollup.held),
+            total: rounding.round(rollup.total),
+            ..rollup
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::{Transaction, TransactionLedger, Type};
+
+    #[test]
+    fn read_hierarchy() {
+        let input = "child,parent\n# cards of acme\n11,10\n12, 10\n\n121,12\n";
+        let hierarchy = Hierarchy::read(input.as_bytes()).unwrap();
+        assert_eq!(hierarchy.len(), 3);
+        assert_eq!(hierarchy.parent(121), Some(12));
+        assert_eq!(hierarchy.root(121), 10);
+        assert_eq!(hierarchy.root(10), 10);
+        assert_eq!(hierarchy.root(7), 7);
+
+        assert!(Hierarchy::read("11,10\n11,9\n".as_bytes()).is_err());
+        assert!(Hierarchy::read("1,2\n2,1\n".as_bytes()).is_err());
+        assert!(Hierarchy::read("11;10\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rollup_per_parent() {
+        let hierarchy = Hierarchy::new([(11, 10), (12, 10), (121, 12)]).unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        Engine::new(&mut tx_ledger, &mut accounts).process(&[
+            Transaction::new(1, Type::Deposit, 11, 5.0),
+            Transaction::new(2, Type::Deposit, 121, 2.5),
+            Transaction::new(3, Type::Deposit, 7, 1.0),
+            Transaction::new(2, Type::Dispute, 121, 0.0),
+            Transaction::new(2, Type::Chargeback, 121, 0.0),
+            Transaction::new(4, Type::Deposit, 12, 0.1),
+            Transaction::new(5, Type::Deposit, 12, 0.2),
+        ]);
+        assert_eq!(
+            rollup(&accounts, &hierarchy),
+            [
+                Rollup {
+                    parent: 7,
+                    accounts: 1,
+                    available: 1.0,
+                    held: 0.0,
+                    total: 1.0,
+                    locked: 0,
+                },
+                Rollup {
+                    parent: 10,
+                    accounts: 3,
+                    available: 5.3,
+                    held: 0.0,
+                    total: 5.3,
+                    locked: 1,
+                },
+            ]
+        );
+    }
+}
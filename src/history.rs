@@ -0,0 +1,103 @@
+//! Point-in-time account state.
+//!
+//! Processing is deterministic, so the processed transactions double as an
+//! event log: the state as of any point is rebuilt by replaying the log up to
+//! that point into fresh accounts.
+
+use crate::account::AccountsRepository;
+use crate::engine::Engine;
+use crate::rounding::Rounding;
+use crate::transaction::{Transaction, TransactionLedger, Type};
+use std::str::FromStr;
+
+/// A point in the log. Transactions carry no timestamp, so points are
+/// positions: either a number of processed rows, or the first row with a
+/// given tx id (and type, to tell e.g. a dispute from its deposit).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AsOf {
+    Index(usize),
+    Tx { id: u32, r#type: Option<Type> },
+}
+
+impl AsOf {
+    /// Number of rows of `log` to replay, or `None` if the point is not in it.
+    pub fn position(self, log: &[Transaction]) -> Option<usize> {
+        match self {
+            AsOf::Index(index) => (index <= log.len()).then_some(index),
+            AsOf::Tx { id, r#type } => log
+                .iter()
+                .position(|tx| tx.id() == id && r#type.is_none_or(|t| t == tx.r#type()))
+                .map(|found| found + 1),
+        }
+    }
+}
+
+impl FromStr for AsOf {
+    type Err = String;
+
+    /// `N` for the first N rows, `tx:ID` or `tx:ID:TYPE` for everything up to
+    /// and including that transaction.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid point in time: {} (expected N, tx:ID or tx:ID:TYPE)",
+                s
+            )
+        };
+        match s.split(':').collect::<Vec<_>>()[..] {
+            [index] => index.parse().map(AsOf::Index).map_err(|_| invalid()),
+            ["tx", id] => Ok(AsOf::Tx {
+                id: id.parse().map_err(|_| invalid())?,
+                r#type: None,
+            }),
+            ["tx", id, r#type] => Ok(AsOf::Tx {
+                id: id.parse().map_err(|_| invalid())?,
+                r#type: Some(r#type.parse()?),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Accounts as they were right after the point `as_of` of `log`.
+pub fn replay(log: &[Transaction], as_of: AsOf, rounding: Rounding) -> Option<AccountsRepository> {
+    let position = as_of.position(log)?;
+    let mut accounts = AccountsRepository::with_rounding(rounding);
+    let mut tx_ledger = TransactionLedger::new();
+    Engine::new(&mut tx_ledger, &mut accounts).process(&log[..position]);
+    Some(accounts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn log() -> Vec<Transaction> {
+        vec![
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Chargeback, 1, 0.0),
+        ]
+    }
+
+    #[test]
+    fn state_when_disputed() {
+        let as_of = "tx:1:dispute".parse().unwrap();
+        let accounts = replay(&log(), as_of, Rounding::HalfUp).unwrap();
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 5.0);
+        assert_eq!(account.held_balance(), 10.0);
+        assert!(!account.locked());
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(AsOf::Index(0).position(&log()), Some(0));
+        assert_eq!(AsOf::Index(5).position(&log()), None);
+        assert_eq!("tx:1".parse::<AsOf>().unwrap().position(&log()), Some(1));
+        assert_eq!("tx:3".parse::<AsOf>().unwrap().position(&log()), None);
+        assert!("tx:1:refund".parse::<AsOf>().is_err());
+        assert!("yesterday".parse::<AsOf>().is_err());
+    }
+}
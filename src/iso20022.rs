@@ -0,0 +1,281 @@
+//! ISO 20022 XML messages as input.
+//!
+//! Entries of bank-to-customer statements, reports and notifications
+//! (camt.053, camt.052, camt.054) become deposits for credits and
+//! withdrawals for debits of the statement's account. Credit transfers of a
+//! payment initiation (pain.001) become withdrawals from the debtor account.
+//!
+//! Accounts are matched to clients with `Iso20022::with_clients`. The tx id
+//! of an entry comes from its reference, see `bank::tx_id`, so that later
+//! disputes can refer to it.
+
+use crate::bank::{label, tx_id, AccountMap};
+use crate::parser::{Warning, WarningKind};
+use crate::transaction::{Transaction, Type};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::io::Read;
+
+#[derive(Clone, Debug, Default)]
+pub struct Iso20022 {
+    clients: AccountMap,
+}
+
+/// What is known of the entry or credit transfer being read.
+#[derive(Default)]
+struct Entry {
+    line: u64,
+    amount: Option<String>,
+    /// `CRDT` or `DBIT`; credit transfers are always debits.
+    indicator: Option<String>,
+    reference: Option<String>,
+    end_to_end: Option<String>,
+    date: Option<String>,
+    debtor: Option<String>,
+    creditor: Option<String>,
+}
+
+impl Iso20022 {
+    pub fn new() -> Iso20022 {
+        Iso20022::default()
+    }
+
+    /// Matches the accounts of the message to clients.
+    pub fn with_clients(mut self, clients: AccountMap) -> Iso20022 {
+        self.clients = clients;
+        self
+    }
+
+    /// Reads a whole message, skipping entries that cannot be turned into a
+    /// transaction with a warning on the line they start on.
+    pub fn parse_with_warnings<R: Read>(
+        &self,
+        mut reader: R,
+    ) -> Result<(Vec<Transaction>, Vec<Warning>), quick_xml::Error> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        // Events start after the whitespace that precedes them.
+        let line_at = |offset: u64| {
+            let bytes = input.as_bytes();
+            let mut end = (offset as usize).min(bytes.len());
+            while end < bytes.len() && bytes[end].is_ascii_whitespace() {
+                end += 1;
+            }
+            bytes[..end].iter().filter(|&&byte| byte == b'\n').count() as u64 + 1
+        };
+
+        let mut xml = Reader::from_str(&input);
+        xml.config_mut().trim_text(true);
+        let mut path: Vec<String> = Vec::new();
+        let mut account = None;
+        let mut batch_date = None;
+        let mut entry: Option<Entry> = None;
+        let mut transactions = Vec::new();
+        let mut warnings = Vec::new();
+        loop {
+            let offset = xml.buffer_position();
+            match xml.read_event()? {
+                Event::Start(start) => {
+                    let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                    match name.as_str() {
+                        "Stmt" | "Rpt" | "Ntfctn" | "PmtInf" => {
+                            account = None;
+                            batch_date = None;
+                        }
+                        "Ntry" | "CdtTrfTxInf" => {
+                            entry = Some(Entry {
+                                line: line_at(offset),
+                                ..Entry::default()
+                            })
+                        }
+                        _ => {}
+                    }
+                    path.push(name);
+                }
+                Event::End(_) => {
+                    let name = path.pop().unwrap_or_default();
+                    if name != "Ntry" && name != "CdtTrfTxInf" {
+                        continue;
+                    }
+                    let mut ended = entry.take().unwrap_or_default();
+                    if name == "CdtTrfTxInf" {
+                        ended.indicator = Some("DBIT".to_string());
+                        ended.date = ended.date.or_else(|| batch_date.clone());
+                    }
+                    let line = ended.line;
+                    match self.transaction(ended, account.as_deref()) {
+                        Ok(tx) => transactions.push(tx),
+                        Err(message) => warnings.push(Warning {
+                            line,
+                            column: None,
+                            kind: WarningKind::Invalid(message),
+                        }),
+                    }
+                }
+                Event::Text(text) => {
+                    let value = Some(text.decode().map_err(quick_xml::Error::from)?.into_owned());
+                    let is = |suffix: &[&str]| {
+                        path.len() >= suffix.len()
+                            && path[path.len() - suffix.len()..]
+                                .iter()
+                                .zip(suffix)
+                                .all(|(name, part)| name == part)
+                    };
+                    let Some(entry) = &mut entry else {
+                        if is(&["Acct", "Id", "IBAN"])
+                            || is(&["Acct", "Id", "Othr", "Id"])
+                            || is(&["DbtrAcct", "Id", "IBAN"])
+                            || is(&["DbtrAcct", "Id", "Othr", "Id"])
+                        {
+                            account = value;
+                        } else if is(&["ReqdExctnDt"]) || is(&["ReqdExctnDt", "Dt"]) {
+                            batch_date = value;
+                        }
+                        continue;
+                    };
+                    if is(&["Ntry", "Amt"]) || is(&["CdtTrfTxInf", "Amt", "InstdAmt"]) {
+                        entry.amount = value;
+                    } else if is(&["Ntry", "CdtDbtInd"]) {
+                        entry.indicator = value;
+                    } else if is(&["Ntry", "NtryRef"])
+                        || (entry.reference.is_none()
+                            && (is(&["Ntry", "AcctSvcrRef"]) || is(&["PmtId", "InstrId"])))
+                    {
+                        entry.reference = value;
+                    } else if is(&["Refs", "EndToEndId"]) || is(&["PmtId", "EndToEndId"]) {
+                        entry.end_to_end = value.filter(|id| id != "NOTPROVIDED");
+                    } else if is(&["BookgDt", "Dt"]) || is(&["BookgDt", "DtTm"]) {
+                        entry.date = value;
+                    } else if is(&["Dbtr", "Nm"]) || is(&["Dbtr", "Pty", "Nm"]) {
+                        entry.debtor = value;
+                    } else if is(&["Cdtr", "Nm"]) || is(&["Cdtr", "Pty", "Nm"]) {
+                        entry.creditor = value;
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok((transactions, warnings))
+    }
+
+    fn transaction(&self, entry: Entry, account: Option<&str>) -> Result<Transaction, String> {
+        let account = account.ok_or("entry outside of an account")?;
+        let client = self.clients.client(account)?;
+        let (r#type, counterparty) = match entry.indicator.as_deref() {
+            Some("CRDT") => (Type::Deposit, entry.debtor),
+            Some("DBIT") => (Type::Withdrawal, entry.creditor),
+            other => return Err(format!("invalid credit/debit indicator {:?}", other)),
+        };
+        let amount = entry
+            .amount
+            .as_deref()
+            .and_then(|amount| amount.parse::<f64>().ok())
+            .filter(|amount| amount.is_finite() && *amount >= 0.0)
+            .ok_or_else(|| format!("invalid amount {:?}", entry.amount))?;
+        let reference = entry
+            .reference
+            .or(entry.end_to_end)
+            .ok_or("entry without a reference")?;
+        Ok(Transaction::new(tx_id(&reference), r#type, client, amount)
+            .with_merchant(counterparty.as_deref().and_then(label))
+            .with_timestamp(entry.date.as_deref().and_then(crate::timestamp::parse)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CAMT_053: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+      <Ntry>
+        <NtryRef>1001</NtryRef>
+        <Amt Ccy="EUR">250.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <BookgDt><Dt>2024-06-01</Dt></BookgDt>
+        <NtryDtls><TxDtls>
+          <RltdPties><Dbtr><Nm>Acme Corporation Europe, GmbH</Nm></Dbtr></RltdPties>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">20.5</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <NtryDtls><TxDtls><Refs><EndToEndId>INV-2024-17</EndToEndId></Refs></TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <NtryRef>1003</NtryRef>
+        <Amt Ccy="EUR">1.00</Amt>
+      </Ntry>
+    </Stmt>
+    <Stmt>
+      <Acct><Id><Othr><Id>7</Id></Othr></Id></Acct>
+      <Ntry>
+        <AcctSvcrRef>1004</AcctSvcrRef>
+        <Amt Ccy="EUR">3.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    const PAIN_001: &str = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <PmtInf>
+      <ReqdExctnDt><Dt>2024-06-03</Dt></ReqdExctnDt>
+      <DbtrAcct><Id><Othr><Id>5</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>2001</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">12.5</InstdAmt></Amt>
+        <Cdtr><Nm>Globex</Nm></Cdtr>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+
+    #[test]
+    fn statement_entries() {
+        let clients = AccountMap::read("DE89370400440532013000,1\n".as_bytes()).unwrap();
+        let (txs, warnings) = Iso20022::new()
+            .with_clients(clients)
+            .parse_with_warnings(CAMT_053.as_bytes())
+            .unwrap();
+        let parsed: Vec<(u32, Type, u16, f64)> = txs
+            .iter()
+            .map(|tx| (tx.id(), tx.r#type(), tx.account_id(), tx.amount()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (1001, Type::Deposit, 1, 250.0),
+                (tx_id("INV-2024-17"), Type::Withdrawal, 1, 20.5),
+                (1004, Type::Deposit, 7, 3.0),
+            ]
+        );
+        assert_eq!(
+            txs[0].merchant().unwrap().as_str(),
+            "Acme Corporation Europe"
+        );
+        assert_eq!(txs[0].timestamp(), Some(1_717_200_000));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 20);
+    }
+
+    #[test]
+    fn payment_initiation() {
+        let (txs, warnings) = Iso20022::new()
+            .parse_with_warnings(PAIN_001.as_bytes())
+            .unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(txs.len(), 1);
+        assert_eq!(
+            (txs[0].id(), txs[0].r#type(), txs[0].account_id()),
+            (2001, Type::Withdrawal, 5)
+        );
+        assert_eq!(txs[0].merchant().unwrap().as_str(), "Globex");
+        assert_eq!(txs[0].timestamp(), Some(1_717_372_800));
+    }
+}
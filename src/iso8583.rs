@@ -0,0 +1,162 @@
+//! A simplified ISO 8583 card message feed as input.
+//!
+//! Every line is one message: its four-digit MTI, then `|`-separated
+//! `element=value` data elements, e.g.
+//! `1240|2=4111111111111111|3=000000|4=000000001250|37=412345678901|43=ACME STORE`.
+//! The message class, the second digit of the MTI, decides what it becomes:
+//!
+//! - `1` authorizations move no money and are skipped;
+//! - `2` presentments become a withdrawal for purchases and cash (processing
+//!   code `00`, `01`) and a deposit for refunds and credits (`20`, `21`);
+//! - `4` chargebacks become a dispute and a chargeback of the presentment
+//!   with the same retrieval reference number.
+//!
+//! The client is the one the card number (element 2) maps to. The tx id is
+//! `bank::tx_id` of the retrieval reference number (element 37). Amounts
+//! (element 4) are in minor units with two decimals. The card acceptor name
+//! (element 43) is the counterparty and the merchant category code (element
+//! 18) the category.
+
+use crate::bank::{label, tx_id, AccountMap};
+use crate::parser::{Warning, WarningKind};
+use crate::transaction::{Transaction, Type};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+#[derive(Clone, Debug, Default)]
+pub struct Iso8583 {
+    clients: AccountMap,
+}
+
+impl Iso8583 {
+    pub fn new() -> Iso8583 {
+        Iso8583::default()
+    }
+
+    /// Matches card numbers to clients.
+    pub fn with_clients(mut self, clients: AccountMap) -> Iso8583 {
+        self.clients = clients;
+        self
+    }
+
+    /// Reads every message of the feed, skipping blank lines and lines
+    /// starting with `#`, and messages that cannot be translated with a
+    /// warning for each.
+    pub fn parse_with_warnings<R: BufRead>(
+        &self,
+        reader: R,
+    ) -> io::Result<(Vec<Transaction>, Vec<Warning>)> {
+        let mut transactions = Vec::new();
+        let mut warnings = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match self.translate(line) {
+                Ok(translated) => transactions.extend(translated),
+                Err(message) => warnings.push(Warning {
+                    line: index as u64 + 1,
+                    column: None,
+                    kind: WarningKind::Invalid(message),
+                }),
+            }
+        }
+        Ok((transactions, warnings))
+    }
+
+    /// The transactions a single message stands for.
+    fn translate(&self, message: &str) -> Result<Vec<Transaction>, String> {
+        let mut parts = message.split('|');
+        let mti = parts.next().unwrap_or_default().trim();
+        if mti.len() != 4 || !mti.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(format!("invalid message type indicator {:?}", mti));
+        }
+        let mut elements = HashMap::new();
+        for part in parts {
+            let (element, value) = part
+                .split_once('=')
+                .and_then(|(element, value)| Some((element.trim().parse::<u8>().ok()?, value)))
+                .ok_or_else(|| format!("invalid data element {:?}", part))?;
+            elements.insert(element, value.trim());
+        }
+        let element = |number: u8| {
+            elements
+                .get(&number)
+                .copied()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("message {} without element {}", mti, number))
+        };
+
+        let class = &mti[1..2];
+        if class == "1" {
+            return Ok(Vec::new());
+        }
+        let client = self.clients.client(element(2)?)?;
+        let id = tx_id(element(37)?);
+        match class {
+            "2" => {
+                let r#type = match element(3)?.get(..2) {
+                    Some("00" | "01") => Type::Withdrawal,
+                    Some("20" | "21") => Type::Deposit,
+                    _ => return Err(format!("unsupported processing code {}", element(3)?)),
+                };
+                let minor: u64 = element(4)?
+                    .parse()
+                    .map_err(|_| format!("invalid amount {}", element(4).unwrap_or_default()))?;
+                let merchant = element(43).ok().and_then(label);
+                let category = element(18).ok().and_then(label);
+                Ok(vec![Transaction::new(
+                    id,
+                    r#type,
+                    client,
+                    minor as f64 / 100.0,
+                )
+                .with_merchant(merchant)
+                .with_category(category)])
+            }
+            "4" => Ok(vec![
+                Transaction::new(id, Type::Dispute, client, 0.0),
+                Transaction::new(id, Type::Chargeback, client, 0.0),
+            ]),
+            _ => Err(format!("unsupported message class of {}", mti)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn card_messages() {
+        let input = "# card feed\n\
+                     1100|2=4111111111111111|4=000000005000|37=000000000001\n\
+                     1240|2=4111111111111111|3=200000|4=000000010000|37=000000000002\n\
+                     1240|2=4111111111111111|3=000000|4=000000001250|18=5812|37=REF3|43=ACME STORE BERLIN\n\
+                     1442|2=4111111111111111|37=REF3\n\
+                     1240|2=4111111111111111|3=300000|4=000000000100|37=000000000004\n\
+                     1240|4=000000000100\n";
+        let clients = AccountMap::read("4111111111111111,8\n".as_bytes()).unwrap();
+        let (txs, warnings) = Iso8583::new()
+            .with_clients(clients)
+            .parse_with_warnings(input.as_bytes())
+            .unwrap();
+        let types: Vec<(Type, u32)> = txs.iter().map(|tx| (tx.r#type(), tx.id())).collect();
+        assert_eq!(
+            types,
+            [
+                (Type::Deposit, 2),
+                (Type::Withdrawal, tx_id("REF3")),
+                (Type::Dispute, tx_id("REF3")),
+                (Type::Chargeback, tx_id("REF3")),
+            ]
+        );
+        assert_eq!(txs[1].amount(), 12.5);
+        assert_eq!(txs[1].merchant().unwrap().as_str(), "ACME STORE BERLIN");
+        assert_eq!(txs[1].category().unwrap().as_str(), "5812");
+        let lines: Vec<u64> = warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, [6, 7]);
+    }
+}
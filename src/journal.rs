@@ -0,0 +1,200 @@
+//! Double-entry view of the engine's movements.
+//!
+//! Every applied operation is recorded as one entry debiting one internal
+//! book and crediting another by the same amount, so the balances of all
+//! books always sum to zero. A client's available and held books mirror the
+//! account's own balances; cash-in is the counterpart of deposits and
+//! withdrawals, chargeback loss collects charged-back funds, fee income
+//! the fees taken from clients, chargeback fees those passed on to them
+//! for chargebacks and promotions the bonuses granted to them.
+
+use crate::expiry::{AutoEvent, AutoReason};
+use crate::money::Money;
+use crate::transaction::{Label, Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Book {
+    ClientAvailable(u16),
+    ClientHeld(u16),
+    ChargebackLoss,
+    CashIn,
+    FeeIncome,
+    ChargebackFees,
+    Promotions,
+}
+
+impl fmt::Display for Book {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Book::ClientAvailable(client) => write!(f, "client:{}:available", client),
+            Book::ClientHeld(client) => write!(f, "client:{}:held", client),
+            Book::ChargebackLoss => f.write_str("chargeback_loss"),
+            Book::CashIn => f.write_str("cash_in"),
+            Book::FeeIncome => f.write_str("fee_income"),
+            Book::ChargebackFees => f.write_str("chargeback_fees"),
+            Book::Promotions => f.write_str("promotions"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Book {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Entry {
+    pub tx: u32,
+    pub r#type: Type,
+    pub debit: Book,
+    pub credit: Book,
+    pub amount: f64,
+    /// Merchant of the deposit or withdrawal the movement stems from.
+    pub merchant: Option<Label>,
+    /// Category of that deposit or withdrawal.
+    pub category: Option<Label>,
+    /// What the engine did on its own, for movements not from the input.
+    pub event: Option<AutoEvent>,
+    pub reason: Option<AutoReason>,
+}
+
+impl Entry {
+    /// Whether this posting is a fee taken alongside the transaction rather
+    /// than the transaction's own movement.
+    pub fn is_fee(&self) -> bool {
+        matches!(self.debit, Book::FeeIncome | Book::ChargebackFees)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Posts the movement of an applied transaction. `origin` is the deposit
+    /// or withdrawal whose funds move: `tx` itself, or the transaction a
+    /// dispute, resolve, chargeback, approve or reject refers to. A deposit
+    /// or withdrawal held for approval only moves its funds to or from the
+    /// held book. What a custom type moves is
+    /// up to its handler, so it is not posted.
+    pub(crate) fn post<M: Money>(
+        &mut self,
+        tx: &Transaction<M>,
+        origin: &Transaction<M>,
+        amount: f64,
+    ) {
+        let client = tx.account_id();
+        let held_deposit = origin.r#type() == Type::Deposit;
+        let (debit, credit) = match tx.r#type() {
+            Type::Deposit if origin.is_pending() => (Book::ClientHeld(client), Book::CashIn),
+            Type::Deposit => (Book::ClientAvailable(client), Book::CashIn),
+            Type::Bonus => (Book::ClientAvailable(client), Book::Promotions),
+            Type::Withdrawal if origin.is_pending() => {
+                (Book::ClientHeld(client), Book::ClientAvailable(client))
+            }
+            Type::Withdrawal => (Book::CashIn, Book::ClientAvailable(client)),
+            Type::Dispute => (Book::ClientHeld(client), Book::ClientAvailable(client)),
+            Type::Resolve => (Book::ClientAvailable(client), Book::ClientHeld(client)),
+            Type::Chargeback => (Book::ChargebackLoss, Book::ClientHeld(client)),
+            Type::Approve if held_deposit => {
+                (Book::ClientAvailable(client), Book::ClientHeld(client))
+            }
+            Type::Approve => (Book::CashIn, Book::ClientHeld(client)),
+            Type::Reject if held_deposit => (Book::CashIn, Book::ClientHeld(client)),
+            Type::Reject => (Book::ClientAvailable(client), Book::ClientHeld(client)),
+            Type::Fee | Type::Custom(_) => return,
+        };
+        self.entries.push(Entry {
+            tx: tx.id(),
+            r#type: tx.r#type(),
+            debit,
+            credit,
+            amount,
+            merchant: origin.merchant(),
+            category: origin.category(),
+            event: None,
+            reason: None,
+        });
+    }
+
+    /// Posts a fee taken from the client of `tx` alongside it.
+    pub(crate) fn post_fee<M: Money>(&mut self, tx: &Transaction<M>, fee: f64) {
+        self.entries.push(Entry {
+            tx: tx.id(),
+            r#type: tx.r#type(),
+            debit: match tx.r#type() {
+                Type::Chargeback => Book::ChargebackFees,
+                _ => Book::FeeIncome,
+            },
+            credit: Book::ClientAvailable(tx.account_id()),
+            amount: fee,
+            merchant: tx.merchant(),
+            category: tx.category(),
+            event: None,
+            reason: None,
+        });
+    }
+
+    /// Names every entry from the `since`th on a movement the engine made
+    /// on its own.
+    pub(crate) fn attribute(&mut self, since: usize, event: AutoEvent, reason: AutoReason) {
+        for entry in &mut self.entries[since..] {
+            entry.event = Some(event);
+            entry.reason = Some(reason);
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Debits minus credits of every book that has been posted to.
+    pub fn balances(&self) -> BTreeMap<Book, f64> {
+        let mut balances = BTreeMap::new();
+        for entry in &self.entries {
+            *balances.entry(entry.debit).or_default() += entry.amount;
+            *balances.entry(entry.credit).or_default() -= entry.amount;
+        }
+        balances
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn postings_balance() {
+        let mut journal = Journal::new();
+        let deposit = Transaction::new(1, Type::Deposit, 1, 10.0);
+        let withdrawal = Transaction::new(2, Type::Withdrawal, 1, 3.0);
+        journal.post(&deposit, &deposit, 10.0);
+        journal.post(&withdrawal, &withdrawal, 3.0);
+        journal.post(&Transaction::new(1, Type::Dispute, 1, 0.0), &deposit, 10.0);
+        journal.post(
+            &Transaction::new(1, Type::Chargeback, 1, 0.0),
+            &deposit,
+            10.0,
+        );
+
+        let balances = journal.balances();
+        assert_eq!(balances[&Book::ClientAvailable(1)], -3.0);
+        assert_eq!(balances[&Book::ClientHeld(1)], 0.0);
+        assert_eq!(balances[&Book::ChargebackLoss], 10.0);
+        assert_eq!(balances[&Book::CashIn], -7.0);
+        assert_eq!(balances.values().sum::<f64>(), 0.0);
+        assert_eq!(Book::ClientHeld(1).to_string(), "client:1:held");
+    }
+}
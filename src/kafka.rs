@@ -0,0 +1,120 @@
+//! A Kafka topic as a `Source`, for `serve --kafka-topic`.
+//!
+//! Every partition of the topic is assigned to the one consumer rather
+//! than balanced over a consumer group, since the engine has to see all
+//! transactions of a client, and the group is only used to commit offsets
+//! to. Offsets are committed by `Server::consume` once a checkpoint covers
+//! them, never automatically. Each partition is a part named
+//! `kafka:<topic>:<partition>`, and its position is the offset of the next
+//! message to read.
+
+use crate::source::{Fetched, Positions, Record, Source};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use std::io;
+use std::time::Duration;
+
+/// How long to wait for the brokers to describe the topic.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    topic: String,
+}
+
+impl KafkaSource {
+    pub fn connect(brokers: &str, topic: &str, group: &str) -> io::Result<KafkaSource> {
+        let consumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .map_err(io::Error::other)?;
+        Ok(KafkaSource {
+            consumer,
+            topic: topic.to_string(),
+        })
+    }
+
+    fn part(&self, partition: i32) -> String {
+        format!("kafka:{}:{}", self.topic, partition)
+    }
+
+    /// `positions` of this topic's partitions, leaving out the others.
+    fn partitions(&self, positions: &Positions) -> io::Result<TopicPartitionList> {
+        let mut partitions = TopicPartitionList::new();
+        for (part, &next) in positions {
+            let Some(partition) = part
+                .strip_prefix("kafka:")
+                .and_then(|part| part.strip_prefix(self.topic.as_str()))
+                .and_then(|part| part.strip_prefix(':'))
+                .and_then(|partition| partition.parse().ok())
+            else {
+                continue;
+            };
+            let offset = i64::try_from(next).map_err(io::Error::other)?;
+            partitions
+                .add_partition_offset(&self.topic, partition, Offset::Offset(offset))
+                .map_err(io::Error::other)?;
+        }
+        Ok(partitions)
+    }
+}
+
+impl Source for KafkaSource {
+    fn seek(&mut self, positions: &Positions) -> io::Result<()> {
+        let metadata = self
+            .consumer
+            .fetch_metadata(Some(&self.topic), METADATA_TIMEOUT)
+            .map_err(io::Error::other)?;
+        let topic = metadata
+            .topics()
+            .iter()
+            .find(|topic| topic.name() == self.topic)
+            .filter(|topic| topic.error().is_none() && !topic.partitions().is_empty())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no such topic: {}", self.topic),
+                )
+            })?;
+        let mut assignment = TopicPartitionList::new();
+        for partition in topic.partitions() {
+            let offset = match positions.get(&self.part(partition.id())) {
+                Some(&next) => Offset::Offset(i64::try_from(next).map_err(io::Error::other)?),
+                None => Offset::Stored,
+            };
+            assignment
+                .add_partition_offset(&self.topic, partition.id(), offset)
+                .map_err(io::Error::other)?;
+        }
+        self.consumer.assign(&assignment).map_err(io::Error::other)
+    }
+
+    fn fetch(&mut self, timeout: Duration) -> io::Result<Fetched> {
+        let message = match self.consumer.poll(timeout) {
+            None => return Ok(Fetched::Idle),
+            Some(message) => message.map_err(io::Error::other)?,
+        };
+        let next = u64::try_from(message.offset() + 1).map_err(io::Error::other)?;
+        Ok(Fetched::Record(Record {
+            part: self.part(message.partition()),
+            next,
+            payload: message.payload().unwrap_or_default().to_vec(),
+        }))
+    }
+
+    fn commit(&mut self, positions: &Positions) -> io::Result<()> {
+        let partitions = self.partitions(positions)?;
+        if partitions.count() == 0 {
+            return Ok(());
+        }
+        self.consumer
+            .commit(&partitions, CommitMode::Sync)
+            .map_err(io::Error::other)
+    }
+}
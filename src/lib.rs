@@ -1,4 +1,105 @@
+//! The engine core (`account`, `engine`, `state`, `transaction`) only needs
+//! `log` and `tracing`. CSV/JSON formats, server mode, the CLI and the
+//! language bindings are opt-in features.
+
 pub mod account;
+pub mod activity;
+#[cfg(feature = "server")]
+pub mod actors;
+pub mod alerts;
+pub mod approval;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bank;
+#[cfg(feature = "json")]
+pub mod checkpoint;
+pub mod clock;
+pub mod currency;
+#[cfg(feature = "server")]
+pub mod dead_letter;
 pub mod engine;
+pub mod expiry;
+pub mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "csv")]
+pub mod fixed_width;
+pub mod fx;
+pub mod hierarchy;
+pub mod history;
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+#[cfg(feature = "csv")]
+pub mod iso8583;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "csv")]
+pub mod locale;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod merge;
+pub mod metrics;
+pub mod money;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "csv")]
+pub mod ofx;
+pub mod ordering;
+#[cfg(feature = "csv")]
 pub mod parser;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod processor;
+#[cfg(feature = "csv")]
+pub mod progress;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+#[cfg(feature = "pseudonymize")]
+pub mod pseudonym;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "csv")]
+pub mod qif;
+#[cfg(feature = "csv")]
+pub mod reconcile;
+#[cfg(feature = "object-store")]
+pub mod remote;
+pub mod reorder;
+#[cfg(all(feature = "csv", feature = "json"))]
+pub mod report;
+pub mod retention;
+pub mod retry;
+pub mod rounding;
+pub mod rules;
+#[cfg(feature = "csv")]
+pub mod schema;
+pub mod screening;
+#[cfg(feature = "rhai")]
+pub mod script;
+pub mod seen;
+pub mod selection;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod simulation;
+#[cfg(all(feature = "csv", feature = "json"))]
+pub mod sink;
+#[cfg(feature = "server")]
+pub mod source;
+pub mod state;
+pub mod statement;
+pub mod summary;
+#[cfg(feature = "otlp")]
+pub mod telemetry;
+pub mod timestamp;
 pub mod transaction;
+pub mod validators;
+pub mod wal;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
@@ -0,0 +1,216 @@
+//! Locale conventions for CSV output, for spreadsheets and legacy systems
+//! that expect e.g. `1.234,5678` rather than `1234.5678`.
+//!
+//! Output is written as usual and then restyled: amounts, the fields written
+//! with a decimal point, get the decimal and thousands separators of the
+//! style, and every field is quoted by its quoting policy. Ids and other
+//! integers are left as they are.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// When fields are enclosed in double quotes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Quoting {
+    /// Only fields holding the delimiter, a quote or a line break.
+    #[default]
+    Necessary,
+    Always,
+    /// Never, even where that makes the output ambiguous.
+    Never,
+}
+
+impl FromStr for Quoting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quoting, String> {
+        match s {
+            "necessary" => Ok(Quoting::Necessary),
+            "always" => Ok(Quoting::Always),
+            "never" => Ok(Quoting::Never),
+            _ => Err(format!(
+                "unknown quoting {:?}, expected necessary, always or never",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Quoting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Quoting::Necessary => "necessary",
+            Quoting::Always => "always",
+            Quoting::Never => "never",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvStyle {
+    delimiter: Option<u8>,
+    decimal_separator: char,
+    thousands_separator: Option<char>,
+    quoting: Quoting,
+}
+
+impl Default for CsvStyle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsvStyle {
+    /// Plain CSV: comma delimited, decimal point, no grouping.
+    pub fn new() -> CsvStyle {
+        CsvStyle {
+            delimiter: None,
+            decimal_separator: '.',
+            thousands_separator: None,
+            quoting: Quoting::Necessary,
+        }
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> CsvStyle {
+        self.delimiter = Some(delimiter);
+        self
+    }
+
+    pub fn with_decimal_separator(mut self, separator: char) -> CsvStyle {
+        self.decimal_separator = separator;
+        self
+    }
+
+    pub fn with_thousands_separator(mut self, separator: Option<char>) -> CsvStyle {
+        self.thousands_separator = separator;
+        self
+    }
+
+    pub fn with_quoting(mut self, quoting: Quoting) -> CsvStyle {
+        self.quoting = quoting;
+        self
+    }
+
+    /// The field delimiter: as set, otherwise `;` with a decimal comma, as
+    /// spreadsheets of those locales expect, and `,` with anything else.
+    pub fn delimiter(&self) -> u8 {
+        match (self.delimiter, self.decimal_separator) {
+            (Some(delimiter), _) => delimiter,
+            (None, ',') => b';',
+            (None, _) => b',',
+        }
+    }
+
+    /// `amount`, written with a decimal point, in this style. `None` for
+    /// anything that is not such an amount.
+    pub fn format_amount(&self, amount: &str) -> Option<String> {
+        let (sign, digits) = match amount.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", amount),
+        };
+        let (integer, fraction) = digits.split_once('.')?;
+        let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if !is_digits(integer) || !is_digits(fraction) {
+            return None;
+        }
+        let mut formatted = sign.to_string();
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                formatted.extend(self.thousands_separator);
+            }
+            formatted.push(digit);
+        }
+        formatted.push(self.decimal_separator);
+        formatted.push_str(fraction);
+        Some(formatted)
+    }
+
+    /// Copies the plain CSV of `input` to `output` in this style.
+    pub fn rewrite<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), Box<dyn Error>> {
+        if Some(self.decimal_separator) == self.thousands_separator {
+            return Err("the decimal and thousands separators must differ".into());
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(input);
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter())
+            .quote_style(match self.quoting {
+                Quoting::Necessary => csv::QuoteStyle::Necessary,
+                Quoting::Always => csv::QuoteStyle::Always,
+                Quoting::Never => csv::QuoteStyle::Never,
+            })
+            .from_writer(output);
+        for record in reader.records() {
+            let record = record?;
+            writer.write_record(record.iter().map(|field| {
+                self.format_amount(field)
+                    .unwrap_or_else(|| field.to_string())
+            }))?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn amounts() {
+        let style = CsvStyle::new()
+            .with_decimal_separator(',')
+            .with_thousands_separator(Some('.'));
+        assert_eq!(style.format_amount("1234.5678").unwrap(), "1.234,5678");
+        assert_eq!(style.format_amount("-1234567.0").unwrap(), "-1.234.567,0");
+        assert_eq!(style.format_amount("123.25").unwrap(), "123,25");
+        assert_eq!(style.format_amount("42"), None);
+        assert_eq!(style.format_amount("1.2.3"), None);
+        assert_eq!(style.format_amount("acme.com"), None);
+        assert_eq!(CsvStyle::new().format_amount("1234.5").unwrap(), "1234.5");
+    }
+
+    #[test]
+    fn european_snapshot() {
+        let input = "client,available,held,total,locked\n1,1234.5678,0.0,1234.5678,false\n";
+        let mut out = Vec::new();
+        CsvStyle::new()
+            .with_decimal_separator(',')
+            .rewrite(input.as_bytes(), &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client;available;held;total;locked\n1;1234,5678;0,0;1234,5678;false\n"
+        );
+
+        let mut out = Vec::new();
+        CsvStyle::new()
+            .with_decimal_separator(',')
+            .with_delimiter(b',')
+            .with_quoting(Quoting::Always)
+            .rewrite(input.as_bytes(), &mut out)
+            .unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("\"1\",\"1234,5678\",\"0,0\",\"1234,5678\",\"false\"\n"));
+    }
+
+    #[test]
+    fn same_separators() {
+        let style = CsvStyle::new()
+            .with_decimal_separator(',')
+            .with_thousands_separator(Some(','));
+        assert!(style.rewrite("1.0\n".as_bytes(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn quoting_round_trip() {
+        for quoting in [Quoting::Necessary, Quoting::Always, Quoting::Never] {
+            assert_eq!(quoting.to_string().parse::<Quoting>().unwrap(), quoting);
+        }
+        assert!("sometimes".parse::<Quoting>().is_err());
+    }
+}
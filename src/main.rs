@@ -1,28 +1,2719 @@
+use clap::{Args, CommandFactory as _, Parser as _, Subcommand};
 use fictional_guide::account::AccountsRepository;
-use fictional_guide::engine::Engine;
-use fictional_guide::parser::Parser;
-use fictional_guide::transaction::TransactionLedger;
+use fictional_guide::activity::{self, ExtendedAccount};
+use fictional_guide::alerts::Threshold;
+use fictional_guide::archive::{self, ColdStorage};
+#[cfg(feature = "arrow")]
+use fictional_guide::arrow;
+use fictional_guide::bank::AccountMap;
+use fictional_guide::currency::Currency;
+use fictional_guide::dead_letter::DeadLetterFile;
+use fictional_guide::engine::{Engine, LockedDisputes, RejectReason, Rejection};
+use fictional_guide::expiry::{ExpiryAction, HoldExpiry};
+use fictional_guide::fees::FeeSchedule;
+use fictional_guide::fixed_width::Layout;
+use fictional_guide::fx::Rates;
+use fictional_guide::hierarchy::{self, Hierarchy};
+use fictional_guide::history::AsOf;
+#[cfg(feature = "iso20022")]
+use fictional_guide::iso20022::Iso20022;
+use fictional_guide::iso8583::Iso8583;
+#[cfg(feature = "kafka")]
+use fictional_guide::kafka::KafkaSource;
+use fictional_guide::locale::{CsvStyle, Quoting};
+use fictional_guide::manifest::{self, Manifest};
+#[cfg(feature = "msgpack")]
+use fictional_guide::msgpack;
+use fictional_guide::ofx::Ofx;
+use fictional_guide::ordering::{self, TimeOrder};
+use fictional_guide::parser::{Parser, Warning};
+#[cfg(feature = "plugins")]
+use fictional_guide::plugin::Plugin;
+use fictional_guide::processor::{Strict, TransactionProcessor as _};
+#[cfg(feature = "protobuf")]
+use fictional_guide::protobuf;
+use fictional_guide::pseudonym::{Pseudonymize, Pseudonymizer};
+use fictional_guide::qif::Qif;
+#[cfg(feature = "object-store")]
+use fictional_guide::remote;
+use fictional_guide::retention::{AuditFile, Retention};
+#[cfg(feature = "object-store")]
+use fictional_guide::retry::{CircuitBreaker, Retry, RetryPolicy};
+use fictional_guide::rounding::Rounding;
+use fictional_guide::rules::Rules;
+use fictional_guide::schema::Schema;
+use fictional_guide::screening::{self, Blocklist};
+#[cfg(feature = "rhai")]
+use fictional_guide::script::Script;
+use fictional_guide::seen::SeenIds;
+use fictional_guide::selection::{ClientFilter, Sample};
+use fictional_guide::server::{
+    CheckpointOptions, Overflow, RateLimits, Server, SnapshotOptions, TcpOptions,
+};
+#[cfg(feature = "signing")]
+use fictional_guide::signing;
+use fictional_guide::simulation::{Simulation, SimulationConfig};
+use fictional_guide::sink::{self, SinkSpec};
+use fictional_guide::state::State;
+use fictional_guide::timestamp::Month;
+#[cfg(feature = "plugins")]
+use fictional_guide::transaction::Type;
+use fictional_guide::transaction::{Transaction, TransactionLedger};
+#[cfg(feature = "xlsx")]
+use fictional_guide::xlsx;
+use fictional_guide::{
+    checkpoint, merge, progress, reconcile, report, server, statement, summary, wal,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+#[cfg(feature = "object-store")]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(clap::Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print a failure as a JSON object with its kind, exit code and message
+    #[arg(long, global = true)]
+    error_json: bool,
+
+    /// Also print a summary of each run to stderr, and with -vv every rejected transaction
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print nothing to stderr, not even skipped rows
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Retry transient object store errors up to N times, with exponential backoff
+    #[cfg(feature = "object-store")]
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Wait before the first retry, doubled for every retry after it
+    #[cfg(feature = "object-store")]
+    #[arg(long, global = true, value_name = "MS", default_value_t = 100)]
+    retry_backoff: u64,
+
+    /// Stop calling the object store for 30 seconds after N transient failures in a row
+    #[cfg(feature = "object-store")]
+    #[arg(long, global = true, value_name = "N", default_value_t = 5)]
+    breaker_threshold: u32,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// CSV files with the transactions to process; several are merged in timestamp order
+    paths: Vec<String>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Write the account snapshot here instead of stdout (.arrows for an Arrow IPC stream with the
+    /// `arrow` feature, CSV otherwise)
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Write the account snapshot to this sink instead, as FORMAT:TARGET with `-` for stdout, e.g.
+    /// csv:-, json:accounts.json, arrow:accounts.arrows or sqlite:state.db; may be repeated
+    #[arg(
+        long = "sink",
+        value_name = "FORMAT:TARGET",
+        conflicts_with_all = ["output", "rollup", "extended", "pseudonymize"]
+    )]
+    sinks: Vec<SinkSpec>,
+
+    /// Write every rejected transaction with its reason code here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    rejects_report: Option<String>,
+
+    /// Write every transaction still under dispute at the end of the run here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    disputes_report: Option<String>,
+
+    /// Write every open dispute and withdrawal pending approval here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    open_items_report: Option<String>,
+
+    /// Write every locked account with the chargeback that locked it here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    locked_report: Option<String>,
+
+    /// Write the accounts without any transaction for --dormant-days here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    dormant_report: Option<String>,
+
+    /// Write the accounts with a negative available balance, largest deficit first, here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    exposure_report: Option<String>,
+
+    /// Days without a transaction after which an account counts as dormant
+    #[arg(long, value_name = "N", default_value_t = 365)]
+    dormant_days: u64,
+
+    /// Write the double-entry journal of every applied movement here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Write volume and chargeback counts per merchant here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    merchant_report: Option<String>,
+
+    /// Write volume and dispute rate per category here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    category_report: Option<String>,
+
+    /// Remember only the last N tx ids for duplicate detection and disputes
+    #[arg(long, value_name = "N")]
+    dedup_window: Option<usize>,
+
+    /// Remember tx ids for duplicate detection and disputes only for this many days after the newest timestamp
+    #[arg(long, value_name = "DAYS")]
+    dedup_window_days: Option<u64>,
+
+    /// Reject deposits and withdrawals whose ids are listed in this file, and add those applied
+    #[arg(long, value_name = "PATH")]
+    seen_ids: Option<String>,
+
+    /// Reject every transaction of the client ids listed in this file, one per line
+    #[arg(long)]
+    blocklist: Option<String>,
+
+    /// Map child client ids to a parent, one child,parent pair per line
+    #[arg(long)]
+    hierarchy: Option<String>,
+
+    /// Write balances summed per top-level parent instead of per client
+    #[arg(long, requires = "hierarchy")]
+    rollup: bool,
+
+    /// Stop with an error at the first rejected transaction, other than a duplicate, instead of skipping it
+    #[arg(long)]
+    strict: bool,
+
+    /// Write a checkpoint of the ledger and accounts here when done, recording a hash of the input
+    #[arg(long, value_name = "DIR")]
+    checkpoint_dir: Option<String>,
+
+    /// Also write a checkpoint after every N transactions
+    #[arg(long, value_name = "N", requires = "checkpoint_dir")]
+    checkpoint_every: Option<NonZeroUsize>,
+
+    /// Continue from the newest checkpoint in --checkpoint-dir, if the input is the one it was written for
+    #[arg(long, requires = "checkpoint_dir")]
+    resume: bool,
+
+    /// Write a CSV snapshot of the accounts here every --snapshot-every transactions, named after the time and offset
+    #[arg(long, value_name = "DIR", requires = "snapshot_every")]
+    snapshot_dir: Option<String>,
+
+    /// Number of transactions between two snapshots in --snapshot-dir
+    #[arg(long, value_name = "N", requires = "snapshot_dir")]
+    snapshot_every: Option<NonZeroUsize>,
+
+    /// Move settled transactions to compressed segments here, bringing them back when a dispute refers to them
+    #[arg(long, value_name = "DIR", requires = "archive_keep")]
+    archive_dir: Option<String>,
+
+    /// Keep this many of the newest transactions in memory, archiving the settled rest every N transactions
+    #[arg(long, value_name = "N", requires = "archive_dir")]
+    archive_keep: Option<NonZeroUsize>,
+
+    /// Add per-client counts of rejected withdrawals, ignored duplicates, open disputes and chargebacks to the snapshot
+    #[arg(long, conflicts_with = "rollup")]
+    extended: bool,
+
+    /// Write the blocked clients that showed up in the input here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "blocklist")]
+    screening_report: Option<String>,
+
+    /// Allow, flag or deny transactions by the rules in this file, one `VERDICT NAME: CONDITION` per line
+    #[arg(long, value_name = "PATH")]
+    rules: Option<String>,
+
+    /// Allow, flag, hold or deny the transactions no rule decided by this rhai script
+    #[cfg(feature = "rhai")]
+    #[arg(long, value_name = "PATH")]
+    script: Option<String>,
+
+    /// Write the transactions a flag or deny rule applied to here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "rules")]
+    rule_report: Option<String>,
+
+    #[command(flatten)]
+    hold_expiry: HoldExpiryArgs,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+
+    #[command(flatten)]
+    csv_style: CsvStyleArgs,
+
+    /// Charge fees on deposits and withdrawals, e.g. withdrawal=0.5,percent=1.5,above=1000
+    #[arg(long, value_name = "SCHEDULE")]
+    fees: Option<FeeSchedule>,
+
+    /// Let a chargeback of an undisputed tx open and settle its dispute at once instead of ignoring it
+    #[arg(long)]
+    direct_chargebacks: bool,
+
+    /// Allow bonuses to be disputed and charged back like deposits
+    #[arg(long)]
+    disputable_bonuses: bool,
+
+    /// Unlock an account locked by chargeback once none of its disputes is left open
+    #[arg(long)]
+    auto_unlock: bool,
+
+    /// What may still reach a locked account: deny, allow-chargeback-only (disputes and chargebacks)
+    /// or allow (also resolves)
+    #[arg(long, value_name = "POLICY", default_value_t = LockedDisputes::Deny)]
+    locked_disputes: LockedDisputes,
+
+    /// Hold withdrawals above this amount until an approve or reject of their tx id arrives
+    #[arg(long, value_name = "AMOUNT")]
+    withdrawal_approval: Option<f64>,
+
+    /// Write every approve and reject with the transaction it decided here (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    decisions_report: Option<String>,
+
+    /// Let locked accounts still take deposits, e.g. refunds; withdrawals stay blocked
+    #[arg(long)]
+    locked_deposits: bool,
+
+    /// Write every dispute closed by hold expiry here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "hold_expiry_days")]
+    expirations_report: Option<String>,
+
+    /// Alert when an account's available, held or total balance goes beyond a limit, e.g.
+    /// available<100 or held>5000; may be repeated
+    #[arg(long = "alert", value_name = "THRESHOLD")]
+    alerts: Vec<Threshold>,
+
+    /// Write every alert with the transaction that raised it here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "alerts")]
+    alerts_report: Option<String>,
+
+    /// Rounding applied to balances and reported amounts: half-up, half-even or floor
+    #[arg(long, default_value_t = Rounding::HalfUp)]
+    rounding: Rounding,
+
+    /// ISO 4217 currency of accounts whose transactions name none, kept at its exponent (e.g. 0
+    /// places for JPY) instead of four decimal places
+    #[arg(long, value_name = "CODE")]
+    currency: Option<Currency>,
+
+    /// Convert amounts in another currency than their account at the dated rates in this CSV file
+    /// (from,to,rate,valid_from,valid_to)
+    #[arg(long, value_name = "PATH")]
+    fx_rates: Option<String>,
+
+    /// Write the transactions no rate was valid for here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "fx_rates")]
+    fx_report: Option<String>,
+
+    /// Only process the input up to this point: N rows, tx:ID or tx:ID:TYPE (e.g. tx:4711:dispute)
+    #[arg(long)]
+    as_of: Option<AsOf>,
+
+    /// Check that rows are in timestamp order and stop if not (verify), or sort them by timestamp (sort)
+    #[arg(long, value_name = "MODE")]
+    time_order: Option<TimeOrder>,
+
+    /// Write the rows that are earlier than a row before them here (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "time_order")]
+    order_report: Option<String>,
+
+    /// Process at most N transactions per tenant, for a quick smoke test of a huge input
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Process only this share of the clients (0 to 1), with all of their transactions
+    #[arg(long, value_name = "FRACTION")]
+    sample: Option<f64>,
+
+    /// Process only these clients' transactions, e.g. 7,19,3000
+    #[arg(long, value_name = "CLIENTS")]
+    filter_client: Option<ClientFilter>,
+
+    /// Seed picking the clients of --sample, the same seed picks the same clients
+    #[arg(long, default_value_t = 0, requires = "sample")]
+    seed: u64,
+
+    /// Process only this tenant's rows, or treat an input without a tenant column as this tenant's
+    #[arg(long)]
+    tenant: Option<String>,
+
+    /// Replace client ids with an HMAC under this key in the snapshot, reports and traces
+    #[arg(
+        long,
+        value_name = "KEY",
+        env = "ENGINE_PSEUDONYMIZE_KEY",
+        hide_env_values = true
+    )]
+    pseudonymize: Option<String>,
+
+    /// Print a per-stage timing breakdown to stderr when done
+    #[arg(long)]
+    timings: bool,
+
+    /// Write a JSON manifest of the run here when done: version, arguments, times, and the inputs
+    /// and written files with their SHA-256 hashes
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<String>,
+
+    /// Parse up to N input files and process up to N tenants in parallel, one per CPU by default; 1 does both in order
+    #[arg(long, value_name = "N")]
+    threads: Option<NonZeroUsize>,
+
+    #[cfg(feature = "signing")]
+    #[command(flatten)]
+    signing: SigningArgs,
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Read the input as fixed-width records cut by this layout: name=OFFSET:WIDTH,...
+    #[arg(long, value_name = "LAYOUT")]
+    fixed_width: Option<Layout>,
+
+    /// Sheet of an .xlsx input holding the transactions, by default "transactions" or the first one
+    #[cfg(feature = "xlsx")]
+    #[arg(long)]
+    sheet: Option<String>,
+
+    /// Read the input as a line-per-message ISO 8583 card feed: MTI|element=value|...
+    #[arg(long, conflicts_with = "fixed_width")]
+    iso8583: bool,
+
+    /// account,client pairs matching the accounts of a bank export (.ofx, .qif, ISO 20022 .xml) or
+    /// the cards of an ISO 8583 feed to clients
+    #[arg(long)]
+    account_map: Option<String>,
+
+    /// TYPE=PATH: read the custom type TYPE and apply it with the WebAssembly plugin at PATH
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", value_name = "TYPE=PATH", value_parser = parse_plugin)]
+    plugins: Vec<(Type, String)>,
+}
+
+#[derive(Args)]
+struct CsvStyleArgs {
+    /// Decimal separator of amounts in the CSV snapshot, e.g. ',' (the delimiter then defaults to ';')
+    #[arg(long, value_name = "CHAR")]
+    decimal_separator: Option<char>,
+
+    /// Group the integer part of amounts in the CSV snapshot in thousands with this separator
+    #[arg(long, value_name = "CHAR")]
+    thousands_separator: Option<char>,
+
+    /// Field delimiter of the CSV snapshot
+    #[arg(long, value_name = "CHAR", value_parser = parse_delimiter)]
+    delimiter: Option<u8>,
+
+    /// When to quote fields of the CSV snapshot: necessary (default), always or never
+    #[arg(long, value_name = "POLICY")]
+    quote: Option<Quoting>,
+}
+
+impl CsvStyleArgs {
+    /// The style asked for, `None` for plain CSV.
+    fn style(&self) -> Option<CsvStyle> {
+        if self.decimal_separator.is_none()
+            && self.thousands_separator.is_none()
+            && self.delimiter.is_none()
+            && self.quote.is_none()
+        {
+            return None;
+        }
+        let style = CsvStyle::new()
+            .with_decimal_separator(self.decimal_separator.unwrap_or('.'))
+            .with_thousands_separator(self.thousands_separator)
+            .with_quoting(self.quote.unwrap_or_default());
+        Some(match self.delimiter {
+            Some(delimiter) => style.with_delimiter(delimiter),
+            None => style,
+        })
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => Err(format!("expected a single ASCII character, got {:?}", s)),
+    }
+}
+
+#[cfg(feature = "plugins")]
+fn parse_plugin(s: &str) -> Result<(Type, String), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected TYPE=PATH, got {:?}", s))?;
+    Ok((Type::custom(name)?, path.to_string()))
+}
+
+#[cfg(feature = "signing")]
+#[derive(Args)]
+struct SigningArgs {
+    /// Sign the snapshot with this hex-encoded ed25519 secret key, writing the signature to <output>.sig
+    #[arg(long, env = "ENGINE_SIGNING_KEY", hide_env_values = true)]
+    signing_key: Option<String>,
+
+    /// Read the hex-encoded signing key from this file; takes precedence over --signing-key
+    #[arg(long)]
+    signing_key_file: Option<String>,
+}
+
+#[cfg(feature = "signing")]
+impl SigningArgs {
+    fn key(&self) -> Result<Option<ed25519_dalek::SigningKey>, Box<dyn Error>> {
+        let hex = match (&self.signing_key_file, &self.signing_key) {
+            (Some(path), _) => std::fs::read_to_string(path)?,
+            (None, Some(hex)) => hex.clone(),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(signing::signing_key(&hex)?))
+    }
+}
+
+#[derive(Args)]
+struct HoldExpiryArgs {
+    /// Close disputes that are still open after this many days
+    #[arg(long, value_name = "DAYS")]
+    hold_expiry_days: Option<u64>,
+
+    /// How expired disputes are closed: resolve or chargeback
+    #[arg(long, default_value_t = ExpiryAction::Resolve, requires = "hold_expiry_days")]
+    hold_expiry_action: ExpiryAction,
+}
+
+impl HoldExpiryArgs {
+    fn policy(&self) -> Option<HoldExpiry> {
+        self.hold_expiry_days
+            .map(|days| HoldExpiry::days(days, self.hold_expiry_action))
+    }
+}
+
+#[derive(Args)]
+struct RetentionArgs {
+    /// Purge transactions whose dispute was settled more than this many days ago
+    #[arg(long, value_name = "DAYS", requires = "audit_log")]
+    dispute_retention_days: Option<u64>,
+
+    /// Append every purged transaction to this file as JSON lines before it is purged
+    #[arg(long, value_name = "PATH", requires = "dispute_retention_days")]
+    audit_log: Option<String>,
+}
+
+impl RetentionArgs {
+    fn policy(&self) -> Option<Retention> {
+        self.dispute_retention_days.map(Retention::days)
+    }
+
+    fn open(&self, path: &str) -> AuditFile {
+        AuditFile::open(std::path::Path::new(path)).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not open audit log {}: {}", path, err),
+            );
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Ingest line-protocol transactions from stdin, a Unix socket or TCP while serving health probes
+    Serve(Box<ServeArgs>),
+    /// Fuzz the engine with a seeded transaction stream and fault injection, checking invariants
+    Simulate(SimulateArgs),
+    /// Compare an engine snapshot with balances from another system
+    Reconcile(ReconcileArgs),
+    /// Print one client's statement for a month: balances, movements and dispute activity
+    Statement(StatementArgs),
+    /// Upgrade checkpoints and write-ahead logs written by older releases to the current format
+    Migrate(MigrateArgs),
+    /// Merge the checkpoints of shards into one, refusing shards that share clients or tx ids
+    MergeState(MergeStateArgs),
+    /// Report the columns and inferred types of an input file and whether the engine can process it
+    Schema(SchemaArgs),
+    /// Print the hex-encoded public key matching the signing key
+    #[cfg(feature = "signing")]
+    PublicKey(SigningArgs),
+    /// Check a snapshot against its detached signature
+    #[cfg(feature = "signing")]
+    Verify(VerifyArgs),
+    /// Print a completion script for a shell
+    Completions(CompletionsArgs),
+    /// Print the man page
+    Man,
+}
+
+#[derive(Args)]
+struct CompletionsArgs {
+    /// Shell to complete in
+    shell: clap_complete::Shell,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address the /healthz, /readyz and /status endpoints listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Accept line-protocol connections on this Unix socket instead of reading stdin
+    #[cfg(unix)]
+    #[arg(long)]
+    unix: Option<std::path::PathBuf>,
+
+    /// Accept CSV or JSON line-protocol connections on this TCP address instead of reading stdin
+    #[arg(long)]
+    tcp: Option<String>,
+
+    /// Require TCP connections to authenticate with `AUTH <token>` as their first line
+    #[arg(long, env = "ENGINE_TCP_TOKEN", requires = "tcp")]
+    token: Option<String>,
+
+    /// Answer the POST /admin requests on --listen, for requests carrying `Authorization: Bearer <token>`
+    #[arg(long, env = "ENGINE_ADMIN_TOKEN")]
+    admin_token: Option<String>,
+
+    /// Maximum records per second accepted on a single TCP connection
+    #[arg(long, requires = "tcp")]
+    rate_limit: Option<u32>,
+
+    /// Maximum transactions per second applied over all inputs and clients
+    #[arg(long, value_name = "TPS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_tps: Option<u32>,
+
+    /// Maximum transactions per second applied for any one client
+    #[arg(long, value_name = "TPS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_client_tps: Option<u32>,
+
+    /// What happens to transactions over --max-tps or --max-client-tps: delay or reject
+    #[arg(long, default_value_t = Overflow::Delay)]
+    rate_overflow: Overflow,
+
+    /// Rounding applied to balances: half-up, half-even or floor
+    #[arg(long, default_value_t = Rounding::HalfUp)]
+    rounding: Rounding,
+
+    /// Persist checkpoints here and resume from the newest one on startup
+    #[arg(long)]
+    checkpoint_dir: Option<std::path::PathBuf>,
+
+    /// Write a checkpoint after this many transactions
+    #[arg(long, requires = "checkpoint_dir")]
+    checkpoint_every: Option<u64>,
+
+    /// Write a checkpoint at least this often, in seconds, when new transactions arrived
+    #[arg(long, requires = "checkpoint_dir")]
+    checkpoint_interval: Option<u64>,
+
+    /// Write a CSV snapshot of the accounts here, named after the time and offset, to follow progress
+    #[arg(long, value_name = "DIR")]
+    snapshot_dir: Option<std::path::PathBuf>,
+
+    /// Write a snapshot after this many transactions
+    #[arg(long, value_name = "N", requires = "snapshot_dir")]
+    snapshot_every: Option<u64>,
+
+    /// Write a snapshot at least this often, in seconds, when new transactions arrived
+    #[arg(long, value_name = "SECONDS", requires = "snapshot_dir")]
+    snapshot_interval: Option<u64>,
+
+    /// Remember only the last N tx ids for duplicate detection and disputes
+    #[arg(long, value_name = "N")]
+    dedup_window: Option<usize>,
+
+    /// Remember tx ids for duplicate detection and disputes only for this many days after the newest timestamp
+    #[arg(long, value_name = "DAYS")]
+    dedup_window_days: Option<u64>,
+
+    #[command(flatten)]
+    hold_expiry: HoldExpiryArgs,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+
+    /// Log every transaction to a write-ahead log in the checkpoint directory and replay it on startup
+    #[arg(long, requires = "checkpoint_dir")]
+    wal: bool,
+
+    /// Consume transactions exactly once from this Kafka topic instead of reading stdin, committing
+    /// offsets once checkpointed
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires_all = ["kafka_brokers", "checkpoint_dir"], conflicts_with = "wal")]
+    kafka_topic: Option<String>,
+
+    /// Kafka bootstrap servers to consume --kafka-topic from, comma-separated
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_name = "HOSTS", requires = "kafka_topic")]
+    kafka_brokers: Option<String>,
+
+    /// Kafka consumer group to commit offsets of --kafka-topic to
+    #[cfg(feature = "kafka")]
+    #[arg(long, default_value = "fictional-guide", requires = "kafka_topic")]
+    kafka_group: String,
+
+    /// Hold transactions back until ones this many seconds later arrived and apply them in timestamp order
+    #[arg(long, value_name = "SECONDS", conflicts_with = "checkpoint_dir")]
+    reorder_window: Option<u64>,
+
+    /// Write the transactions that arrived behind the reorder window here once stdin is closed
+    /// (.json for JSON, CSV otherwise)
+    #[arg(long, requires = "reorder_window")]
+    late_report: Option<String>,
+
+    /// Append every rejected transaction and unparseable TCP line to this file as JSON lines, with the reason
+    #[arg(long, value_name = "PATH")]
+    dead_letters: Option<String>,
+
+    /// Apply each client's transactions on an actor of its own, queueing up to MAILBOX per client
+    #[arg(long, value_name = "MAILBOX", conflicts_with_all = ["checkpoint_dir", "dedup_window", "dedup_window_days", "snapshot_dir", "dispute_retention_days"])]
+    actors: Option<NonZeroUsize>,
+
+    /// Threads running the actors [default: number of CPUs]
+    #[arg(long, value_name = "N", requires = "actors")]
+    actor_workers: Option<NonZeroUsize>,
+}
+
+#[derive(Args)]
+struct SimulateArgs {
+    /// Seed of the generated run; the same seed always reproduces the same run
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Number of transactions to generate
+    #[arg(long, default_value_t = 10_000)]
+    steps: u64,
+
+    /// Number of distinct clients
+    #[arg(long, default_value_t = 16)]
+    clients: u16,
+
+    /// Probability of replaying an earlier tx id
+    #[arg(long, default_value_t = 0.05)]
+    duplicate_rate: f64,
+
+    /// Probability of a withdrawal that must be rejected
+    #[arg(long, default_value_t = 0.05)]
+    reject_rate: f64,
+
+    /// Probability of holding a dispute back for a while
+    #[arg(long, default_value_t = 0.1)]
+    delayed_dispute_rate: f64,
+}
+
+#[derive(Args)]
+struct ReconcileArgs {
+    /// Snapshot written by this engine
+    output: String,
+
+    /// Balances exported by the other system, in the same CSV format
+    expected: String,
+
+    /// Largest difference between two amounts still treated as equal
+    #[arg(long, default_value_t = 0.0001)]
+    tolerance: f64,
+
+    /// Rounding applied to reported differences: half-up, half-even or floor
+    #[arg(long, default_value_t = Rounding::HalfUp)]
+    rounding: Rounding,
+
+    /// Write the mismatch report here instead of stdout (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    report: Option<String>,
+}
+
+#[derive(Args)]
+struct StatementArgs {
+    /// CSV file with the transactions, including a timestamp column
+    path: String,
+
+    /// Client the statement is for
+    #[arg(long)]
+    client: u16,
+
+    /// Month of the statement, as YYYY-MM
+    #[arg(long)]
+    month: Month,
+
+    /// Statement of this tenant's rows when the input has a tenant column
+    #[arg(long)]
+    tenant: Option<String>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Rounding applied to balances and amounts: half-up, half-even or floor
+    #[arg(long, default_value_t = Rounding::HalfUp)]
+    rounding: Rounding,
+
+    /// Fee schedule the transactions were processed with, see the main command
+    #[arg(long, value_name = "SCHEDULE")]
+    fees: Option<FeeSchedule>,
+
+    /// Write the statement here instead of stdout (.json for JSON, CSV otherwise)
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct MigrateArgs {
+    /// Checkpoint directories (every checkpoint and the wal in them), archive directories, or single
+    /// checkpoint, wal or archive segment files
+    #[arg(required = true)]
+    paths: Vec<std::path::PathBuf>,
+}
+
+#[derive(Args)]
+struct MergeStateArgs {
+    /// Checkpoint files of the shards
+    #[arg(required = true)]
+    shards: Vec<std::path::PathBuf>,
+
+    /// Where to write the merged checkpoint
+    #[arg(short, long)]
+    output: std::path::PathBuf,
+}
+
+#[derive(Args)]
+struct SchemaArgs {
+    /// CSV file to inspect
+    path: String,
+
+    /// Inspect only the first N rows
+    #[arg(long, value_name = "N")]
+    sample: Option<u64>,
+}
+
+#[cfg(feature = "signing")]
+#[derive(Args)]
+struct VerifyArgs {
+    /// Snapshot to check
+    snapshot: String,
+
+    /// Detached signature, <snapshot>.sig by default
+    #[arg(long)]
+    signature: Option<String>,
+
+    /// Hex-encoded ed25519 public key of the engine run
+    #[arg(long)]
+    public_key: String,
+}
 
 fn main() {
-    let mut args = std::env::args();
-    let _prog_name = args.next().expect("USAGE: cargo run");
+    let cli = Cli::parse();
+    ERROR_JSON.store(cli.error_json, Ordering::Relaxed);
+    #[cfg(feature = "object-store")]
+    let _ = RETRY.set(Retry::new(
+        RetryPolicy {
+            retries: cli.retries,
+            backoff: Duration::from_millis(cli.retry_backoff),
+            ..RetryPolicy::default()
+        },
+        CircuitBreaker::new(cli.breaker_threshold, Duration::from_secs(30)),
+    ));
 
-    let path = args.next().unwrap_or_else(|| {
-        println!("provide file path");
-        process::exit(1);
+    #[cfg(feature = "otlp")]
+    let _telemetry = fictional_guide::telemetry::Telemetry::init().unwrap_or_else(|err| {
+        fail(
+            Failure::Other,
+            format_args!("could not initialize tracing: {}", err),
+        );
     });
-    let transactions = Parser::parse(&path).unwrap_or_else(|err| {
-        println!("could not parse input: {}", err);
-        process::exit(1);
+
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Summary,
+        (false, 2) => Verbosity::Details,
+        (false, _) => Verbosity::Debug,
+    };
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    // Fails when tracing took over `log` already, whose records then end up
+    // in the exported spans instead.
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(match verbosity {
+            Verbosity::Quiet | Verbosity::Normal | Verbosity::Summary => log::LevelFilter::Off,
+            Verbosity::Details => log::LevelFilter::Info,
+            Verbosity::Debug => log::LevelFilter::Debug,
+        });
+    }
+
+    match cli.command {
+        Some(Command::Serve(args)) => serve(*args),
+        Some(Command::Simulate(args)) => simulate(args),
+        Some(Command::Reconcile(args)) => reconcile(args),
+        Some(Command::Statement(args)) => statement(args),
+        Some(Command::Migrate(args)) => migrate(args),
+        Some(Command::MergeState(args)) => merge_state(args),
+        Some(Command::Schema(args)) => schema(args),
+        #[cfg(feature = "signing")]
+        Some(Command::PublicKey(args)) => public_key(args),
+        #[cfg(feature = "signing")]
+        Some(Command::Verify(args)) => verify(args),
+        Some(Command::Completions(args)) => completions(args),
+        Some(Command::Man) => man(),
+        None => run(cli.run),
+    }
+}
+
+/// What made a command give up, each kind with its own exit code so that
+/// orchestration can branch on it. Invalid arguments keep clap's code 2.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Failure {
+    /// Anything not covered below, such as options that do not fit the input.
+    Other,
+    /// The input could not be read as transactions.
+    Parse,
+    /// `--strict` stopped at a rejected transaction.
+    Rejected,
+    /// A file, key or socket could not be read or written.
+    Io,
+    /// A balance invariant did not hold.
+    Invariant,
+    /// `reconcile` found balances that differ.
+    Mismatch,
+}
+
+impl Failure {
+    fn code(self) -> i32 {
+        match self {
+            Failure::Other => 1,
+            Failure::Parse => 3,
+            Failure::Rejected => 4,
+            Failure::Io => 5,
+            Failure::Invariant => 6,
+            Failure::Mismatch => 7,
+        }
+    }
+
+    /// `Io` when the input could not be read at all, `Parse` otherwise.
+    fn of_input(err: &(dyn Error + 'static)) -> Failure {
+        let unreadable = err.is::<std::io::Error>()
+            || err
+                .downcast_ref::<csv::Error>()
+                .is_some_and(|err| matches!(err.kind(), csv::ErrorKind::Io(_)));
+        match unreadable {
+            true => Failure::Io,
+            false => Failure::Parse,
+        }
+    }
+}
+
+/// How much goes to stderr, from `-q` to `-vvv`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Nothing.
+    Quiet,
+    /// Warnings about skipped input rows.
+    Normal,
+    /// Also a summary of each run: transactions applied and rejected, by
+    /// reason.
+    Summary,
+    /// Also every rejected transaction and other engine events.
+    Details,
+    /// Also engine debug messages.
+    Debug,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Summary,
+        3 => Verbosity::Details,
+        _ => Verbosity::Debug,
+    }
+}
+
+/// Prints the engine's log records to stderr, up to the level picked by
+/// the verbosity.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}: {}",
+                record.level().as_str().to_ascii_lowercase(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set by `--error-json`.
+static ERROR_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--retries`, `--retry-backoff` and `--breaker-threshold`.
+#[cfg(feature = "object-store")]
+static RETRY: OnceLock<Retry> = OnceLock::new();
+
+/// Runs `op`, which talks to an object store, under the retry policy.
+#[cfg(feature = "object-store")]
+fn with_retry<T>(op: impl FnMut() -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    RETRY
+        .get_or_init(Retry::default)
+        .run(op, |err| remote::is_transient(err.as_ref()))
+        .map_err(|err| err.to_string().into())
+}
+
+/// Prints `message` on stderr, as JSON with `--error-json`, and exits with
+/// the code of `failure`.
+fn fail(failure: Failure, message: fmt::Arguments) -> ! {
+    if ERROR_JSON.load(Ordering::Relaxed) {
+        let error = serde_json::json!({
+            "error": failure,
+            "code": failure.code(),
+            "message": message.to_string(),
+        });
+        eprintln!("{}", error);
+    } else {
+        eprintln!("{}", message);
+    }
+    process::exit(failure.code())
+}
+
+fn run(args: RunArgs) {
+    if args.paths.is_empty() {
+        fail(Failure::Other, format_args!("provide file path"));
+    }
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let parallelism = args
+        .threads
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let parse = |paths: &[String]| -> Vec<Result<Partitions, (Failure, String)>> {
+        paths
+            .iter()
+            .map(|path| {
+                parse_input(path, &args.input)
+                    .map_err(|err| (Failure::of_input(err.as_ref()), err.to_string()))
+            })
+            .collect()
+    };
+    let parsed = match parallelism.min(args.paths.len()) {
+        0 | 1 => parse(&args.paths),
+        // Each worker parses a run of consecutive files, so that joining
+        // them in turn keeps the files in order.
+        threads => std::thread::scope(|scope| {
+            let workers: Vec<_> = args
+                .paths
+                .chunks(args.paths.len().div_ceil(threads))
+                .map(|chunk| scope.spawn(|| parse(chunk)))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("input parser panicked"))
+                .collect()
+        }),
+    };
+    let inputs: Vec<Partitions> = parsed
+        .into_iter()
+        .map(|input| {
+            input.unwrap_or_else(|(failure, err)| {
+                fail(failure, format_args!("could not parse input: {}", err));
+            })
+        })
+        .collect();
+    let input_rows: Vec<usize> = inputs
+        .iter()
+        .map(|partitions| partitions.values().map(Vec::len).sum())
+        .collect();
+    let mut partitions = merge_inputs(inputs);
+    if let Some(tenant) = &args.tenant {
+        partitions = select_tenant(partitions, tenant);
+    }
+    if partitions.is_empty() {
+        partitions.insert(None, Vec::new());
+    }
+    if let Some(err) = partitions
+        .keys()
+        .flatten()
+        .find_map(|tenant| check_tenant(tenant).err())
+    {
+        fail(Failure::Parse, format_args!("{}", err));
+    }
+    if partitions.len() > 1 {
+        if args.output.is_none() && args.sinks.is_empty() {
+            fail(
+                Failure::Other,
+                format_args!(
+                    "input has several tenants, give --output or --sink with a {{tenant}} placeholder"
+                ),
+            );
+        }
+        let paths = [
+            &args.output,
+            &args.rejects_report,
+            &args.disputes_report,
+            &args.open_items_report,
+            &args.locked_report,
+            &args.dormant_report,
+            &args.exposure_report,
+            &args.journal,
+            &args.merchant_report,
+            &args.category_report,
+            &args.screening_report,
+            &args.rule_report,
+            &args.fx_report,
+            &args.expirations_report,
+            &args.decisions_report,
+            &args.alerts_report,
+            &args.order_report,
+            &args.checkpoint_dir,
+            &args.snapshot_dir,
+            &args.archive_dir,
+            &args.retention.audit_log,
+            &args.seen_ids,
+        ];
+        let sinks = args.sinks.iter().map(|sink| &sink.target);
+        if paths
+            .into_iter()
+            .flatten()
+            .chain(sinks)
+            .any(|path| !path.contains("{tenant}"))
+        {
+            fail(
+                Failure::Other,
+                format_args!(
+                    "input has several tenants, output paths need a {{tenant}} placeholder"
+                ),
+            );
+        }
+    }
+    let pseudonymizer = args
+        .pseudonymize
+        .as_deref()
+        .map(|key| Pseudonymizer::new(key.as_bytes()));
+    if let Some(order) = args.time_order {
+        for (tenant, transactions) in partitions.iter_mut() {
+            let late = ordering::out_of_order(transactions);
+            if let Some(path) = &args.order_report {
+                let path = tenant_path(path, tenant.as_deref());
+                write_report(&late, &path, pseudonymizer.as_ref()).unwrap_or_else(|err| {
+                    fail(
+                        Failure::Io,
+                        format_args!("could not write order report: {}", err),
+                    );
+                });
+            }
+            match (order, late.first()) {
+                (TimeOrder::Verify, Some(first)) => fail(
+                    Failure::Parse,
+                    format_args!(
+                        "input is not in timestamp order: row {} (tx {}) at {} comes after {}, {} out of order in all",
+                        first.row,
+                        first.tx,
+                        first.timestamp,
+                        first.after,
+                        late.len()
+                    ),
+                ),
+                (TimeOrder::Verify, None) => {}
+                (TimeOrder::Sort, _) => ordering::sort_by_timestamp(transactions),
+            }
+        }
+    }
+    if let Some(as_of) = args.as_of {
+        if partitions.len() > 1 {
+            fail(
+                Failure::Other,
+                format_args!("--as-of needs a single tenant, pick one with --tenant"),
+            );
+        }
+        for transactions in partitions.values_mut() {
+            let position = as_of.position(transactions).unwrap_or_else(|| {
+                fail(
+                    Failure::Other,
+                    format_args!("--as-of point is not in the input"),
+                );
+            });
+            transactions.truncate(position);
+        }
+    }
+    let sample = args.sample.map(|fraction| {
+        Sample::new(fraction, args.seed)
+            .unwrap_or_else(|err| fail(Failure::Other, format_args!("{}", err)))
+    });
+    for transactions in partitions.values_mut() {
+        if let Some(filter) = &args.filter_client {
+            filter.apply(transactions);
+        }
+        if let Some(sample) = &sample {
+            sample.apply(transactions);
+        }
+        if let Some(limit) = args.limit {
+            transactions.truncate(limit);
+        }
+    }
+
+    let rates = args.fx_rates.as_deref().map(|path| {
+        let source = std::fs::read_to_string(path).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not read fx rates: {}", err),
+            );
+        });
+        Rates::parse(&source).unwrap_or_else(|err| {
+            fail(
+                Failure::Parse,
+                format_args!("invalid fx rates in {}: {}", path, err),
+            );
+        })
+    });
+    let blocklist = args.blocklist.as_deref().map(|path| {
+        read_blocklist(path).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not read blocklist: {}", err),
+            );
+        })
+    });
+    let rules = args.rules.as_deref().map(|path| {
+        let source = std::fs::read_to_string(path).unwrap_or_else(|err| {
+            fail(Failure::Io, format_args!("could not read rules: {}", err));
+        });
+        Rules::parse(&source).unwrap_or_else(|err| {
+            fail(
+                Failure::Parse,
+                format_args!("invalid rules in {}: {}", path, err),
+            );
+        })
+    });
+    #[cfg(feature = "rhai")]
+    let script = args.script.as_deref().map(|path| {
+        let source = std::fs::read_to_string(path).unwrap_or_else(|err| {
+            fail(Failure::Io, format_args!("could not read script: {}", err));
+        });
+        Script::compile(&source).unwrap_or_else(|err| {
+            fail(
+                Failure::Parse,
+                format_args!("invalid script in {}: {}", path, err),
+            );
+        })
+    });
+    #[cfg(feature = "plugins")]
+    let plugins: Vec<(Type, Plugin)> = args
+        .input
+        .plugins
+        .iter()
+        .map(|(r#type, path)| {
+            let plugin = std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|wasm| Plugin::load(&wasm))
+                .unwrap_or_else(|err| {
+                    fail(
+                        Failure::Io,
+                        format_args!("could not load plugin {}: {}", path, err),
+                    );
+                });
+            (*r#type, plugin)
+        })
+        .collect();
+    let hierarchy = args.hierarchy.as_deref().map(|path| {
+        read_hierarchy(path).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not read hierarchy: {}", err),
+            );
+        })
+    });
+    let mut timings = Timings {
+        parse: started.elapsed(),
+        ..Default::default()
+    };
+    let input = args.checkpoint_dir.as_ref().map(|_| {
+        fingerprint(&args).unwrap_or_else(|err| {
+            fail(Failure::Io, format_args!("could not hash input: {}", err));
+        })
+    });
+    let shared = Shared {
+        blocklist: blocklist.as_ref(),
+        rules: rules.as_ref(),
+        #[cfg(feature = "rhai")]
+        script: script.as_ref(),
+        #[cfg(feature = "plugins")]
+        plugins: &plugins,
+        rates: rates.as_ref(),
+        rollup: hierarchy.as_ref().filter(|_| args.rollup),
+        pseudonymizer: pseudonymizer.as_ref(),
+        input: input.as_deref(),
+    };
+    let tenants: Vec<_> = partitions.iter().collect();
+    let run_tenants = |tenants: &[(&Option<String>, &Vec<Transaction>)], timings: &mut Timings| {
+        for (tenant, transactions) in tenants {
+            run_tenant(&args, tenant.as_deref(), transactions, shared, timings);
+        }
+    };
+    let threads = parallelism.min(tenants.len());
+    if threads <= 1 {
+        run_tenants(&tenants, &mut timings);
+    } else {
+        // Tenants share nothing, so each worker takes its own slice of them.
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = tenants
+                .chunks(tenants.len().div_ceil(threads))
+                .map(|chunk| {
+                    scope.spawn(|| {
+                        let mut timings = Timings::default();
+                        run_tenants(chunk, &mut timings);
+                        timings
+                    })
+                })
+                .collect();
+            for worker in workers {
+                timings.add(&worker.join().expect("tenant worker panicked"));
+            }
+        });
+    }
+
+    if args.timings {
+        eprint!("{}", timings);
+    }
+    if let Some(path) = &args.manifest {
+        let tenants: Vec<_> = partitions.keys().map(Option::as_deref).collect();
+        write_manifest(path, &args, &input_rows, &tenants, timings.rows, started_at)
+            .unwrap_or_else(|err| {
+                fail(
+                    Failure::Io,
+                    format_args!("could not write manifest: {}", err),
+                );
+            });
+    }
+}
+
+/// Options whose values are kept out of the manifest.
+const SECRET_OPTIONS: [&str; 2] = ["--pseudonymize", "--signing-key"];
+
+/// Writes the manifest of a finished run, see `manifest`.
+fn write_manifest(
+    path: &str,
+    args: &RunArgs,
+    input_rows: &[usize],
+    tenants: &[Option<&str>],
+    processed: usize,
+    started_at: SystemTime,
+) -> std::io::Result<()> {
+    let inputs = args
+        .paths
+        .iter()
+        .zip(input_rows)
+        .map(|(path, rows)| {
+            let local = Path::new(path).is_file();
+            Ok(manifest::Input {
+                path: path.clone(),
+                sha256: local
+                    .then(|| manifest::sha256(Path::new(path)))
+                    .transpose()?,
+                rows: *rows,
+            })
+        })
+        .collect::<std::io::Result<_>>()?;
+    let outputs = artifacts(args, tenants, started_at)?
+        .iter()
+        .map(|path| manifest::Artifact::of(path))
+        .collect::<std::io::Result<_>>()?;
+    let arguments = manifest::redacted(std::env::args().skip(1), &SECRET_OPTIONS);
+    Manifest {
+        inputs,
+        processed,
+        outputs,
+        ..Manifest::new(arguments, started_at)
+    }
+    .write(Path::new(path))
+}
+
+/// The local files a run wrote: its snapshot, reports and logs, and those
+/// in its checkpoint, snapshot and archive directories that changed since
+/// `since`.
+fn artifacts(
+    args: &RunArgs,
+    tenants: &[Option<&str>],
+    since: SystemTime,
+) -> std::io::Result<Vec<PathBuf>> {
+    let files = [
+        &args.rejects_report,
+        &args.disputes_report,
+        &args.open_items_report,
+        &args.locked_report,
+        &args.dormant_report,
+        &args.exposure_report,
+        &args.journal,
+        &args.merchant_report,
+        &args.category_report,
+        &args.screening_report,
+        &args.rule_report,
+        &args.fx_report,
+        &args.expirations_report,
+        &args.decisions_report,
+        &args.alerts_report,
+        &args.order_report,
+        &args.retention.audit_log,
+        &args.seen_ids,
+    ];
+    let dirs = [&args.checkpoint_dir, &args.snapshot_dir, &args.archive_dir];
+    // File times come from a coarser clock and may lag `since` a little.
+    let since = since - Duration::from_secs(1);
+    let mut paths = BTreeSet::new();
+    for tenant in tenants {
+        if let Some(output) = &args.output {
+            let output = tenant_path(output, *tenant);
+            paths.insert(PathBuf::from(format!("{}.sig", output)));
+            paths.insert(PathBuf::from(output));
+        }
+        for file in files.into_iter().flatten() {
+            paths.insert(PathBuf::from(tenant_path(file, *tenant)));
+        }
+        for sink in &args.sinks {
+            paths.insert(PathBuf::from(tenant_path(&sink.target, *tenant)));
+        }
+        for dir in dirs.into_iter().flatten() {
+            let dir = PathBuf::from(tenant_path(dir, *tenant));
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if entry.metadata()?.modified()? >= since {
+                    paths.insert(entry.path());
+                }
+            }
+        }
+    }
+    Ok(paths.into_iter().filter(|path| path.is_file()).collect())
+}
+
+/// What the tenants of a run share besides its options.
+#[derive(Copy, Clone)]
+struct Shared<'a> {
+    blocklist: Option<&'a Blocklist>,
+    rules: Option<&'a Rules>,
+    #[cfg(feature = "rhai")]
+    script: Option<&'a Script>,
+    #[cfg(feature = "plugins")]
+    plugins: &'a [(Type, Plugin)],
+    rates: Option<&'a Rates>,
+    rollup: Option<&'a Hierarchy>,
+    pseudonymizer: Option<&'a Pseudonymizer>,
+    /// Fingerprint of the input files, with `--checkpoint-dir`.
+    input: Option<&'a str>,
+}
+
+/// Processes one tenant's transactions into its own ledger and accounts
+/// and writes its outputs.
+fn run_tenant(
+    args: &RunArgs,
+    tenant: Option<&str>,
+    transactions: &[Transaction],
+    shared: Shared,
+    timings: &mut Timings,
+) {
+    let Shared {
+        blocklist,
+        rules,
+        #[cfg(feature = "rhai")]
+        script,
+        #[cfg(feature = "plugins")]
+        plugins,
+        rates,
+        rollup,
+        pseudonymizer,
+        input,
+    } = shared;
+    let started = Instant::now();
+    let client_label = pseudonymizer.map(|pseudonymizer| |client| pseudonymizer.client(client));
+    let checkpoint_dir = args
+        .checkpoint_dir
+        .as_ref()
+        .map(|dir| std::path::PathBuf::from(tenant_path(dir, tenant)));
+    let restored = match &checkpoint_dir {
+        Some(dir) if args.resume => resume(dir, args.rounding, input),
+        _ => None,
+    };
+    let (mut tx_ledger, account_repo, offset) = match restored {
+        Some(state) => {
+            let offset = state.offset();
+            (state.tx_ledger, state.accounts, offset)
+        }
+        None => (
+            TransactionLedger::default(),
+            AccountsRepository::with_rounding(args.rounding),
+            0,
+        ),
+    };
+    // Configured the same whether resumed or not, as checkpoints keep none
+    // of it.
+    tx_ledger.set_window(args.dedup_window);
+    tx_ledger.set_max_age(
+        args.dedup_window_days
+            .map(|days| Duration::from_secs(days * 86_400)),
+    );
+    let mut account_repo = account_repo
+        .with_currency(args.currency)
+        .with_locked_deposits(args.locked_deposits);
+    let remaining = &transactions[usize::try_from(offset)
+        .unwrap_or(usize::MAX)
+        .min(transactions.len())..];
+    let every = args
+        .checkpoint_every
+        .map_or(remaining.len().max(1), NonZeroUsize::get);
+    let snapshot_dir = args
+        .snapshot_dir
+        .as_ref()
+        .map(|dir| std::path::PathBuf::from(tenant_path(dir, tenant)));
+    let snapshot_every = args.snapshot_every.map(NonZeroUsize::get);
+    let archive_keep = args.archive_keep.map(NonZeroUsize::get);
+    // Chunks end wherever a checkpoint, a snapshot or archiving is due.
+    let step = [snapshot_every, archive_keep]
+        .into_iter()
+        .flatten()
+        .fold(every, gcd);
+    let mut archived = 0;
+    let mut archive_if_due = |engine: &mut Engine, chunk: &[Transaction]| {
+        archived += chunk.len();
+        let Some(keep) = archive_keep.filter(|keep| archived.is_multiple_of(*keep)) else {
+            return;
+        };
+        engine.archive_settled(keep).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not archive transactions: {}", err),
+            );
+        });
+    };
+    let mut applied = offset;
+    let mut save_checkpoint = |engine: &Engine, chunk: &[Transaction]| {
+        applied += chunk.len() as u64;
+        let done = (applied - offset) as usize;
+        if let (Some(dir), Some(snapshot_every)) = (&snapshot_dir, snapshot_every) {
+            if done.is_multiple_of(snapshot_every) {
+                progress::write(dir, engine.accounts, applied, SystemTime::now()).unwrap_or_else(
+                    |err| {
+                        fail(
+                            Failure::Io,
+                            format_args!("could not write snapshot: {}", err),
+                        );
+                    },
+                );
+            }
+        }
+        if !done.is_multiple_of(every) && done != remaining.len() {
+            return;
+        }
+        if let Some(dir) = &checkpoint_dir {
+            let progress = checkpoint::Progress {
+                offset: applied,
+                last_tx_id: chunk.last().map(Transaction::id),
+                input: input.map(str::to_string),
+                ..Default::default()
+            };
+            checkpoint::write_progress(dir, engine.tx_ledger, engine.accounts, &progress)
+                .unwrap_or_else(|err| {
+                    fail(
+                        Failure::Io,
+                        format_args!("could not write checkpoint: {}", err),
+                    );
+                });
+        }
+    };
+    let seen_ids_path = args.seen_ids.as_ref().map(|path| tenant_path(path, tenant));
+    let mut seen_ids = seen_ids_path.as_ref().map(|path| {
+        SeenIds::open(std::path::Path::new(path)).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not read seen ids {}: {}", path, err),
+            );
+        })
     });
-    let mut account_repo = AccountsRepository::default();
-    let mut tx_ledger = TransactionLedger::default();
+    let mut cold_storage = args.archive_dir.as_ref().map(|dir| {
+        let dir = tenant_path(dir, tenant);
+        ColdStorage::open(std::path::Path::new(&dir)).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not open archive {}: {}", dir, err),
+            );
+        })
+    });
+    let mut audit_log = args
+        .retention
+        .audit_log
+        .as_ref()
+        .map(|path| args.retention.open(&tenant_path(path, tenant)));
     let mut engine = Engine::new(&mut tx_ledger, &mut account_repo);
-    engine.process(&transactions);
+    if let Some(storage) = &mut cold_storage {
+        engine = engine.with_cold_storage(storage);
+    }
+    if let (Some(policy), Some(audit_log)) = (args.retention.policy(), &mut audit_log) {
+        engine = engine.with_retention(policy, audit_log);
+    }
+    let summaries = args.merchant_report.is_some() || args.category_report.is_some();
+    if args.journal.is_some() || summaries {
+        engine = engine.with_journal();
+    }
+    if let Some(label) = &client_label {
+        engine = engine.with_client_label(label);
+    }
+    if let Some(blocklist) = blocklist {
+        engine = engine.with_screening(blocklist);
+    }
+    if let Some(rules) = rules {
+        engine = engine.with_rules(rules);
+    }
+    #[cfg(feature = "rhai")]
+    if let Some(script) = script {
+        engine = engine.with_script(script);
+    }
+    #[cfg(feature = "plugins")]
+    for (r#type, plugin) in plugins {
+        engine = engine
+            .with_handler(*r#type, plugin)
+            .expect("plugins are for custom types");
+    }
+    if let Some(rates) = rates {
+        engine = engine.with_rates(rates);
+    }
+    if let Some(policy) = args.hold_expiry.policy() {
+        engine = engine.with_hold_expiry(policy);
+    }
+    if let Some(schedule) = args.fees {
+        engine = engine.with_fees(schedule);
+    }
+    if args.disputable_bonuses {
+        engine = engine.with_disputable_bonuses();
+    }
+    if args.direct_chargebacks {
+        engine = engine.with_direct_chargebacks();
+    }
+    if args.auto_unlock {
+        engine = engine.with_auto_unlock();
+    }
+    engine = engine.with_locked_disputes(args.locked_disputes);
+    if let Some(threshold) = args.withdrawal_approval {
+        engine = engine.with_withdrawal_approval(threshold);
+    }
+    for threshold in &args.alerts {
+        engine = engine.with_alert(*threshold);
+    }
+    if let Some(seen) = &mut seen_ids {
+        engine = engine.with_seen_ids(seen);
+    }
+    if args.strict {
+        let mut strict = Strict::new(engine);
+        for chunk in remaining.chunks(step) {
+            strict.process(chunk);
+            if let Some(rejection) = strict.halted() {
+                fail(
+                    Failure::Rejected,
+                    format_args!(
+                        "stopped at rejected {} of tx {}: {:?}",
+                        rejection.r#type, rejection.tx, rejection.reason
+                    ),
+                );
+            }
+            archive_if_due(strict.inner_mut(), chunk);
+            save_checkpoint(strict.inner(), chunk);
+        }
+        engine = strict.into_inner();
+    } else {
+        for chunk in remaining.chunks(step) {
+            engine.process(chunk);
+            archive_if_due(&mut engine, chunk);
+            save_checkpoint(&engine, chunk);
+        }
+    }
+    if let Some(filter) = &args.filter_client {
+        engine.accounts.retain(|client| filter.keeps(client));
+    }
+    let processed = Instant::now();
+    if verbosity() >= Verbosity::Summary {
+        print_summary(tenant, remaining.len(), engine.rejections());
+    }
+
+    if let Some(path) = &args.rejects_report {
+        let path = tenant_path(path, tenant);
+        write_report(engine.rejections(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write rejects report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.fx_report {
+        let path = tenant_path(path, tenant);
+        let unrated: Vec<Rejection> = engine
+            .rejections()
+            .iter()
+            .filter(|rejection| rejection.reason == RejectReason::NoFxRate)
+            .cloned()
+            .collect();
+        write_report(&unrated, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write fx report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.disputes_report {
+        let path = tenant_path(path, tenant);
+        let disputes = activity::open_disputes(engine.tx_ledger, transactions);
+        write_report(&disputes, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write disputes report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.open_items_report {
+        let path = tenant_path(path, tenant);
+        let items = activity::open_items(engine.tx_ledger, transactions);
+        write_report(&items, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write open items report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.locked_report {
+        let path = tenant_path(path, tenant);
+        let locked = activity::locked_accounts(engine.accounts);
+        write_report(&locked, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write locked accounts report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.dormant_report {
+        let path = tenant_path(path, tenant);
+        let dormant = activity::dormant_accounts(engine.accounts, transactions, args.dormant_days);
+        write_report(&dormant, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write dormant accounts report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.exposure_report {
+        let path = tenant_path(path, tenant);
+        let negative = activity::negative_exposure(engine.accounts);
+        write_report(&negative, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write exposure report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.screening_report {
+        let path = tenant_path(path, tenant);
+        let hits = screening::hits(engine.rejections());
+        write_report(&hits, &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write screening report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.expirations_report {
+        let path = tenant_path(path, tenant);
+        write_report(engine.expirations(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write expirations report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.decisions_report {
+        let path = tenant_path(path, tenant);
+        write_report(engine.decisions(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write decisions report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.alerts_report {
+        let path = tenant_path(path, tenant);
+        write_report(engine.alerts(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write alerts report: {}", err),
+            );
+        });
+    }
+
+    if let Some(path) = &args.rule_report {
+        let path = tenant_path(path, tenant);
+        write_report(engine.rule_hits(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write rule report: {}", err),
+            );
+        });
+    }
+
+    if let (Some(path), Some(journal)) = (&args.journal, engine.journal()) {
+        let path = tenant_path(path, tenant);
+        write_report(journal.entries(), &path, pseudonymizer).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write journal: {}", err),
+            );
+        });
+    }
+
+    if let (Some(path), Some(journal)) = (&args.merchant_report, engine.journal()) {
+        let path = tenant_path(path, tenant);
+        report::write_file(
+            &summary::by_merchant(journal.entries(), args.rounding),
+            &path,
+        )
+        .unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write merchant report: {}", err),
+            );
+        });
+    }
+
+    if let (Some(path), Some(journal)) = (&args.category_report, engine.journal()) {
+        let path = tenant_path(path, tenant);
+        report::write_file(
+            &summary::by_category(journal.entries(), args.rounding),
+            &path,
+        )
+        .unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write category report: {}", err),
+            );
+        });
+    }
+
+    let extended = args.extended.then(|| {
+        activity::extended(
+            engine.accounts,
+            engine.tx_ledger,
+            transactions,
+            engine.rejections(),
+        )
+    });
+    let output = args.output.as_deref().map(|path| tenant_path(path, tenant));
+    let style = args.csv_style.style();
+    let snapshot = Snapshot {
+        output: output.as_deref(),
+        rollup,
+        extended: extended.as_deref(),
+        pseudonymizer,
+        style: style.as_ref(),
+    };
+    #[cfg(feature = "signing")]
+    let written = match args.signing.key() {
+        Ok(Some(key)) => write_signed_snapshot(&account_repo, snapshot, &key),
+        Ok(None) if !args.sinks.is_empty() => write_sinks(&account_repo, &args.sinks, tenant),
+        Ok(None) => write_snapshot(&account_repo, snapshot),
+        Err(err) => {
+            fail(
+                Failure::Io,
+                format_args!("could not load signing key: {}", err),
+            );
+        }
+    };
+    #[cfg(not(feature = "signing"))]
+    let written = match args.sinks.is_empty() {
+        true => write_snapshot(&account_repo, snapshot),
+        false => write_sinks(&account_repo, &args.sinks, tenant),
+    };
+    written.unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not display output: {}", err),
+        );
+    });
+    // Only once the output is written, so that a failed run can be retried.
+    if let (Some(path), Some(seen)) = (&seen_ids_path, &mut seen_ids) {
+        seen.save().unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write seen ids {}: {}", path, err),
+            );
+        });
+    }
+
+    timings.process += processed - started;
+    timings.output += processed.elapsed();
+    timings.rows += transactions.len();
+    timings.ledger_size += tx_ledger.len();
+    timings.account_count += account_repo.len();
+}
+
+/// Keeps only `tenant`'s rows. An input without a tenant column belongs to
+/// `tenant` as a whole.
+fn select_tenant(mut partitions: Partitions, tenant: &str) -> Partitions {
+    let tenant = Some(tenant.to_string());
+    let rows = match partitions.remove(&None) {
+        Some(rows) => rows,
+        None => partitions.remove(&tenant).unwrap_or_default(),
+    };
+    BTreeMap::from([(tenant, rows)])
+}
+
+/// The state saved in `dir` to resume from, if there is any. Refuses a
+/// checkpoint written for another input than `input`.
+fn resume(dir: &std::path::Path, rounding: Rounding, input: Option<&str>) -> Option<State> {
+    let state = checkpoint::load_latest(dir, rounding).unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not read checkpoints in {}: {}", dir.display(), err),
+        );
+    })?;
+    if state.input() != input {
+        fail(
+            Failure::Other,
+            format_args!(
+                "the checkpoint in {} was written for another input or other options, not resuming",
+                dir.display()
+            ),
+        );
+    }
+    log::info!("resuming after {} transactions", state.offset());
+    Some(state)
+}
+
+/// A SHA-256 hash of the contents of the input files, in order, and of the
+/// options that pick which of their rows are processed, so that a run with
+/// other options does not resume from the checkpoints of this one.
+fn fingerprint(args: &RunArgs) -> std::io::Result<String> {
+    let mut contents = Vec::new();
+    for path in &args.paths {
+        contents.push(Box::new(File::open(path)?) as Box<dyn std::io::Read>);
+    }
+    let selection = format!(
+        "{:?}",
+        (
+            &args.filter_client,
+            args.sample.map(|sample| (sample, args.seed)),
+            args.limit,
+            args.as_of,
+            &args.tenant,
+            args.time_order,
+        )
+    );
+    contents.push(Box::new(std::io::Cursor::new(selection)));
+    Ok(format!("sha256:{}", manifest::sha256_of(contents)?))
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+/// Refuses a tenant name that would lead `tenant_path` out of the
+/// directory of its template.
+fn check_tenant(tenant: &str) -> Result<(), String> {
+    if tenant.contains(['/', '\\', '\0']) || tenant.contains("..") {
+        return Err(format!(
+            "invalid tenant {:?}: path separators, .. and NUL are not allowed",
+            tenant
+        ));
+    }
+    Ok(())
+}
+
+fn tenant_path(template: &str, tenant: Option<&str>) -> String {
+    match tenant {
+        Some(tenant) => template.replace("{tenant}", tenant),
+        None => template.to_string(),
+    }
+}
+
+/// The `-v` line of a tenant: how many transactions were applied and why
+/// the others were rejected.
+fn print_summary(tenant: Option<&str>, transactions: usize, rejections: &[Rejection]) {
+    let mut reasons: BTreeMap<String, usize> = BTreeMap::new();
+    for rejection in rejections {
+        let reason = serde_json::to_value(rejection.reason).expect("reasons serialize");
+        *reasons
+            .entry(reason.as_str().unwrap_or_default().to_string())
+            .or_default() += 1;
+    }
+    let reasons: Vec<String> = reasons
+        .iter()
+        .map(|(reason, count)| format!("{}: {}", reason, count))
+        .collect();
+    eprintln!(
+        "{}{} transactions, {} applied, {} rejected{}",
+        tenant
+            .map(|tenant| format!("{}: ", tenant))
+            .unwrap_or_default(),
+        transactions,
+        transactions.saturating_sub(rejections.len()),
+        rejections.len(),
+        match reasons.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", reasons.join(", ")),
+        }
+    );
+}
+
+/// Stage breakdown printed by `--timings`. Ledger and account counts are
+/// their sizes at the end of the run, which a dedup window, archiving or
+/// retention may keep below what they were in between. Figures of several
+/// tenants add up.
+#[derive(Default)]
+struct Timings {
+    parse: Duration,
+    process: Duration,
+    output: Duration,
+    rows: usize,
+    ledger_size: usize,
+    account_count: usize,
+}
+
+impl Timings {
+    /// Adds the figures of tenants processed elsewhere, so that durations
+    /// are summed over workers rather than wall-clock time.
+    fn add(&mut self, other: &Timings) {
+        self.parse += other.parse;
+        self.process += other.process;
+        self.output += other.output;
+        self.rows += other.rows;
+        self.ledger_size += other.ledger_size;
+        self.account_count += other.account_count;
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows_per_sec = self.rows as f64 / self.process.as_secs_f64().max(f64::EPSILON);
+        writeln!(f, "parse:    {:>10.3?}", self.parse)?;
+        writeln!(
+            f,
+            "process:  {:>10.3?} ({:.0} rows/s)",
+            self.process, rows_per_sec
+        )?;
+        writeln!(f, "output:   {:>10.3?}", self.output)?;
+        writeln!(f, "rows:     {:>10}", self.rows)?;
+        writeln!(f, "ledger:   {:>10} tx (final)", self.ledger_size)?;
+        writeln!(f, "accounts: {:>10} (final)", self.account_count)
+    }
+}
+
+fn completions(args: CompletionsArgs) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+}
+
+fn man() {
+    clap_mangen::Man::new(Cli::command())
+        .render(&mut std::io::stdout())
+        .unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write man page: {}", err),
+            );
+        });
+}
+
+fn simulate(args: SimulateArgs) {
+    let config = SimulationConfig {
+        seed: args.seed,
+        steps: args.steps,
+        clients: args.clients,
+        duplicate_rate: args.duplicate_rate,
+        reject_rate: args.reject_rate,
+        delayed_dispute_rate: args.delayed_dispute_rate,
+    };
+    match Simulation::new(config).run() {
+        Ok(report) => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("reports serialize")
+        ),
+        Err(violation) => {
+            fail(Failure::Invariant, format_args!("{}", violation));
+        }
+    }
+}
+
+fn reconcile(args: ReconcileArgs) {
+    let read = |path: &str| {
+        File::open(path)
+            .map_err(csv::Error::from)
+            .and_then(reconcile::read_balances)
+            .unwrap_or_else(|err| {
+                fail(
+                    Failure::Io,
+                    format_args!("could not read {}: {}", path, err),
+                );
+            })
+    };
+    let output = read(&args.output);
+    let expected = read(&args.expected);
+
+    let mismatches = reconcile::reconcile(&output, &expected, args.tolerance, args.rounding);
+    let written = match &args.report {
+        Some(path) => report::write_file(&mismatches, path),
+        None => report::write(&mismatches, report::Format::Csv, std::io::stdout()),
+    };
+    written.unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not write mismatch report: {}", err),
+        );
+    });
+    if !mismatches.is_empty() {
+        fail(
+            Failure::Mismatch,
+            format_args!("{} mismatches found", mismatches.len()),
+        );
+    }
+}
+
+fn statement(args: StatementArgs) {
+    let mut partitions = parse_input(&args.path, &args.input).unwrap_or_else(|err| {
+        fail(
+            Failure::of_input(err.as_ref()),
+            format_args!("could not parse input: {}", err),
+        );
+    });
+    if let Some(tenant) = &args.tenant {
+        partitions = select_tenant(partitions, tenant);
+    }
+    if partitions.len() > 1 {
+        fail(
+            Failure::Other,
+            format_args!("input has several tenants, pick one with --tenant"),
+        );
+    }
+    let log = partitions.into_values().next().unwrap_or_default();
+
+    let statement = statement::statement(&log, args.client, args.month, args.rounding, args.fees);
+    let written = match &args.output {
+        Some(path) if report::Format::from_path(path) == report::Format::Json => File::create(path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|file| Ok(serde_json::to_writer_pretty(file, &statement)?)),
+        Some(path) => report::write_file(&statement.rows(), path),
+        None => report::write(&statement.rows(), report::Format::Csv, std::io::stdout()),
+    };
+    written.unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not write statement: {}", err),
+        );
+    });
+}
+
+fn migrate(args: MigrateArgs) {
+    let mut failed = false;
+    for path in &args.paths {
+        let files = if path.is_dir() {
+            checkpoint::list(path).and_then(|mut files| {
+                files.extend(Some(path.join(server::WAL_FILE)).filter(|wal| wal.exists()));
+                files.extend(archive::segments(path)?);
+                Ok(files)
+            })
+        } else {
+            Ok(vec![path.clone()])
+        };
+        let files = files.unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not list {}: {}", path.display(), err),
+            );
+        });
+        for file in files {
+            let (migrated, current) = match file.extension().and_then(|e| e.to_str()) {
+                Some("json") => (checkpoint::migrate(&file), checkpoint::VERSION),
+                Some("gz") => (archive::migrate(&file), archive::VERSION),
+                _ => (wal::migrate(&file), wal::VERSION),
+            };
+            match migrated {
+                Ok(from) if from == current => {
+                    println!("{}: up to date (version {})", file.display(), current)
+                }
+                Ok(from) => println!(
+                    "{}: migrated from version {} to {}",
+                    file.display(),
+                    from,
+                    current
+                ),
+                Err(err) => {
+                    println!("{}: could not migrate: {}", file.display(), err);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn merge_state(args: MergeStateArgs) {
+    let collisions = checkpoint::merge(&args.shards, &args.output).unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not merge checkpoints: {}", err),
+        );
+    });
+    if !collisions.is_empty() {
+        for collision in &collisions {
+            eprintln!("collision: {}", collision);
+        }
+        fail(
+            Failure::Other,
+            format_args!(
+                "shards overlap in {} ids, {} not written",
+                collisions.len(),
+                args.output.display()
+            ),
+        );
+    }
+}
 
-    account_repo.display_all().unwrap_or_else(|err| {
-        println!("could not display output: {}", err);
+fn schema(args: SchemaArgs) {
+    let schema = File::open(&args.path)
+        .map_err(csv::Error::from)
+        .and_then(|file| Schema::inspect(file, args.sample))
+        .unwrap_or_else(|err| {
+            fail(
+                Failure::Parse,
+                format_args!("could not inspect input: {}", err),
+            );
+        });
+    print!("{}", schema);
+    if !schema.is_compatible() {
         process::exit(1);
+    }
+}
+
+type Partitions = BTreeMap<Option<String>, Vec<Transaction>>;
+
+/// Parses the input as fixed-width records with `--fixed-width`, as an
+/// ISO 8583 feed with `--iso8583`, and otherwise in the format its extension
+/// names: a workbook for .xlsx (`xlsx` feature), a bank export for .ofx,
+/// .qfx, .qif and .xml (`iso20022` feature), protobuf messages for .pb
+/// (`protobuf` feature), MessagePack for .msgpack and .mpk (`msgpack`
+/// feature), an Arrow IPC stream for .arrows (`arrow` feature) and CSV for
+/// anything else. Prints a warning to
+/// stderr for every row that was skipped or only partly read.
+fn parse_input(path: &str, input: &InputArgs) -> Result<Partitions, Box<dyn Error>> {
+    #[cfg(feature = "xlsx")]
+    if path.to_ascii_lowercase().ends_with(".xlsx") {
+        let (partitions, warnings) = xlsx::parse_tenants_with_warnings(
+            std::io::BufReader::new(File::open(path)?),
+            input.sheet.as_deref(),
+        )?;
+        print_warnings(warnings);
+        return Ok(partitions);
+    }
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    let bank_export = match extension.as_deref() {
+        _ if input.fixed_width.is_some() => None,
+        _ if input.iso8583 => Some(
+            Iso8583::new()
+                .with_clients(account_map(input)?)
+                .parse_with_warnings(std::io::BufReader::new(File::open(path)?))?,
+        ),
+        Some("ofx" | "qfx") => Some(
+            Ofx::new()
+                .with_clients(account_map(input)?)
+                .parse_with_warnings(File::open(path)?)?,
+        ),
+        Some("qif") => Some(
+            Qif::new()
+                .with_clients(account_map(input)?)
+                .parse_with_warnings(std::io::BufReader::new(File::open(path)?))?,
+        ),
+        #[cfg(feature = "iso20022")]
+        Some("xml") => Some(
+            Iso20022::new()
+                .with_clients(account_map(input)?)
+                .parse_with_warnings(File::open(path)?)?,
+        ),
+        _ => None,
+    };
+    if let Some((transactions, warnings)) = bank_export {
+        print_warnings(warnings);
+        return Ok(Partitions::from([(None, transactions)]));
+    }
+    #[cfg(feature = "object-store")]
+    let reader: Box<dyn std::io::Read> = if remote::is_url(path) {
+        Box::new(remote::Reader::open(
+            path,
+            RETRY.get_or_init(Retry::default),
+        )?)
+    } else {
+        Box::new(File::open(path)?)
+    };
+    #[cfg(not(feature = "object-store"))]
+    let reader = File::open(path)?;
+    let (partitions, warnings) = match (&input.fixed_width, extension.as_deref()) {
+        (Some(layout), _) => layout.parse_tenants_with_warnings(std::io::BufReader::new(reader))?,
+        #[cfg(feature = "protobuf")]
+        (None, Some("pb")) => {
+            protobuf::parse_tenants_with_warnings(std::io::BufReader::new(reader))?
+        }
+        #[cfg(feature = "msgpack")]
+        (None, Some("msgpack" | "mpk")) => {
+            msgpack::parse_tenants_with_warnings(std::io::BufReader::new(reader))?
+        }
+        #[cfg(feature = "arrow")]
+        (None, Some("arrows")) => {
+            arrow::parse_tenants_with_warnings(std::io::BufReader::new(reader))?
+        }
+        #[cfg(feature = "plugins")]
+        (None, _) => input
+            .plugins
+            .iter()
+            .fold(Parser::new(), |parser, (r#type, _)| {
+                parser.with_custom_type(*r#type)
+            })
+            .read(reader)?,
+        #[cfg(not(feature = "plugins"))]
+        (None, _) => Parser::parse_tenants_with_warnings(reader)?,
+    };
+    print_warnings(warnings);
+    Ok(partitions)
+}
+
+/// Every tenant's transactions of all `inputs`, merged by timestamp.
+fn merge_inputs(mut inputs: Vec<Partitions>) -> Partitions {
+    if inputs.len() == 1 {
+        return inputs.pop().unwrap_or_default();
+    }
+    let mut tenants: BTreeMap<Option<String>, Vec<Vec<Transaction>>> = BTreeMap::new();
+    for input in inputs {
+        for (tenant, transactions) in input {
+            tenants.entry(tenant).or_default().push(transactions);
+        }
+    }
+    tenants
+        .into_iter()
+        .map(|(tenant, inputs)| (tenant, merge::by_timestamp(inputs)))
+        .collect()
+}
+
+fn account_map(input: &InputArgs) -> Result<AccountMap, Box<dyn Error>> {
+    Ok(match &input.account_map {
+        Some(path) => AccountMap::read(std::io::BufReader::new(File::open(path)?))?,
+        None => AccountMap::default(),
+    })
+}
+
+fn print_warnings(warnings: Vec<Warning>) {
+    if verbosity() == Verbosity::Quiet {
+        return;
+    }
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+fn read_blocklist(path: &str) -> Result<Blocklist, Box<dyn Error>> {
+    Ok(Blocklist::read(std::io::BufReader::new(File::open(path)?))?)
+}
+
+fn read_hierarchy(path: &str) -> Result<Hierarchy, Box<dyn Error>> {
+    Ok(Hierarchy::read(std::io::BufReader::new(File::open(path)?))?)
+}
+
+fn write_report<T>(
+    records: &[T],
+    path: &str,
+    pseudonymizer: Option<&Pseudonymizer>,
+) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize + Pseudonymize,
+{
+    match pseudonymizer {
+        Some(pseudonymizer) => {
+            let records: Vec<_> = records
+                .iter()
+                .map(|r| r.pseudonymize(pseudonymizer))
+                .collect();
+            report::write_file(&records, path)
+        }
+        None => report::write_file(records, path),
+    }
+}
+
+/// Where and how the final account snapshot is written.
+#[derive(Copy, Clone)]
+struct Snapshot<'a> {
+    output: Option<&'a str>,
+    /// Roll balances up per parent of this hierarchy.
+    rollup: Option<&'a Hierarchy>,
+    /// Write these accounts with their counters instead.
+    extended: Option<&'a [ExtendedAccount]>,
+    pseudonymizer: Option<&'a Pseudonymizer>,
+    /// Locale conventions for CSV, plain CSV without.
+    style: Option<&'a CsvStyle>,
+}
+
+impl Snapshot<'_> {
+    /// Writes an Arrow IPC stream when the output is an .arrows file
+    /// (`arrow` feature), CSV in the requested style otherwise.
+    fn write<W: std::io::Write>(
+        &self,
+        accounts: &AccountsRepository,
+        writer: W,
+    ) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "arrow")]
+        if self
+            .output
+            .is_some_and(|output| output.to_ascii_lowercase().ends_with(".arrows"))
+        {
+            if self.rollup.is_some() || self.extended.is_some() || self.pseudonymizer.is_some() {
+                return Err(
+                    "Arrow snapshots hold plain accounts, without --rollup, --extended or --pseudonymize"
+                        .into(),
+                );
+            }
+            return Ok(arrow::write_accounts(&accounts.sorted(), writer)?);
+        }
+        if let Some(style) = self.style {
+            let mut plain = Vec::new();
+            self.write_csv(accounts, &mut plain)?;
+            return style.rewrite(plain.as_slice(), writer);
+        }
+        self.write_csv(accounts, writer)
+    }
+
+    fn write_csv<W: std::io::Write>(
+        &self,
+        accounts: &AccountsRepository,
+        writer: W,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(extended) = self.extended {
+            return match self.pseudonymizer {
+                Some(pseudonymizer) => {
+                    let extended: Vec<_> = extended
+                        .iter()
+                        .map(|account| account.pseudonymize(pseudonymizer))
+                        .collect();
+                    report::write(&extended, report::Format::Csv, writer)
+                }
+                None => report::write(extended, report::Format::Csv, writer),
+            };
+        }
+        if let Some(hierarchy) = self.rollup {
+            let rollups = hierarchy::rollup(accounts, hierarchy);
+            return match self.pseudonymizer {
+                Some(pseudonymizer) => {
+                    let rollups: Vec<_> = rollups
+                        .iter()
+                        .map(|rollup| rollup.pseudonymize(pseudonymizer))
+                        .collect();
+                    report::write(&rollups, report::Format::Csv, writer)
+                }
+                None => report::write(&rollups, report::Format::Csv, writer),
+            };
+        }
+        match self.pseudonymizer {
+            Some(pseudonymizer) => {
+                let accounts: Vec<_> = accounts
+                    .sorted()
+                    .iter()
+                    .map(|account| account.pseudonymize(pseudonymizer))
+                    .collect();
+                report::write(&accounts, report::Format::Csv, writer)
+            }
+            None => accounts.write_csv(writer),
+        }
+    }
+}
+
+fn write_snapshot(accounts: &AccountsRepository, snapshot: Snapshot) -> Result<(), Box<dyn Error>> {
+    match snapshot.output {
+        None => snapshot.write(accounts, std::io::stdout()),
+        #[cfg(feature = "object-store")]
+        Some(location) if remote::is_url(location) => with_retry(|| {
+            let mut writer = remote::Writer::create(location)?;
+            snapshot.write(accounts, &mut writer)?;
+            writer.finish()
+        }),
+        Some(path) => snapshot.write(accounts, File::create(path)?),
+    }
+}
+
+/// Feeds every `--sink` in one pass, with `{tenant}` in their targets
+/// filled in like in `--output`.
+fn write_sinks(
+    accounts: &AccountsRepository,
+    sinks: &[SinkSpec],
+    tenant: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let sinks: Vec<SinkSpec> = sinks
+        .iter()
+        .map(|spec| SinkSpec {
+            target: tenant_path(&spec.target, tenant),
+            ..spec.clone()
+        })
+        .collect();
+    sink::Registry::new().write_all(&sinks, accounts)
+}
+
+/// Renders the snapshot in memory so that exactly the bytes written are
+/// signed, then writes it and its detached signature next to each other.
+#[cfg(feature = "signing")]
+fn write_signed_snapshot(
+    accounts: &AccountsRepository,
+    snapshot: Snapshot,
+    key: &ed25519_dalek::SigningKey,
+) -> Result<(), Box<dyn Error>> {
+    let output = snapshot.output.ok_or("signing requires --output")?;
+    let mut bytes = Vec::new();
+    snapshot.write(accounts, &mut bytes)?;
+    let signature = signing::sign(key, &bytes);
+    write_bytes(&bytes, output)?;
+    write_bytes(signature.as_bytes(), &format!("{}.sig", output))
+}
+
+#[cfg(feature = "signing")]
+fn write_bytes(bytes: &[u8], location: &str) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+
+    #[cfg(feature = "object-store")]
+    if remote::is_url(location) {
+        return with_retry(|| {
+            let mut writer = remote::Writer::create(location)?;
+            writer.write_all(bytes)?;
+            writer.finish()
+        });
+    }
+    Ok(File::create(location)?.write_all(bytes)?)
+}
+
+#[cfg(feature = "signing")]
+fn public_key(args: SigningArgs) {
+    match args.key() {
+        Ok(Some(key)) => println!("{}", signing::encode_hex(key.verifying_key().as_bytes())),
+        Ok(None) => {
+            fail(
+                Failure::Other,
+                format_args!("provide --signing-key, --signing-key-file or ENGINE_SIGNING_KEY"),
+            );
+        }
+        Err(err) => {
+            fail(
+                Failure::Io,
+                format_args!("could not load signing key: {}", err),
+            );
+        }
+    }
+}
+
+#[cfg(feature = "signing")]
+fn verify(args: VerifyArgs) {
+    let signature_path = args
+        .signature
+        .unwrap_or_else(|| format!("{}.sig", args.snapshot));
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let key = signing::verifying_key(&args.public_key)?;
+        let snapshot = std::fs::read(&args.snapshot)?;
+        let signature = std::fs::read_to_string(&signature_path)?;
+        Ok(signing::verify(&key, &snapshot, &signature)?)
+    })();
+    match result {
+        Ok(()) => println!("signature ok"),
+        Err(err) => {
+            fail(
+                Failure::Other,
+                format_args!("could not verify {}: {}", args.snapshot, err),
+            );
+        }
+    }
+}
+
+fn serve(args: ServeArgs) {
+    let server = match &args.checkpoint_dir {
+        Some(dir) => {
+            let options = CheckpointOptions {
+                dir: dir.clone(),
+                every: args.checkpoint_every,
+                interval: args.checkpoint_interval.map(Duration::from_secs),
+                wal: args.wal,
+                rounding: args.rounding,
+            };
+            Server::with_checkpoints(options).unwrap_or_else(|err| {
+                fail(
+                    Failure::Io,
+                    format_args!(
+                        "could not restore checkpoint from {}: {}",
+                        dir.display(),
+                        err
+                    ),
+                );
+            })
+        }
+        None => Server::with_rounding(args.rounding),
+    };
+    let server = match args.dedup_window {
+        Some(capacity) => server.with_dedup_window(capacity),
+        None => server,
+    };
+    let server = match args.dedup_window_days {
+        Some(days) => server.with_dedup_max_age(Duration::from_secs(days * 86_400)),
+        None => server,
+    };
+    let server = match &args.snapshot_dir {
+        Some(dir) => server.with_snapshots(SnapshotOptions {
+            dir: dir.clone(),
+            every: args.snapshot_every,
+            interval: args.snapshot_interval.map(Duration::from_secs),
+        }),
+        None => server,
+    };
+    let server = match (args.max_tps, args.max_client_tps) {
+        (None, None) => server,
+        (global, per_client) => server.with_rate_limits(RateLimits {
+            global,
+            per_client,
+            overflow: args.rate_overflow,
+        }),
+    };
+    let server = match args.reorder_window {
+        Some(window) => server.with_reorder_window(window),
+        None => server,
+    };
+    let server = match &args.dead_letters {
+        Some(path) => server.with_dead_letters(
+            DeadLetterFile::open(std::path::Path::new(path)).unwrap_or_else(|err| {
+                fail(
+                    Failure::Io,
+                    format_args!("could not open dead letters {}: {}", path, err),
+                );
+            }),
+        ),
+        None => server,
+    };
+    let server = match args.actors {
+        Some(mailbox) => {
+            let workers = args
+                .actor_workers
+                .or_else(|| std::thread::available_parallelism().ok())
+                .map_or(1, NonZeroUsize::get);
+            server.with_actors(mailbox.get(), workers)
+        }
+        None => server,
+    };
+    let server = match args.hold_expiry.policy() {
+        Some(policy) => server.with_hold_expiry(policy),
+        None => server,
+    };
+    let server = match (args.retention.policy(), &args.retention.audit_log) {
+        (Some(policy), Some(path)) => server.with_retention(policy, args.retention.open(path)),
+        _ => server,
+    };
+    let server = match &args.admin_token {
+        Some(token) => server.with_admin_token(token.clone()),
+        None => server,
+    };
+    server.listen_http(&args.listen).unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not listen on {}: {}", args.listen, err),
+        );
+    });
+
+    let mut acceptors = Vec::new();
+    #[cfg(unix)]
+    if let Some(path) = &args.unix {
+        acceptors.push(server.listen_unix(path).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not listen on {}: {}", path.display(), err),
+            );
+        }));
+    }
+    if let Some(addr) = &args.tcp {
+        let options = TcpOptions {
+            token: args.token.clone(),
+            rate_limit: args.rate_limit,
+        };
+        acceptors.push(server.listen_tcp(addr, options).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not listen on {}: {}", addr, err),
+            );
+        }));
+    }
+    #[cfg(feature = "kafka")]
+    if let (Some(topic), Some(brokers)) = (&args.kafka_topic, &args.kafka_brokers) {
+        let consumed = KafkaSource::connect(brokers, topic, &args.kafka_group)
+            .and_then(|mut source| server.consume(&mut source));
+        if let Err(err) = consumed {
+            fail(
+                Failure::Io,
+                format_args!("could not consume {}: {}", topic, err),
+            );
+        }
+        return;
+    }
+    if !acceptors.is_empty() {
+        for acceptor in acceptors {
+            acceptor.join().expect("listener stopped");
+        }
+        return;
+    }
+    server.ingest(std::io::stdin().lock());
+
+    let late = server.late_events();
+    let mut state = server.into_state();
+    if let Some(path) = &args.late_report {
+        report::write_file(&late, path).unwrap_or_else(|err| {
+            fail(
+                Failure::Io,
+                format_args!("could not write late report: {}", err),
+            );
+        });
+    }
+    state.accounts.display_all().unwrap_or_else(|err| {
+        fail(
+            Failure::Io,
+            format_args!("could not display output: {}", err),
+        );
     });
 }
@@ -0,0 +1,188 @@
+//! Run manifests, for audits that must show what produced a snapshot.
+//!
+//! A manifest is written once a run is done, as JSON. It records the
+//! version of the engine, the arguments it ran with, when it started and
+//! finished, every input file with its SHA-256 hash and number of rows, and
+//! every file the run wrote with its hash. Values of options given in
+//! `secret_options` are redacted from the arguments, so that a manifest can
+//! be archived next to the snapshot without leaking keys.
+
+use crate::expiry::unix_seconds;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    /// Command-line arguments after the program name, secrets redacted.
+    pub arguments: Vec<String>,
+    /// Seconds since the Unix epoch the run started at.
+    pub started_at: u64,
+    /// Seconds since the Unix epoch the run finished at.
+    pub finished_at: u64,
+    pub inputs: Vec<Input>,
+    /// Transactions processed after filtering, over all tenants.
+    pub processed: usize,
+    pub outputs: Vec<Artifact>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Input {
+    pub path: String,
+    /// `None` for an input that is not a local file, e.g. an object store URL.
+    pub sha256: Option<String>,
+    /// Transactions parsed from it, before filtering.
+    pub rows: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+impl Artifact {
+    pub fn of(path: &Path) -> io::Result<Artifact> {
+        Ok(Artifact {
+            path: path.display().to_string(),
+            sha256: sha256(path)?,
+            bytes: fs::metadata(path)?.len(),
+        })
+    }
+}
+
+impl Manifest {
+    /// A manifest of a run of this version that started at `started_at` and
+    /// finishes now, without inputs or outputs yet.
+    pub fn new(arguments: Vec<String>, started_at: SystemTime) -> Manifest {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments,
+            started_at: unix_seconds(started_at),
+            finished_at: unix_seconds(SystemTime::now()),
+            inputs: Vec::new(),
+            processed: 0,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Writes the manifest to `path` as pretty-printed JSON, in full or not
+    /// at all.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        let file = File::create(&temporary)?;
+        serde_json::to_writer_pretty(&file, self)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// Hex-encoded SHA-256 hash of the contents of `path`.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    sha256_of([File::open(path)?])
+}
+
+/// Hex-encoded SHA-256 hash of everything `readers` yield, one after the
+/// other.
+pub fn sha256_of<R: Read>(readers: impl IntoIterator<Item = R>) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    for mut reader in readers {
+        io::copy(&mut reader, &mut hasher)?;
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// `arguments` with the value of every option in `secret_options` replaced,
+/// whether given as `--option value` or `--option=value`.
+pub fn redacted<I>(arguments: I, secret_options: &[&str]) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut redacted = Vec::new();
+    let mut secret_next = false;
+    for argument in arguments {
+        let option = argument
+            .split_once('=')
+            .map_or(argument.as_str(), |(option, _)| option);
+        let redact = match secret_next {
+            true => "[redacted]".to_string(),
+            false if secret_options.contains(&option) && option != argument => {
+                format!("{}=[redacted]", option)
+            }
+            false => argument.clone(),
+        };
+        secret_next = !secret_next && secret_options.contains(&argument.as_str());
+        redacted.push(redact);
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redacts_secret_options() {
+        let arguments = [
+            "in.csv",
+            "--pseudonymize",
+            "k3y",
+            "--signing-key=ab12",
+            "--strict",
+        ]
+        .map(String::from);
+        assert_eq!(
+            redacted(arguments, &["--pseudonymize", "--signing-key"]),
+            [
+                "in.csv",
+                "--pseudonymize",
+                "[redacted]",
+                "--signing-key=[redacted]",
+                "--strict"
+            ]
+        );
+    }
+
+    #[test]
+    fn hashes_and_writes() {
+        let dir = std::env::temp_dir().join(format!("fg-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("accounts.csv");
+        fs::write(&output, "abc").unwrap();
+        let started_at = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(10);
+        let manifest = Manifest {
+            inputs: vec![Input {
+                path: "in.csv".to_string(),
+                sha256: None,
+                rows: 3,
+            }],
+            processed: 3,
+            outputs: vec![Artifact::of(&output).unwrap()],
+            ..Manifest::new(vec!["in.csv".to_string()], started_at)
+        };
+        assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(manifest.started_at, 10);
+        assert_eq!(
+            manifest.outputs[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(manifest.outputs[0].bytes, 3);
+
+        let path = dir.join("manifest.json");
+        manifest.write(&path).unwrap();
+        let read: Manifest = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read, manifest);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Several inputs merged into one stream in time order, e.g. a day's files
+//! from different acquirers, so that the result does not depend on which
+//! input is listed first.
+
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Interleaves `inputs` by timestamp, each input being in time order
+/// already. The order within an input is always kept.
+///
+/// Between inputs, at the same timestamp new transactions go before
+/// disputes and disputes before resolves and chargebacks, so that a dispute
+/// in one input of a deposit in another finds it either way; remaining ties
+/// go by tx id and client, and only then by input. A row without a
+/// timestamp counts as at the time of the row before it in its input. If no
+/// row has a timestamp at all, the inputs are simply concatenated.
+pub fn by_timestamp<M: Money>(inputs: Vec<Vec<Transaction<M>>>) -> Vec<Transaction<M>> {
+    if inputs.iter().flatten().all(|tx| tx.timestamp().is_none()) {
+        return inputs.into_iter().flatten().collect();
+    }
+    let mut merged = Vec::with_capacity(inputs.iter().map(Vec::len).sum());
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|input| (input.into_iter().peekable(), 0))
+        .collect();
+    let mut heads = BinaryHeap::new();
+    for (index, (input, last)) in inputs.iter_mut().enumerate() {
+        if let Some(tx) = input.peek() {
+            heads.push(Reverse(key(tx, last, index)));
+        }
+    }
+    while let Some(Reverse((.., index))) = heads.pop() {
+        let (input, last) = &mut inputs[index];
+        merged.extend(input.next());
+        if let Some(tx) = input.peek() {
+            heads.push(Reverse(key(tx, last, index)));
+        }
+    }
+    merged
+}
+
+/// Where `tx` sorts among the heads of the inputs, `last` being the latest
+/// timestamp seen in its input.
+fn key<M: Money>(tx: &Transaction<M>, last: &mut u64, index: usize) -> (u64, u8, u32, u16, usize) {
+    *last = tx.timestamp().unwrap_or(*last);
+    let stage = match tx.r#type() {
+        Type::Dispute => 1,
+        Type::Resolve | Type::Chargeback | Type::Approve | Type::Reject => 2,
+        Type::Deposit | Type::Withdrawal | Type::Bonus | Type::Fee | Type::Custom(_) => 0,
+    };
+    (*last, stage, tx.id(), tx.account_id(), index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(id: u32, r#type: Type, timestamp: Option<u64>) -> Transaction {
+        Transaction::new(id, r#type, 1, 1.0).with_timestamp(timestamp)
+    }
+
+    fn order(inputs: Vec<Vec<Transaction>>) -> Vec<(u32, Type)> {
+        by_timestamp(inputs)
+            .iter()
+            .map(|tx| (tx.id(), tx.r#type()))
+            .collect()
+    }
+
+    #[test]
+    fn either_order() {
+        let a = vec![
+            at(1, Type::Deposit, Some(10)),
+            at(3, Type::Deposit, Some(30)),
+            at(4, Type::Withdrawal, None),
+        ];
+        let b = vec![
+            at(2, Type::Deposit, Some(20)),
+            at(3, Type::Dispute, Some(30)),
+            at(3, Type::Resolve, Some(40)),
+        ];
+        let expected = [
+            (1, Type::Deposit),
+            (2, Type::Deposit),
+            (3, Type::Deposit),
+            (4, Type::Withdrawal),
+            (3, Type::Dispute),
+            (3, Type::Resolve),
+        ];
+        assert_eq!(order(vec![a.clone(), b.clone()]), expected);
+        assert_eq!(order(vec![b, a]), expected);
+    }
+
+    #[test]
+    fn without_timestamps() {
+        let a = vec![at(2, Type::Deposit, None)];
+        let b = vec![at(1, Type::Deposit, None)];
+        assert_eq!(order(vec![a, b]), [(2, Type::Deposit), (1, Type::Deposit)]);
+    }
+}
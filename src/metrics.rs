@@ -0,0 +1,119 @@
+use crate::engine::RejectReason;
+use crate::transaction::{Label, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Outcome counters for one transaction type.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TypeCounts {
+    /// Changed balances as requested.
+    pub applied: u64,
+    /// Had no effect because of what it referenced: a duplicate id, an
+    /// unknown tx, another client's tx or a tx in the wrong dispute state.
+    pub ignored: u64,
+    /// Refused by the account itself (insufficient funds or a locked account)
+    /// or by screening.
+    pub rejected: u64,
+}
+
+impl TypeCounts {
+    fn merge(&mut self, other: &TypeCounts) {
+        self.applied += other.applied;
+        self.ignored += other.ignored;
+        self.rejected += other.rejected;
+    }
+}
+
+/// Counters updated while the engine processes transactions, meant to be
+/// exported by embedders to their own telemetry.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EngineMetrics {
+    pub deposit: TypeCounts,
+    pub withdrawal: TypeCounts,
+    pub dispute: TypeCounts,
+    pub resolve: TypeCounts,
+    pub chargeback: TypeCounts,
+    pub bonus: TypeCounts,
+    pub approve: TypeCounts,
+    pub reject: TypeCounts,
+    /// Fees given as input, which are always refused: only the engine posts
+    /// them.
+    pub fee: TypeCounts,
+    /// By name, the custom types, see `Type::custom`.
+    pub custom: BTreeMap<Label, TypeCounts>,
+    pub accounts_created: u64,
+    /// Accounts that went from unlocked to locked.
+    pub accounts_locked: u64,
+}
+
+impl EngineMetrics {
+    pub fn for_type(&self, r#type: Type) -> &TypeCounts {
+        const NONE: &TypeCounts = &TypeCounts {
+            applied: 0,
+            ignored: 0,
+            rejected: 0,
+        };
+        match r#type {
+            Type::Deposit => &self.deposit,
+            Type::Withdrawal => &self.withdrawal,
+            Type::Dispute => &self.dispute,
+            Type::Resolve => &self.resolve,
+            Type::Chargeback => &self.chargeback,
+            Type::Bonus => &self.bonus,
+            Type::Approve => &self.approve,
+            Type::Reject => &self.reject,
+            Type::Fee => &self.fee,
+            Type::Custom(name) => self.custom.get(&name).unwrap_or(NONE),
+        }
+    }
+
+    fn for_type_mut(&mut self, r#type: Type) -> &mut TypeCounts {
+        match r#type {
+            Type::Deposit => &mut self.deposit,
+            Type::Withdrawal => &mut self.withdrawal,
+            Type::Dispute => &mut self.dispute,
+            Type::Resolve => &mut self.resolve,
+            Type::Chargeback => &mut self.chargeback,
+            Type::Bonus => &mut self.bonus,
+            Type::Approve => &mut self.approve,
+            Type::Reject => &mut self.reject,
+            Type::Fee => &mut self.fee,
+            Type::Custom(name) => self.custom.entry(name).or_default(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, r#type: Type, result: Result<(), RejectReason>) {
+        let counts = self.for_type_mut(r#type);
+        match result {
+            Ok(()) => counts.applied += 1,
+            Err(
+                RejectReason::InsufficientFunds
+                | RejectReason::LockedAccount
+                | RejectReason::BlockedClient
+                | RejectReason::AmountOutOfBounds
+                | RejectReason::TooPrecise
+                | RejectReason::TypeNotAllowed
+                | RejectReason::ClientNotAllowed
+                | RejectReason::Invalid
+                | RejectReason::RateLimited
+                | RejectReason::RuleDenied
+                | RejectReason::UnhandledType
+                | RejectReason::NoFxRate,
+            ) => counts.rejected += 1,
+            Err(..) => counts.ignored += 1,
+        }
+    }
+
+    /// Adds the counters of `other`, e.g. of an engine used for a single batch.
+    pub fn merge(&mut self, other: &EngineMetrics) {
+        let custom = other.custom.keys().map(|name| Type::Custom(*name));
+        for r#type in Type::BUILT_IN.into_iter().chain(custom) {
+            self.for_type_mut(r#type).merge(other.for_type(r#type));
+        }
+        self.accounts_created += other.accounts_created;
+        self.accounts_locked += other.accounts_locked;
+    }
+}
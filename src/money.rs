@@ -0,0 +1,122 @@
+//! The amount type the engine core computes with.
+//!
+//! `Account`, `Transaction` and `Engine` are generic over a `Money` type and
+//! default to `f64`. `i64` counts integer minor units of `10^-PRECISION`,
+//! so 1.5 is stored as 15000 and no rounding ever happens. Any other type,
+//! such as a wrapper around a decimal type, can be plugged in by
+//! implementing `Money` for it.
+//!
+//! Fees, the journal and the reports still compute in `f64` and convert at
+//! the boundary with `to_f64` and `from_f64`.
+
+use crate::rounding::{Rounding, PRECISION};
+use std::fmt;
+use std::ops::{Add, Sub};
+
+pub trait Money:
+    Copy + Default + PartialOrd + Add<Output = Self> + Sub<Output = Self> + fmt::Debug
+{
+    /// `self` brought to `PRECISION` decimal places with `rounding`.
+    fn round(self, rounding: Rounding) -> Self;
+
+    /// `self` brought to `decimals` places, at most `PRECISION`.
+    fn round_to(self, rounding: Rounding, decimals: i32) -> Self;
+
+    fn from_f64(amount: f64) -> Self;
+
+    fn to_f64(self) -> f64;
+}
+
+impl Money for f64 {
+    fn round(self, rounding: Rounding) -> f64 {
+        rounding.round(self)
+    }
+
+    fn round_to(self, rounding: Rounding, decimals: i32) -> f64 {
+        rounding.round_to(self, decimals)
+    }
+
+    fn from_f64(amount: f64) -> f64 {
+        amount
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+const MINOR_UNITS: f64 = 10_i64.pow(PRECISION as u32) as f64;
+
+/// Integer minor units, already exact at `PRECISION`.
+impl Money for i64 {
+    fn round(self, _rounding: Rounding) -> i64 {
+        self
+    }
+
+    fn round_to(self, rounding: Rounding, decimals: i32) -> i64 {
+        let step = 10_i64.pow((PRECISION - decimals.clamp(0, PRECISION)) as u32);
+        let (units, rest) = (self.div_euclid(step), self.rem_euclid(step));
+        let up = match rounding {
+            Rounding::HalfUp => 2 * rest > step || (2 * rest == step && self > 0),
+            Rounding::HalfEven => 2 * rest > step || (2 * rest == step && units % 2 != 0),
+            Rounding::Floor => false,
+        };
+        (units + i64::from(up)) * step
+    }
+
+    /// Rounds half away from zero to the nearest minor unit.
+    fn from_f64(amount: f64) -> i64 {
+        (amount * MINOR_UNITS).round() as i64
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64 / MINOR_UNITS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{Engine, RejectReason};
+    use crate::transaction::{Transaction, TransactionLedger, Type};
+
+    #[test]
+    fn minor_units() {
+        assert_eq!(i64::from_f64(1.5), 15_000);
+        assert_eq!(i64::from_f64(0.00005), 1);
+        assert_eq!(15_000_i64.to_f64(), 1.5);
+    }
+
+    #[test]
+    fn minor_units_to_fewer_decimals() {
+        assert_eq!(12_345_000_i64.round_to(Rounding::HalfUp, 0), 12_350_000);
+        assert_eq!(12_345_000_i64.round_to(Rounding::HalfEven, 2), 12_345_000);
+        assert_eq!(15_000_i64.round_to(Rounding::HalfEven, 0), 20_000);
+        assert_eq!(25_000_i64.round_to(Rounding::HalfEven, 0), 20_000);
+        assert_eq!((-15_000_i64).round_to(Rounding::HalfUp, 0), -20_000);
+        assert_eq!((-14_999_i64).round_to(Rounding::Floor, 0), -20_000);
+        assert_eq!(12_345_i64.round_to(Rounding::HalfUp, 4), 12_345);
+    }
+
+    #[test]
+    fn engine_over_minor_units() {
+        let mut accounts = AccountsRepository::<i64>::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 1),
+            Transaction::new(2, Type::Deposit, 1, 2),
+            Transaction::new(3, Type::Withdrawal, 1, 4),
+            Transaction::new(1, Type::Dispute, 1, 0),
+        ]);
+        assert_eq!(
+            engine.rejections()[0].reason,
+            RejectReason::InsufficientFunds
+        );
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 2);
+        assert_eq!(account.held_balance(), 1);
+        assert_eq!(account.total_balance(), 3);
+    }
+}
@@ -0,0 +1,125 @@
+//! MessagePack input: one array of records, each a map with the CSV column
+//! names as keys or an array of the values in CSV column order.
+//!
+//! Records are decoded one at a time and checked the same way as CSV rows:
+//! one that cannot become a transaction is skipped with a warning whose
+//! line is the record's position in the array, counting from 1.
+
+use crate::parser::{Tenants, Warning, WarningKind};
+use crate::transaction::{Transaction, Type};
+use std::io::{self, Read};
+
+/// Reads the array of records from `reader`, split by their `tenant` key
+/// like `Parser::parse_tenants_with_warnings`.
+pub fn parse_tenants_with_warnings<R: Read>(mut reader: R) -> io::Result<(Tenants, Vec<Warning>)> {
+    let invalid =
+        |err: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+    let records = rmp::decode::read_array_len(&mut reader).map_err(|err| invalid(&err))?;
+    let mut tenants = Tenants::new();
+    let mut warnings = Vec::new();
+    for line in 1..=u64::from(records) {
+        let record = rmpv::decode::read_value(&mut reader).map_err(|err| invalid(&err))?;
+        let tenant = record.as_map().and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_str() == Some("tenant"))
+                .and_then(|(_, tenant)| tenant.as_str())
+                .map(String::from)
+        });
+        let warn = |kind| Warning {
+            line,
+            column: None,
+            kind,
+        };
+        // Values of rmpv cannot stand for unit variants such as the type, so
+        // the record is decoded once more from its own bytes.
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &record)?;
+        match rmp_serde::from_slice::<Transaction>(&bytes) {
+            Ok(tx) if matches!(tx.r#type(), Type::Custom(_)) => {
+                warnings.push(warn(WarningKind::UnknownType(tx.r#type().to_string())))
+            }
+            Ok(tx)
+                if matches!(tx.r#type(), Type::Deposit | Type::Withdrawal | Type::Bonus)
+                    && tx.optional_amount().is_none() =>
+            {
+                warnings.push(warn(WarningKind::EmptyAmount))
+            }
+            Ok(tx) => match tenant.as_deref() {
+                Some("") => tracing::debug!(tx = tx.id(), "skipped record without tenant"),
+                _ => tenants.entry(tenant).or_default().push(tx),
+            },
+            Err(err) => warnings.push(warn(WarningKind::Invalid(err.to_string()))),
+        }
+    }
+    Ok((tenants, warnings))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rmpv::Value;
+
+    fn record(entries: &[(&str, Value)]) -> Value {
+        Value::Map(
+            entries
+                .iter()
+                .map(|(key, value)| (Value::from(*key), value.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn records() {
+        let input = Value::Array(vec![
+            record(&[
+                ("type", "deposit".into()),
+                ("client", 1.into()),
+                ("tx", 1.into()),
+                ("amount", 5.5.into()),
+                ("merchant", "acme".into()),
+            ]),
+            Value::Array(vec!["withdrawal".into(), 1.into(), 2.into(), 2.into()]),
+            record(&[
+                ("type", "deposit".into()),
+                ("client", 1.into()),
+                ("tx", 3.into()),
+            ]),
+            record(&[
+                ("type", "refund".into()),
+                ("client", 1.into()),
+                ("tx", 4.into()),
+            ]),
+            record(&[
+                ("type", "dispute".into()),
+                ("client", 1.into()),
+                ("tx", 1.into()),
+                ("amount", Value::Nil),
+            ]),
+        ]);
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &input).unwrap();
+
+        let (tenants, warnings) = parse_tenants_with_warnings(bytes.as_slice()).unwrap();
+        let txs = &tenants[&None];
+        let parsed: Vec<(Type, u32, Option<f64>)> = txs
+            .iter()
+            .map(|tx| (tx.r#type(), tx.id(), tx.optional_amount()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 1, Some(5.5)),
+                (Type::Withdrawal, 2, Some(2.0)),
+                (Type::Dispute, 1, None),
+            ]
+        );
+        assert_eq!(txs[0].merchant().unwrap().as_str(), "acme");
+        let lines: Vec<u64> = warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, [3, 4]);
+        assert_eq!(warnings[0].kind, WarningKind::EmptyAmount);
+
+        bytes.pop();
+        assert!(parse_tenants_with_warnings(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! OFX (and QFX) bank and credit card statements as input.
+//!
+//! Both the SGML flavour of OFX 1.x, where simple elements are not closed,
+//! and the XML of OFX 2.x are read. Every statement line (`STMTTRN`) of an
+//! account becomes a deposit when its amount is positive and a withdrawal
+//! when it is negative. `FITID` gives the tx id, see `bank::tx_id`, `NAME`
+//! the counterparty and `DTPOSTED` the timestamp.
+
+use crate::bank::{label, tx_id, AccountMap};
+use crate::parser::{Warning, WarningKind};
+use crate::transaction::{Transaction, Type};
+use std::io::{self, Read};
+
+#[derive(Clone, Debug, Default)]
+pub struct Ofx {
+    clients: AccountMap,
+}
+
+/// What is known of the statement line being read.
+#[derive(Default)]
+struct Line {
+    line: u64,
+    amount: Option<String>,
+    id: Option<String>,
+    name: Option<String>,
+    posted: Option<String>,
+}
+
+impl Ofx {
+    pub fn new() -> Ofx {
+        Ofx::default()
+    }
+
+    /// Matches the `ACCTID` of each statement to a client.
+    pub fn with_clients(mut self, clients: AccountMap) -> Ofx {
+        self.clients = clients;
+        self
+    }
+
+    /// Reads a whole file, skipping statement lines that cannot be turned
+    /// into a transaction with a warning on the line they start on.
+    pub fn parse_with_warnings<R: Read>(
+        &self,
+        mut reader: R,
+    ) -> io::Result<(Vec<Transaction>, Vec<Warning>)> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+
+        let mut account = None;
+        let mut in_account = false;
+        let mut current: Option<Line> = None;
+        let mut transactions = Vec::new();
+        let mut warnings = Vec::new();
+        let mut line = 1;
+        let mut rest = input.as_str();
+        while let Some(start) = rest.find('<') {
+            line += rest[..start].matches('\n').count() as u64;
+            let Some(end) = rest[start..].find('>') else {
+                break;
+            };
+            let tag = rest[start + 1..start + end].trim();
+            rest = &rest[start + end + 1..];
+            let value_end = rest.find('<').unwrap_or(rest.len());
+            let value = unescape(rest[..value_end].trim());
+
+            match tag.to_ascii_uppercase().as_str() {
+                "BANKACCTFROM" | "CCACCTFROM" => in_account = true,
+                "/BANKACCTFROM" | "/CCACCTFROM" => in_account = false,
+                "ACCTID" if in_account => account = Some(value),
+                "STMTTRN" => {
+                    current = Some(Line {
+                        line,
+                        ..Line::default()
+                    })
+                }
+                "/STMTTRN" => {
+                    let Some(ended) = current.take() else {
+                        continue;
+                    };
+                    match self.transaction(&ended, account.as_deref()) {
+                        Ok(tx) => transactions.push(tx),
+                        Err(message) => warnings.push(Warning {
+                            line: ended.line,
+                            column: None,
+                            kind: WarningKind::Invalid(message),
+                        }),
+                    }
+                }
+                name => {
+                    if let Some(current) = &mut current {
+                        match name {
+                            "TRNAMT" => current.amount = Some(value),
+                            "FITID" => current.id = Some(value),
+                            "NAME" => current.name = Some(value),
+                            "DTPOSTED" => current.posted = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok((transactions, warnings))
+    }
+
+    fn transaction(&self, line: &Line, account: Option<&str>) -> Result<Transaction, String> {
+        let client = self
+            .clients
+            .client(account.ok_or("statement line outside of an account")?)?;
+        let amount = line
+            .amount
+            .as_deref()
+            .and_then(|amount| amount.replace(',', ".").parse::<f64>().ok())
+            .filter(|amount| amount.is_finite())
+            .ok_or_else(|| format!("invalid amount {:?}", line.amount))?;
+        let r#type = if amount < 0.0 {
+            Type::Withdrawal
+        } else {
+            Type::Deposit
+        };
+        let id = line.id.as_deref().ok_or("statement line without a FITID")?;
+        Ok(Transaction::new(tx_id(id), r#type, client, amount.abs())
+            .with_merchant(line.name.as_deref().and_then(label))
+            .with_timestamp(line.posted.as_deref().and_then(date_time)))
+    }
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Unix seconds of an OFX `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]` date time,
+/// UTC unless an offset in hours is given.
+fn date_time(value: &str) -> Option<u64> {
+    let (digits, zone) = match value.split_once('[') {
+        Some((digits, zone)) => (digits, Some(zone.trim_end_matches(']'))),
+        None => (value, None),
+    };
+    let digits = digits.split('.').next()?;
+    if digits.len() < 8 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let part = |from: usize, to: usize| digits.get(from..to).unwrap_or("00");
+    let utc = crate::timestamp::parse(&format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &digits[..4],
+        &digits[4..6],
+        &digits[6..8],
+        part(8, 10),
+        part(10, 12),
+        part(12, 14)
+    ))?;
+    let offset: f64 = match zone {
+        Some(zone) => zone.split(':').next()?.parse().ok()?,
+        None => 0.0,
+    };
+    u64::try_from(utc as i64 - (offset * 3600.0) as i64).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100\n\
+DATA:OFXSGML\n\
+\n\
+<OFX>\n\
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>\n\
+<BANKACCTFROM><BANKID>121099999<ACCTID>999988<ACCTTYPE>CHECKING</BANKACCTFROM>\n\
+<BANKTRANLIST>\n\
+<STMTTRN>\n\
+<TRNTYPE>CREDIT\n\
+<DTPOSTED>20240601120000[-5:EST]\n\
+<TRNAMT>200.00\n\
+<FITID>1001\n\
+<NAME>Payroll AT&amp;T\n\
+</STMTTRN>\n\
+<STMTTRN>\n\
+<TRNTYPE>DEBIT\n\
+<DTPOSTED>20240602\n\
+<TRNAMT>-50,25\n\
+<FITID>A-77\n\
+</STMTTRN>\n\
+<STMTTRN>\n\
+<TRNTYPE>DEBIT\n\
+<TRNAMT>-1.00\n\
+</STMTTRN>\n\
+</BANKTRANLIST>\n\
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>\n\
+</OFX>\n";
+
+    #[test]
+    fn statement_lines() {
+        let clients = AccountMap::read("999988,4\n".as_bytes()).unwrap();
+        let (txs, warnings) = Ofx::new()
+            .with_clients(clients)
+            .parse_with_warnings(SGML.as_bytes())
+            .unwrap();
+        let parsed: Vec<(u32, Type, u16, f64, Option<u64>)> = txs
+            .iter()
+            .map(|tx| {
+                (
+                    tx.id(),
+                    tx.r#type(),
+                    tx.account_id(),
+                    tx.amount(),
+                    tx.timestamp(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (1001, Type::Deposit, 4, 200.0, Some(1_717_261_200)),
+                (
+                    tx_id("A-77"),
+                    Type::Withdrawal,
+                    4,
+                    50.25,
+                    Some(1_717_286_400)
+                ),
+            ]
+        );
+        assert_eq!(txs[0].merchant().unwrap().as_str(), "Payroll AT&T");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 21);
+    }
+
+    #[test]
+    fn xml() {
+        let input = r#"<?xml version="1.0"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+<CCACCTFROM><ACCTID>12</ACCTID></CCACCTFROM>
+<BANKTRANLIST>
+<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><TRNAMT>-9.99</TRNAMT><FITID>5</FITID></STMTTRN>
+</BANKTRANLIST>
+</CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>"#;
+        let (txs, warnings) = Ofx::new().parse_with_warnings(input.as_bytes()).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(
+            (
+                txs[0].id(),
+                txs[0].r#type(),
+                txs[0].account_id(),
+                txs[0].amount()
+            ),
+            (5, Type::Withdrawal, 12, 9.99)
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Timestamp order within one input, for feeds whose row order is not the
+//! order the events happened in.
+//!
+//! A row without a timestamp counts as at the time of the row before it,
+//! as in `merge`, so that it stays with the row it followed.
+
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do about an input that is not in timestamp order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimeOrder {
+    /// Refuse to process it.
+    Verify,
+    /// Sort it by timestamp first, keeping the input order of rows at the
+    /// same time.
+    Sort,
+}
+
+impl FromStr for TimeOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "verify" => Ok(TimeOrder::Verify),
+            "sort" => Ok(TimeOrder::Sort),
+            _ => Err(format!(
+                "invalid time order: {} (expected verify or sort)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimeOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeOrder::Verify => "verify",
+            TimeOrder::Sort => "sort",
+        })
+    }
+}
+
+/// A row with an earlier timestamp than a row before it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct OutOfOrder {
+    /// 1-based position of the row among the transactions.
+    pub row: usize,
+    pub r#type: Type,
+    pub client: u16,
+    pub tx: u32,
+    pub timestamp: u64,
+    /// The latest timestamp of the rows before it.
+    pub after: u64,
+}
+
+/// Every row of `transactions` that is earlier than a row before it.
+pub fn out_of_order<M: Money>(transactions: &[Transaction<M>]) -> Vec<OutOfOrder> {
+    let mut latest = None;
+    let mut late = Vec::new();
+    for (index, tx) in transactions.iter().enumerate() {
+        let Some(timestamp) = tx.timestamp() else {
+            continue;
+        };
+        match latest {
+            Some(after) if timestamp < after => late.push(OutOfOrder {
+                row: index + 1,
+                r#type: tx.r#type(),
+                client: tx.account_id(),
+                tx: tx.id(),
+                timestamp,
+                after,
+            }),
+            _ => latest = Some(timestamp),
+        }
+    }
+    late
+}
+
+/// Sorts `transactions` by timestamp, stably.
+pub fn sort_by_timestamp<M: Money>(transactions: &mut Vec<Transaction<M>>) {
+    let mut last = 0;
+    let mut keyed: Vec<_> = transactions
+        .drain(..)
+        .map(|tx| {
+            last = tx.timestamp().unwrap_or(last);
+            (last, tx)
+        })
+        .collect();
+    keyed.sort_by_key(|(timestamp, _)| *timestamp);
+    transactions.extend(keyed.into_iter().map(|(_, tx)| tx));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn late_rows() {
+        let at =
+            |id, timestamp| Transaction::new(id, Type::Deposit, 1, 1.0).with_timestamp(timestamp);
+        let mut transactions = vec![
+            at(1, Some(10)),
+            at(2, Some(30)),
+            at(3, None),
+            at(4, Some(20)),
+            at(5, Some(30)),
+            at(6, Some(5)),
+        ];
+        let late: Vec<(usize, u32, u64)> = out_of_order(&transactions)
+            .iter()
+            .map(|row| (row.row, row.tx, row.after))
+            .collect();
+        assert_eq!(late, [(4, 4, 30), (6, 6, 30)]);
+
+        sort_by_timestamp(&mut transactions);
+        let ids: Vec<u32> = transactions.iter().map(Transaction::id).collect();
+        assert_eq!(ids, [6, 1, 4, 2, 3, 5]);
+        assert!(out_of_order(&transactions).is_empty());
+        assert_eq!("sort".parse(), Ok(TimeOrder::Sort));
+    }
+}
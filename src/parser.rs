@@ -1,29 +1,332 @@
-use crate::transaction::Transaction;
-use csv::ReaderBuilder;
-use std::{fmt::Display, str::FromStr};
+use crate::transaction::{Transaction, Type};
+use csv::{ReaderBuilder, StringRecord};
+use std::{collections::BTreeMap, fmt, fmt::Display, fs::File, io, str::FromStr};
 
 use serde::{Deserialize, Deserializer};
 
-pub struct Parser {}
+/// Reads transactions from CSV. The associated functions read the built-in
+/// types only; a `Parser` configured with `with_custom_type` also keeps the
+/// rows of custom types.
+#[derive(Clone, Debug, Default)]
+pub struct Parser {
+    custom_types: Vec<Type>,
+}
+
+/// Transactions by tenant, see `Parser::parse_tenants`.
+pub type Tenants = BTreeMap<Option<String>, Vec<Transaction>>;
+
+/// A problem with one row of the input that did not stop parsing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// Line the row starts on, counting the header as line 1.
+    pub line: u64,
+    /// Column (1-based field) the problem is in, when it is in one.
+    pub column: Option<u64>,
+    pub kind: WarningKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WarningKind {
+    /// More fields than the header names. They are ignored, the row is kept.
+    TrailingColumns(usize),
+    /// A deposit, withdrawal or bonus without an amount. The row is skipped.
+    EmptyAmount,
+    /// A type the engine does not know. The row is skipped.
+    UnknownType(String),
+    /// Any other value that could not be read. The row is skipped.
+    Invalid(String),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ", column {}", column)?;
+        }
+        match &self.kind {
+            WarningKind::TrailingColumns(count) => {
+                write!(f, ": {} trailing column(s) ignored", count)
+            }
+            WarningKind::EmptyAmount => write!(f, ": empty amount, row skipped"),
+            WarningKind::UnknownType(value) => {
+                write!(f, ": unknown type {:?}, row skipped", value)
+            }
+            WarningKind::Invalid(message) => write!(f, ": {}, row skipped", message),
+        }
+    }
+}
 
 impl Parser {
-    pub fn parse(file_path: &str) -> Result<Vec<Transaction>, csv::Error> {
+    pub fn new() -> Parser {
+        Default::default()
+    }
+
+    /// Keeps the rows of the custom `r#type` instead of skipping them as
+    /// unknown, see `Type::custom`.
+    pub fn with_custom_type(mut self, r#type: Type) -> Parser {
+        if matches!(r#type, Type::Custom(_)) && !self.custom_types.contains(&r#type) {
+            self.custom_types.push(r#type);
+        }
+        self
+    }
+
+    /// Like `parse_tenants_with_warnings`, keeping the rows of this
+    /// parser's custom types.
+    pub fn read<R: io::Read>(&self, reader: R) -> Result<(Tenants, Vec<Warning>), csv::Error> {
         let mut rdr = ReaderBuilder::new()
             .flexible(true)
             .trim(csv::Trim::All)
-            .from_path(file_path)?;
+            .from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        let mut error = None;
+        let parsed = self.tenants(&headers, until_io_error(rdr.records(), &mut error));
+        match error {
+            Some(err) => Err(err),
+            None => Ok(parsed),
+        }
+    }
 
+    #[tracing::instrument]
+    pub fn parse(file_path: &str) -> Result<Vec<Transaction>, csv::Error> {
+        Self::parse_reader(File::open(file_path)?)
+    }
+
+    /// Reads a whole CSV document with a header row, skipping malformed rows.
+    pub fn parse_reader<R: io::Read>(reader: R) -> Result<Vec<Transaction>, csv::Error> {
+        Ok(Self::parse_reader_with_warnings(reader)?.0)
+    }
+
+    /// Like `parse_reader`, also returning a warning for every row that was
+    /// skipped or only partly read.
+    pub fn parse_reader_with_warnings<R: io::Read>(
+        reader: R,
+    ) -> Result<(Vec<Transaction>, Vec<Warning>), csv::Error> {
         let mut result = Vec::new();
-        for r in rdr.deserialize() {
-            match r {
-                Err(..) => continue,
-                Ok(tx) => result.push(tx),
+        let mut warnings = Vec::new();
+        Self::read_rows(reader, &mut warnings, |_, tx| result.push(tx))?;
+        Ok((result, warnings))
+    }
+
+    /// Like `parse_reader`, but splits the rows by their `tenant` column,
+    /// keeping the input order within each tenant. Without such a column
+    /// every row ends up under `None`; with it, rows leaving it blank are
+    /// dropped.
+    pub fn parse_tenants<R: io::Read>(reader: R) -> Result<Tenants, csv::Error> {
+        Ok(Self::parse_tenants_with_warnings(reader)?.0)
+    }
+
+    /// Like `parse_tenants`, also returning the warnings `parse_reader_with_warnings`
+    /// would.
+    pub fn parse_tenants_with_warnings<R: io::Read>(
+        reader: R,
+    ) -> Result<(Tenants, Vec<Warning>), csv::Error> {
+        Parser::new().read(reader)
+    }
+
+    /// `read_records` of the built-in types into transactions split by
+    /// their `tenant` column.
+    pub(crate) fn read_tenants<I>(headers: &StringRecord, records: I) -> (Tenants, Vec<Warning>)
+    where
+        I: IntoIterator<Item = Result<StringRecord, csv::Error>>,
+    {
+        Parser::new().tenants(headers, records)
+    }
+
+    fn tenants<I>(&self, headers: &StringRecord, records: I) -> (Tenants, Vec<Warning>)
+    where
+        I: IntoIterator<Item = Result<StringRecord, csv::Error>>,
+    {
+        let mut result = Tenants::new();
+        let mut warnings = Vec::new();
+        self.read_records(headers, records, &mut warnings, |tenant, tx| match tenant {
+            Some("") => tracing::debug!(tx = tx.id(), "skipped row without tenant"),
+            _ => result.entry(tenant.map(String::from)).or_default().push(tx),
+        });
+        (result, warnings)
+    }
+
+    fn read_rows<R, F>(reader: R, warnings: &mut Vec<Warning>, sink: F) -> Result<(), csv::Error>
+    where
+        R: io::Read,
+        F: FnMut(Option<&str>, Transaction),
+    {
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        let mut error = None;
+        Parser::new().read_records(
+            &headers,
+            until_io_error(rdr.records(), &mut error),
+            warnings,
+            sink,
+        );
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Turns already split, trimmed `records` into transactions for `sink`,
+    /// whatever format they were split from. Records should carry their
+    /// position so that warnings can point at their line.
+    #[tracing::instrument(skip_all, fields(rows = tracing::field::Empty, skipped = tracing::field::Empty))]
+    fn read_records<I, F>(
+        &self,
+        headers: &StringRecord,
+        records: I,
+        warnings: &mut Vec<Warning>,
+        mut sink: F,
+    ) where
+        I: IntoIterator<Item = Result<StringRecord, csv::Error>>,
+        F: FnMut(Option<&str>, Transaction),
+    {
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let tenant_column = column("tenant");
+        let type_column = column("type");
+        let amount_column = column("amount");
+        let mut rows = 0;
+        let mut skipped = 0;
+        for record in records {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    warnings.push(Warning {
+                        line: err.position().map_or(0, |p| p.line()),
+                        column: None,
+                        kind: WarningKind::Invalid(err.to_string()),
+                    });
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let line = record.position().map_or(0, |p| p.line());
+            let warn = |column: Option<usize>, kind| Warning {
+                line,
+                column: column.map(|column| column as u64 + 1),
+                kind,
+            };
+            if record.len() > headers.len() {
+                warnings.push(warn(
+                    Some(headers.len()),
+                    WarningKind::TrailingColumns(record.len() - headers.len()),
+                ));
             }
+            let tx = match record.deserialize::<Transaction>(Some(headers)) {
+                Ok(tx) => tx,
+                Err(err) => {
+                    tracing::debug!(line, %err, "skipped malformed row");
+                    let value = type_column.and_then(|column| record.get(column));
+                    warnings.push(match value {
+                        Some(value) if value.parse::<Type>().is_err() => {
+                            warn(type_column, WarningKind::UnknownType(value.to_string()))
+                        }
+                        _ => {
+                            let (column, message) = match err.kind() {
+                                csv::ErrorKind::Deserialize { err, .. } => (
+                                    err.field().map(|field| field as usize),
+                                    err.kind().to_string(),
+                                ),
+                                _ => (None, err.to_string()),
+                            };
+                            warn(column, WarningKind::Invalid(message))
+                        }
+                    });
+                    skipped += 1;
+                    continue;
+                }
+            };
+            if matches!(tx.r#type(), Type::Custom(_)) && !self.custom_types.contains(&tx.r#type()) {
+                tracing::debug!(line, "skipped row of unknown type");
+                let value = tx.r#type().to_string();
+                warnings.push(warn(type_column, WarningKind::UnknownType(value)));
+                skipped += 1;
+                continue;
+            }
+            let needs_amount =
+                matches!(tx.r#type(), Type::Deposit | Type::Withdrawal | Type::Bonus);
+            if needs_amount && tx.optional_amount().is_none() {
+                tracing::debug!(line, "skipped row without amount");
+                warnings.push(warn(amount_column, WarningKind::EmptyAmount));
+                skipped += 1;
+                continue;
+            }
+            tracing::trace!(line, tx = tx.id(), "parsed");
+            rows += 1;
+            sink(tenant_column.and_then(|column| record.get(column)), tx)
+        }
+        let span = tracing::Span::current();
+        span.record("rows", rows);
+        span.record("skipped", skipped);
+    }
+
+    /// Lazily reads headerless `type,client,tx,amount` records of the built-in
+    /// types, as used by the line protocol in server mode. Malformed lines
+    /// are skipped.
+    pub fn stream<R: io::Read>(reader: R) -> impl Iterator<Item = Transaction> {
+        Self::try_stream(reader).filter_map(Result::ok)
+    }
+
+    /// Like `stream`, but yields an error for every malformed line, e.g. to
+    /// hand to `TransactionProcessor::process_fallible`.
+    pub fn try_stream<R: io::Read>(
+        reader: R,
+    ) -> impl Iterator<Item = Result<Transaction, csv::Error>> {
+        ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader)
+            .into_records()
+            .map(|record| {
+                let record = record?;
+                record.deserialize::<(BuiltIn,)>(None)?;
+                record.deserialize(None)
+            })
+    }
+
+    /// Parses a single line-protocol record, either a headerless CSV row or a
+    /// JSON object using the CSV column names as keys.
+    #[cfg(feature = "json")]
+    pub fn parse_line(line: &str) -> Option<Transaction> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            return serde_json::from_str(line)
+                .ok()
+                .filter(|tx: &Transaction| !matches!(tx.r#type(), Type::Custom(_)));
         }
-        Ok(result)
+        Self::stream(line.as_bytes()).next()
     }
 }
 
+/// The type in the first field of a headerless record, if it is built in.
+struct BuiltIn;
+
+impl<'de> Deserialize<'de> for BuiltIn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse::<Type>()
+            .map(|_| BuiltIn)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// `records` up to the first I/O error, which is left in `error`. Nothing
+/// can be read past it, so it fails the whole read rather than one row.
+fn until_io_error<'a, I>(
+    records: I,
+    error: &'a mut Option<csv::Error>,
+) -> impl Iterator<Item = Result<StringRecord, csv::Error>> + 'a
+where
+    I: Iterator<Item = Result<StringRecord, csv::Error>> + 'a,
+{
+    records.map_while(move |record| match record {
+        Err(err) if err.is_io_error() => {
+            *error = Some(err);
+            None
+        }
+        record => Some(record),
+    })
+}
+
 pub fn arbitrary_tx_amount<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
     D: Deserializer<'de>,
@@ -43,3 +346,140 @@ where
         Amount::String(s) => s.parse::<T>().map_err(serde::de::Error::custom),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_error_fails_the_read() {
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+            }
+        }
+        let input = io::Read::chain(
+            "type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(),
+            Broken,
+        );
+        let err = Parser::parse_tenants_with_warnings(input).err().unwrap();
+        assert!(err.is_io_error());
+    }
+
+    #[test]
+    fn try_stream() {
+        let rows: Vec<_> =
+            Parser::try_stream("deposit,1,1,5.0\nbogus,1,2,1.0\nwithdrawal,1,3,1.0\n".as_bytes())
+                .map(|row| {
+                    row.map(|tx| tx.id())
+                        .map_err(|err| err.position().map(|p| p.line()))
+                })
+                .collect();
+        assert_eq!(rows, [Ok(1), Err(Some(2)), Ok(3)]);
+    }
+
+    #[test]
+    fn tenants() {
+        let input = "tenant,type,client,tx,amount\n\
+                     acme,deposit,1,1,5.0\n\
+                     globex,deposit,1,1,2.0\n\
+                     acme,withdrawal,1,2,1.0\n\
+                     ,deposit,1,3,1.0\n";
+        let tenants = Parser::parse_tenants(input.as_bytes()).unwrap();
+        let sizes: Vec<(Option<&str>, usize)> = tenants
+            .iter()
+            .map(|(tenant, txs)| (tenant.as_deref(), txs.len()))
+            .collect();
+        assert_eq!(sizes, [(Some("acme"), 2), (Some("globex"), 1)]);
+
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\n";
+        let tenants = Parser::parse_tenants(input.as_bytes()).unwrap();
+        assert_eq!(tenants[&None].len(), 1);
+    }
+
+    #[test]
+    fn warnings() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,\n\
+                     deposit,1,3,1.0,extra\n\
+                     refund,1,4,1.0\n\
+                     deposit,1,x,1.0\n\
+                     dispute,1,1\n";
+        let (txs, warnings) = Parser::parse_reader_with_warnings(input.as_bytes()).unwrap();
+        let ids: Vec<u32> = txs.iter().map(|tx| tx.id()).collect();
+        assert_eq!(ids, [1, 3, 1]);
+        let positions: Vec<(u64, Option<u64>)> = warnings
+            .iter()
+            .map(|warning| (warning.line, warning.column))
+            .collect();
+        assert_eq!(
+            positions,
+            [(3, Some(4)), (4, Some(5)), (5, Some(1)), (6, Some(3))]
+        );
+        assert_eq!(warnings[0].kind, WarningKind::EmptyAmount);
+        assert_eq!(warnings[1].kind, WarningKind::TrailingColumns(1));
+        assert_eq!(
+            warnings[2].to_string(),
+            "line 5, column 1: unknown type \"refund\", row skipped"
+        );
+        assert!(matches!(warnings[3].kind, WarningKind::Invalid(_)));
+    }
+
+    #[test]
+    fn custom_types() {
+        let payout = Type::custom("payout").unwrap();
+        let input = "type,client,tx,amount\n\
+                     payout,1,1,5.0\n\
+                     rebate,1,2,1.0\n";
+        let (txs, _) = Parser::parse_reader_with_warnings(input.as_bytes()).unwrap();
+        assert!(txs.is_empty());
+        assert!(Parser::parse_line("payout,1,1,5.0").is_none());
+
+        let (tenants, warnings) = Parser::new()
+            .with_custom_type(payout)
+            .read(input.as_bytes())
+            .unwrap();
+        let txs = &tenants[&None];
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].r#type(), payout);
+        assert_eq!(txs[0].r#type().to_string(), "payout");
+        assert_eq!(
+            warnings[0].kind,
+            WarningKind::UnknownType("rebate".to_string())
+        );
+    }
+
+    #[test]
+    fn merchants() {
+        let input = "type,client,tx,amount,merchant\n\
+                     deposit,1,1,5.0,acme\n\
+                     deposit,1,2,5.0,\n\
+                     deposit,1,3,5.0,a merchant name far too long to fit\n";
+        let txs = Parser::parse_reader(input.as_bytes()).unwrap();
+        assert_eq!(txs.len(), 3);
+        assert_eq!(txs[0].merchant().unwrap().as_str(), "acme");
+        assert!(txs[1].merchant().is_none());
+        assert_eq!(
+            txs[2].merchant().unwrap().as_str(),
+            "a merchant name far too"
+        );
+
+        let input = "type,client,tx,amount,counterparty\ndeposit,1,1,5.0,globex\n";
+        let txs = Parser::parse_reader(input.as_bytes()).unwrap();
+        assert_eq!(txs[0].merchant().unwrap().as_str(), "globex");
+    }
+
+    #[test]
+    fn timestamps() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,1717200000\n\
+                     deposit,1,2,5.0,2024-06-01T00:00:01Z\n\
+                     dispute,1,1,,\n\
+                     deposit,1,3,5.0,last tuesday\n";
+        let txs = Parser::parse_reader(input.as_bytes()).unwrap();
+        let timestamps: Vec<Option<u64>> = txs.iter().map(|tx| tx.timestamp()).collect();
+        assert_eq!(timestamps, [Some(1_717_200_000), Some(1_717_200_001), None]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Handlers for custom transaction types loaded from WebAssembly modules,
+//! so that partner-specific behavior ships without recompiling the crate.
+//!
+//! A plugin is a module that exports
+//!
+//! ```text
+//! apply(client: i64, tx: i64, amount: f64, available: f64, held: f64, locked: i32) -> i32
+//! ```
+//!
+//! The engine calls it for every transaction of the custom type it is
+//! registered for, with the transaction and its client's account before it
+//! (`locked` is 1 for a locked account, 0 otherwise). The plugin hands back
+//! how the account changes by calling the imported `env.delta(available:
+//! f64, held: f64)`, as often as it likes; the moves add up, and the total
+//! follows. It returns 0 to apply them, 1 to reject the transaction for
+//! insufficient funds, 2 for a locked account, and anything else to reject
+//! it as invalid.
+//!
+//! Plugins are sandboxed: they see nothing but their arguments, import
+//! nothing but `env.delta`, and run on `FUEL` units of fuel per
+//! transaction. One that traps or runs out rejects the transaction as
+//! invalid. Moves that would take the available or held funds below zero,
+//! or change a locked account, are refused as the account would refuse a
+//! withdrawal.
+
+use crate::account::Account;
+use crate::engine::{Handler, RejectReason};
+use crate::money::Money;
+use crate::transaction::Transaction;
+use std::sync::Mutex;
+use wasmi::{Caller, Config, Linker, Module, Store, TypedFunc};
+
+/// Fuel a plugin may burn per transaction, roughly one unit per
+/// instruction.
+pub const FUEL: u64 = 1_000_000;
+
+/// What a plugin asked to move while handling one transaction.
+#[derive(Default)]
+struct Delta {
+    available: f64,
+    held: f64,
+}
+
+type Apply = TypedFunc<(i64, i64, f64, f64, f64, i32), i32>;
+
+pub struct Plugin {
+    store: Mutex<Store<Delta>>,
+    apply: Apply,
+}
+
+impl Plugin {
+    /// Compiles and instantiates the module `wasm`, in the binary format.
+    pub fn load(wasm: &[u8]) -> Result<Plugin, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|err| err.to_string())?;
+        let mut store = Store::new(&engine, Delta::default());
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap(
+                "env",
+                "delta",
+                |mut caller: Caller<'_, Delta>, available: f64, held: f64| {
+                    let delta = caller.data_mut();
+                    delta.available += available;
+                    delta.held += held;
+                },
+            )
+            .map_err(|err| err.to_string())?;
+        store.set_fuel(FUEL).map_err(|err| err.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|err| err.to_string())?;
+        let apply = instance
+            .get_typed_func(&store, "apply")
+            .map_err(|err| format!("no usable apply export: {}", err))?;
+        Ok(Plugin {
+            store: Mutex::new(store),
+            apply,
+        })
+    }
+}
+
+impl<M: Money> Handler<M> for Plugin {
+    fn apply(&self, tx: &Transaction<M>, account: &mut Account<M>) -> Result<(), RejectReason> {
+        let mut store = self.store.lock().unwrap();
+        *store.data_mut() = Delta::default();
+        store.set_fuel(FUEL).map_err(|_| RejectReason::Invalid)?;
+        let arguments = (
+            i64::from(tx.account_id()),
+            i64::from(tx.id()),
+            tx.optional_amount().map_or(0.0, M::to_f64),
+            account.available_balance().to_f64(),
+            account.held_balance().to_f64(),
+            i32::from(account.locked()),
+        );
+        match self.apply.call(&mut *store, arguments) {
+            Ok(0) => {}
+            Ok(1) => return Err(RejectReason::InsufficientFunds),
+            Ok(2) => return Err(RejectReason::LockedAccount),
+            Ok(_) => return Err(RejectReason::Invalid),
+            Err(err) => {
+                log::warn!("plugin failed on tx {}: {}", tx.id(), err);
+                return Err(RejectReason::Invalid);
+            }
+        }
+        let delta = store.data();
+        if !delta.available.is_finite() || !delta.held.is_finite() {
+            return Err(RejectReason::Invalid);
+        }
+        account.adjust(M::from_f64(delta.available), M::from_f64(delta.held))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::Engine;
+    use crate::transaction::{TransactionLedger, Type};
+
+    /// Moves the amount from the available to the held funds, if covered.
+    const RESERVE: &str = r#"
+        (module
+          (import "env" "delta" (func $delta (param f64 f64)))
+          (func (export "apply")
+                (param $client i64) (param $tx i64) (param $amount f64)
+                (param $available f64) (param $held f64) (param $locked i32)
+                (result i32)
+            (if (f64.gt (local.get $amount) (local.get $available))
+              (then (return (i32.const 1))))
+            (call $delta (f64.neg (local.get $amount)) (local.get $amount))
+            (i32.const 0)))
+    "#;
+
+    const ENDLESS: &str = r#"
+        (module
+          (func (export "apply")
+                (param i64 i64 f64 f64 f64 i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn plugins_handle_custom_types() {
+        let reserve = Type::custom("reserve").unwrap();
+        let spin = Type::custom("spin").unwrap();
+        let reserving = Plugin::load(&wat::parse_str(RESERVE).unwrap()).unwrap();
+        let spinning = Plugin::load(&wat::parse_str(ENDLESS).unwrap()).unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_handler(reserve, &reserving)
+            .unwrap()
+            .with_handler(spin, &spinning)
+            .unwrap();
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, reserve, 1, 4.0),
+            Transaction::new(3, reserve, 1, 7.0),
+            Transaction::new(4, spin, 1, 1.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [RejectReason::InsufficientFunds, RejectReason::Invalid]
+        );
+        let account = accounts.get(1).unwrap();
+        assert_eq!(account.available_balance(), 6.0);
+        assert_eq!(account.held_balance(), 4.0);
+        assert_eq!(account.total_balance(), 10.0);
+
+        assert!(Plugin::load(b"not wasm").is_err());
+        let no_apply = wat::parse_str("(module)").unwrap();
+        assert!(Plugin::load(&no_apply).is_err());
+    }
+}
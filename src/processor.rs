@@ -0,0 +1,245 @@
+//! One interface over the ways transactions can be processed.
+//!
+//! `Engine` is the standard `TransactionProcessor`. Alternatives wrap or
+//! replace it behind the same trait, so the CLI and library users can swap
+//! them without changing how they feed transactions in; `Strict` is one
+//! that refuses to go on after the first rejection that is not a benign
+//! duplicate.
+//!
+//! Input that is still being parsed can be fed in as it is read, errors and
+//! all, with `process_fallible`; an `ErrorPolicy` says what becomes of the
+//! items that failed to parse.
+
+use crate::engine::{Engine, RejectReason, Rejection};
+use crate::metrics::EngineMetrics;
+use crate::money::Money;
+use crate::transaction::Transaction;
+
+/// Transactions handed to `process` at once by `process_fallible`.
+const FALLIBLE_BATCH_SIZE: usize = 1024;
+
+/// What `process_fallible` does with input that failed to parse.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Drops it and goes on.
+    #[default]
+    Skip,
+    /// Goes on and returns every error at the end.
+    Collect,
+    /// Stops at the first error, having applied everything before it.
+    Abort,
+}
+
+pub trait TransactionProcessor<M: Money = f64> {
+    /// Applies `transactions` in order.
+    fn process(&mut self, transactions: &[Transaction<M>]);
+
+    /// Every transaction refused so far, in processing order.
+    fn rejections(&self) -> &[Rejection];
+
+    fn metrics(&self) -> &EngineMetrics;
+
+    /// Applies the transactions of `input` in order, such as the rows of
+    /// `Parser::try_stream`, handling the errors in it by `policy`. Returns
+    /// the errors collected, or the one `ErrorPolicy::Abort` stopped at.
+    fn process_fallible<I, E>(&mut self, input: I, policy: ErrorPolicy) -> Result<Vec<E>, E>
+    where
+        I: IntoIterator<Item = Result<Transaction<M>, E>>,
+        Self: Sized,
+    {
+        let mut batch = Vec::with_capacity(FALLIBLE_BATCH_SIZE);
+        let mut errors = Vec::new();
+        for item in input {
+            match item {
+                Ok(tx) => {
+                    batch.push(tx);
+                    if batch.len() == FALLIBLE_BATCH_SIZE {
+                        self.process(&batch);
+                        batch.clear();
+                    }
+                }
+                Err(err) => match policy {
+                    ErrorPolicy::Skip => log::debug!("skipped input that failed to parse"),
+                    ErrorPolicy::Collect => errors.push(err),
+                    ErrorPolicy::Abort => {
+                        self.process(&batch);
+                        return Err(err);
+                    }
+                },
+            }
+        }
+        self.process(&batch);
+        Ok(errors)
+    }
+}
+
+impl<M: Money> TransactionProcessor<M> for Engine<'_, M> {
+    fn process(&mut self, transactions: &[Transaction<M>]) {
+        Engine::process(self, transactions)
+    }
+
+    fn rejections(&self) -> &[Rejection] {
+        Engine::rejections(self)
+    }
+
+    fn metrics(&self) -> &EngineMetrics {
+        Engine::metrics(self)
+    }
+}
+
+/// Stops at the first transaction the wrapped processor rejects: that one
+/// and everything after it, in this and any later batch, is left
+/// unprocessed. Rejections for a reason it tolerates, by default only
+/// `DuplicateTx` since a repeated transaction changes nothing, are let
+/// through like any other.
+pub struct Strict<P> {
+    inner: P,
+    tolerated: Vec<RejectReason>,
+    halted: Option<Rejection>,
+}
+
+impl<P> Strict<P> {
+    pub fn new(inner: P) -> Strict<P> {
+        Strict {
+            inner,
+            tolerated: vec![RejectReason::DuplicateTx],
+            halted: None,
+        }
+    }
+
+    /// Goes on after rejections for `reasons`, and halts at all others.
+    pub fn tolerating(mut self, reasons: &[RejectReason]) -> Strict<P> {
+        self.tolerated = reasons.to_vec();
+        self
+    }
+
+    /// The rejection processing stopped at, if any.
+    pub fn halted(&self) -> Option<&Rejection> {
+        self.halted.as_ref()
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<M: Money, P: TransactionProcessor<M>> TransactionProcessor<M> for Strict<P> {
+    fn process(&mut self, transactions: &[Transaction<M>]) {
+        for tx in transactions {
+            if self.halted.is_some() {
+                return;
+            }
+            let rejected = self.inner.rejections().len();
+            self.inner.process(std::slice::from_ref(tx));
+            self.halted = self
+                .inner
+                .rejections()
+                .get(rejected)
+                .filter(|rejection| !self.tolerated.contains(&rejection.reason))
+                .copied();
+        }
+    }
+
+    fn rejections(&self) -> &[Rejection] {
+        self.inner.rejections()
+    }
+
+    fn metrics(&self) -> &EngineMetrics {
+        self.inner.metrics()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::transaction::{TransactionLedger, Type};
+
+    fn run(processor: &mut dyn TransactionProcessor) {
+        processor.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Withdrawal, 1, 6.0),
+            Transaction::new(3, Type::Deposit, 1, 1.0),
+        ]);
+        processor.process(&[Transaction::new(4, Type::Deposit, 1, 1.0)]);
+    }
+
+    #[test]
+    fn engine_goes_on() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        run(&mut Engine::new(&mut tx_ledger, &mut accounts));
+        assert_eq!(accounts.get(1).unwrap().available_balance(), 7.0);
+    }
+
+    #[test]
+    fn fallible_input() {
+        let input = || {
+            vec![
+                Ok(Transaction::new(1, Type::Deposit, 1, 5.0)),
+                Err("line 3"),
+                Ok(Transaction::new(2, Type::Deposit, 1, 1.0)),
+                Err("line 5"),
+            ]
+        };
+        for (policy, result, balance) in [
+            (ErrorPolicy::Skip, Ok(vec![]), 6.0),
+            (ErrorPolicy::Collect, Ok(vec!["line 3", "line 5"]), 6.0),
+            (ErrorPolicy::Abort, Err("line 3"), 5.0),
+        ] {
+            let mut accounts = AccountsRepository::new();
+            let mut tx_ledger = TransactionLedger::new();
+            let mut engine = Engine::new(&mut tx_ledger, &mut accounts);
+            assert_eq!(engine.process_fallible(input(), policy), result);
+            assert_eq!(accounts.get(1).unwrap().available_balance(), balance);
+        }
+    }
+
+    #[test]
+    fn strict_stops_at_the_first_rejection() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut strict = Strict::new(Engine::new(&mut tx_ledger, &mut accounts));
+        run(&mut strict);
+        let halted = strict.halted().unwrap();
+        assert_eq!(
+            (halted.tx, halted.reason),
+            (2, RejectReason::InsufficientFunds)
+        );
+        assert_eq!(strict.metrics().deposit.applied, 1);
+        drop(strict);
+        assert_eq!(accounts.get(1).unwrap().available_balance(), 5.0);
+    }
+
+    #[test]
+    fn strict_tolerates_duplicates() {
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut strict = Strict::new(Engine::new(&mut tx_ledger, &mut accounts));
+        strict.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 1, 1.0),
+        ]);
+        assert!(strict.halted().is_none());
+        assert_eq!(strict.rejections()[0].reason, RejectReason::DuplicateTx);
+
+        let mut strict = strict.tolerating(&[RejectReason::InsufficientFunds]);
+        strict.process(&[
+            Transaction::new(3, Type::Withdrawal, 1, 9.0),
+            Transaction::new(2, Type::Deposit, 1, 1.0),
+            Transaction::new(4, Type::Deposit, 1, 1.0),
+        ]);
+        assert_eq!(strict.halted().unwrap().tx, 2);
+        drop(strict);
+        assert_eq!(accounts.get(1).unwrap().available_balance(), 6.0);
+    }
+}
@@ -0,0 +1,88 @@
+//! Account snapshots taken while a long run is still going.
+//!
+//! Unlike checkpoints, which exist to resume from and are pruned, these are
+//! for people: every one is the plain CSV snapshot the run would end with,
+//! had it stopped there, and all of them are kept. Each is named after the
+//! time it was taken and the offset (number of transactions applied) it
+//! was taken at, so that they sort in the order they were written.
+
+use crate::account::AccountsRepository;
+use crate::expiry::unix_seconds;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const PREFIX: &str = "snapshot-";
+const SUFFIX: &str = ".csv";
+
+/// Writes the snapshot of `accounts` after `offset` transactions, taken at
+/// `at`, into `dir`.
+pub fn write(
+    dir: &Path,
+    accounts: &AccountsRepository,
+    offset: u64,
+    at: SystemTime,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = format!(
+        "{}{:010}-{:020}{}",
+        PREFIX,
+        unix_seconds(at),
+        offset,
+        SUFFIX
+    );
+    let path = dir.join(name);
+    let mut bytes = Vec::new();
+    accounts
+        .write_csv(&mut bytes)
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    // Never leaves a half-written snapshot for a reader to pick up.
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, bytes)?;
+    fs::rename(&temporary, &path)?;
+    Ok(path)
+}
+
+/// Snapshot files in `dir`, oldest first.
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        if name.is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX)) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn named_by_time_and_offset() {
+        let dir = std::env::temp_dir().join(format!("fg-progress-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut accounts = AccountsRepository::new();
+        accounts.get_or_create(1).deposit(5.0).unwrap();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_717_200_000);
+        let first = write(&dir, &accounts, 2, at).unwrap();
+        accounts.get_or_create(2).deposit(1.0).unwrap();
+        let second = write(&dir, &accounts, 10, at + Duration::from_secs(1)).unwrap();
+
+        assert_eq!(list(&dir).unwrap(), [first.clone(), second.clone()]);
+        assert_eq!(
+            first.file_name().unwrap(),
+            "snapshot-1717200000-00000000000000000002.csv"
+        );
+        assert_eq!(
+            fs::read_to_string(&second).unwrap(),
+            "client,available,held,total,locked\n1,5.0,0.0,5.0,false\n2,1.0,0.0,1.0,false\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
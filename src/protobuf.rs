@@ -0,0 +1,204 @@
+//! Protobuf input: length-delimited `Transaction` messages as defined in
+//! `proto/transaction.proto`, with types generated by prost at build time.
+//!
+//! Messages are checked the same way as CSV rows: one that cannot become a
+//! transaction is skipped with a warning whose line is the message's
+//! position in the stream, counting from 1.
+
+use crate::parser::{Tenants, Warning, WarningKind};
+use crate::transaction::{Label, Transaction, Type};
+use prost::Message;
+use std::io::{self, Read};
+
+/// Largest message accepted. A transaction takes a few dozen bytes, so a
+/// bigger size prefix means a corrupt stream rather than a message to
+/// allocate a buffer for.
+pub const MAX_MESSAGE: usize = 1 << 20;
+
+/// The generated message types.
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/fictional_guide.rs"));
+}
+
+/// Decodes length-delimited messages from `reader` one at a time, so that
+/// a stream is processed as it arrives. Stops at the end of the input, or
+/// with an error at a truncated or undecodable message, or one longer than
+/// `MAX_MESSAGE`.
+pub struct Reader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(reader: R) -> Reader<R> {
+        Reader {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// The size prefix of the next message, `None` at a clean end of input.
+    fn length(&mut self) -> io::Result<Option<usize>> {
+        let mut length = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            }
+            length |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(length as usize));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid message length",
+        ))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<proto::Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = match self.length() {
+            Ok(length) => length?,
+            Err(err) => return Some(Err(err)),
+        };
+        if length > MAX_MESSAGE {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message of {} bytes is over {}", length, MAX_MESSAGE),
+            )));
+        }
+        self.buffer.resize(length, 0);
+        if let Err(err) = self.reader.read_exact(&mut self.buffer) {
+            return Some(Err(err));
+        }
+        Some(
+            proto::Transaction::decode(self.buffer.as_slice())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        )
+    }
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = String;
+
+    fn try_from(message: proto::Transaction) -> Result<Transaction, String> {
+        use proto::TransactionType as Proto;
+        let r#type = match message.r#type() {
+            Proto::Deposit => Type::Deposit,
+            Proto::Withdrawal => Type::Withdrawal,
+            Proto::Dispute => Type::Dispute,
+            Proto::Resolve => Type::Resolve,
+            Proto::Chargeback => Type::Chargeback,
+            Proto::Bonus => Type::Bonus,
+            Proto::Approve => Type::Approve,
+            Proto::Reject => Type::Reject,
+            Proto::Unspecified => return Err("unknown type".to_string()),
+        };
+        let client = u16::try_from(message.client)
+            .map_err(|_| format!("invalid client {}", message.client))?;
+        match (r#type, message.amount) {
+            (_, Some(amount)) if !amount.is_finite() || amount < 0.0 => {
+                return Err(format!("invalid amount {}", amount))
+            }
+            (Type::Deposit | Type::Withdrawal | Type::Bonus, None) => {
+                return Err("empty amount".to_string())
+            }
+            _ => {}
+        }
+        let label = |value: Option<String>| value.as_deref().map(Label::new).transpose();
+        let mut tx = Transaction::new(message.tx, r#type, client, 0.0)
+            .with_merchant(label(message.merchant)?)
+            .with_category(label(message.category)?)
+            .with_timestamp(message.timestamp);
+        tx.amount = message.amount;
+        Ok(tx)
+    }
+}
+
+/// Reads every message of `reader`, split by tenant like
+/// `Parser::parse_tenants_with_warnings`.
+pub fn parse_tenants_with_warnings<R: Read>(reader: R) -> io::Result<(Tenants, Vec<Warning>)> {
+    let mut tenants = Tenants::new();
+    let mut warnings = Vec::new();
+    for (index, message) in Reader::new(reader).enumerate() {
+        let message = message?;
+        let tenant = message.tenant.clone();
+        match Transaction::try_from(message) {
+            Ok(tx) => tenants.entry(tenant).or_default().push(tx),
+            Err(message) => warnings.push(Warning {
+                line: index as u64 + 1,
+                column: None,
+                kind: WarningKind::Invalid(message),
+            }),
+        }
+    }
+    Ok((tenants, warnings))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proto::TransactionType;
+
+    fn message(r#type: TransactionType, tx: u32, amount: Option<f64>) -> proto::Transaction {
+        proto::Transaction {
+            r#type: r#type.into(),
+            client: 1,
+            tx,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn length_delimited() {
+        let mut input = Vec::new();
+        for message in [
+            message(TransactionType::Deposit, 1, Some(5.0)),
+            proto::Transaction {
+                merchant: Some("acme".to_string()),
+                timestamp: Some(1_717_200_000),
+                ..message(TransactionType::Withdrawal, 2, Some(1.5))
+            },
+            message(TransactionType::Deposit, 3, None),
+            message(TransactionType::Dispute, 1, None),
+        ] {
+            message.encode_length_delimited(&mut input).unwrap();
+        }
+        let (tenants, warnings) = parse_tenants_with_warnings(input.as_slice()).unwrap();
+        let txs = &tenants[&None];
+        let parsed: Vec<(Type, u32, Option<f64>)> = txs
+            .iter()
+            .map(|tx| (tx.r#type(), tx.id(), tx.optional_amount()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 1, Some(5.0)),
+                (Type::Withdrawal, 2, Some(1.5)),
+                (Type::Dispute, 1, None),
+            ]
+        );
+        assert_eq!(txs[1].merchant().unwrap().as_str(), "acme");
+        assert_eq!(txs[1].timestamp(), Some(1_717_200_000));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 3);
+
+        input.pop();
+        assert!(parse_tenants_with_warnings(input.as_slice()).is_err());
+
+        let oversized = [0xff, 0xff, 0xff, 0xff, 0x0f];
+        let err = Reader::new(oversized.as_slice())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,496 @@
+//! Keyed pseudonyms for client ids.
+//!
+//! A client id is replaced by a truncated HMAC-SHA256 of the id under a
+//! secret key, so the same client maps to the same pseudonym for as long as
+//! the key is reused, while nobody without the key can map it back.
+
+use crate::account::{Account, LockReason};
+use crate::activity::{
+    DormantAccount, ExtendedAccount, LockedAccount, NegativeAccount, OpenDispute, OpenItem,
+    OpenItemKind,
+};
+use crate::alerts::{Alert, Balance, Comparison};
+use crate::approval::Decision;
+use crate::engine::{RejectReason, Rejection};
+use crate::expiry::{AutoEvent, AutoReason, Expiration};
+use crate::hierarchy::Rollup;
+use crate::journal::{Book, Entry};
+use crate::ordering::OutOfOrder;
+use crate::rules::{RuleHit, Verdict};
+use crate::screening::ScreeningHit;
+use crate::transaction::{Label, Type};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    mac: Hmac<Sha256>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Pseudonymizer {
+        Pseudonymizer {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// 16 hex characters derived from the client id.
+    pub fn client(&self, client: u16) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(&client.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn book(&self, book: Book) -> String {
+        match book {
+            Book::ClientAvailable(client) => format!("client:{}:available", self.client(client)),
+            Book::ClientHeld(client) => format!("client:{}:held", self.client(client)),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// A record with its client ids replaced by pseudonyms, keeping the shape
+/// (and thus the column names) of the original.
+pub trait Pseudonymize {
+    type Output: Serialize;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> Self::Output;
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousAccount {
+    client: String,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+}
+
+impl Pseudonymize for Account {
+    type Output = PseudonymousAccount;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousAccount {
+        PseudonymousAccount {
+            client: pseudonymizer.client(self.client_id()),
+            available: self.available_balance(),
+            held: self.held_balance(),
+            total: self.total_balance(),
+            locked: self.locked(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousRejection {
+    r#type: Type,
+    client: String,
+    tx: u32,
+    amount: Option<f64>,
+    reason: RejectReason,
+}
+
+impl Pseudonymize for Rejection {
+    type Output = PseudonymousRejection;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousRejection {
+        PseudonymousRejection {
+            r#type: self.r#type,
+            client: pseudonymizer.client(self.client),
+            tx: self.tx,
+            amount: self.amount,
+            reason: self.reason,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousEntry {
+    tx: u32,
+    r#type: Type,
+    debit: String,
+    credit: String,
+    amount: f64,
+    merchant: Option<Label>,
+    category: Option<Label>,
+    event: Option<AutoEvent>,
+    reason: Option<AutoReason>,
+}
+
+impl Pseudonymize for Entry {
+    type Output = PseudonymousEntry;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousEntry {
+        PseudonymousEntry {
+            tx: self.tx,
+            r#type: self.r#type,
+            debit: pseudonymizer.book(self.debit),
+            credit: pseudonymizer.book(self.credit),
+            amount: self.amount,
+            merchant: self.merchant,
+            category: self.category,
+            event: self.event,
+            reason: self.reason,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousScreeningHit {
+    client: String,
+    transactions: u64,
+    deposits: f64,
+    withdrawals: f64,
+}
+
+impl Pseudonymize for ScreeningHit {
+    type Output = PseudonymousScreeningHit;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousScreeningHit {
+        PseudonymousScreeningHit {
+            client: pseudonymizer.client(self.client),
+            transactions: self.transactions,
+            deposits: self.deposits,
+            withdrawals: self.withdrawals,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousOutOfOrder {
+    row: usize,
+    r#type: Type,
+    client: String,
+    tx: u32,
+    timestamp: u64,
+    after: u64,
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousRuleHit {
+    r#type: Type,
+    client: String,
+    tx: u32,
+    rule: String,
+    verdict: Verdict,
+}
+
+impl Pseudonymize for RuleHit {
+    type Output = PseudonymousRuleHit;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousRuleHit {
+        PseudonymousRuleHit {
+            r#type: self.r#type,
+            client: pseudonymizer.client(self.client),
+            tx: self.tx,
+            rule: self.rule.clone(),
+            verdict: self.verdict,
+        }
+    }
+}
+
+impl Pseudonymize for OutOfOrder {
+    type Output = PseudonymousOutOfOrder;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousOutOfOrder {
+        PseudonymousOutOfOrder {
+            row: self.row,
+            r#type: self.r#type,
+            client: pseudonymizer.client(self.client),
+            tx: self.tx,
+            timestamp: self.timestamp,
+            after: self.after,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousExpiration {
+    tx: u32,
+    client: String,
+    action: Type,
+    amount: f64,
+    disputed_at: u64,
+    expired_at: u64,
+}
+
+impl Pseudonymize for Expiration {
+    type Output = PseudonymousExpiration;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousExpiration {
+        PseudonymousExpiration {
+            tx: self.tx,
+            client: pseudonymizer.client(self.client),
+            action: self.action,
+            amount: self.amount,
+            disputed_at: self.disputed_at,
+            expired_at: self.expired_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousDecision {
+    tx: u32,
+    client: String,
+    r#type: Type,
+    amount: f64,
+    decision: Type,
+    requested_at: Option<u64>,
+    decided_at: Option<u64>,
+}
+
+impl Pseudonymize for Decision {
+    type Output = PseudonymousDecision;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousDecision {
+        PseudonymousDecision {
+            tx: self.tx,
+            client: pseudonymizer.client(self.client),
+            r#type: self.r#type,
+            amount: self.amount,
+            decision: self.decision,
+            requested_at: self.requested_at,
+            decided_at: self.decided_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousAlert {
+    tx: u32,
+    client: String,
+    balance: Balance,
+    comparison: Comparison,
+    limit: f64,
+    value: f64,
+    timestamp: Option<u64>,
+}
+
+impl Pseudonymize for Alert {
+    type Output = PseudonymousAlert;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousAlert {
+        PseudonymousAlert {
+            tx: self.tx,
+            client: pseudonymizer.client(self.client),
+            balance: self.balance,
+            comparison: self.comparison,
+            limit: self.limit,
+            value: self.value,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousExtendedAccount {
+    client: String,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    lock_reason: Option<LockReason>,
+    rejected_withdrawals: u64,
+    ignored_duplicates: u64,
+    open_disputes: u64,
+    chargebacks: u64,
+    chargeback_ratio: f64,
+}
+
+impl Pseudonymize for ExtendedAccount {
+    type Output = PseudonymousExtendedAccount;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousExtendedAccount {
+        PseudonymousExtendedAccount {
+            client: pseudonymizer.client(self.client),
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            lock_reason: self.lock_reason,
+            rejected_withdrawals: self.rejected_withdrawals,
+            ignored_duplicates: self.ignored_duplicates,
+            open_disputes: self.open_disputes,
+            chargebacks: self.chargebacks,
+            chargeback_ratio: self.chargeback_ratio,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousOpenDispute {
+    client: String,
+    tx: u32,
+    amount: f64,
+    disputed_at: Option<u64>,
+    age_days: Option<u64>,
+}
+
+impl Pseudonymize for OpenDispute {
+    type Output = PseudonymousOpenDispute;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousOpenDispute {
+        PseudonymousOpenDispute {
+            client: pseudonymizer.client(self.client),
+            tx: self.tx,
+            amount: self.amount,
+            disputed_at: self.disputed_at,
+            age_days: self.age_days,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousOpenItem {
+    client: String,
+    tx: u32,
+    item: OpenItemKind,
+    amount: f64,
+    opened_at: Option<u64>,
+    age_days: Option<u64>,
+}
+
+impl Pseudonymize for OpenItem {
+    type Output = PseudonymousOpenItem;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousOpenItem {
+        PseudonymousOpenItem {
+            client: pseudonymizer.client(self.client),
+            tx: self.tx,
+            item: self.item,
+            amount: self.amount,
+            opened_at: self.opened_at,
+            age_days: self.age_days,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousLockedAccount {
+    client: String,
+    reason: Option<LockReason>,
+    tx: Option<u32>,
+    amount: Option<f64>,
+    locked_at: Option<u64>,
+}
+
+impl Pseudonymize for LockedAccount {
+    type Output = PseudonymousLockedAccount;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousLockedAccount {
+        PseudonymousLockedAccount {
+            client: pseudonymizer.client(self.client),
+            reason: self.reason,
+            tx: self.tx,
+            amount: self.amount,
+            locked_at: self.locked_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousDormantAccount {
+    client: String,
+    last_activity: u64,
+    idle_days: u64,
+}
+
+impl Pseudonymize for DormantAccount {
+    type Output = PseudonymousDormantAccount;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousDormantAccount {
+        PseudonymousDormantAccount {
+            client: pseudonymizer.client(self.client),
+            last_activity: self.last_activity,
+            idle_days: self.idle_days,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousNegativeAccount {
+    client: String,
+    available: f64,
+    total: f64,
+    deficit: f64,
+    cumulative_deficit: f64,
+}
+
+impl Pseudonymize for NegativeAccount {
+    type Output = PseudonymousNegativeAccount;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousNegativeAccount {
+        PseudonymousNegativeAccount {
+            client: pseudonymizer.client(self.client),
+            available: self.available,
+            total: self.total,
+            deficit: self.deficit,
+            cumulative_deficit: self.cumulative_deficit,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PseudonymousRollup {
+    parent: String,
+    accounts: u64,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: u64,
+}
+
+impl Pseudonymize for Rollup {
+    type Output = PseudonymousRollup;
+
+    fn pseudonymize(&self, pseudonymizer: &Pseudonymizer) -> PseudonymousRollup {
+        PseudonymousRollup {
+            parent: pseudonymizer.client(self.parent),
+            accounts: self.accounts,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn consistent_per_key() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        let first = pseudonymizer.client(1);
+        assert_eq!(first.len(), 16);
+        assert_eq!(first, pseudonymizer.client(1));
+        assert_ne!(first, pseudonymizer.client(2));
+        assert_ne!(first, Pseudonymizer::new(b"other").client(1));
+    }
+
+    #[test]
+    fn journal_books() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        let entry = Entry {
+            tx: 1,
+            r#type: Type::Deposit,
+            debit: Book::ClientAvailable(7),
+            credit: Book::CashIn,
+            amount: 1.0,
+            merchant: None,
+            category: None,
+            event: None,
+            reason: None,
+        };
+        let entry = entry.pseudonymize(&pseudonymizer);
+        assert_eq!(
+            entry.debit,
+            format!("client:{}:available", pseudonymizer.client(7))
+        );
+        assert_eq!(entry.credit, "cash_in");
+    }
+}
@@ -0,0 +1,137 @@
+//! Python bindings, built with the `python` feature:
+//!
+//! ```python
+//! import fictional_guide
+//! engine = fictional_guide.Engine()
+//! engine.submit(fictional_guide.Transaction("deposit", client=1, tx=1, amount=5.0))
+//! engine.account(1).available
+//! ```
+
+// pyo3's generated wrappers convert `PyErr` into itself.
+#![allow(clippy::useless_conversion)]
+
+use crate::account::Account;
+use crate::state::State;
+use crate::transaction::{Transaction, Type};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+#[pyclass(name = "Transaction", frozen)]
+#[derive(Clone)]
+pub struct PyTransaction {
+    inner: Transaction,
+}
+
+#[pymethods]
+impl PyTransaction {
+    #[new]
+    #[pyo3(signature = (kind, client, tx, amount = 0.0))]
+    fn new(kind: &str, client: u16, tx: u32, amount: f64) -> PyResult<Self> {
+        let kind = kind.parse::<Type>().map_err(PyValueError::new_err)?;
+        Ok(PyTransaction {
+            inner: Transaction::new(tx, kind, client, amount),
+        })
+    }
+
+    #[getter]
+    fn kind(&self) -> String {
+        self.inner.r#type().to_string()
+    }
+
+    #[getter]
+    fn client(&self) -> u16 {
+        self.inner.account_id()
+    }
+
+    #[getter]
+    fn tx(&self) -> u32 {
+        self.inner.id()
+    }
+
+    #[getter]
+    fn amount(&self) -> f64 {
+        self.inner.amount()
+    }
+}
+
+/// Read-only copy of an account taken when it was requested.
+#[pyclass(name = "Account", frozen, get_all)]
+pub struct PyAccount {
+    client: u16,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+}
+
+impl From<&Account> for PyAccount {
+    fn from(account: &Account) -> Self {
+        PyAccount {
+            client: account.client_id(),
+            available: account.available_balance(),
+            held: account.held_balance(),
+            total: account.total_balance(),
+            locked: account.locked(),
+        }
+    }
+}
+
+#[pyclass(name = "Engine")]
+#[derive(Default)]
+pub struct PyEngine {
+    state: State,
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    fn new() -> Self {
+        Default::default()
+    }
+
+    fn submit(&mut self, tx: &PyTransaction) {
+        self.state.apply(&tx.inner);
+    }
+
+    fn process(&mut self, txs: Vec<PyTransaction>) {
+        for tx in &txs {
+            self.state.apply(&tx.inner);
+        }
+    }
+
+    fn account(&self, client: u16) -> Option<PyAccount> {
+        self.state
+            .accounts
+            .get(client)
+            .as_deref()
+            .map(PyAccount::from)
+    }
+
+    /// All accounts ordered by client id.
+    fn snapshot(&self) -> Vec<PyAccount> {
+        self.state
+            .accounts
+            .sorted()
+            .iter()
+            .map(PyAccount::from)
+            .collect()
+    }
+
+    /// The same CSV the CLI prints.
+    fn snapshot_csv(&self) -> PyResult<String> {
+        let mut csv = Vec::new();
+        self.state
+            .accounts
+            .write_csv(&mut csv)
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        String::from_utf8(csv).map_err(|err| PyRuntimeError::new_err(err.to_string()))
+    }
+}
+
+#[pymodule]
+fn fictional_guide(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEngine>()?;
+    m.add_class::<PyTransaction>()?;
+    m.add_class::<PyAccount>()?;
+    Ok(())
+}
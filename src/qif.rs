@@ -0,0 +1,249 @@
+//! QIF bank exports as input.
+//!
+//! Every record of a bank, cash or card list becomes a deposit when its
+//! amount (`T`) is positive and a withdrawal when it is negative, with the
+//! payee (`P`) as counterparty, the category (`L`) as category and the date
+//! (`D`, month first) as timestamp.
+//!
+//! The client is that of the `!Account` block the records follow, or of the
+//! `*` entry of the account map when the file has no such block. QIF has no
+//! unique ids: a numeric check number (`N`) is the tx id, otherwise the id
+//! is `bank::tx_id` of the account, date, amount and payee, with repeats
+//! told apart by how many came before.
+
+use crate::bank::{label, tx_id, AccountMap};
+use crate::parser::{Warning, WarningKind};
+use crate::transaction::{Transaction, Type};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+#[derive(Clone, Debug, Default)]
+pub struct Qif {
+    clients: AccountMap,
+}
+
+/// Fields of the record being read, by their code letter.
+#[derive(Default)]
+struct Record {
+    line: u64,
+    fields: HashMap<char, String>,
+}
+
+impl Qif {
+    pub fn new() -> Qif {
+        Qif::default()
+    }
+
+    /// Matches account names of `!Account` blocks to clients.
+    pub fn with_clients(mut self, clients: AccountMap) -> Qif {
+        self.clients = clients;
+        self
+    }
+
+    /// Reads a whole file, skipping records that cannot be turned into a
+    /// transaction with a warning on the line they start on. Lists of other
+    /// kinds than transactions, such as categories, are ignored.
+    pub fn parse_with_warnings<R: BufRead>(
+        &self,
+        reader: R,
+    ) -> io::Result<(Vec<Transaction>, Vec<Warning>)> {
+        let mut account = String::new();
+        let mut in_account = false;
+        let mut in_transactions = false;
+        let mut record: Option<Record> = None;
+        let mut seen: HashMap<String, u32> = HashMap::new();
+        let mut transactions = Vec::new();
+        let mut warnings = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end();
+            if let Some(header) = line.strip_prefix('!') {
+                let header = header.trim().to_ascii_lowercase();
+                in_account = header == "account";
+                in_transactions = [
+                    "type:bank",
+                    "type:cash",
+                    "type:ccard",
+                    "type:oth a",
+                    "type:oth l",
+                ]
+                .contains(&header.as_str());
+                continue;
+            }
+            let Some(code) = line.chars().next() else {
+                continue;
+            };
+            let value = line[code.len_utf8()..].trim();
+            if in_account {
+                match code {
+                    'N' => account = value.to_string(),
+                    '^' => in_account = false,
+                    _ => {}
+                }
+                continue;
+            }
+            if !in_transactions {
+                continue;
+            }
+            let current = record.get_or_insert_with(|| Record {
+                line: index as u64 + 1,
+                ..Record::default()
+            });
+            if code != '^' {
+                current
+                    .fields
+                    .entry(code)
+                    .or_insert_with(|| value.to_string());
+                continue;
+            }
+            let ended = record.take().unwrap_or_default();
+            match self.transaction(&ended, &account, &mut seen) {
+                Ok(tx) => transactions.push(tx),
+                Err(message) => warnings.push(Warning {
+                    line: ended.line,
+                    column: None,
+                    kind: WarningKind::Invalid(message),
+                }),
+            }
+        }
+        Ok((transactions, warnings))
+    }
+
+    fn transaction(
+        &self,
+        record: &Record,
+        account: &str,
+        seen: &mut HashMap<String, u32>,
+    ) -> Result<Transaction, String> {
+        let client = self.clients.client(account)?;
+        let field = |code| record.fields.get(&code).map(String::as_str);
+        let raw = field('T').or(field('U'));
+        let amount = raw
+            .map(|amount| amount.replace(',', ""))
+            .and_then(|amount| amount.parse::<f64>().ok())
+            .filter(|amount| amount.is_finite())
+            .ok_or_else(|| format!("invalid amount {:?}", raw))?;
+        let r#type = if amount < 0.0 {
+            Type::Withdrawal
+        } else {
+            Type::Deposit
+        };
+        let id = match field('N').and_then(|number| number.parse().ok()) {
+            Some(number) => number,
+            None => {
+                let key = format!(
+                    "{}|{}|{}|{}",
+                    account,
+                    field('D').unwrap_or_default(),
+                    raw.unwrap_or_default(),
+                    field('P').unwrap_or_default()
+                );
+                let repeats = seen.entry(key.clone()).or_default();
+                *repeats += 1;
+                match *repeats {
+                    1 => tx_id(&key),
+                    repeat => tx_id(&format!("{}#{}", key, repeat)),
+                }
+            }
+        };
+        Ok(Transaction::new(id, r#type, client, amount.abs())
+            .with_merchant(field('P').and_then(label))
+            .with_category(field('L').and_then(label))
+            .with_timestamp(field('D').and_then(date)))
+    }
+}
+
+/// Unix seconds of a month-first QIF date such as `6/ 1/24`, `06/01/2024`
+/// or `6/1'24`, at midnight UTC. Two-digit years from 70 on are in the
+/// 1900s.
+fn date(value: &str) -> Option<u64> {
+    let mut parts = value
+        .split(['/', '\'', '-'])
+        .map(|part| part.trim().parse::<u32>());
+    let (month, day, year) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    let year = match year {
+        0..=69 => 2000 + year,
+        70..=99 => 1900 + year,
+        year => year,
+    };
+    crate::timestamp::parse(&format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let input = "!Account\n\
+                     NChecking\n\
+                     TBank\n\
+                     ^\n\
+                     !Type:Bank\n\
+                     D6/ 1/24\n\
+                     T1,200.00\n\
+                     PAcme Payroll\n\
+                     LSalary\n\
+                     ^\n\
+                     D06/02/2024\n\
+                     T-20.00\n\
+                     N1234\n\
+                     PGrocer\n\
+                     ^\n\
+                     D6/3'24\n\
+                     T-5.00\n\
+                     PCoffee\n\
+                     ^\n\
+                     D6/3'24\n\
+                     T-5.00\n\
+                     PCoffee\n\
+                     ^\n\
+                     D6/4/24\n\
+                     Tlots\n\
+                     ^\n";
+        let clients = AccountMap::read("Checking,2\n".as_bytes()).unwrap();
+        let (txs, warnings) = Qif::new()
+            .with_clients(clients)
+            .parse_with_warnings(input.as_bytes())
+            .unwrap();
+        let parsed: Vec<(Type, u16, f64, Option<u64>)> = txs
+            .iter()
+            .map(|tx| (tx.r#type(), tx.account_id(), tx.amount(), tx.timestamp()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 2, 1200.0, Some(1_717_200_000)),
+                (Type::Withdrawal, 2, 20.0, Some(1_717_286_400)),
+                (Type::Withdrawal, 2, 5.0, Some(1_717_372_800)),
+                (Type::Withdrawal, 2, 5.0, Some(1_717_372_800)),
+            ]
+        );
+        assert_eq!(txs[1].id(), 1234);
+        assert_ne!(txs[2].id(), txs[3].id());
+        assert_eq!(txs[0].category().unwrap().as_str(), "Salary");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 24);
+    }
+
+    #[test]
+    fn without_account() {
+        let input = "!Type:CCard\nD12/31/99\nT-3.5\n^\n";
+        let (txs, warnings) = Qif::new().parse_with_warnings(input.as_bytes()).unwrap();
+        assert_eq!((txs.len(), warnings.len()), (0, 1));
+
+        let clients = AccountMap::read("*,9\n".as_bytes()).unwrap();
+        let (txs, _) = Qif::new()
+            .with_clients(clients)
+            .parse_with_warnings(input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            (txs[0].account_id(), txs[0].timestamp()),
+            (9, Some(946_598_400))
+        );
+    }
+}
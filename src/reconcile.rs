@@ -0,0 +1,127 @@
+//! Compares an engine snapshot with balances exported by another system.
+
+use crate::rounding::Rounding;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// One row of a snapshot in the engine's output format.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Balance {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// A difference between the two files. `field` is `account` when the client
+/// is missing from one side, in which case that side's value is empty.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Mismatch {
+    pub client: u16,
+    pub field: &'static str,
+    pub output: Option<String>,
+    pub expected: Option<String>,
+    pub difference: Option<f64>,
+}
+
+pub fn read_balances<R: io::Read>(reader: R) -> Result<BTreeMap<u16, Balance>, csv::Error> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut balances = BTreeMap::new();
+    for balance in rdr.deserialize() {
+        let balance: Balance = balance?;
+        balances.insert(balance.client, balance);
+    }
+    Ok(balances)
+}
+
+/// Lists every difference, ordered by client. Amounts are equal when they
+/// differ by no more than `tolerance`; reported differences are rounded
+/// with `rounding`.
+pub fn reconcile(
+    output: &BTreeMap<u16, Balance>,
+    expected: &BTreeMap<u16, Balance>,
+    tolerance: f64,
+    rounding: Rounding,
+) -> Vec<Mismatch> {
+    let mut clients: Vec<&u16> = output.keys().chain(expected.keys()).collect();
+    clients.sort();
+    clients.dedup();
+
+    let mut mismatches = Vec::new();
+    for client in clients {
+        match (output.get(client), expected.get(client)) {
+            (Some(ours), Some(theirs)) => {
+                let amounts = [
+                    ("available", ours.available, theirs.available),
+                    ("held", ours.held, theirs.held),
+                    ("total", ours.total, theirs.total),
+                ];
+                for (field, ours, theirs) in amounts {
+                    if (ours - theirs).abs() > tolerance {
+                        mismatches.push(Mismatch {
+                            client: *client,
+                            field,
+                            output: Some(ours.to_string()),
+                            expected: Some(theirs.to_string()),
+                            difference: Some(rounding.round(ours - theirs)),
+                        });
+                    }
+                }
+                if ours.locked != theirs.locked {
+                    mismatches.push(Mismatch {
+                        client: *client,
+                        field: "locked",
+                        output: Some(ours.locked.to_string()),
+                        expected: Some(theirs.locked.to_string()),
+                        difference: None,
+                    });
+                }
+            }
+            (ours, theirs) => mismatches.push(Mismatch {
+                client: *client,
+                field: "account",
+                output: ours.map(|_| "present".into()),
+                expected: theirs.map(|_| "present".into()),
+                difference: None,
+            }),
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn balances(csv: &str) -> BTreeMap<u16, Balance> {
+        read_balances(csv.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn identical_within_tolerance() {
+        let output = balances("client,available,held,total,locked\n1,1.5,0.0,1.5,false\n");
+        let expected =
+            balances("client, available, held, total, locked\n1, 1.50004, 0, 1.5, false\n");
+        assert!(reconcile(&output, &expected, 0.0001, Rounding::HalfUp).is_empty());
+    }
+
+    #[test]
+    fn reports_differences() {
+        let output = balances(
+            "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n2,3.0,0.0,3.0,true\n",
+        );
+        let expected = balances(
+            "client,available,held,total,locked\n1,1.0,0.0,1.5,false\n3,1.0,0.0,1.0,false\n",
+        );
+        let mismatches = reconcile(&output, &expected, 0.0001, Rounding::HalfUp);
+        let fields: Vec<(u16, &str)> = mismatches.iter().map(|m| (m.client, m.field)).collect();
+        assert_eq!(fields, [(1, "available"), (2, "account"), (3, "account")]);
+        assert_eq!(mismatches[0].difference, Some(0.5));
+        assert_eq!(mismatches[1].expected, None);
+        assert_eq!(mismatches[2].output, None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Blocking adapters over `object_store`, so `s3://`, `gs://`, `az://`,
+//! `https://` and `file://` URLs can be used wherever the CLI reads or writes
+//! a file. Objects are streamed in chunks rather than buffered whole.
+//!
+//! Credentials and region come from the usual environment variables of each
+//! backend (`AWS_ACCESS_KEY_ID`, `AWS_REGION`, `GOOGLE_SERVICE_ACCOUNT`, ...).
+//!
+//! Transient errors, those `is_transient` accepts, are retried by the
+//! `Retry` given: a download that breaks off resumes where it stopped, and
+//! writers are recreated by their caller, nothing being visible in the
+//! store before `Writer::finish`.
+
+use crate::retry::{Retry, RetryError};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::buffered::BufWriter;
+use object_store::path::Path;
+use object_store::{GetOptions, GetRange, ObjectStore};
+use std::io;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+use url::Url;
+
+pub type Error = Box<dyn std::error::Error>;
+
+/// Whether `err`, or an error it was caused by, is one from the store that
+/// may go away on retrying, such as a timeout or a server error. The HTTP
+/// client of each store has already retried those a few times itself.
+pub fn is_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut next = Some(err);
+    while let Some(err) = next {
+        let err = err
+            .downcast_ref::<io::Error>()
+            .and_then(|err| err.get_ref())
+            .map_or(err, |inner| inner as &(dyn std::error::Error + 'static));
+        if let Some(err) = err.downcast_ref::<object_store::Error>() {
+            return matches!(err, object_store::Error::Generic { .. });
+        }
+        next = err.source();
+    }
+    false
+}
+
+/// Whether `location` should be opened through an object store rather than
+/// as a local path.
+pub fn is_url(location: &str) -> bool {
+    location.contains("://")
+}
+
+fn open_store(location: &str) -> Result<(Box<dyn ObjectStore>, Path, Runtime), Error> {
+    let url = Url::parse(location)?;
+    let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+    let (store, path) = object_store::parse_url_opts(&url, options)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    Ok((store, path, runtime))
+}
+
+/// Downloads an object chunk by chunk as it is read.
+pub struct Reader {
+    runtime: Runtime,
+    store: Box<dyn ObjectStore>,
+    path: Path,
+    retry: Retry,
+    chunks: BoxStream<'static, object_store::Result<Bytes>>,
+    current: Bytes,
+    /// Bytes received so far, where a download that broke off resumes.
+    received: usize,
+}
+
+impl Reader {
+    pub fn open(location: &str, retry: &Retry) -> Result<Reader, Error> {
+        let (store, path, runtime) = open_store(location)?;
+        let mut reader = Reader {
+            runtime,
+            store,
+            path,
+            retry: retry.clone(),
+            chunks: futures::stream::empty().boxed(),
+            current: Bytes::new(),
+            received: 0,
+        };
+        reader.get()?;
+        Ok(reader)
+    }
+
+    /// (Re)starts the download at `received`.
+    fn get(&mut self) -> Result<(), RetryError<object_store::Error>> {
+        let options = GetOptions {
+            range: (self.received > 0).then_some(GetRange::Offset(self.received)),
+            ..Default::default()
+        };
+        let result = self.retry.run(
+            || {
+                self.runtime
+                    .block_on(self.store.get_opts(&self.path, options.clone()))
+            },
+            |err| is_transient(err),
+        )?;
+        self.chunks = result.into_stream();
+        Ok(())
+    }
+}
+
+impl io::Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.runtime.block_on(self.chunks.next()) {
+                Some(Ok(chunk)) => {
+                    self.received += chunk.len();
+                    self.current = chunk;
+                }
+                Some(Err(err)) if is_transient(&err) && self.retry.policy().retries > 0 => {
+                    log::warn!("download broke off after {} bytes: {}", self.received, err);
+                    self.get().map_err(io::Error::other)?;
+                }
+                Some(Err(err)) => return Err(io::Error::other(err)),
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Uploads an object, switching to a multipart upload once enough data has
+/// been written. Nothing is visible in the store until `finish` succeeds.
+pub struct Writer {
+    runtime: Runtime,
+    inner: BufWriter,
+}
+
+impl Writer {
+    pub fn create(location: &str) -> Result<Writer, Error> {
+        let (store, path, runtime) = open_store(location)?;
+        Ok(Writer {
+            runtime,
+            inner: BufWriter::new(Arc::from(store), path),
+        })
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.shutdown())?;
+        Ok(())
+    }
+}
+
+impl io::Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.runtime
+            .block_on(self.inner.put(Bytes::copy_from_slice(buf)))
+            .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn roundtrip_through_file_url() {
+        let path = std::env::temp_dir().join(format!("fg-remote-{}.csv", std::process::id()));
+        let location = Url::from_file_path(&path).unwrap().to_string();
+        assert!(is_url(&location));
+
+        let mut writer = Writer::create(&location).unwrap();
+        writer.write_all(b"type,client,tx,amount\n").unwrap();
+        writer.write_all(b"deposit,1,1,1.0\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut content = String::new();
+        Reader::open(&location, &Retry::default())
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "type,client,tx,amount\ndeposit,1,1,1.0\n");
+        std::fs::remove_file(path).unwrap();
+
+        let missing = Reader::open(&location, &Retry::default()).err().unwrap();
+        assert!(!is_transient(missing.as_ref()));
+        let timeout = io::Error::other(object_store::Error::Generic {
+            store: "S3",
+            source: "timed out".into(),
+        });
+        assert!(is_transient(&timeout));
+    }
+}
@@ -0,0 +1,133 @@
+//! Bounded reordering of a stream by timestamp, for feeds whose rows arrive
+//! somewhat out of order.
+//!
+//! Transactions are held back until the watermark, the latest timestamp
+//! seen less the window, has passed them, and are then released in
+//! timestamp order. A transaction that arrives with a timestamp already
+//! behind the watermark is late: waiting for it would have meant a larger
+//! window, so it is released at once and reported.
+
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A transaction that arrived behind the watermark.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LateEvent {
+    pub r#type: Type,
+    pub client: u16,
+    pub tx: u32,
+    pub timestamp: u64,
+    /// The watermark it arrived behind.
+    pub watermark: u64,
+}
+
+pub struct ReorderBuffer<M = f64> {
+    window: u64,
+    /// Keyed by timestamp and arrival, so that ties keep arrival order.
+    pending: BTreeMap<(u64, u64), Transaction<M>>,
+    arrivals: u64,
+    latest: Option<u64>,
+    /// Timestamp of the transaction before, for one without a timestamp.
+    last: u64,
+}
+
+impl<M: Money> ReorderBuffer<M> {
+    /// Holds transactions back until `window` seconds later ones arrived.
+    pub fn new(window: u64) -> ReorderBuffer<M> {
+        ReorderBuffer {
+            window,
+            pending: BTreeMap::new(),
+            arrivals: 0,
+            latest: None,
+            last: 0,
+        }
+    }
+
+    /// Everything older than this has been released.
+    pub fn watermark(&self) -> Option<u64> {
+        self.latest.map(|latest| latest.saturating_sub(self.window))
+    }
+
+    /// Number of transactions held back.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Takes in `tx` and appends what can be released now to `ready`, in
+    /// order. A transaction without a timestamp counts as at the time of
+    /// the one that arrived before it.
+    pub fn push(
+        &mut self,
+        tx: Transaction<M>,
+        ready: &mut Vec<Transaction<M>>,
+    ) -> Option<LateEvent> {
+        let timestamp = tx.timestamp().unwrap_or(self.last);
+        self.last = timestamp;
+        if let Some(watermark) = self.watermark().filter(|&watermark| timestamp < watermark) {
+            ready.push(tx);
+            return Some(LateEvent {
+                r#type: tx.r#type(),
+                client: tx.account_id(),
+                tx: tx.id(),
+                timestamp,
+                watermark,
+            });
+        }
+        self.pending.insert((timestamp, self.arrivals), tx);
+        self.arrivals += 1;
+        self.latest = self.latest.max(Some(timestamp));
+        let watermark = self.watermark().unwrap_or(0);
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.key().0 > watermark {
+                break;
+            }
+            ready.push(entry.remove());
+        }
+        None
+    }
+
+    /// Releases everything held back, in order, e.g. at the end of the
+    /// stream.
+    pub fn flush(&mut self) -> Vec<Transaction<M>> {
+        std::mem::take(&mut self.pending).into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn watermark() {
+        let at =
+            |id, timestamp| Transaction::new(id, Type::Deposit, 1, 1.0).with_timestamp(timestamp);
+        let mut buffer = ReorderBuffer::new(10);
+        let mut ready = Vec::new();
+        for tx in [at(1, Some(100)), at(2, Some(95)), at(3, None)] {
+            assert_eq!(buffer.push(tx, &mut ready), None);
+        }
+        assert!(ready.is_empty());
+        let ids = |ready: &[Transaction]| ready.iter().map(Transaction::id).collect::<Vec<_>>();
+        buffer.push(at(4, Some(108)), &mut ready);
+        assert_eq!(ids(&ready), [2, 3]);
+        buffer.push(at(5, Some(111)), &mut ready);
+        assert_eq!(ids(&ready), [2, 3, 1]);
+        assert_eq!(buffer.watermark(), Some(101));
+
+        ready.clear();
+        let late = buffer.push(at(6, Some(99)), &mut ready);
+        assert_eq!(late.map(|late| (late.tx, late.watermark)), Some((6, 101)));
+        assert_eq!(ids(&ready), [6]);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(ids(&buffer.flush()), [4, 5]);
+        assert!(buffer.is_empty());
+    }
+}
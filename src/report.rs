@@ -0,0 +1,86 @@
+//! Machine-readable reports written alongside the account snapshot. The
+//! format follows the file extension: `.json` gives a JSON array, anything
+//! else CSV with a header row.
+
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Format {
+        if path.to_ascii_lowercase().ends_with(".json") {
+            Format::Json
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+pub fn write<T: Serialize, W: Write>(
+    records: &[T],
+    format: Format,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+        }
+        Format::Json => serde_json::to_writer_pretty(writer, records)?,
+    }
+    Ok(())
+}
+
+pub fn write_file<T: Serialize>(records: &[T], path: &str) -> Result<(), Box<dyn Error>> {
+    write(records, Format::from_path(path), File::create(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::{RejectReason, Rejection};
+    use crate::transaction::{Transaction, Type};
+
+    fn rejections() -> Vec<Rejection> {
+        vec![Rejection::new(
+            &Transaction::new(3, Type::Withdrawal, 1, 2.5),
+            RejectReason::InsufficientFunds,
+        )]
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path("rejects.JSON"), Format::Json);
+        assert_eq!(Format::from_path("rejects.csv"), Format::Csv);
+        assert_eq!(Format::from_path("rejects"), Format::Csv);
+    }
+
+    #[test]
+    fn rejections_as_csv() {
+        let mut out = Vec::new();
+        write(&rejections(), Format::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type,client,tx,amount,reason\nwithdrawal,1,3,2.5,insufficient_funds\n"
+        );
+    }
+
+    #[test]
+    fn rejections_as_json() {
+        let mut out = Vec::new();
+        write(&rejections(), Format::Json, &mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value[0]["reason"], "insufficient_funds");
+        assert_eq!(value[0]["type"], "withdrawal");
+    }
+}
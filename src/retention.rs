@@ -0,0 +1,218 @@
+//! Retention of settled disputes.
+//!
+//! A transaction that was disputed stays in the ledger after its dispute is
+//! resolved or charged back, with its client, amount, merchant and category,
+//! for as long as the engine runs. With a `Retention` the engine notes, by
+//! its `Clock`, when each dispute is settled, and before every transaction
+//! purges the transactions whose dispute was settled longer ago than the
+//! policy allows. Each is handed to the `AuditLog` as a `Purged` record
+//! first and only leaves the ledger once the log took it; if the log fails,
+//! the transaction stays and is tried again before the next one.
+//!
+//! Only the id of a purged transaction stays in the ledger: a later dispute
+//! of it is rejected as `tx_not_found`, and a deposit or withdrawal reusing
+//! it as `duplicate_tx`, so a replay is never applied twice. Accounts, and
+//! the lock a chargeback left on one, are not touched.
+
+use crate::expiry::unix_seconds;
+use crate::money::Money;
+use crate::transaction::{Label, Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "json")]
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(feature = "json")]
+use std::io::Write;
+#[cfg(feature = "json")]
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Retention {
+    /// How long a settled dispute is kept.
+    pub after: Duration,
+}
+
+impl Retention {
+    pub fn days(days: u64) -> Retention {
+        Retention {
+            after: Duration::from_secs(days * 86_400),
+        }
+    }
+}
+
+/// Audit record of a transaction purged after its dispute was settled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Purged {
+    pub tx: u32,
+    pub client: u16,
+    /// Type of the disputed transaction.
+    pub r#type: Type,
+    pub amount: f64,
+    pub merchant: Option<Label>,
+    pub category: Option<Label>,
+    pub timestamp: Option<u64>,
+    /// `resolve` or `chargeback`.
+    pub outcome: Type,
+    /// Seconds since the Unix epoch the dispute was settled at.
+    pub settled_at: u64,
+    /// Seconds since the Unix epoch the transaction was purged at.
+    pub purged_at: u64,
+}
+
+impl Purged {
+    pub fn new<M: Money>(tx: &Transaction<M>, settled_at: SystemTime, now: SystemTime) -> Purged {
+        Purged {
+            tx: tx.id(),
+            client: tx.account_id(),
+            r#type: tx.r#type(),
+            amount: tx.amount().to_f64(),
+            merchant: tx.merchant(),
+            category: tx.category(),
+            timestamp: tx.timestamp(),
+            outcome: match tx.is_charged_back() {
+                true => Type::Chargeback,
+                false => Type::Resolve,
+            },
+            settled_at: unix_seconds(settled_at),
+            purged_at: unix_seconds(now),
+        }
+    }
+}
+
+/// Where purged transactions are exported to before they are dropped.
+pub trait AuditLog: Send {
+    fn record(&mut self, purged: &Purged) -> io::Result<()>;
+}
+
+/// Keeps the records in memory, e.g. for tests or for a caller that
+/// exports them itself.
+impl AuditLog for Vec<Purged> {
+    fn record(&mut self, purged: &Purged) -> io::Result<()> {
+        self.push(*purged);
+        Ok(())
+    }
+}
+
+/// Appends records to a file, one JSON object per line.
+#[cfg(feature = "json")]
+pub struct AuditFile {
+    file: File,
+}
+
+#[cfg(feature = "json")]
+impl AuditFile {
+    pub fn open(path: &Path) -> io::Result<AuditFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditFile { file })
+    }
+}
+
+#[cfg(feature = "json")]
+impl AuditLog for AuditFile {
+    fn record(&mut self, purged: &Purged) -> io::Result<()> {
+        let mut line = serde_json::to_vec(purged)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        // Nothing is purged before its record is on disk.
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::clock::ManualClock;
+    use crate::engine::{Engine, RejectReason};
+    use crate::transaction::TransactionLedger;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    struct Failing;
+
+    impl AuditLog for Failing {
+        fn record(&mut self, _: &Purged) -> io::Result<()> {
+            Err(io::Error::other("audit log is down"))
+        }
+    }
+
+    #[test]
+    fn purges_after_export() {
+        let clock = ManualClock::default();
+        let mut audit_log = Vec::new();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_clock(&clock)
+            .with_retention(Retention::days(30), &mut audit_log);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(2, Type::Deposit, 1, 5.0),
+            Transaction::new(3, Type::Deposit, 2, 7.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Resolve, 1, 0.0),
+            Transaction::new(3, Type::Dispute, 2, 0.0),
+        ]);
+        clock.advance(10 * DAY);
+        engine.process(&[Transaction::new(3, Type::Chargeback, 2, 0.0)]);
+        clock.advance(25 * DAY);
+        engine.process(&[Transaction::new(4, Type::Deposit, 1, 1.0)]);
+        assert!(engine.tx_ledger.get(1).is_none());
+        assert!(engine.tx_ledger.get(2).is_some() && engine.tx_ledger.get(3).is_some());
+        clock.advance(5 * DAY);
+        engine.process(&[
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [RejectReason::TxNotFound, RejectReason::DuplicateTx]
+        );
+        assert!(engine.tx_ledger.get(3).is_none());
+        drop(engine);
+
+        let outcomes: Vec<(u32, Type, u64, u64)> = audit_log
+            .iter()
+            .map(|purged| {
+                (
+                    purged.tx,
+                    purged.outcome,
+                    purged.settled_at,
+                    purged.purged_at,
+                )
+            })
+            .collect();
+        let day = DAY.as_secs();
+        assert_eq!(
+            outcomes,
+            [
+                (1, Type::Resolve, 0, 35 * day),
+                (3, Type::Chargeback, 10 * day, 40 * day)
+            ]
+        );
+        assert!(accounts.get(2).unwrap().locked());
+    }
+
+    #[test]
+    fn keeps_what_the_log_refused() {
+        let clock = ManualClock::default();
+        let mut audit_log = Failing;
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_clock(&clock)
+            .with_retention(Retention::days(1), &mut audit_log);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            Transaction::new(1, Type::Resolve, 1, 0.0),
+        ]);
+        clock.advance(2 * DAY);
+        engine.purge_settled();
+        assert!(engine.tx_ledger.get(1).is_some());
+    }
+}
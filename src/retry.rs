@@ -0,0 +1,227 @@
+//! Retries with exponential backoff for calls to storage backends that can
+//! fail transiently, behind a circuit breaker that stops calling a backend
+//! that keeps failing.
+//!
+//! Only errors the caller deems transient are retried and counted by the
+//! breaker; anything else is handed back at once as `RetryError::Permanent`,
+//! for the caller to handle like any other error of that backend.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often and how long to wait before retrying a transient failure.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every retry after it.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// No retries.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retry number `retry`, counting from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+/// Opens after `threshold` transient failures in a row, refusing every call
+/// until `cool_down` has passed. Then it lets calls through again, and one
+/// more failure opens it anew.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cool_down: Duration,
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cool_down: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold: threshold.max(1),
+            cool_down,
+            failures: 0,
+            opened_at: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.opened_at
+            .is_some_and(|opened_at| opened_at.elapsed() < self.cool_down)
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.opened_at = None;
+    }
+
+    fn failed(&mut self) {
+        self.failures += 1;
+        if self.failures >= self.threshold {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    /// Opens after 5 failures in a row, for 30 seconds.
+    fn default() -> CircuitBreaker {
+        CircuitBreaker::new(5, Duration::from_secs(30))
+    }
+}
+
+/// Why `Retry::run` gave up.
+#[derive(Debug, PartialEq)]
+pub enum RetryError<E> {
+    /// The call failed with an error not worth retrying.
+    Permanent(E),
+    /// Every attempt failed transiently; this is the last error.
+    Exhausted { attempts: u32, last: E },
+    /// The breaker is open, so the call was not made (again).
+    CircuitOpen,
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryError::Permanent(err) => err.fmt(f),
+            RetryError::Exhausted { attempts, last } => {
+                write!(f, "{} (gave up after {} attempts)", last, attempts)
+            }
+            RetryError::CircuitOpen => f.write_str("circuit breaker is open"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RetryError::Permanent(err) | RetryError::Exhausted { last: err, .. } => Some(err),
+            RetryError::CircuitOpen => None,
+        }
+    }
+}
+
+/// A retry policy and the circuit breaker of the backend it is used with.
+/// Clones share the breaker, so that every caller of a backend sees it
+/// open.
+#[derive(Clone, Debug, Default)]
+pub struct Retry {
+    policy: RetryPolicy,
+    breaker: Arc<Mutex<CircuitBreaker>>,
+}
+
+impl Retry {
+    pub fn new(policy: RetryPolicy, breaker: CircuitBreaker) -> Retry {
+        Retry {
+            policy,
+            breaker: Arc::new(Mutex::new(breaker)),
+        }
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Calls `op` until it succeeds, fails with an error `is_transient`
+    /// rejects, runs out of retries or the breaker opens, sleeping by the
+    /// policy between attempts.
+    pub fn run<T, E>(
+        &self,
+        mut op: impl FnMut() -> Result<T, E>,
+        is_transient: impl Fn(&E) -> bool,
+    ) -> Result<T, RetryError<E>> {
+        let mut attempts = 0;
+        loop {
+            if self.breaker().is_open() {
+                return Err(RetryError::CircuitOpen);
+            }
+            attempts += 1;
+            let err = match op() {
+                Ok(value) => {
+                    self.breaker().succeeded();
+                    return Ok(value);
+                }
+                Err(err) if !is_transient(&err) => return Err(RetryError::Permanent(err)),
+                Err(err) => err,
+            };
+            self.breaker().failed();
+            if attempts > self.policy.retries {
+                return Err(RetryError::Exhausted {
+                    attempts,
+                    last: err,
+                });
+            }
+            let delay = self.policy.delay(attempts - 1);
+            log::warn!("attempt {} failed, retrying in {:?}", attempts, delay);
+            thread::sleep(delay);
+        }
+    }
+
+    fn breaker(&self) -> std::sync::MutexGuard<'_, CircuitBreaker> {
+        self.breaker.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_and_breaker() {
+        let policy = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        };
+        let delays: Vec<u128> = (0..4)
+            .map(|retry| policy.delay(retry).as_millis())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 4]);
+
+        let retry = Retry::new(policy, CircuitBreaker::new(3, Duration::from_secs(60)));
+        let mut calls = 0;
+        let flaky = retry.run(
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err("timeout")
+                } else {
+                    Ok(calls)
+                }
+            },
+            |err| *err == "timeout",
+        );
+        assert_eq!(flaky, Ok(3));
+        assert_eq!(
+            retry.run(|| Err::<(), _>("not found"), |err| *err == "timeout"),
+            Err(RetryError::Permanent("not found"))
+        );
+
+        let down = retry.run(|| Err::<(), _>("timeout"), |_| true);
+        assert_eq!(down, Err(RetryError::CircuitOpen));
+        assert!(retry.clone().breaker().is_open());
+        assert_eq!(
+            retry.run(|| Ok::<_, &str>(()), |_| true),
+            Err(RetryError::CircuitOpen)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Decimal places kept for every balance and reported amount.
+pub const PRECISION: i32 = 4;
+
+const SCALE: f64 = 10_000.0;
+
+/// How amounts are brought to `PRECISION` decimal places.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Ties away from zero, e.g. 0.00005 becomes 0.0001.
+    #[default]
+    HalfUp,
+    /// Ties to the even neighbour (banker's rounding), e.g. 0.00005 becomes 0.0.
+    HalfEven,
+    /// Towards negative infinity.
+    Floor,
+}
+
+impl Rounding {
+    pub fn round(self, value: f64) -> f64 {
+        self.round_to(value, PRECISION)
+    }
+
+    /// `value` brought to `decimals` places instead of `PRECISION`, e.g.
+    /// to the exponent of a currency.
+    pub fn round_to(self, value: f64, decimals: i32) -> f64 {
+        let scale = if decimals == PRECISION {
+            SCALE
+        } else {
+            10_f64.powi(decimals)
+        };
+        // Snap away representation error first, so that a value written as
+        // 1.00005 is treated as the tie it was meant to be rather than as
+        // 10000.499999... scaled units.
+        let scaled = (value * scale * 1e6).round() / 1e6;
+        let rounded = match self {
+            Rounding::HalfUp => scaled.round(),
+            Rounding::HalfEven => scaled.round_ties_even(),
+            Rounding::Floor => scaled.floor(),
+        };
+        rounded / scale
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-even" => Ok(Rounding::HalfEven),
+            "floor" => Ok(Rounding::Floor),
+            _ => Err(format!(
+                "unknown rounding mode: {} (expected half-up, half-even or floor)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rounding::HalfUp => "half-up",
+            Rounding::HalfEven => "half-even",
+            Rounding::Floor => "floor",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn modes() {
+        assert_eq!(Rounding::HalfUp.round(1.00005), 1.0001);
+        assert_eq!(Rounding::HalfEven.round(1.00005), 1.0);
+        assert_eq!(Rounding::HalfEven.round(1.00015), 1.0002);
+        assert_eq!(Rounding::Floor.round(1.00009), 1.0);
+        assert_eq!(Rounding::Floor.round(-1.00001), -1.0001);
+        assert_eq!(Rounding::HalfUp.round(-1.00005), -1.0001);
+        assert_eq!(Rounding::HalfEven.round(1.88889), 1.8889);
+    }
+
+    #[test]
+    fn decimals() {
+        assert_eq!(Rounding::HalfUp.round_to(1234.5, 0), 1235.0);
+        assert_eq!(Rounding::HalfEven.round_to(1234.5, 0), 1234.0);
+        assert_eq!(Rounding::HalfUp.round_to(1.0005, 3), 1.001);
+        assert_eq!(Rounding::Floor.round_to(2.199, 2), 2.19);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("half-even".parse(), Ok(Rounding::HalfEven));
+        assert!("up".parse::<Rounding>().is_err());
+    }
+}
@@ -0,0 +1,556 @@
+//! Validation rules kept in a file rather than in code, so that risk
+//! analysts can tune them without a new release.
+//!
+//! Every line that is neither empty nor a `#` comment is one rule:
+//!
+//! ```text
+//! deny large_withdrawal: type == "withdrawal" and amount > 10000
+//! flag gambling: category in ["casino", "betting"] and amount > available / 2
+//! ```
+//!
+//! A rule gives its verdict, `allow`, `flag`, `hold` or `deny`, and its
+//! name, then the condition under which it applies. Conditions see the
+//! transaction's `type`, `client`, `tx`, `amount`, `merchant`, `category`,
+//! `timestamp` and `currency`, and its client's account as `available`,
+//! `held`, `total` and `locked`; an account not opened yet holds nothing and
+//! is not locked. A field the transaction does not have, such as the amount
+//! of a dispute, is `none`, which only equals `none` and is neither less nor
+//! greater than anything. Conditions combine comparisons with `and`, `or`,
+//! `not` and parentheses, and numbers with `+`, `-`, `*` and `/`.
+//!
+//! Rules are tried in order and the first one whose condition holds
+//! decides: `deny` rejects the transaction as `rule_denied`, `flag` lets it
+//! through but reports it, `hold` reports a deposit or withdrawal and holds
+//! its funds until it is approved or rejected, like `flag` for any other
+//! type, and `allow` lets it through without trying the rules after it. A
+//! transaction no rule applies to is allowed, unless a `script` of the
+//! `rhai` feature decides otherwise.
+
+use crate::account::Account;
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Verdict {
+    Allow,
+    Flag,
+    Hold,
+    Deny,
+}
+
+impl FromStr for Verdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Verdict::Allow),
+            "flag" => Ok(Verdict::Flag),
+            "hold" => Ok(Verdict::Hold),
+            "deny" => Ok(Verdict::Deny),
+            _ => Err(format!(
+                "invalid verdict: {} (expected allow, flag, hold or deny)",
+                s
+            )),
+        }
+    }
+}
+
+/// A transaction a `flag`, `hold` or `deny` rule applied to.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RuleHit {
+    pub r#type: Type,
+    pub client: u16,
+    pub tx: u32,
+    pub rule: String,
+    pub verdict: Verdict,
+}
+
+/// A rules file that could not be read.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// 1-based line of the rule.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub verdict: Verdict,
+    pub name: String,
+    condition: Expr,
+}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn parse(source: &str) -> Result<Rules, ParseError> {
+        let mut rules = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| ParseError {
+                line: index + 1,
+                message,
+            };
+            let (head, condition) = line
+                .split_once(':')
+                .ok_or_else(|| error("expected VERDICT NAME: CONDITION".to_string()))?;
+            let (verdict, name) = match head.split_whitespace().collect::<Vec<_>>()[..] {
+                [verdict, name] => (verdict.parse().map_err(error)?, name.to_string()),
+                _ => return Err(error("expected VERDICT NAME before the colon".to_string())),
+            };
+            let condition = Tokens::new(condition).and_then(|mut tokens| {
+                let expr = tokens.or()?;
+                match tokens.peek() {
+                    None => Ok(expr),
+                    Some(token) => Err(format!("unexpected {}", token)),
+                }
+            });
+            rules.push(Rule {
+                verdict,
+                name,
+                condition: condition.map_err(error)?,
+            });
+        }
+        Ok(Rules { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first rule whose condition `tx` meets, given its client's
+    /// `account` if it has one.
+    pub fn evaluate<M: Money>(
+        &self,
+        tx: &Transaction<M>,
+        account: Option<&Account<M>>,
+    ) -> Option<&Rule> {
+        let fields = Fields { tx, account };
+        self.rules
+            .iter()
+            .find(|rule| rule.condition.eval(&fields) == Value::Bool(true))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    None,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Merchant,
+    Category,
+    Timestamp,
+    Currency,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "type" => Field::Type,
+            "client" => Field::Client,
+            "tx" => Field::Tx,
+            "amount" => Field::Amount,
+            "merchant" => Field::Merchant,
+            "category" => Field::Category,
+            "timestamp" => Field::Timestamp,
+            "currency" => Field::Currency,
+            "available" => Field::Available,
+            "held" => Field::Held,
+            "total" => Field::Total,
+            "locked" => Field::Locked,
+            _ => return Err(format!("unknown field {}", s)),
+        })
+    }
+}
+
+struct Fields<'a, M> {
+    tx: &'a Transaction<M>,
+    account: Option<&'a Account<M>>,
+}
+
+impl<M: Money> Fields<'_, M> {
+    fn get(&self, field: Field) -> Value {
+        let text = |value: Option<String>| value.map_or(Value::None, Value::Str);
+        let balance = |balance: fn(&Account<M>) -> M| {
+            Value::Num(
+                self.account
+                    .map_or(0.0, |account| balance(account).to_f64()),
+            )
+        };
+        match field {
+            Field::Type => Value::Str(self.tx.r#type().to_string()),
+            Field::Client => Value::Num(f64::from(self.tx.account_id())),
+            Field::Tx => Value::Num(f64::from(self.tx.id())),
+            Field::Amount => self
+                .tx
+                .optional_amount()
+                .map_or(Value::None, |amount| Value::Num(amount.to_f64())),
+            Field::Merchant => text(self.tx.merchant().map(|label| label.to_string())),
+            Field::Category => text(self.tx.category().map(|label| label.to_string())),
+            Field::Timestamp => self
+                .tx
+                .timestamp()
+                .map_or(Value::None, |timestamp| Value::Num(timestamp as f64)),
+            Field::Currency => text(self.tx.currency().map(|currency| currency.to_string())),
+            Field::Available => balance(Account::available_balance),
+            Field::Held => balance(Account::held_balance),
+            Field::Total => balance(Account::total_balance),
+            Field::Locked => Value::Bool(self.account.is_some_and(Account::locked)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Value(Value),
+    Field(Field),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    In(Box<Expr>, Vec<Value>),
+}
+
+impl Expr {
+    fn eval<M: Money>(&self, fields: &Fields<M>) -> Value {
+        let truth = |expr: &Expr| expr.eval(fields) == Value::Bool(true);
+        match self {
+            Expr::Value(value) => value.clone(),
+            Expr::Field(field) => fields.get(*field),
+            Expr::Not(expr) => Value::Bool(!truth(expr)),
+            Expr::And(left, right) => Value::Bool(truth(left) && truth(right)),
+            Expr::Or(left, right) => Value::Bool(truth(left) || truth(right)),
+            Expr::In(expr, list) => Value::Bool(list.contains(&expr.eval(fields))),
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(fields), right.eval(fields));
+                let ordering = match (&left, &right) {
+                    (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+                    (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                    _ => None,
+                };
+                let number = |f: fn(f64, f64) -> f64| match (&left, &right) {
+                    (Value::Num(a), Value::Num(b)) => Value::Num(f(*a, *b)),
+                    _ => Value::None,
+                };
+                match op {
+                    Op::Eq => Value::Bool(left == right),
+                    Op::Ne => Value::Bool(left != right),
+                    Op::Lt => Value::Bool(ordering.is_some_and(|o| o.is_lt())),
+                    Op::Le => Value::Bool(ordering.is_some_and(|o| o.is_le())),
+                    Op::Gt => Value::Bool(ordering.is_some_and(|o| o.is_gt())),
+                    Op::Ge => Value::Bool(ordering.is_some_and(|o| o.is_ge())),
+                    Op::Add => number(|a, b| a + b),
+                    Op::Sub => number(|a, b| a - b),
+                    Op::Mul => number(|a, b| a * b),
+                    Op::Div => number(|a, b| a / b),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Num(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{:?}", word),
+            Token::Num(number) => write!(f, "{}", number),
+            Token::Str(text) => write!(f, "string {:?}", text),
+            Token::Symbol(symbol) => write!(f, "{:?}", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 16] = [
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "(", ")", "[", "]", ",", "!",
+];
+
+/// A recursive descent parser over the tokens of one condition.
+struct Tokens {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Tokens {
+    fn new(source: &str) -> Result<Tokens, String> {
+        let mut tokens = Vec::new();
+        let mut rest = source.trim_start();
+        while let Some(c) = rest.chars().next() {
+            let len = if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push(Token::Word(rest[..len].to_string()));
+                len
+            } else if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .unwrap_or(rest.len());
+                let number = rest[..len]
+                    .parse()
+                    .map_err(|_| format!("invalid number {}", &rest[..len]))?;
+                tokens.push(Token::Num(number));
+                len
+            } else if c == '"' {
+                let end = rest[1..]
+                    .find('"')
+                    .ok_or_else(|| "unterminated string".to_string())?;
+                tokens.push(Token::Str(rest[1..end + 1].to_string()));
+                end + 2
+            } else {
+                let symbol = SYMBOLS
+                    .into_iter()
+                    .find(|symbol| rest.starts_with(symbol))
+                    .ok_or_else(|| format!("unexpected {:?}", c))?;
+                tokens.push(Token::Symbol(symbol));
+                symbol.len()
+            };
+            rest = rest[len..].trim_start();
+        }
+        Ok(Tokens { tokens, next: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    /// Takes the next token if it is the keyword or symbol `expected`.
+    fn eat(&mut self, expected: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Word(word)) => word == expected,
+            Some(Token::Symbol(symbol)) => *symbol == expected,
+            _ => false,
+        };
+        self.next += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        if self.eat(expected) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(format!("expected {:?}, found {}", expected, token)),
+            None => Err(format!("expected {:?} at the end", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat("not") || self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        if self.eat("in") {
+            self.expect("[")?;
+            let mut list = Vec::new();
+            while !self.eat("]") {
+                if !list.is_empty() {
+                    self.expect(",")?;
+                }
+                match self.atom()? {
+                    Expr::Value(value) => list.push(value),
+                    _ => return Err("expected a list of literals after in".to_string()),
+                }
+            }
+            return Ok(Expr::In(Box::new(left), list));
+        }
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        match ops.into_iter().find(|(symbol, _)| self.eat(symbol)) {
+            Some((_, op)) => Ok(Expr::Binary(op, Box::new(left), Box::new(self.sum()?))),
+            None => Ok(left),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = match () {
+                _ if self.eat("+") => Op::Add,
+                _ if self.eat("-") => Op::Sub,
+                _ => return Ok(expr),
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.atom()?;
+        loop {
+            let op = match () {
+                _ if self.eat("*") => Op::Mul,
+                _ if self.eat("/") => Op::Div,
+                _ => return Ok(expr),
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.atom()?));
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("-") {
+            return match self.atom()? {
+                Expr::Value(Value::Num(number)) => Ok(Expr::Value(Value::Num(-number))),
+                expr => Ok(Expr::Binary(
+                    Op::Sub,
+                    Box::new(Expr::Value(Value::Num(0.0))),
+                    Box::new(expr),
+                )),
+            };
+        }
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| "unexpected end of condition".to_string())?;
+        self.next += 1;
+        Ok(match token {
+            Token::Num(number) => Expr::Value(Value::Num(number)),
+            Token::Str(text) => Expr::Value(Value::Str(text)),
+            Token::Word(word) => match word.as_str() {
+                "true" => Expr::Value(Value::Bool(true)),
+                "false" => Expr::Value(Value::Bool(false)),
+                "none" => Expr::Value(Value::None),
+                _ => Expr::Field(word.parse()?),
+            },
+            Token::Symbol(symbol) => return Err(format!("unexpected {:?}", symbol)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_rule_decides() {
+        let rules = Rules::parse(
+            r#"
+            # vetted clients are never held up
+            allow vetted: client in [7, 8]
+            deny large_withdrawal: type == "withdrawal" and amount > 100
+            flag half_balance: amount > available / 2 and not locked
+            flag no_amount: amount == none
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 4);
+        let mut account = Account::new(1);
+        account.deposit(50.0).unwrap();
+        let rule =
+            |tx: Transaction, account| rules.evaluate(&tx, account).map(|rule| &rule.name[..]);
+
+        let withdrawal = |client, amount| Transaction::new(1, Type::Withdrawal, client, amount);
+        assert_eq!(
+            rule(withdrawal(1, 500.0), Some(&account)),
+            Some("large_withdrawal")
+        );
+        assert_eq!(rule(withdrawal(7, 500.0), Some(&account)), Some("vetted"));
+        assert_eq!(
+            rule(withdrawal(1, 30.0), Some(&account)),
+            Some("half_balance")
+        );
+        assert_eq!(rule(withdrawal(1, 20.0), Some(&account)), None);
+        assert_eq!(rule(withdrawal(1, 20.0), None), Some("half_balance"));
+        let mut dispute = Transaction::new(1, Type::Dispute, 1, 0.0);
+        dispute.amount = None;
+        assert_eq!(rule(dispute, Some(&account)), Some("no_amount"));
+
+        let error = Rules::parse("deny x: amount > 1\nflag y: balance > 1").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: unknown field balance");
+        assert!(Rules::parse("deny x: (amount > 1").is_err());
+        assert!(Rules::parse("block x: amount > 1").is_err());
+    }
+}
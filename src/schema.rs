@@ -0,0 +1,281 @@
+//! Checks an input file against the schema the engine expects, without
+//! processing it.
+//!
+//! `inspect` reads the header and up to a number of rows, infers a type for
+//! every column and lists whatever would make rows get skipped: missing
+//! columns, values of the wrong type, unknown transaction types and
+//! deposits or withdrawals without an amount. Disputes, resolves and
+//! chargebacks may leave the amount empty or have no amount column at all.
+
+use crate::transaction::Type;
+use csv::ReaderBuilder;
+use std::{fmt, io};
+
+/// Columns every row needs.
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+
+/// Optional columns the parser reads; any other column is ignored.
+const OPTIONAL: [&str; 7] = [
+    "amount",
+    "merchant",
+    "counterparty",
+    "category",
+    "timestamp",
+    "currency",
+    "tenant",
+];
+
+/// The narrowest type that holds every non-empty value of a column.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColumnType {
+    /// No values at all.
+    Empty,
+    Integer,
+    Decimal,
+    Timestamp,
+    Text,
+}
+
+impl ColumnType {
+    fn of(value: &str) -> ColumnType {
+        if value.is_empty() {
+            ColumnType::Empty
+        } else if value.parse::<u64>().is_ok() {
+            ColumnType::Integer
+        } else if value.parse::<f64>().is_ok() {
+            ColumnType::Decimal
+        } else if crate::timestamp::parse(value).is_some() {
+            ColumnType::Timestamp
+        } else {
+            ColumnType::Text
+        }
+    }
+
+    /// The type of a column holding values of both `self` and `other`.
+    fn widen(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (ColumnType::Empty, other) | (other, ColumnType::Empty) => other,
+            (ColumnType::Integer, ColumnType::Decimal)
+            | (ColumnType::Decimal, ColumnType::Integer) => ColumnType::Decimal,
+            (ColumnType::Integer, ColumnType::Timestamp)
+            | (ColumnType::Timestamp, ColumnType::Integer) => ColumnType::Timestamp,
+            (a, b) if a == b => a,
+            _ => ColumnType::Text,
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColumnType::Empty => "empty",
+            ColumnType::Integer => "integer",
+            ColumnType::Decimal => "decimal",
+            ColumnType::Timestamp => "timestamp",
+            ColumnType::Text => "text",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub r#type: ColumnType,
+    /// Sampled rows leaving the column empty.
+    pub empty: u64,
+    /// Whether the parser reads the column.
+    pub known: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+    pub rows_sampled: u64,
+    /// Deposits, withdrawals and bonuses in the sample without an amount.
+    pub missing_amounts: u64,
+    /// Everything that makes the file, or some of its rows, unusable.
+    pub problems: Vec<String>,
+}
+
+impl Schema {
+    /// Reads the header and at most `sample` rows, all of them with `None`.
+    pub fn inspect<R: io::Read>(reader: R, sample: Option<u64>) -> Result<Schema, csv::Error> {
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        let mut columns: Vec<Column> = headers
+            .iter()
+            .map(|name| Column {
+                name: name.to_string(),
+                r#type: ColumnType::Empty,
+                empty: 0,
+                known: REQUIRED.contains(&name) || OPTIONAL.contains(&name),
+            })
+            .collect();
+        let position = |name: &str| headers.iter().position(|header| header == name);
+        let type_column = position("type");
+        let amount_column = position("amount");
+
+        let mut problems: Vec<String> = REQUIRED
+            .iter()
+            .filter(|name| position(name).is_none())
+            .map(|name| format!("missing required column {:?}", name))
+            .collect();
+        let mut unknown_types = 0;
+        let mut rows_sampled = 0;
+        let mut missing_amounts = 0;
+        for record in rdr.records() {
+            if sample.is_some_and(|sample| rows_sampled >= sample) {
+                break;
+            }
+            let record = record?;
+            rows_sampled += 1;
+            for (column, value) in columns.iter_mut().zip(record.iter()) {
+                let r#type = ColumnType::of(value);
+                column.empty += u64::from(r#type == ColumnType::Empty);
+                column.r#type = column.r#type.widen(r#type);
+            }
+            for column in columns.iter_mut().skip(record.len()) {
+                column.empty += 1;
+            }
+            let r#type = type_column.and_then(|column| record.get(column));
+            match r#type.map(str::parse::<Type>) {
+                Some(Ok(Type::Deposit | Type::Withdrawal | Type::Bonus)) => {
+                    let amount = amount_column.and_then(|column| record.get(column));
+                    missing_amounts += u64::from(amount.unwrap_or_default().is_empty());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => unknown_types += 1,
+            }
+        }
+
+        let expected = [
+            ("client", ColumnType::Integer),
+            ("tx", ColumnType::Integer),
+            ("amount", ColumnType::Decimal),
+            ("timestamp", ColumnType::Timestamp),
+        ];
+        for column in &columns {
+            let wanted = expected.iter().find(|(name, _)| *name == column.name);
+            if let Some(&(name, wanted)) = wanted {
+                if column.r#type.widen(wanted) != wanted {
+                    problems.push(format!(
+                        "column {:?} holds {} values, expected {}",
+                        name, column.r#type, wanted
+                    ));
+                }
+            }
+        }
+        if unknown_types > 0 && type_column.is_some() {
+            problems.push(format!(
+                "{} row(s) with an unknown transaction type",
+                unknown_types
+            ));
+        }
+        if missing_amounts > 0 {
+            problems.push(format!(
+                "{} deposit, withdrawal or bonus row(s) without an amount",
+                missing_amounts
+            ));
+        }
+        Ok(Schema {
+            columns,
+            rows_sampled,
+            missing_amounts,
+            problems,
+        })
+    }
+
+    /// Whether every sampled row would be processed.
+    pub fn is_compatible(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "columns:")?;
+        for column in &self.columns {
+            write!(f, "  {:<12} {}", column.name, column.r#type)?;
+            if column.empty > 0 && column.r#type != ColumnType::Empty {
+                write!(f, ", {} empty", column.empty)?;
+            }
+            if !column.known {
+                write!(f, " (ignored)")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "rows sampled: {}", self.rows_sampled)?;
+        writeln!(
+            f,
+            "compatible: {}",
+            if self.is_compatible() { "yes" } else { "no" }
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compatible_without_amounts_on_disputes() {
+        let input = "type,client,tx,amount,note\n\
+                     deposit,1,1,5.0,first\n\
+                     deposit,1,2,3,\n\
+                     dispute,1,1\n\
+                     resolve,1,1,\n";
+        let schema = Schema::inspect(input.as_bytes(), None).unwrap();
+        assert!(schema.is_compatible(), "{:?}", schema.problems);
+        assert_eq!(schema.rows_sampled, 4);
+        let types: Vec<(&str, ColumnType, u64)> = schema
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.r#type, column.empty))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("type", ColumnType::Text, 0),
+                ("client", ColumnType::Integer, 0),
+                ("tx", ColumnType::Integer, 0),
+                ("amount", ColumnType::Decimal, 2),
+                ("note", ColumnType::Text, 3),
+            ]
+        );
+        assert!(!schema.columns[4].known);
+
+        let input = "type,client,tx\ndispute,1,1\nchargeback,1,1\n";
+        assert!(Schema::inspect(input.as_bytes(), None)
+            .unwrap()
+            .is_compatible());
+    }
+
+    #[test]
+    fn problems() {
+        let input = "type,tx,amount\n\
+                     deposit,1,\n\
+                     refund,2,1.0\n\
+                     withdrawal,x,1.0\n";
+        let schema = Schema::inspect(input.as_bytes(), None).unwrap();
+        assert_eq!(schema.missing_amounts, 1);
+        assert_eq!(
+            schema.problems,
+            [
+                "missing required column \"client\"",
+                "column \"tx\" holds text values, expected integer",
+                "1 row(s) with an unknown transaction type",
+                "1 deposit, withdrawal or bonus row(s) without an amount",
+            ]
+        );
+
+        let schema = Schema::inspect(input.as_bytes(), Some(1)).unwrap();
+        assert_eq!(schema.rows_sampled, 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Sanctions and blocklist screening.
+//!
+//! The engine asks a `Screening` about the client of every transaction
+//! before touching its account; transactions of a blocked client are
+//! rejected with `RejectReason::BlockedClient`.
+
+use crate::engine::{RejectReason, Rejection};
+use crate::transaction::Type;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, BufRead};
+
+pub trait Screening {
+    fn is_blocked(&self, client: u16) -> bool;
+}
+
+/// A fixed set of blocked client ids.
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    clients: HashSet<u16>,
+}
+
+impl Blocklist {
+    pub fn new(clients: impl IntoIterator<Item = u16>) -> Blocklist {
+        Blocklist {
+            clients: clients.into_iter().collect(),
+        }
+    }
+
+    /// Reads one client id per line. Blank lines and lines starting with `#`
+    /// are skipped, as is a leading `client` header.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Blocklist> {
+        let mut clients = HashSet::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (index == 0 && line == "client") {
+                continue;
+            }
+            let client = line.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid client id on line {}: {:?}", index + 1, line),
+                )
+            })?;
+            clients.insert(client);
+        }
+        Ok(Blocklist { clients })
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl Screening for Blocklist {
+    fn is_blocked(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+}
+
+/// The transactions refused for one blocked client.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ScreeningHit {
+    pub client: u16,
+    pub transactions: u64,
+    /// Sum of the refused deposits.
+    pub deposits: f64,
+    /// Sum of the refused withdrawals.
+    pub withdrawals: f64,
+}
+
+/// One hit per blocked client that showed up in `rejections`, ordered by
+/// client.
+pub fn hits(rejections: &[Rejection]) -> Vec<ScreeningHit> {
+    let mut hits: BTreeMap<u16, ScreeningHit> = BTreeMap::new();
+    for rejection in rejections {
+        if rejection.reason != RejectReason::BlockedClient {
+            continue;
+        }
+        let hit = hits.entry(rejection.client).or_insert(ScreeningHit {
+            client: rejection.client,
+            transactions: 0,
+            deposits: 0.0,
+            withdrawals: 0.0,
+        });
+        hit.transactions += 1;
+        let amount = rejection.amount.unwrap_or_default();
+        match rejection.r#type {
+            Type::Deposit => hit.deposits += amount,
+            Type::Withdrawal => hit.withdrawals += amount,
+            _ => {}
+        }
+    }
+    hits.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::Engine;
+    use crate::transaction::{Transaction, TransactionLedger};
+
+    #[test]
+    fn read_blocklist() {
+        let blocklist = Blocklist::read("client\n# sanctioned\n7\n\n 9 \n".as_bytes()).unwrap();
+        assert_eq!(blocklist.len(), 2);
+        assert!(blocklist.is_blocked(7));
+        assert!(blocklist.is_blocked(9));
+        assert!(!blocklist.is_blocked(1));
+        assert!(Blocklist::read("7\nseven\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn blocked_clients_are_rejected() {
+        let blocklist = Blocklist::new([2]);
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts).with_screening(&blocklist);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 5.0),
+            Transaction::new(2, Type::Deposit, 2, 5.0),
+            Transaction::new(3, Type::Withdrawal, 2, 1.0),
+            Transaction::new(2, Type::Dispute, 2, 0.0),
+        ]);
+        assert_eq!(engine.rejections().len(), 3);
+        assert_eq!(
+            hits(engine.rejections()),
+            [ScreeningHit {
+                client: 2,
+                transactions: 3,
+                deposits: 5.0,
+                withdrawals: 1.0,
+            }]
+        );
+        assert!(accounts.get(2).is_none());
+        assert_eq!(accounts.get(1).unwrap().available_balance(), 5.0);
+    }
+}
@@ -0,0 +1,161 @@
+//! Validation rules written as a rhai script, for checks the rules file
+//! cannot express, such as loops over lists or helper functions.
+//!
+//! The script runs once per transaction that no rule of `Engine::with_rules`
+//! decided, and sees the same names as rule conditions: `type`, `client`,
+//! `tx`, `amount`, `merchant`, `category`, `timestamp` and `currency` of the
+//! transaction, and `available`, `held`, `total` and `locked` of its
+//! client's account before it. A field the transaction does not have is
+//! `()`. Amounts and balances are floats, ids and timestamps integers.
+//!
+//! ```text
+//! if type == "withdrawal" && amount > available / 2.0 { "flag" }
+//! else if merchant in ["casino", "betting"] { "deny" }
+//! ```
+//!
+//! The script evaluates to `"allow"`, `"flag"`, `"hold"` or `"deny"`, with
+//! the meaning they have for rules, or to `()` to have no say. A script
+//! that fails or runs for more than `MAX_OPERATIONS` steps denies the
+//! transaction, so that a broken script never lets through what it should
+//! have stopped.
+
+use crate::account::Account;
+use crate::money::Money;
+use crate::rules::Verdict;
+use crate::transaction::Transaction;
+use rhai::{Dynamic, Scope, AST};
+
+/// Steps a script may take per transaction before it is stopped.
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+pub struct Script {
+    engine: rhai::Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn compile(source: &str) -> Result<Script, String> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|err| err.to_string())?;
+        Ok(Script { engine, ast })
+    }
+
+    /// The verdict of the script on `tx`, given its client's `account` if
+    /// it has one, or `None` if it has no say.
+    pub fn evaluate<M: Money>(
+        &self,
+        tx: &Transaction<M>,
+        account: Option<&Account<M>>,
+    ) -> Result<Option<Verdict>, String> {
+        let text = |value: Option<String>| value.map_or(Dynamic::UNIT, Dynamic::from);
+        let balance = |balance: fn(&Account<M>) -> M| {
+            account.map_or(0.0, |account| balance(account).to_f64())
+        };
+        let mut scope = Scope::new();
+        scope
+            .push_constant("type", tx.r#type().to_string())
+            .push_constant("client", i64::from(tx.account_id()))
+            .push_constant("tx", i64::from(tx.id()))
+            .push_constant_dynamic(
+                "amount",
+                tx.optional_amount()
+                    .map_or(Dynamic::UNIT, |amount| Dynamic::from(amount.to_f64())),
+            )
+            .push_constant_dynamic("merchant", text(tx.merchant().map(|l| l.to_string())))
+            .push_constant_dynamic("category", text(tx.category().map(|l| l.to_string())))
+            .push_constant_dynamic(
+                "timestamp",
+                tx.timestamp()
+                    .and_then(|timestamp| i64::try_from(timestamp).ok())
+                    .map_or(Dynamic::UNIT, Dynamic::from),
+            )
+            .push_constant_dynamic("currency", text(tx.currency().map(|c| c.to_string())))
+            .push_constant("available", balance(Account::available_balance))
+            .push_constant("held", balance(Account::held_balance))
+            .push_constant("total", balance(Account::total_balance))
+            .push_constant("locked", account.is_some_and(Account::locked));
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|err| err.to_string())?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        match result.into_string() {
+            Ok(verdict) => verdict.parse().map(Some),
+            Err(r#type) => Err(format!(
+                "script returned {} (expected a verdict or ())",
+                r#type
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::{Engine, RejectReason};
+    use crate::rules::Rules;
+    use crate::transaction::{Label, TransactionLedger, Type};
+
+    #[test]
+    fn evaluates_fields() {
+        let script = Script::compile(
+            r#"
+            if type == "withdrawal" && amount > available / 2.0 { "flag" }
+            else if merchant in ["casino", "betting"] { "deny" }
+            else if type == "dispute" && timestamp == () { "hold" }
+            "#,
+        )
+        .unwrap();
+        let mut accounts = AccountsRepository::<f64>::new();
+        accounts.get_or_create(1).deposit(100.0).unwrap();
+        let account = accounts.get(1).unwrap();
+        let verdict = |tx: &Transaction| script.evaluate(tx, Some(&account));
+        let withdrawal = Transaction::new(1, Type::Withdrawal, 1, 60.0);
+        assert_eq!(verdict(&withdrawal), Ok(Some(Verdict::Flag)));
+        let deposit =
+            Transaction::new(2, Type::Deposit, 1, 5.0).with_merchant(Label::new("casino").ok());
+        assert_eq!(verdict(&deposit), Ok(Some(Verdict::Deny)));
+        let dispute = Transaction::new(2, Type::Dispute, 1, 0.0);
+        assert_eq!(verdict(&dispute), Ok(Some(Verdict::Hold)));
+        let small = Transaction::new(3, Type::Withdrawal, 1, 10.0);
+        assert_eq!(verdict(&small), Ok(None));
+
+        assert!(Script::compile("if {").is_err());
+        let wrong = Script::compile("42").unwrap();
+        assert!(wrong.evaluate(&small, None).is_err());
+        let endless = Script::compile("loop {}").unwrap();
+        assert!(endless.evaluate(&small, None).is_err());
+    }
+
+    #[test]
+    fn runs_after_rules() {
+        let rules = Rules::parse("allow vetted: client == 7").unwrap();
+        let script = Script::compile(r#"if amount > 50.0 { "deny" }"#).unwrap();
+        let broken = Script::compile("amount.nonexistent()").unwrap();
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts)
+            .with_rules(&rules)
+            .with_script(&script);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 100.0),
+            Transaction::new(2, Type::Deposit, 1, 10.0),
+            Transaction::new(3, Type::Deposit, 7, 100.0),
+        ]);
+        let reasons: Vec<(u32, RejectReason)> = engine
+            .rejections()
+            .iter()
+            .map(|rejection| (rejection.tx, rejection.reason))
+            .collect();
+        assert_eq!(reasons, [(1, RejectReason::RuleDenied)]);
+        assert_eq!(engine.rule_hits()[0].rule, "script");
+
+        let mut engine = engine.with_script(&broken);
+        engine.process(&[Transaction::new(4, Type::Deposit, 1, 1.0)]);
+        assert_eq!(engine.rejections()[1].reason, RejectReason::RuleDenied);
+    }
+}
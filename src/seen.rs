@@ -0,0 +1,194 @@
+//! Ids of the deposits and withdrawals applied in earlier runs, kept on
+//! disk so that a file sent again on a later day cannot apply them twice
+//! even when the ledger of those runs is not loaded.
+//!
+//! The file holds one id, or one inclusive `first-last` range of ids, per
+//! line. New ids are appended as single lines by `save`; once those
+//! outnumber the ranges they would merge into, the file is rewritten as
+//! sorted ranges. A line cut short by a crash mid-write is dropped on
+//! reading, and the file rewritten on the next save; a malformed line
+//! anywhere else is an error.
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Lines a file may hold before `save` considers compacting it.
+const COMPACT_AFTER: usize = 1024;
+
+pub struct SeenIds {
+    path: PathBuf,
+    ids: BTreeSet<u32>,
+    /// Inserted since the last save.
+    unsaved: Vec<u32>,
+    /// Lines in the file.
+    lines: usize,
+    /// Whether the file ends in a line cut short, which appending to would
+    /// corrupt.
+    torn: bool,
+}
+
+impl SeenIds {
+    /// The ids stored at `path`, or none if there is no file yet.
+    pub fn open(path: &Path) -> io::Result<SeenIds> {
+        let mut seen = SeenIds {
+            path: path.to_path_buf(),
+            ids: BTreeSet::new(),
+            unsaved: Vec::new(),
+            lines: 0,
+            torn: false,
+        };
+        let mut contents = String::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_string(&mut contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(seen),
+            Err(err) => return Err(err),
+        };
+        let complete = contents.rfind('\n').map_or("", |end| &contents[..end]);
+        seen.torn = !contents.is_empty() && !contents.ends_with('\n');
+        for (index, line) in complete.lines().enumerate() {
+            let (first, last) = parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed id on line {}: {:?}", index + 1, line),
+                )
+            })?;
+            seen.ids.extend(first..=last);
+            seen.lines += 1;
+        }
+        Ok(seen)
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Records `id`, returning whether it was new.
+    pub fn insert(&mut self, id: u32) -> bool {
+        let new = self.ids.insert(id);
+        if new {
+            self.unsaved.push(id);
+        }
+        new
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Appends the ids inserted since the last save to the file, compacting
+    /// it if it has grown to more than twice its compacted size.
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.torn {
+            return self.compact();
+        }
+        if !self.unsaved.is_empty() {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            let mut lines = String::new();
+            for id in &self.unsaved {
+                lines.push_str(&format!("{}\n", id));
+            }
+            file.write_all(lines.as_bytes())?;
+            file.sync_all()?;
+            self.lines += self.unsaved.len();
+            self.unsaved.clear();
+        }
+        if self.lines > COMPACT_AFTER && self.lines > 2 * self.ranges().len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file as the sorted ranges of every id, including any not
+    /// saved yet.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let ranges = self.ranges();
+        let mut contents = String::new();
+        for (first, last) in &ranges {
+            if first == last {
+                contents.push_str(&format!("{}\n", first));
+            } else {
+                contents.push_str(&format!("{}-{}\n", first, last));
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.lines = ranges.len();
+        self.unsaved.clear();
+        self.torn = false;
+        Ok(())
+    }
+
+    /// The ids as inclusive ranges of consecutive ids, in order.
+    fn ranges(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &id in &self.ids {
+            match ranges.last_mut() {
+                Some((_, last)) if last.checked_add(1) == Some(id) => *last = id,
+                _ => ranges.push((id, id)),
+            }
+        }
+        ranges
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u32, u32)> {
+    let (first, last) = match line.split_once('-') {
+        Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+        None => {
+            let id = line.trim().parse().ok()?;
+            (id, id)
+        }
+    };
+    (first <= last).then_some((first, last))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn save_and_compact() {
+        let dir = std::env::temp_dir().join(format!("seen-ids-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("seen");
+        let _ = std::fs::remove_file(&path);
+
+        let mut seen = SeenIds::open(&path).unwrap();
+        assert!(seen.is_empty());
+        for id in [3, 1, 2, 7] {
+            assert!(seen.insert(id));
+        }
+        assert!(!seen.insert(2));
+        seen.save().unwrap();
+        // A line cut short by a crash is dropped.
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"1")
+            .unwrap();
+
+        let mut seen = SeenIds::open(&path).unwrap();
+        assert_eq!(seen.len(), 4);
+        assert!(seen.contains(7) && !seen.contains(4));
+        seen.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1-3\n7\n");
+        assert_eq!(SeenIds::open(&path).unwrap().len(), 4);
+
+        std::fs::write(&path, "1\nx\n").unwrap();
+        assert!(SeenIds::open(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Cutting a huge input down for a quick smoke test or an investigation: a
+//! seeded sample of its clients, a given list of clients, and at most so
+//! many rows.
+//!
+//! Both pick clients rather than rows, so that every picked client keeps all
+//! of its transactions and disputes still find what they refer to.
+
+use crate::money::Money;
+use crate::transaction::Transaction;
+use std::collections::{BTreeSet, HashSet};
+use std::str::FromStr;
+
+/// A pseudo-random share of the clients, the same for the same seed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sample {
+    fraction: f64,
+    seed: u64,
+}
+
+impl Sample {
+    /// Keeps about `fraction` of the clients, between 0 and 1.
+    pub fn new(fraction: f64, seed: u64) -> Result<Sample, String> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(format!(
+                "sample fraction {} is not between 0 and 1",
+                fraction
+            ));
+        }
+        Ok(Sample { fraction, seed })
+    }
+
+    pub fn keeps(&self, client: u16) -> bool {
+        // SplitMix64 of the seed and the client id, as a number in [0, 1).
+        let mut z = self
+            .seed
+            .wrapping_add(u64::from(client).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.fraction
+    }
+
+    /// Drops the transactions of every client left out of the sample.
+    pub fn apply<M: Money>(&self, transactions: &mut Vec<Transaction<M>>) {
+        transactions.retain(|tx| self.keeps(tx.account_id()));
+    }
+}
+
+/// The clients given by id, e.g. `7,19,3000`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientFilter {
+    clients: BTreeSet<u16>,
+}
+
+impl ClientFilter {
+    pub fn keeps(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+
+    /// Drops the transactions of every other client, except the ones with
+    /// a tx id one of the given clients also uses. Those stay so that a
+    /// dispute of another client's deposit or a reused id is still
+    /// rejected as it would be on the whole input.
+    pub fn apply<M: Money>(&self, transactions: &mut Vec<Transaction<M>>) {
+        let referenced: HashSet<u32> = transactions
+            .iter()
+            .filter(|tx| self.keeps(tx.account_id()))
+            .map(|tx| tx.id())
+            .collect();
+        transactions.retain(|tx| self.keeps(tx.account_id()) || referenced.contains(&tx.id()));
+    }
+}
+
+impl FromStr for ClientFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClientFilter, String> {
+        let clients = s
+            .split(',')
+            .map(|client| {
+                client
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid client id {:?}", client))
+            })
+            .collect::<Result<BTreeSet<u16>, _>>()?;
+        Ok(ClientFilter { clients })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Type;
+
+    #[test]
+    fn whole_clients() {
+        let sample = Sample::new(0.25, 7).unwrap();
+        let kept = (0..=u16::MAX)
+            .filter(|&client| sample.keeps(client))
+            .count();
+        assert!((15_000..17_500).contains(&kept), "kept {}", kept);
+
+        let mut transactions: Vec<Transaction> = (0..400)
+            .map(|id| Transaction::new(id, Type::Deposit, (id % 40) as u16, 1.0))
+            .collect();
+        sample.apply(&mut transactions);
+        assert!(!transactions.is_empty());
+        for tx in &transactions {
+            assert!(sample.keeps(tx.account_id()));
+        }
+        assert_eq!(transactions.len() % 10, 0);
+
+        let other_seed = Sample::new(0.25, 8).unwrap();
+        assert!((0..40).any(|client| sample.keeps(client) != other_seed.keeps(client)));
+        assert!((0..40).all(|client| Sample::new(1.0, 7).unwrap().keeps(client)));
+        assert!((0..40).all(|client| !Sample::new(0.0, 7).unwrap().keeps(client)));
+        assert!(Sample::new(1.5, 7).is_err());
+    }
+
+    #[test]
+    fn given_clients() {
+        let filter: ClientFilter = "7, 19".parse().unwrap();
+        let mut transactions = vec![
+            Transaction::new(1, Type::Deposit, 7, 5.0),
+            Transaction::new(2, Type::Deposit, 8, 5.0),
+            Transaction::new(3, Type::Deposit, 8, 1.0),
+            Transaction::new(3, Type::Dispute, 19, 0.0),
+            Transaction::new(2, Type::Withdrawal, 20, 1.0),
+        ];
+        filter.apply(&mut transactions);
+        let kept: Vec<(u32, u16)> = transactions
+            .iter()
+            .map(|tx| (tx.id(), tx.account_id()))
+            .collect();
+        assert_eq!(kept, [(1, 7), (3, 8), (3, 19)]);
+        assert!("7,x".parse::<ClientFilter>().is_err());
+        assert!("70000".parse::<ClientFilter>().is_err());
+    }
+}
@@ -0,0 +1,1669 @@
+use crate::account::{Account, AccountsReader, AccountsRepository, Snapshot};
+use crate::actors::Router;
+use crate::checkpoint;
+use crate::dead_letter::{DeadLetter, DeadLetterSink, DeadLetters};
+use crate::engine::RejectReason;
+use crate::expiry::HoldExpiry;
+use crate::parser::Parser;
+use crate::progress;
+use crate::reorder::{LateEvent, ReorderBuffer};
+use crate::retention::{AuditLog, Retention};
+use crate::rounding::Rounding;
+use crate::source::{Fetched, Positions, Source};
+use crate::state::State;
+use crate::transaction::Transaction;
+use crate::wal::Wal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Engine state shared between the ingestion loop and the HTTP endpoints.
+#[derive(Default)]
+struct Shared {
+    state: State,
+    ready: bool,
+    checkpoints: Option<Checkpoints>,
+    recovery: Option<Recovery>,
+    router: Option<Router>,
+    /// Set while intake is paused, see `Server::pause`.
+    paused: bool,
+    limits: Option<Limits>,
+    rate_limited: u64,
+    reorder: Option<ReorderBuffer>,
+    late: Vec<LateEvent>,
+    dead_letters: Option<DeadLetters>,
+    snapshots: Option<Snapshots>,
+    /// Bearer token the `/admin` requests must carry, see
+    /// `Server::with_admin_token`.
+    admin_token: Option<String>,
+}
+
+impl Shared {
+    fn status(&self) -> Status {
+        let (ledger_size, account_count) = match &self.router {
+            Some(router) => (router.transactions(), router.accounts()),
+            None => (self.state.tx_ledger.len(), self.state.accounts.len()),
+        };
+        Status {
+            ledger_size,
+            account_count,
+            wal_lag: self
+                .checkpoints
+                .as_ref()
+                .filter(|checkpoints| checkpoints.wal.is_some())
+                .map(|checkpoints| self.state.offset() - checkpoints.offset),
+            last_tx_id: self.state.last_tx_id(),
+            offset: self.state.offset(),
+            recovery: self.recovery,
+            paused: self.paused,
+            rate_limited: self.rate_limited,
+            buffered: self.reorder.as_ref().map_or(0, ReorderBuffer::len),
+            late: self.late.len(),
+        }
+    }
+
+    /// Applies `tx`, or with a reorder window, whatever its arrival
+    /// releases.
+    fn apply(&mut self, tx: &Transaction) {
+        let Some(reorder) = &mut self.reorder else {
+            return self.apply_now(tx);
+        };
+        let mut ready = Vec::new();
+        if let Some(late) = reorder.push(*tx, &mut ready) {
+            log::warn!(
+                "tx {} at {} arrived behind the watermark {}",
+                late.tx,
+                late.timestamp,
+                late.watermark
+            );
+            self.late.push(late);
+        }
+        for tx in &ready {
+            self.apply_now(tx);
+        }
+    }
+
+    /// Applies everything the reorder window holds back.
+    fn flush_reorder(&mut self) {
+        let ready = match &mut self.reorder {
+            Some(reorder) => reorder.flush(),
+            None => return,
+        };
+        for tx in &ready {
+            self.apply_now(tx);
+        }
+    }
+
+    fn apply_now(&mut self, tx: &Transaction) {
+        let offset = self.state.offset() + 1;
+        if let Some(wal) = self.checkpoints.as_mut().and_then(|c| c.wal.as_mut()) {
+            if let Err(err) = wal.append(offset, tx) {
+                log::warn!(
+                    "could not log tx {} to the wal, dropping it: {}",
+                    tx.id(),
+                    err
+                );
+                return;
+            }
+        }
+        match &mut self.router {
+            Some(router) => {
+                router.dispatch(*tx);
+                self.state.advance(tx);
+            }
+            None => {
+                if let (Err(reason), Some(dead_letters)) =
+                    (self.state.try_apply(tx), &self.dead_letters)
+                {
+                    dead_letters.send(DeadLetter::rejected(tx, reason));
+                }
+            }
+        }
+        self.checkpoint_if_due();
+        self.snapshot_if_due();
+    }
+
+    fn checkpoint_if_due(&mut self) {
+        let Some(checkpoints) = &mut self.checkpoints else {
+            return;
+        };
+        let pending = self.state.offset() - checkpoints.offset;
+        let options = &checkpoints.options;
+        let due = pending > 0
+            && (options.every.is_some_and(|every| pending >= every)
+                || options
+                    .interval
+                    .is_some_and(|interval| checkpoints.written_at.elapsed() >= interval));
+        if !due {
+            return;
+        }
+        match self.write_checkpoint() {
+            Some(Ok(path)) => log::debug!("wrote checkpoint {}", path.display()),
+            Some(Err(err)) => log::warn!("could not write checkpoint: {}", err),
+            None => {}
+        }
+    }
+
+    fn snapshot_if_due(&mut self) {
+        let Some(snapshots) = &mut self.snapshots else {
+            return;
+        };
+        let pending = self.state.offset() - snapshots.offset;
+        let options = &snapshots.options;
+        let due = pending > 0
+            && (options.every.is_some_and(|every| pending >= every)
+                || options
+                    .interval
+                    .is_some_and(|interval| snapshots.written_at.elapsed() >= interval));
+        if !due {
+            return;
+        }
+        let offset = self.state.offset();
+        match progress::write(
+            &options.dir,
+            &self.state.accounts,
+            offset,
+            SystemTime::now(),
+        ) {
+            Ok(path) => log::debug!("wrote snapshot {}", path.display()),
+            Err(err) => log::warn!("could not write snapshot: {}", err),
+        }
+        snapshots.offset = offset;
+        snapshots.written_at = Instant::now();
+    }
+
+    /// Writes a checkpoint now, if checkpoints are configured, and empties
+    /// the WAL it covers.
+    fn write_checkpoint(&mut self) -> Option<io::Result<PathBuf>> {
+        let checkpoints = self.checkpoints.as_mut()?;
+        let written = checkpoint::write(&checkpoints.options.dir, &self.state);
+        if written.is_ok() {
+            if let Some(Err(err)) = checkpoints.wal.as_mut().map(Wal::truncate) {
+                log::warn!("could not truncate wal: {}", err);
+            }
+            checkpoints.positions = self.state.positions().clone();
+        }
+        checkpoints.offset = self.state.offset();
+        checkpoints.written_at = Instant::now();
+        Some(written)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Status {
+    ledger_size: usize,
+    account_count: usize,
+    wal_lag: Option<u64>,
+    last_tx_id: Option<u32>,
+    /// Transactions applied since the very first start, checkpoints included.
+    offset: u64,
+    recovery: Option<Recovery>,
+    paused: bool,
+    /// Transactions refused as `RateLimited` since the start.
+    rate_limited: u64,
+    /// Transactions held back by the reorder window.
+    buffered: usize,
+    /// Transactions that arrived behind the watermark since the start.
+    late: usize,
+}
+
+/// Every account as of `offset` transactions.
+#[derive(Serialize)]
+struct Accounts<'a> {
+    offset: u64,
+    accounts: Vec<&'a Account>,
+}
+
+/// What happened while restoring state on startup.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Recovery {
+    /// Offset of the checkpoint restored, 0 when starting from scratch.
+    pub checkpoint_offset: u64,
+    /// WAL entries replayed on top of the checkpoint.
+    pub replayed: u64,
+    pub duration_ms: u64,
+}
+
+/// Where and how often the server persists its state. A checkpoint is
+/// written once `every` transactions or `interval` have passed since the
+/// last one, whichever comes first. With `wal`, every transaction is also
+/// logged to `WAL_FILE` in `dir` before it is applied, so nothing accepted
+/// since the last checkpoint is lost in a crash. Balances are rounded with
+/// `rounding`, restored ones included.
+#[derive(Clone, Debug)]
+pub struct CheckpointOptions {
+    pub dir: PathBuf,
+    pub every: Option<u64>,
+    pub interval: Option<Duration>,
+    pub wal: bool,
+    pub rounding: Rounding,
+}
+
+pub const WAL_FILE: &str = "wal.log";
+
+/// How long `Server::consume` waits for a record before checking for
+/// checkpoints to commit.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest line taken on a TCP connection or in an HTTP request, line
+/// ending included. A longer
+/// one closes the connection instead of being buffered whole.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// How long a TCP connection may send nothing, `AUTH` included, before it
+/// is closed.
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long reading a request or writing a response on the HTTP listener
+/// may stall, as one slow client holds up all the others.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Checkpoints {
+    options: CheckpointOptions,
+    offset: u64,
+    /// Source positions the last checkpoint written covers.
+    positions: Positions,
+    written_at: Instant,
+    wal: Option<Wal>,
+}
+
+/// Where and how often the server writes account snapshots to look at, see
+/// `progress`. One is written once `every` transactions or `interval` have
+/// passed since the last one, whichever comes first.
+#[derive(Clone, Debug)]
+pub struct SnapshotOptions {
+    pub dir: PathBuf,
+    pub every: Option<u64>,
+    pub interval: Option<Duration>,
+}
+
+struct Snapshots {
+    options: SnapshotOptions,
+    offset: u64,
+    written_at: Instant,
+}
+
+/// Settings applied to every connection of the TCP line-protocol listener.
+#[derive(Clone, Debug, Default)]
+pub struct TcpOptions {
+    /// When set, a connection must open with `AUTH <token>` before sending records.
+    pub token: Option<String>,
+    /// Maximum number of records accepted per second on one connection. Faster
+    /// senders are slowed down rather than disconnected.
+    pub rate_limit: Option<u32>,
+}
+
+/// Fixed one-second window limiter, one per connection, client or server.
+struct RateLimiter {
+    per_second: u32,
+    window_start: Instant,
+    used: u32,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> RateLimiter {
+        RateLimiter {
+            per_second,
+            window_start: Instant::now(),
+            used: 0,
+        }
+    }
+
+    /// How long until the window has room for another record, if it is
+    /// full.
+    fn wait(&mut self) -> Option<Duration> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+        (self.used >= self.per_second).then(|| Duration::from_secs(1).saturating_sub(elapsed))
+    }
+
+    fn acquire(&mut self) {
+        if let Some(wait) = self.wait() {
+            thread::sleep(wait);
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+        self.used += 1;
+    }
+}
+
+/// Limits on the transactions applied per second, over all inputs.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    /// Over all clients.
+    pub global: Option<u32>,
+    /// For any one client.
+    pub per_client: Option<u32>,
+    pub overflow: Overflow,
+}
+
+/// What happens to a transaction over a `RateLimits` limit.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Overflow {
+    /// Held back until the limits allow it, slowing its input down.
+    #[default]
+    Delay,
+    /// Refused as `RejectReason::RateLimited`. It is not applied and does
+    /// not advance the offset, so the producer has to send it again.
+    Reject,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delay" => Ok(Overflow::Delay),
+            "reject" => Ok(Overflow::Reject),
+            _ => Err(format!(
+                "invalid overflow policy: {} (expected delay or reject)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Overflow::Delay => "delay",
+            Overflow::Reject => "reject",
+        })
+    }
+}
+
+struct Limits {
+    options: RateLimits,
+    global: Option<RateLimiter>,
+    clients: HashMap<u16, RateLimiter>,
+}
+
+impl Limits {
+    /// Counts a transaction of `client` against the limits if both have
+    /// room, or says how long until they do.
+    fn admit(&mut self, client: u16) -> Result<(), Duration> {
+        let mut limiters = Vec::with_capacity(2);
+        limiters.extend(self.global.as_mut());
+        if let Some(per_second) = self.options.per_client {
+            limiters.push(
+                self.clients
+                    .entry(client)
+                    .or_insert_with(|| RateLimiter::new(per_second)),
+            );
+        }
+        if let Some(wait) = limiters
+            .iter_mut()
+            .filter_map(|limiter| limiter.wait())
+            .max()
+        {
+            return Err(wait);
+        }
+        for limiter in limiters {
+            limiter.used += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Long-running mode: transactions arrive as line-protocol records while
+/// `/healthz`, `/readyz`, `/status`, `/accounts` and `/late` are served
+/// over HTTP.
+#[derive(Clone)]
+pub struct Server {
+    shared: Arc<Mutex<Shared>>,
+    /// Wakes up intake waiting in `intake` once the server resumes.
+    resumed: Arc<Condvar>,
+    /// Balances of single accounts, read without taking the lock of
+    /// `shared`. One per worker with actors.
+    accounts: Vec<AccountsReader>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::from_shared(Shared::default())
+    }
+
+    /// A server whose accounts round balances with `rounding`.
+    pub fn with_rounding(rounding: Rounding) -> Server {
+        let mut shared = Shared::default();
+        shared.state.accounts = AccountsRepository::with_rounding(rounding);
+        Server::from_shared(shared)
+    }
+
+    fn from_shared(shared: Shared) -> Server {
+        let accounts = vec![shared.state.accounts.reader()];
+        Server {
+            shared: Arc::new(Mutex::new(shared)),
+            resumed: Default::default(),
+            accounts,
+        }
+    }
+
+    /// A server that resumes from the newest valid checkpoint in
+    /// `options.dir`, if there is one, and keeps writing new ones. With a WAL
+    /// the entries logged after that checkpoint are replayed on top of it,
+    /// and the recovered state has to pass `State::verify` before the server
+    /// accepts anything new.
+    pub fn with_checkpoints(options: CheckpointOptions) -> io::Result<Server> {
+        let started = Instant::now();
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut state = match checkpoint::load_latest(&options.dir, options.rounding)? {
+            Some(state) => state,
+            None => {
+                let mut state = State::new();
+                state.accounts = AccountsRepository::with_rounding(options.rounding);
+                state
+            }
+        };
+        let checkpoint_offset = state.offset();
+
+        let mut wal = None;
+        let mut replayed = 0;
+        if options.wal {
+            let path = options.dir.join(WAL_FILE);
+            for (offset, tx) in Wal::read(&path)? {
+                if offset <= state.offset() {
+                    continue;
+                }
+                if offset != state.offset() + 1 {
+                    return Err(invalid(format!(
+                        "wal jumps from offset {} to {}",
+                        state.offset(),
+                        offset
+                    )));
+                }
+                state.apply(&tx);
+                replayed += 1;
+            }
+            state.verify().map_err(invalid)?;
+            // Fold the replayed tail into a checkpoint so the log can start
+            // over, dropping any line torn by the crash along with it.
+            if replayed > 0 {
+                checkpoint::write(&options.dir, &state)?;
+            }
+            std::fs::create_dir_all(&options.dir)?;
+            let mut log = Wal::open(&path)?;
+            log.truncate()?;
+            wal = Some(log);
+        }
+
+        let recovery = Recovery {
+            checkpoint_offset,
+            replayed,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        log::info!(
+            "recovered offset {} from checkpoint {} and {} wal entries in {} ms",
+            state.offset(),
+            recovery.checkpoint_offset,
+            recovery.replayed,
+            recovery.duration_ms
+        );
+
+        let interval = options.interval;
+        let server = Server::from_shared(Shared {
+            checkpoints: Some(Checkpoints {
+                offset: state.offset(),
+                positions: state.positions().clone(),
+                written_at: Instant::now(),
+                options,
+                wal,
+            }),
+            state,
+            ready: false,
+            recovery: Some(recovery),
+            router: None,
+            paused: false,
+            limits: None,
+            rate_limited: 0,
+            reorder: None,
+            late: Vec::new(),
+            dead_letters: None,
+            snapshots: None,
+            admin_token: None,
+        });
+
+        // Idle servers still persist what arrived since the last checkpoint.
+        if let Some(interval) = interval {
+            let shared = Arc::downgrade(&server.shared);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                match shared.upgrade() {
+                    Some(shared) => shared.lock().unwrap().checkpoint_if_due(),
+                    None => return,
+                }
+            });
+        }
+        Ok(server)
+    }
+
+    /// Remembers only the last `capacity` tx ids for duplicate detection and
+    /// disputes, see `TransactionLedger`. Restored ids count as added in id
+    /// order.
+    pub fn with_dedup_window(self, capacity: usize) -> Server {
+        let mut shared = self.shared.lock().unwrap();
+        shared.state.tx_ledger.set_window(Some(capacity));
+        drop(shared);
+        self
+    }
+
+    /// Remembers tx ids for duplicate detection and disputes only for
+    /// `max_age` after the newest timestamp, see `TransactionLedger`.
+    pub fn with_dedup_max_age(self, max_age: Duration) -> Server {
+        let mut shared = self.shared.lock().unwrap();
+        shared.state.tx_ledger.set_max_age(Some(max_age));
+        drop(shared);
+        self
+    }
+
+    /// Closes disputes that stay open longer than `policy` allows, checked
+    /// whenever a transaction arrives.
+    pub fn with_hold_expiry(self, policy: HoldExpiry) -> Server {
+        let mut shared = self.shared.lock().unwrap();
+        shared.state.set_hold_expiry(Some(policy));
+        if let Some(router) = &mut shared.router {
+            router.set_hold_expiry(Some(policy));
+        }
+        drop(shared);
+        self
+    }
+
+    /// Purges transactions whose dispute was settled longer ago than
+    /// `policy` allows, after exporting them to `audit_log`, see
+    /// `retention`. Actors keep ledgers of their own, so this does not go
+    /// with them.
+    pub fn with_retention<L: AuditLog + 'static>(self, policy: Retention, audit_log: L) -> Server {
+        self.shared
+            .lock()
+            .unwrap()
+            .state
+            .set_retention(policy, audit_log);
+        self
+    }
+
+    /// Answers `POST /admin/pause`, `/admin/resume` and `/admin/drain` on
+    /// the HTTP listener, for requests carrying `Authorization: Bearer
+    /// <token>`. Without a token the admin requests are refused.
+    pub fn with_admin_token(self, token: String) -> Server {
+        self.shared.lock().unwrap().admin_token = Some(token);
+        self
+    }
+
+    /// Limits how many transactions are applied per second, over all
+    /// inputs, in addition to the limit of each TCP connection.
+    pub fn with_rate_limits(self, limits: RateLimits) -> Server {
+        self.shared.lock().unwrap().limits = Some(Limits {
+            options: limits,
+            global: limits.global.map(RateLimiter::new),
+            clients: HashMap::new(),
+        });
+        self
+    }
+
+    /// Holds transactions back until ones `window` seconds later arrived
+    /// and applies them in timestamp order, see `reorder`. Transactions
+    /// arriving later than that are applied at once and listed by
+    /// `late_events`. Held back transactions are not in checkpoints or the
+    /// WAL yet, so this does not go with checkpoints.
+    pub fn with_reorder_window(self, window: u64) -> Server {
+        self.shared.lock().unwrap().reorder = Some(ReorderBuffer::new(window));
+        self
+    }
+
+    /// Sends every transaction rejected from now on to `sink`, with the
+    /// reason, along with the lines the TCP listener could not parse. See
+    /// `dead_letter`.
+    pub fn with_dead_letters<S: DeadLetterSink + 'static>(self, sink: S) -> Server {
+        let mut shared = self.shared.lock().unwrap();
+        let dead_letters = DeadLetters::new(sink);
+        if let Some(router) = &mut shared.router {
+            router.set_dead_letters(Some(dead_letters.clone()));
+        }
+        shared.dead_letters = Some(dead_letters);
+        drop(shared);
+        self
+    }
+
+    /// Writes account snapshots into `options.dir` as transactions are
+    /// applied, see `progress`. With actors the accounts only come together
+    /// in `into_state`, so this does not go with them.
+    pub fn with_snapshots(self, options: SnapshotOptions) -> Server {
+        let interval = options.interval;
+        let mut shared = self.shared.lock().unwrap();
+        shared.snapshots = Some(Snapshots {
+            offset: shared.state.offset(),
+            written_at: Instant::now(),
+            options,
+        });
+        drop(shared);
+        // Idle servers still write what arrived since the last snapshot.
+        if let Some(interval) = interval {
+            let shared = Arc::downgrade(&self.shared);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                match shared.upgrade() {
+                    Some(shared) => shared.lock().unwrap().snapshot_if_due(),
+                    None => return,
+                }
+            });
+        }
+        self
+    }
+
+    /// Every transaction that arrived behind the watermark so far.
+    pub fn late_events(&self) -> Vec<LateEvent> {
+        self.shared.lock().unwrap().late.clone()
+    }
+
+    /// Applies every client's transactions on an actor of its own, see
+    /// `actors`, instead of one after the other under the server's lock.
+    /// Each actor queues up to `mailbox` transactions, and `workers`
+    /// threads run them. Accounts are opened like those of the server's
+    /// state. The ledger and accounts only come together again in
+    /// `into_state`, so this does not go with checkpoints or a dedup window.
+    pub fn with_actors(mut self, mailbox: usize, workers: usize) -> Server {
+        let mut shared = self.shared.lock().unwrap();
+        let mut router = Router::new(mailbox, workers, &shared.state.accounts);
+        router.set_hold_expiry(shared.state.hold_expiry());
+        router.set_dead_letters(shared.dead_letters.clone());
+        self.accounts = router.readers();
+        shared.router = Some(router);
+        drop(shared);
+        self
+    }
+
+    /// Binds the HTTP listener and answers requests on a background thread,
+    /// one at a time. A client that stalls for `HTTP_TIMEOUT` is dropped.
+    pub fn listen_http<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let server = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = server.handle(stream) {
+                    log::warn!("could not answer http request: {:?}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Accepts line-protocol connections on a Unix domain socket, ingesting
+    /// each connection on its own thread. A stale socket file left behind by
+    /// a previous run is replaced.
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(&self, path: P) -> io::Result<JoinHandle<()>> {
+        let path = path.as_ref();
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        self.shared.lock().unwrap().ready = true;
+
+        let server = self.clone();
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let server = server.clone();
+                        thread::spawn(move || server.ingest(stream));
+                    }
+                    Err(err) => log::warn!("could not accept unix connection: {:?}", err),
+                }
+            }
+        }))
+    }
+
+    /// Accepts CSV or JSON line-protocol connections over TCP, each handled on
+    /// its own thread.
+    pub fn listen_tcp<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+        options: TcpOptions,
+    ) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        self.shared.lock().unwrap().ready = true;
+
+        let server = self.clone();
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let server = server.clone();
+                        let options = options.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = stream.set_read_timeout(Some(TCP_READ_TIMEOUT)) {
+                                log::warn!("could not set tcp read timeout: {:?}", err);
+                                return;
+                            }
+                            let reader = match stream.try_clone() {
+                                Ok(reader) => BufReader::new(reader),
+                                Err(err) => {
+                                    log::warn!("could not read tcp connection: {:?}", err);
+                                    return;
+                                }
+                            };
+                            if let Err(err) = server.ingest_lines(reader, stream, &options) {
+                                log::warn!("tcp connection failed: {:?}", err);
+                            }
+                        });
+                    }
+                    Err(err) => log::warn!("could not accept tcp connection: {:?}", err),
+                }
+            }
+        }))
+    }
+
+    fn ingest_lines<R: BufRead, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        options: &TcpOptions,
+    ) -> io::Result<()> {
+        let mut line = String::new();
+        if let Some(token) = &options.token {
+            let authorized = read_line(&mut reader, &mut line)?
+                && line
+                    .trim()
+                    .strip_prefix("AUTH ")
+                    .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+            if !authorized {
+                return writer.write_all(b"ERR unauthorized\n");
+            }
+            writer.write_all(b"OK\n")?;
+        }
+
+        let mut limiter = options.rate_limit.map(RateLimiter::new);
+        let dead_letters = self.shared.lock().unwrap().dead_letters.clone();
+        while read_line(&mut reader, &mut line)? {
+            if let Some(limiter) = limiter.as_mut() {
+                limiter.acquire();
+            }
+            match Parser::parse_line(&line) {
+                Some(tx) => self.submit(&tx),
+                None => {
+                    log::warn!("could not parse line: {:?}", line);
+                    if let Some(dead_letters) = &dead_letters {
+                        dead_letters.send(DeadLetter::unparseable(&line));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies every record read from `input`, returning once it is exhausted.
+    /// Records already covered by a restored checkpoint are skipped, so a
+    /// restarted server can be fed the same input from the start.
+    pub fn ingest<R: Read>(&self, input: R) {
+        let offset = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.ready = true;
+            shared.state.offset()
+        };
+        for tx in Parser::stream(input).skip(offset as usize) {
+            self.submit(&tx);
+        }
+    }
+
+    /// Applies the records of `source` until it ends, exactly once across
+    /// restarts, see `source`: it is read from the positions of the
+    /// restored checkpoint, and every checkpoint written is committed back
+    /// to it. Needs checkpoints without a WAL, as the source is the log
+    /// replayed after a crash.
+    pub fn consume<S: Source>(&self, source: &mut S) -> io::Result<()> {
+        let (positions, mut committed) = {
+            let mut shared = self.shared.lock().unwrap();
+            let Some(checkpoints) = shared.checkpoints.as_ref().filter(|c| c.wal.is_none()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "consuming a source needs checkpoints without a wal",
+                ));
+            };
+            let committed = checkpoints.offset;
+            shared.ready = true;
+            (shared.state.positions().clone(), committed)
+        };
+        source.seek(&positions)?;
+        let dead_letters = self.shared.lock().unwrap().dead_letters.clone();
+        let mut ended = false;
+        while !ended {
+            match source.fetch(FETCH_TIMEOUT)? {
+                Fetched::Record(record) => {
+                    let line = String::from_utf8_lossy(&record.payload);
+                    let position = (record.part.as_str(), record.next);
+                    match Parser::parse_line(&line) {
+                        Some(tx) => self.submit_at(&tx, Some(position)),
+                        None => {
+                            log::warn!("could not parse record: {:?}", line);
+                            if let Some(dead_letters) = &dead_letters {
+                                dead_letters.send(DeadLetter::unparseable(&line));
+                            }
+                            let mut shared = self.shared.lock().unwrap();
+                            shared.state.set_position(position.0, position.1);
+                        }
+                    }
+                }
+                Fetched::Idle => {}
+                Fetched::End => {
+                    ended = true;
+                    self.shared.lock().unwrap().write_checkpoint().transpose()?;
+                }
+            }
+            let shared = self.shared.lock().unwrap();
+            let Some(checkpoints) = &shared.checkpoints else {
+                continue;
+            };
+            if checkpoints.offset != committed || ended {
+                committed = checkpoints.offset;
+                let positions = checkpoints.positions.clone();
+                drop(shared);
+                source.commit(&positions)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `tx` once intake is not paused and the rate limits allow,
+    /// or refuses it if they do not and overflow is rejected.
+    fn submit(&self, tx: &Transaction) {
+        self.submit_at(tx, None)
+    }
+
+    /// `submit`, recording along with `tx` the source position to resume
+    /// from after it, if it came from a `Source`.
+    fn submit_at(&self, tx: &Transaction, position: Option<(&str, u64)>) {
+        loop {
+            let shared = self.shared.lock().unwrap();
+            let mut shared = self
+                .resumed
+                .wait_while(shared, |shared| shared.paused)
+                .unwrap();
+            let admitted = match &mut shared.limits {
+                Some(limits) => limits
+                    .admit(tx.account_id())
+                    .map_err(|wait| (wait, limits.options.overflow)),
+                None => Ok(()),
+            };
+            if let Err((wait, Overflow::Delay)) = admitted {
+                drop(shared);
+                thread::sleep(wait);
+                continue;
+            }
+            match admitted {
+                Ok(()) => {
+                    // A refused one is not applied, so it must not be
+                    // resumed past either.
+                    if let Some((part, next)) = position {
+                        shared.state.set_position(part, next);
+                    }
+                    return shared.apply(tx);
+                }
+                Err(..) => {
+                    log::warn!("rate limited tx {} of client {}", tx.id(), tx.account_id());
+                    shared.rate_limited += 1;
+                    if let Some(dead_letters) = &shared.dead_letters {
+                        dead_letters.send(DeadLetter::rejected(tx, RejectReason::RateLimited));
+                    }
+                    return shared.state.refuse(tx, RejectReason::RateLimited);
+                }
+            }
+        }
+    }
+
+    /// Stops applying transactions until `resume`. Records keep being read
+    /// up to the one each connection is waiting to apply, so producers see
+    /// backpressure rather than errors, and `/readyz` reports the server
+    /// as not ready. Once this returns, no transaction is being applied.
+    pub fn pause(&self) {
+        self.shared.lock().unwrap().paused = true;
+    }
+
+    pub fn resume(&self) {
+        self.shared.lock().unwrap().paused = false;
+        self.resumed.notify_all();
+    }
+
+    /// Pauses intake, applies what the reorder window holds back, waits for
+    /// the actors to apply what they were sent,
+    /// and writes a checkpoint of everything applied, so that the server
+    /// can be stopped without losing or replaying anything. Returns the
+    /// offset drained to.
+    pub fn drain(&self) -> io::Result<u64> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.paused = true;
+        shared.flush_reorder();
+        if let Some(router) = &shared.router {
+            router.flush();
+        }
+        if let Some(written) = shared.write_checkpoint() {
+            log::info!("wrote checkpoint {} while draining", written?.display());
+        }
+        Ok(shared.state.offset())
+    }
+
+    /// The state reached so far, after applying what the reorder window
+    /// holds back. With actors, waits for them to apply everything
+    /// dispatched and stops them.
+    pub fn into_state(self) -> State {
+        let mut shared = self.shared.lock().unwrap();
+        shared.flush_reorder();
+        let mut state = std::mem::take(&mut shared.state);
+        if let Some(router) = shared.router.take() {
+            state.settle(router.finish());
+        }
+        state
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        read_line(&mut reader, &mut request_line)?;
+        let mut request = request_line.split_whitespace();
+        let method = request.next().unwrap_or("GET");
+        let path = request.next().unwrap_or("/");
+
+        let mut token = None;
+        let mut header = String::new();
+        while read_line(&mut reader, &mut header)? && !header.trim().is_empty() {
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    token = value.trim().strip_prefix("Bearer ").map(str::to_string);
+                }
+            }
+        }
+
+        let (status, body) = match method {
+            "POST" => self.admin(path, token.as_deref()),
+            _ => self.route(path),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    fn route(&self, path: &str) -> (&'static str, String) {
+        if let Some(client) = path.strip_prefix("/accounts/") {
+            return self.account(client);
+        }
+        let shared = match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(..) => return ("500 Internal Server Error", r#""poisoned""#.into()),
+        };
+        match path {
+            "/healthz" => ("200 OK", r#""ok""#.into()),
+            "/readyz" if shared.paused => ("503 Service Unavailable", r#""paused""#.into()),
+            "/readyz" if shared.ready => ("200 OK", r#""ready""#.into()),
+            "/readyz" => ("503 Service Unavailable", r#""not ready""#.into()),
+            "/status" => match serde_json::to_string(&shared.status()) {
+                Ok(body) => ("200 OK", body),
+                Err(..) => ("500 Internal Server Error", r#""unserializable""#.into()),
+            },
+            "/late" => match serde_json::to_string(&shared.late) {
+                Ok(body) => ("200 OK", body),
+                Err(..) => ("500 Internal Server Error", r#""unserializable""#.into()),
+            },
+            "/accounts" => {
+                // Taken between two transactions, with actors once they
+                // applied everything dispatched, and serialized after the
+                // lock is released so ingestion goes on meanwhile.
+                let offset = shared.state.offset();
+                let snapshots = match &shared.router {
+                    Some(router) => {
+                        router.flush();
+                        router.snapshots()
+                    }
+                    None => vec![shared.state.accounts.snapshot()],
+                };
+                drop(shared);
+                let mut accounts: Vec<&Account> =
+                    snapshots.iter().flat_map(Snapshot::sorted).collect();
+                accounts.sort_by_key(|account| account.client_id());
+                match serde_json::to_string(&Accounts { offset, accounts }) {
+                    Ok(body) => ("200 OK", body),
+                    Err(..) => ("500 Internal Server Error", r#""unserializable""#.into()),
+                }
+            }
+            _ => ("404 Not Found", r#""not found""#.into()),
+        }
+    }
+}
+
+impl Server {
+    /// One account, read through `accounts` so that it is answered while
+    /// a transaction is being applied, as of the last one applied to it.
+    fn account(&self, client: &str) -> (&'static str, String) {
+        let Ok(client) = client.parse() else {
+            return ("400 Bad Request", r#""invalid client""#.into());
+        };
+        match self
+            .accounts
+            .iter()
+            .find_map(|accounts| accounts.get(client))
+        {
+            Some(account) => match serde_json::to_string(&account) {
+                Ok(body) => ("200 OK", body),
+                Err(..) => ("500 Internal Server Error", r#""unserializable""#.into()),
+            },
+            None => ("404 Not Found", r#""unknown client""#.into()),
+        }
+    }
+
+    /// Answers an admin request that came with `token`, if the server has
+    /// an admin token and it matches.
+    fn admin(&self, path: &str, token: Option<&str>) -> (&'static str, String) {
+        let authorized = match &self.shared.lock().unwrap().admin_token {
+            Some(expected) => {
+                token.is_some_and(|given| constant_time_eq(given.as_bytes(), expected.as_bytes()))
+            }
+            None => return ("403 Forbidden", r#""admin disabled""#.into()),
+        };
+        if !authorized {
+            return ("401 Unauthorized", r#""unauthorized""#.into());
+        }
+        match path {
+            "/admin/pause" => {
+                self.pause();
+                ("200 OK", r#""paused""#.into())
+            }
+            "/admin/resume" => {
+                self.resume();
+                ("200 OK", r#""resumed""#.into())
+            }
+            "/admin/drain" => match self.drain() {
+                Ok(offset) => ("200 OK", format!(r#"{{"offset":{}}}"#, offset)),
+                Err(err) => {
+                    log::warn!("could not write checkpoint while draining: {}", err);
+                    ("500 Internal Server Error", r#""checkpoint failed""#.into())
+                }
+            },
+            _ => ("404 Not Found", r#""not found""#.into()),
+        }
+    }
+}
+
+/// Reads the next line of `reader` into `line`, without its line ending.
+/// Returns false at the end of the input, and fails on a line longer than
+/// `MAX_LINE`.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<bool> {
+    line.clear();
+    let read = reader.by_ref().take(MAX_LINE).read_line(line)?;
+    if read == 0 {
+        return Ok(false);
+    }
+    if !line.ends_with('\n') && read as u64 == MAX_LINE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line longer than {} bytes", MAX_LINE),
+        ));
+    }
+    let end = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(end);
+    Ok(true)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Type;
+
+    #[test]
+    fn not_ready_before_ingestion() {
+        let server = Server::new();
+        assert_eq!(server.route("/healthz").0, "200 OK");
+        assert_eq!(server.route("/readyz").0, "503 Service Unavailable");
+        assert_eq!(server.admin("/admin/pause", Some("")).0, "403 Forbidden");
+    }
+
+    #[test]
+    fn status_after_ingestion() {
+        let server = Server::new();
+        server.ingest("deposit,1,1,5.0\ndeposit,2,2,1.0\ndispute,1,1\nbogus\n".as_bytes());
+        assert_eq!(server.route("/readyz").0, "200 OK");
+        assert_eq!(
+            server.shared.lock().unwrap().status(),
+            Status {
+                ledger_size: 2,
+                account_count: 2,
+                wal_lag: None,
+                last_tx_id: Some(1),
+                offset: 3,
+                recovery: None,
+                paused: false,
+                rate_limited: 0,
+                buffered: 0,
+                late: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn accounts_snapshot() {
+        let server = Server::new();
+        server.ingest("deposit,2,1,5.0\ndeposit,1,2,1.5\n".as_bytes());
+        let snapshot = server.shared.lock().unwrap().state.accounts.snapshot();
+        for tx in [
+            Transaction::new(3, Type::Withdrawal, 2, 1.0),
+            Transaction::new(4, Type::Deposit, 3, 1.0),
+        ] {
+            server.shared.lock().unwrap().apply(&tx);
+        }
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.get(2).unwrap().available_balance(), 5.0);
+        assert_eq!(
+            server.route("/accounts"),
+            (
+                "200 OK",
+                concat!(
+                    r#"{"offset":4,"accounts":["#,
+                    r#"{"client":1,"available":1.5,"held":0.0,"total":1.5,"locked":false},"#,
+                    r#"{"client":2,"available":4.0,"held":0.0,"total":4.0,"locked":false},"#,
+                    r#"{"client":3,"available":1.0,"held":0.0,"total":1.0,"locked":false}]}"#
+                )
+                .to_string()
+            )
+        );
+        assert_eq!(
+            server.route("/accounts/3"),
+            (
+                "200 OK",
+                r#"{"client":3,"available":1.0,"held":0.0,"total":1.0,"locked":false}"#.to_string()
+            )
+        );
+        assert_eq!(server.route("/accounts/4").0, "404 Not Found");
+        assert_eq!(server.route("/accounts/x").0, "400 Bad Request");
+    }
+
+    #[test]
+    fn pause_and_drain() {
+        let dir = std::env::temp_dir().join(format!("fg-server-drain-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = Server::with_checkpoints(CheckpointOptions {
+            dir: dir.clone(),
+            every: None,
+            interval: None,
+            wal: false,
+            rounding: Rounding::default(),
+        })
+        .unwrap()
+        .with_admin_token("s3cret".to_string());
+        server.ingest("deposit,1,1,5.0\n".as_bytes());
+        assert_eq!(server.admin("/admin/pause", None).0, "401 Unauthorized");
+        assert_eq!(
+            server.admin("/admin/pause", Some("guess")).0,
+            "401 Unauthorized"
+        );
+        assert_eq!(server.route("/readyz").0, "200 OK");
+        assert_eq!(server.admin("/admin/pause", Some("s3cret")).0, "200 OK");
+        assert_eq!(server.route("/readyz").0, "503 Service Unavailable");
+
+        let producer = server.clone();
+        let ingestion = thread::spawn(move || {
+            let mut output = Vec::new();
+            producer
+                .ingest_lines(
+                    "deposit,1,2,1.0\n".as_bytes(),
+                    &mut output,
+                    &TcpOptions::default(),
+                )
+                .unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(server.shared.lock().unwrap().state.offset(), 1);
+        assert_eq!(
+            server.admin("/admin/drain", Some("s3cret")),
+            ("200 OK", r#"{"offset":1}"#.to_string())
+        );
+        let restored = checkpoint::load_latest(&dir, Rounding::default()).unwrap();
+        assert_eq!(restored.map(|state| state.offset()), Some(1));
+
+        server.resume();
+        ingestion.join().unwrap();
+        assert!(!server.shared.lock().unwrap().status().paused);
+        assert_eq!(server.shared.lock().unwrap().state.offset(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rate_limits() {
+        let input = "deposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,2,3,1.0\ndeposit,1,4,1.0\n";
+        let server = Server::new().with_rate_limits(RateLimits {
+            global: Some(10),
+            per_client: Some(2),
+            overflow: Overflow::Reject,
+        });
+        server.ingest(input.as_bytes());
+        let status = server.shared.lock().unwrap().status();
+        assert_eq!((status.offset, status.rate_limited), (3, 1));
+        let state = server.into_state();
+        assert_eq!(state.accounts.get(1).unwrap().available_balance(), 2.0);
+        assert_eq!(state.metrics().deposit.rejected, 1);
+
+        // A refused record is read again on resuming.
+        let server = Server::new().with_rate_limits(RateLimits {
+            global: None,
+            per_client: Some(1),
+            overflow: Overflow::Reject,
+        });
+        server.submit_at(
+            &Transaction::new(1, Type::Deposit, 1, 1.0),
+            Some(("topic:0", 1)),
+        );
+        server.submit_at(
+            &Transaction::new(2, Type::Deposit, 1, 1.0),
+            Some(("topic:0", 2)),
+        );
+        let positions = Positions::from([("topic:0".to_string(), 1)]);
+        assert_eq!(server.into_state().positions(), &positions);
+
+        let server = Server::new().with_rate_limits(RateLimits {
+            global: Some(3),
+            per_client: None,
+            overflow: Overflow::Delay,
+        });
+        let started = Instant::now();
+        server.ingest(input.as_bytes());
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(server.into_state().offset(), 4);
+        assert_eq!("reject".parse(), Ok(Overflow::Reject));
+        assert!("drop".parse::<Overflow>().is_err());
+    }
+
+    #[test]
+    fn dead_letters() {
+        let input = "deposit,1,1,1.0\nwithdrawal,1,2,5.0\ndeposit,1,x,1.0\ndeposit,2,1,1.0\n";
+        for server in [Server::new(), Server::new().with_actors(4, 2)] {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let server = server.with_dead_letters(sender);
+            server
+                .ingest_lines(input.as_bytes(), io::sink(), &TcpOptions::default())
+                .unwrap();
+            server.into_state();
+            let mut letters: Vec<String> = receiver
+                .try_iter()
+                .map(|letter| serde_json::to_string(&letter).unwrap())
+                .collect();
+            letters.sort();
+            assert_eq!(
+                letters,
+                [
+                    r#"{"line":"deposit,1,x,1.0","reason":"unparseable"}"#,
+                    r#"{"type":"deposit","client":2,"tx":1,"amount":1.0,"reason":"conflicting_tx"}"#,
+                    r#"{"type":"withdrawal","client":1,"tx":2,"amount":5.0,"reason":"insufficient_funds"}"#,
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reorder_window() {
+        let server = Server::new().with_reorder_window(60);
+        server.ingest(
+            concat!(
+                "withdrawal,1,2,3.0,,,100\n",
+                "deposit,1,1,5.0,,,90\n",
+                "deposit,1,3,1.0,,,200\n",
+                "deposit,1,5,2.0,,,80\n",
+                "deposit,1,4,1.0,,,210\n",
+            )
+            .as_bytes(),
+        );
+        let status = server.shared.lock().unwrap().status();
+        assert_eq!((status.offset, status.buffered, status.late), (3, 2, 1));
+        assert_eq!(
+            server.route("/late").1,
+            r#"[{"type":"deposit","client":1,"tx":5,"timestamp":80,"watermark":140}]"#
+        );
+        let state = server.into_state();
+        assert_eq!(state.offset(), 5);
+        let account = state.accounts.get(1).unwrap();
+        assert_eq!(
+            (account.available_balance(), account.held_balance()),
+            (6.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn actors() {
+        let server = Server::new().with_actors(4, 2);
+        server
+            .ingest("deposit,1,1,5.0\ndeposit,2,2,1.0\ndeposit,2,3,1.0\ndispute,1,1\n".as_bytes());
+        assert_eq!(
+            server.route("/accounts").1,
+            concat!(
+                r#"{"offset":4,"accounts":["#,
+                r#"{"client":1,"available":0.0,"held":5.0,"total":5.0,"locked":false},"#,
+                r#"{"client":2,"available":2.0,"held":0.0,"total":2.0,"locked":false}]}"#
+            )
+        );
+        assert_eq!(server.route("/accounts/2").0, "200 OK");
+        let status = server.shared.lock().unwrap().status();
+        assert_eq!((status.ledger_size, status.account_count), (3, 2));
+        assert_eq!(status.offset, 4);
+        let state = server.into_state();
+        assert_eq!(state.accounts.get(1).unwrap().held_balance(), 5.0);
+        assert_eq!(state.accounts.len(), 2);
+        assert_eq!(state.offset(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_ingestion() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("fg-{}.sock", std::process::id()));
+        let server = Server::new();
+        server.listen_unix(&path).unwrap();
+        assert_eq!(server.route("/readyz").0, "200 OK");
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"deposit,7,1,2.5\ndeposit,7,2,1.0\n")
+            .unwrap();
+        drop(client);
+
+        for _ in 0..200 {
+            if server.shared.lock().unwrap().state.last_tx_id() == Some(2) {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(server.shared.lock().unwrap().state.tx_ledger.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tcp_lines_accept_csv_and_json() {
+        let server = Server::new();
+        let input =
+            "deposit,1,1,2.0\n{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":3.0}\n";
+        let mut output = Vec::new();
+        server
+            .ingest_lines(input.as_bytes(), &mut output, &TcpOptions::default())
+            .unwrap();
+        assert!(output.is_empty());
+        assert_eq!(server.shared.lock().unwrap().state.tx_ledger.len(), 2);
+    }
+
+    #[test]
+    fn tcp_lines_require_token() {
+        let server = Server::new();
+        let options = TcpOptions {
+            token: Some("secret".into()),
+            rate_limit: None,
+        };
+
+        let mut output = Vec::new();
+        server
+            .ingest_lines(
+                "AUTH wrong\ndeposit,1,1,2.0\n".as_bytes(),
+                &mut output,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(output, b"ERR unauthorized\n");
+        assert!(server.shared.lock().unwrap().state.tx_ledger.is_empty());
+
+        let mut output = Vec::new();
+        server
+            .ingest_lines(
+                "AUTH secret\ndeposit,1,1,2.0\n".as_bytes(),
+                &mut output,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(output, b"OK\n");
+        assert_eq!(server.shared.lock().unwrap().state.tx_ledger.len(), 1);
+
+        let endless = "A".repeat(MAX_LINE as usize + 1);
+        let err = server
+            .ingest_lines(endless.as_bytes(), io::sink(), &options)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn http_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        Server::new().handle(stream).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n\"ok\""));
+    }
+
+    #[test]
+    fn restart_resumes_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("fg-server-ckpt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = CheckpointOptions {
+            dir: dir.clone(),
+            every: Some(2),
+            interval: None,
+            wal: false,
+            rounding: Rounding::Floor,
+        };
+        let input = "deposit,1,1,5.0\ndeposit,1,2,1.0\ndeposit,1,3,2.00009\n";
+
+        let server = Server::with_checkpoints(options.clone()).unwrap();
+        server.ingest(input.as_bytes());
+        assert_eq!(server.shared.lock().unwrap().state.offset(), 3);
+
+        // Only the first two records made it into a checkpoint.
+        let server = Server::with_checkpoints(options).unwrap();
+        assert_eq!(server.shared.lock().unwrap().state.offset(), 2);
+        server.ingest(input.as_bytes());
+        let state = server.into_state();
+        assert_eq!(state.offset(), 3);
+        assert_eq!(state.accounts.rounding(), Rounding::Floor);
+        assert_eq!(state.accounts.get(1).unwrap().available_balance(), 8.0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A single-partition topic that fails once its reader reaches `crash`.
+    struct Topic {
+        records: Vec<&'static str>,
+        at: usize,
+        crash: Option<usize>,
+        committed: Vec<Positions>,
+    }
+
+    impl Source for Topic {
+        fn seek(&mut self, positions: &Positions) -> io::Result<()> {
+            self.at = positions.get("topic:0").map_or(0, |&next| next as usize);
+            Ok(())
+        }
+
+        fn fetch(&mut self, _: Duration) -> io::Result<Fetched> {
+            if Some(self.at) == self.crash {
+                return Err(io::Error::other("crashed"));
+            }
+            let Some(record) = self.records.get(self.at) else {
+                return Ok(Fetched::End);
+            };
+            self.at += 1;
+            Ok(Fetched::Record(crate::source::Record {
+                part: "topic:0".to_string(),
+                next: self.at as u64,
+                payload: record.as_bytes().to_vec(),
+            }))
+        }
+
+        fn commit(&mut self, positions: &Positions) -> io::Result<()> {
+            self.committed.push(positions.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn consumes_exactly_once() {
+        let dir = std::env::temp_dir().join(format!("fg-server-consume-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = CheckpointOptions {
+            dir: dir.clone(),
+            every: Some(2),
+            interval: None,
+            wal: false,
+            rounding: Rounding::default(),
+        };
+        let records = vec![
+            "deposit,1,1,5.0",
+            "nonsense",
+            "deposit,1,2,1.0",
+            "deposit,1,3,2.0",
+            "deposit,1,4,4.0",
+        ];
+        let mut topic = Topic {
+            records: records.clone(),
+            at: 0,
+            crash: Some(4),
+            committed: Vec::new(),
+        };
+        let server = Server::with_checkpoints(options.clone()).unwrap();
+        assert!(server.consume(&mut topic).is_err());
+        assert_eq!(server.shared.lock().unwrap().state.offset(), 3);
+        let after_two = Positions::from([("topic:0".to_string(), 3)]);
+        assert_eq!(topic.committed, [after_two]);
+
+        // The third deposit was applied but not checkpointed, so it is read
+        // again; the first two are not.
+        let mut topic = Topic {
+            records,
+            at: 0,
+            crash: None,
+            committed: Vec::new(),
+        };
+        let server = Server::with_checkpoints(options.clone()).unwrap();
+        server.consume(&mut topic).unwrap();
+        let at_end = Positions::from([("topic:0".to_string(), 5)]);
+        assert_eq!(topic.committed.last(), Some(&at_end));
+        let state = server.into_state();
+        assert_eq!(state.offset(), 4);
+        assert_eq!(state.positions(), &at_end);
+        assert_eq!(state.accounts.get(1).unwrap().available_balance(), 12.0);
+
+        let server = Server::new();
+        assert!(server.consume(&mut topic).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn periodic_snapshots() {
+        let dir = std::env::temp_dir().join(format!("fg-server-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = Server::new().with_snapshots(SnapshotOptions {
+            dir: dir.clone(),
+            every: Some(2),
+            interval: None,
+        });
+        server.ingest("deposit,1,1,5.0\ndeposit,1,2,1.0\ndeposit,1,3,2.0\n".as_bytes());
+        let snapshots = progress::list(&dir).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(std::fs::read_to_string(&snapshots[0])
+            .unwrap()
+            .ends_with("\n1,6.0,0.0,6.0,false\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn crash_recovery_replays_wal() {
+        let dir = std::env::temp_dir().join(format!("fg-server-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = CheckpointOptions {
+            dir: dir.clone(),
+            every: Some(2),
+            interval: None,
+            wal: true,
+            rounding: Rounding::default(),
+        };
+
+        let server = Server::with_checkpoints(options.clone()).unwrap();
+        let mut output = Vec::new();
+        let input = "deposit,1,1,5.0\ndeposit,1,2,1.0\ndeposit,1,3,2.0\ndispute,1,3\n";
+        server
+            .ingest_lines(input.as_bytes(), &mut output, &TcpOptions::default())
+            .unwrap();
+        assert_eq!(server.shared.lock().unwrap().status().wal_lag, Some(0));
+        // Simulate a crash: nothing is shut down cleanly.
+        drop(server);
+
+        let server = Server::with_checkpoints(options).unwrap();
+        let status = server.shared.lock().unwrap().status();
+        assert_eq!(status.offset, 4);
+        assert_eq!(
+            status.recovery.map(|r| (r.checkpoint_offset, r.replayed)),
+            Some((4, 0))
+        );
+
+        let mut output = Vec::new();
+        server
+            .ingest_lines(
+                "resolve,1,3\n".as_bytes(),
+                &mut output,
+                &TcpOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(server.shared.lock().unwrap().status().wal_lag, Some(1));
+        drop(server);
+
+        let server = Server::with_checkpoints(CheckpointOptions {
+            dir: dir.clone(),
+            every: None,
+            interval: None,
+            wal: true,
+            rounding: Rounding::default(),
+        })
+        .unwrap();
+        let status = server.shared.lock().unwrap().status();
+        assert_eq!(
+            status.recovery.map(|r| (r.checkpoint_offset, r.replayed)),
+            Some((4, 1))
+        );
+        let state = server.into_state();
+        assert_eq!(state.accounts.get(1).unwrap().available_balance(), 8.0);
+        assert!(!state.tx_ledger.get(3).unwrap().is_dispute());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_path() {
+        let server = Server::new();
+        assert_eq!(server.route("/nope").0, "404 Not Found");
+    }
+}
@@ -0,0 +1,98 @@
+//! Detached ed25519 signatures over output snapshots.
+//!
+//! Keys and signatures are exchanged as hex strings: a signing key is the
+//! 32-byte secret seed, a verifying key the 32-byte public key and a
+//! signature 64 bytes.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHex,
+    InvalidKey,
+    InvalidSignature,
+    BadSignature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHex => f.write_str("not a hex string"),
+            Error::InvalidKey => f.write_str("not a 32-byte ed25519 key"),
+            Error::InvalidSignature => f.write_str("not a 64-byte ed25519 signature"),
+            Error::BadSignature => f.write_str("signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn signing_key(hex: &str) -> Result<SigningKey, Error> {
+    let bytes = decode_hex(hex)?;
+    let seed = bytes.try_into().map_err(|_| Error::InvalidKey)?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn verifying_key(hex: &str) -> Result<VerifyingKey, Error> {
+    let bytes = decode_hex(hex)?;
+    let bytes = bytes.try_into().map_err(|_| Error::InvalidKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| Error::InvalidKey)
+}
+
+/// Hex-encoded detached signature of `data`.
+pub fn sign(key: &SigningKey, data: &[u8]) -> String {
+    encode_hex(&key.sign(data).to_bytes())
+}
+
+pub fn verify(key: &VerifyingKey, data: &[u8], signature: &str) -> Result<(), Error> {
+    let bytes = decode_hex(signature)?;
+    let signature = Signature::from_slice(&bytes).map_err(|_| Error::InvalidSignature)?;
+    key.verify(data, &signature)
+        .map_err(|_| Error::BadSignature)
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(Error::InvalidHex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidHex))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    #[test]
+    fn sign_and_verify() {
+        let key = signing_key(SEED).unwrap();
+        let public = verifying_key(&encode_hex(key.verifying_key().as_bytes())).unwrap();
+        let snapshot = b"client,available,held,total,locked\n1,1.5,0.0,1.5,false\n";
+
+        let signature = sign(&key, snapshot);
+        assert_eq!(signature.len(), 128);
+        assert!(verify(&public, snapshot, &signature).is_ok());
+
+        let tampered = b"client,available,held,total,locked\n1,9.5,0.0,9.5,false\n";
+        assert!(matches!(
+            verify(&public, tampered, &signature),
+            Err(Error::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(matches!(signing_key("abc"), Err(Error::InvalidHex)));
+        assert!(matches!(signing_key("abcd"), Err(Error::InvalidKey)));
+    }
+}
@@ -0,0 +1,366 @@
+//! Deterministic simulation of the engine's state machine.
+//!
+//! A seeded generator produces a random but reproducible transaction stream,
+//! injecting faults (duplicate ids, transactions that must be rejected and
+//! disputes that arrive late), and account invariants are checked after
+//! every single step. A failing run is reproduced by re-running its seed.
+//! Time is simulated too: the engine's clock advances one second per step.
+
+use crate::account::AccountsRepository;
+use crate::clock::ManualClock;
+use crate::engine::Engine;
+use crate::transaction::{Transaction, TransactionLedger, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+const EPSILON: f64 = 1e-3;
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    pub steps: u64,
+    pub clients: u16,
+    /// Probability that a step replays an earlier deposit or withdrawal id.
+    pub duplicate_rate: f64,
+    /// Probability that a step sends a withdrawal the account cannot cover.
+    pub reject_rate: f64,
+    /// Probability that a dispute is held back for up to 50 steps.
+    pub delayed_dispute_rate: f64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            steps: 10_000,
+            clients: 16,
+            duplicate_rate: 0.05,
+            reject_rate: 0.05,
+            delayed_dispute_rate: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SimulationReport {
+    pub steps: u64,
+    pub rejected: u64,
+    pub duplicates_injected: u64,
+    pub rejects_injected: u64,
+    pub disputes_delayed: u64,
+    pub accounts_locked: u64,
+}
+
+#[derive(Debug)]
+pub struct InvariantViolation {
+    pub seed: u64,
+    pub step: u64,
+    pub tx: Transaction,
+    pub message: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invariant violated at step {} (seed {}) after {:?} of tx {} for client {}: {}",
+            self.step,
+            self.seed,
+            self.tx.r#type(),
+            self.tx.id(),
+            self.tx.account_id(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// SplitMix64, small and good enough to drive a simulation reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.unit() < p
+    }
+
+    fn amount(&mut self) -> f64 {
+        (1 + self.below(100_000)) as f64 / 100.0
+    }
+}
+
+#[derive(PartialEq)]
+enum Expectation {
+    Any,
+    Rejected,
+}
+
+pub struct Simulation {
+    config: SimulationConfig,
+    rng: Rng,
+    accounts: AccountsRepository,
+    tx_ledger: TransactionLedger,
+    clock: ManualClock,
+    next_id: u32,
+    /// Deposit and withdrawal ids sent so far, per client.
+    client_txs: HashMap<u16, Vec<u32>>,
+    delayed: BTreeMap<u64, Vec<Transaction>>,
+    locked: HashSet<u16>,
+    report: SimulationReport,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig) -> Simulation {
+        Simulation {
+            rng: Rng(config.seed),
+            config,
+            accounts: AccountsRepository::new(),
+            tx_ledger: TransactionLedger::new(),
+            clock: ManualClock::default(),
+            next_id: 1,
+            client_txs: HashMap::new(),
+            delayed: BTreeMap::new(),
+            locked: HashSet::new(),
+            report: SimulationReport::default(),
+        }
+    }
+
+    pub fn run(mut self) -> Result<SimulationReport, Box<InvariantViolation>> {
+        for step in 0..self.config.steps {
+            let (tx, expectation) = self.next_transaction(step);
+            self.step(step, tx, expectation)?;
+        }
+        Ok(self.report)
+    }
+
+    fn fresh_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn random_client(&mut self) -> u16 {
+        1 + self.rng.below(self.config.clients.max(1) as u64) as u16
+    }
+
+    fn known_tx(&mut self, client: u16) -> Option<u32> {
+        let txs = self.client_txs.get(&client)?;
+        let index = self.rng.below(txs.len() as u64) as usize;
+        txs.get(index).copied()
+    }
+
+    fn next_transaction(&mut self, step: u64) -> (Transaction, Expectation) {
+        if let Some(mut due) = self.delayed.first_entry().filter(|e| *e.key() <= step) {
+            let tx = due.get_mut().pop().expect("empty delayed bucket");
+            if due.get().is_empty() {
+                due.remove();
+            }
+            return (tx, Expectation::Any);
+        }
+
+        let client = self.random_client();
+        if self.rng.chance(self.config.duplicate_rate) {
+            if let Some(id) = self.known_tx(client) {
+                if let Some(original) = self.tx_ledger.get(id).copied() {
+                    self.report.duplicates_injected += 1;
+                    return (original, Expectation::Rejected);
+                }
+            }
+        }
+        if self.rng.chance(self.config.reject_rate) {
+            let available = self
+                .accounts
+                .get(client)
+                .map_or(0.0, |account| account.available_balance());
+            self.report.rejects_injected += 1;
+            let id = self.fresh_id();
+            let tx = Transaction::new(id, Type::Withdrawal, client, available + self.rng.amount());
+            return (tx, Expectation::Rejected);
+        }
+
+        let roll = self.rng.below(100);
+        let referenced = if roll >= 65 {
+            self.known_tx(client)
+        } else {
+            None
+        };
+        let tx = match (roll, referenced) {
+            (45..=69, _) => {
+                let id = self.fresh_id();
+                Transaction::new(id, Type::Withdrawal, client, self.rng.amount())
+            }
+            (70..=84, Some(id)) if self.rng.chance(self.config.delayed_dispute_rate) => {
+                let due = step + 1 + self.rng.below(50);
+                let dispute = Transaction::new(id, Type::Dispute, client, 0.0);
+                self.delayed.entry(due).or_default().push(dispute);
+                self.report.disputes_delayed += 1;
+                let id = self.fresh_id();
+                Transaction::new(id, Type::Deposit, client, self.rng.amount())
+            }
+            (70..=84, Some(id)) => Transaction::new(id, Type::Dispute, client, 0.0),
+            (85..=98, Some(id)) => Transaction::new(id, Type::Resolve, client, 0.0),
+            (99, Some(id)) => Transaction::new(id, Type::Chargeback, client, 0.0),
+            _ => {
+                let id = self.fresh_id();
+                Transaction::new(id, Type::Deposit, client, self.rng.amount())
+            }
+        };
+        (tx, Expectation::Any)
+    }
+
+    fn step(
+        &mut self,
+        step: u64,
+        tx: Transaction,
+        expectation: Expectation,
+    ) -> Result<(), Box<InvariantViolation>> {
+        let client = tx.account_id();
+        let before = self.balances(client);
+
+        let mut engine =
+            Engine::new(&mut self.tx_ledger, &mut self.accounts).with_clock(&self.clock);
+        engine.process(std::slice::from_ref(&tx));
+        self.clock.advance(Duration::from_secs(1));
+        let rejected = !engine.rejections().is_empty();
+        if rejected {
+            self.report.rejected += 1;
+        }
+        self.report.steps += 1;
+        if matches!(tx.r#type(), Type::Deposit | Type::Withdrawal)
+            && expectation == Expectation::Any
+        {
+            self.client_txs.entry(client).or_default().push(tx.id());
+        }
+
+        let seed = self.config.seed;
+        let violation = |message: String| {
+            Box::new(InvariantViolation {
+                seed,
+                step,
+                tx,
+                message,
+            })
+        };
+        if expectation == Expectation::Rejected && !rejected {
+            return Err(violation("injected fault was applied".into()));
+        }
+        let after = self.balances(client);
+        if rejected && before.is_some() && before != after {
+            return Err(violation(format!(
+                "rejected transaction changed balances from {:?} to {:?}",
+                before, after
+            )));
+        }
+        self.check_account(client).map_err(violation)
+    }
+
+    fn balances(&self, client: u16) -> Option<(f64, f64, f64, bool)> {
+        self.accounts.get(client).map(|a| {
+            (
+                a.available_balance(),
+                a.held_balance(),
+                a.total_balance(),
+                a.locked(),
+            )
+        })
+    }
+
+    fn check_account(&mut self, client: u16) -> Result<(), String> {
+        let Some(account) = self.accounts.get(client) else {
+            return Err("account was not created".into());
+        };
+        let (available, held, total) = (
+            account.available_balance(),
+            account.held_balance(),
+            account.total_balance(),
+        );
+        if (available + held - total).abs() > EPSILON {
+            return Err(format!(
+                "available {} + held {} != total {}",
+                available, held, total
+            ));
+        }
+        if held < -EPSILON {
+            return Err(format!("negative held balance {}", held));
+        }
+        if self.locked.contains(&client) {
+            if !account.locked() {
+                return Err("locked account was unlocked".into());
+            }
+            return Ok(());
+        }
+        if account.locked() {
+            self.locked.insert(client);
+            self.report.accounts_locked += 1;
+            return Ok(());
+        }
+
+        let disputed: f64 = self
+            .client_txs
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.tx_ledger.get(*id))
+            .filter(|tx| tx.is_dispute())
+            .map(Transaction::amount)
+            .sum();
+        if (disputed - held).abs() > EPSILON {
+            return Err(format!(
+                "held {} does not match disputed amount {}",
+                held, disputed
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(seed: u64) -> SimulationConfig {
+        SimulationConfig {
+            seed,
+            steps: 5_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn invariants_hold() {
+        for seed in 0..5 {
+            let report = Simulation::new(config(seed)).run().unwrap();
+            assert_eq!(report.steps, 5_000);
+            assert!(report.duplicates_injected > 0);
+            assert!(report.rejects_injected > 0);
+            assert!(report.disputes_delayed > 0);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let first = Simulation::new(config(42)).run().unwrap();
+        let second = Simulation::new(config(42)).run().unwrap();
+        assert_eq!(first, second);
+        assert_ne!(first, Simulation::new(config(43)).run().unwrap());
+    }
+}
@@ -0,0 +1,432 @@
+//! Destinations for the final account snapshot. Several sinks can be fed in
+//! a single pass over the accounts, each opened from a `format:target` spec
+//! by the factory registered for its format, e.g. `csv:-` for CSV on
+//! stdout, `json:accounts.json` or `sqlite:state.db`.
+
+use crate::account::{Account, AccountsRepository};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Receives every account of the snapshot, ordered by client id, then
+/// `finish` once all of them were written.
+pub trait Sink {
+    fn write(&mut self, account: &Account) -> Result<(), Box<dyn Error>>;
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>>;
+}
+
+/// Opens a sink for the target of a spec.
+pub type Factory = fn(&str) -> Result<Box<dyn Sink>, Box<dyn Error>>;
+
+/// A sink format and where it writes to, `-` standing for stdout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SinkSpec {
+    pub format: String,
+    pub target: String,
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SinkSpec, String> {
+        match s.split_once(':') {
+            Some((format, target)) if !format.is_empty() && !target.is_empty() => Ok(SinkSpec {
+                format: format.to_ascii_lowercase(),
+                target: target.to_string(),
+            }),
+            _ => Err(format!("expected FORMAT:TARGET, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.format, self.target)
+    }
+}
+
+/// Sink factories by format name.
+#[derive(Clone)]
+pub struct Registry {
+    factories: BTreeMap<String, Factory>,
+}
+
+impl Registry {
+    /// A registry without any format.
+    pub fn empty() -> Registry {
+        Registry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// The formats built into this build: `csv` and `json`, plus `arrow` and
+    /// `sqlite` with their features.
+    pub fn new() -> Registry {
+        let registry = Registry::empty()
+            .with_format("csv", CsvSink::open)
+            .with_format("json", JsonSink::open);
+        #[cfg(feature = "arrow")]
+        let registry = registry.with_format("arrow", ArrowSink::open);
+        #[cfg(feature = "sqlite")]
+        let registry = registry.with_format("sqlite", SqliteSink::open);
+        registry
+    }
+
+    /// Adds `format`, replacing any factory already registered for it.
+    pub fn with_format(mut self, format: &str, factory: Factory) -> Registry {
+        self.factories.insert(format.to_ascii_lowercase(), factory);
+        self
+    }
+
+    pub fn formats(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    pub fn open(&self, spec: &SinkSpec) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        let factory = self.factories.get(&spec.format).ok_or_else(|| {
+            format!(
+                "unknown sink format {:?}, expected one of {}",
+                spec.format,
+                self.formats().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        factory(&spec.target).map_err(|err| format!("could not open {}: {}", spec, err).into())
+    }
+
+    /// Opens every sink of `specs` and feeds them all in one pass over
+    /// `accounts`. Files are written under a temporary name and replace
+    /// their target once the sink finished, so no target is touched unless
+    /// every sink could be opened.
+    pub fn write_all(
+        &self,
+        specs: &[SinkSpec],
+        accounts: &AccountsRepository,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut sinks = specs
+            .iter()
+            .map(|spec| self.open(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        for account in accounts.sorted() {
+            for sink in &mut sinks {
+                sink.write(&account)?;
+            }
+        }
+        sinks.into_iter().try_for_each(Sink::finish)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a file-backed sink writes to: stdout for `-`, or a file next to
+/// the target that replaces it on `commit`.
+enum Output {
+    Stdout(io::Stdout),
+    File(BufWriter<File>, Temporary, PathBuf),
+}
+
+impl Output {
+    fn open(target: &str) -> io::Result<Output> {
+        Ok(match target {
+            "-" => Output::Stdout(io::stdout()),
+            path => {
+                let temporary = PathBuf::from(format!("{}.tmp", path));
+                let file = File::create(&temporary)?;
+                Output::File(
+                    BufWriter::new(file),
+                    Temporary(Some(temporary)),
+                    PathBuf::from(path),
+                )
+            }
+        })
+    }
+
+    fn commit(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(writer, temporary, path) => {
+                writer
+                    .into_inner()
+                    .map_err(|err| err.into_error())?
+                    .sync_all()?;
+                temporary.persist(&path)
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(writer, ..) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(writer, ..) => writer.flush(),
+        }
+    }
+}
+
+/// A file removed when dropped, unless it was renamed into place.
+struct Temporary(Option<PathBuf>);
+
+impl Temporary {
+    fn persist(mut self, path: &Path) -> io::Result<()> {
+        if let Some(temporary) = &self.0 {
+            fs::rename(temporary, path)?;
+            self.0 = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Temporary {
+    fn drop(&mut self) {
+        if let Some(temporary) = &self.0 {
+            let _ = fs::remove_file(temporary);
+        }
+    }
+}
+
+/// The snapshot as CSV, as written without sinks.
+pub struct CsvSink {
+    writer: csv::Writer<Output>,
+}
+
+impl CsvSink {
+    pub fn open(target: &str) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        Ok(Box::new(CsvSink {
+            writer: csv::Writer::from_writer(Output::open(target)?),
+        }))
+    }
+}
+
+impl Sink for CsvSink {
+    fn write(&mut self, account: &Account) -> Result<(), Box<dyn Error>> {
+        Ok(self.writer.serialize(account)?)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+        let output = self.writer.into_inner().map_err(|err| err.into_error())?;
+        Ok(output.commit()?)
+    }
+}
+
+/// The snapshot as a JSON array of accounts.
+pub struct JsonSink {
+    writer: Output,
+    written: usize,
+}
+
+impl JsonSink {
+    pub fn open(target: &str) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        Ok(Box::new(JsonSink {
+            writer: Output::open(target)?,
+            written: 0,
+        }))
+    }
+}
+
+impl Sink for JsonSink {
+    fn write(&mut self, account: &Account) -> Result<(), Box<dyn Error>> {
+        self.writer.write_all(if self.written == 0 {
+            b"[\n  "
+        } else {
+            b",\n  "
+        })?;
+        serde_json::to_writer(&mut self.writer, account)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+        self.writer
+            .write_all(if self.written == 0 { b"[]\n" } else { b"\n]\n" })?;
+        Ok(self.writer.commit()?)
+    }
+}
+
+/// The snapshot as an Arrow IPC stream, see `arrow::write_accounts`.
+/// Accounts are collected and written as one batch at the end.
+#[cfg(feature = "arrow")]
+pub struct ArrowSink {
+    writer: Output,
+    accounts: Vec<Account>,
+}
+
+#[cfg(feature = "arrow")]
+impl ArrowSink {
+    pub fn open(target: &str) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        Ok(Box::new(ArrowSink {
+            writer: Output::open(target)?,
+            accounts: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl Sink for ArrowSink {
+    fn write(&mut self, account: &Account) -> Result<(), Box<dyn Error>> {
+        self.accounts.push(account.clone());
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+        let mut sink = *self;
+        crate::arrow::write_accounts(&sink.accounts, &mut sink.writer)?;
+        Ok(sink.writer.commit()?)
+    }
+}
+
+/// The snapshot as rows of an `accounts` table of a SQLite database,
+/// created if needed. Rows of clients already in the table are replaced,
+/// all in one transaction committed at the end.
+#[cfg(feature = "sqlite")]
+pub struct SqliteSink {
+    connection: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    pub fn open(target: &str) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        let connection = rusqlite::Connection::open(target)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                 client INTEGER PRIMARY KEY,
+                 available REAL NOT NULL,
+                 held REAL NOT NULL,
+                 total REAL NOT NULL,
+                 locked INTEGER NOT NULL
+             );
+             BEGIN;",
+        )?;
+        Ok(Box::new(SqliteSink { connection }))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Sink for SqliteSink {
+    fn write(&mut self, account: &Account) -> Result<(), Box<dyn Error>> {
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO accounts VALUES (?1, ?2, ?3, ?4, ?5)")?
+            .execute(rusqlite::params![
+                account.client_id(),
+                account.available_balance(),
+                account.held_balance(),
+                account.total_balance(),
+                account.locked(),
+            ])?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+        Ok(self.connection.execute_batch("COMMIT;")?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn accounts() -> AccountsRepository {
+        let mut accounts = AccountsRepository::new();
+        accounts.get_or_create(2).deposit(1.5).unwrap();
+        accounts.get_or_create(1).deposit(3.0).unwrap();
+        accounts
+    }
+
+    #[test]
+    fn spec() {
+        let spec: SinkSpec = "JSON:out/accounts.json".parse().unwrap();
+        assert_eq!(spec.format, "json");
+        assert_eq!(spec.target, "out/accounts.json");
+        assert_eq!(spec.to_string(), "json:out/accounts.json");
+        assert!("accounts.json".parse::<SinkSpec>().is_err());
+        assert!("csv:".parse::<SinkSpec>().is_err());
+    }
+
+    #[test]
+    fn tee() {
+        let dir = std::env::temp_dir().join(format!("sinks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("accounts.csv").display().to_string();
+        let json = dir.join("accounts.json").display().to_string();
+        let specs = [
+            format!("csv:{}", csv).parse().unwrap(),
+            format!("json:{}", json).parse().unwrap(),
+        ];
+        Registry::new().write_all(&specs, &accounts()).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&csv).unwrap(),
+            "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n2,1.5,0.0,1.5,false\n"
+        );
+        let value: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(value[1]["client"], 2);
+        assert_eq!(value[1]["total"], 1.5);
+
+        let missing = dir
+            .join("missing")
+            .join("accounts.csv")
+            .display()
+            .to_string();
+        let specs = [
+            format!("json:{}", json).parse().unwrap(),
+            format!("csv:{}", missing).parse().unwrap(),
+        ];
+        assert!(Registry::new()
+            .write_all(&specs, &AccountsRepository::new())
+            .is_err());
+        assert_eq!(
+            value,
+            serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&json).unwrap())
+                .unwrap()
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_format() {
+        let spec = "parquet:accounts.parquet".parse().unwrap();
+        let err = Registry::new().write_all(&[spec], &accounts()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unknown sink format \"parquet\""));
+        let registry = Registry::empty().with_format("parquet", |_| Err("not built".into()));
+        let spec = "parquet:accounts.parquet".parse().unwrap();
+        assert_eq!(
+            registry.open(&spec).err().unwrap().to_string(),
+            "could not open parquet:accounts.parquet: not built"
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite() {
+        let path = std::env::temp_dir().join(format!("sinks-{}.db", std::process::id()));
+        let spec = format!("sqlite:{}", path.display()).parse().unwrap();
+        Registry::new().write_all(&[spec], &accounts()).unwrap();
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let total: f64 = connection
+            .query_row("SELECT total FROM accounts WHERE client = 2", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(total, 1.5);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+//! Inputs the server can replay from a position, such as a Kafka topic,
+//! for exactly-once processing.
+//!
+//! A source hands out records together with the position to resume from
+//! after each. `Server::consume` records that position in the state along
+//! with the transaction, so every checkpoint says exactly which records it
+//! covers. Persisting comes first and telling the source second: once a
+//! checkpoint is written, its positions are committed back to the source.
+//! On restart the positions of the restored checkpoint win over whatever
+//! the source committed, so a crash between the two steps neither applies
+//! a record twice nor skips one.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+
+/// Position to resume each part of a source from, e.g. the next offset of
+/// every partition of a topic, by a name unique over all sources.
+pub type Positions = BTreeMap<String, u64>;
+
+/// One record of a source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// The part of the source it came from, as named in `Positions`.
+    pub part: String,
+    /// The position to resume that part from after this record.
+    pub next: u64,
+    /// A line of the line protocol, CSV or JSON.
+    pub payload: Vec<u8>,
+}
+
+/// What `Source::fetch` found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fetched {
+    Record(Record),
+    /// Nothing arrived in time; the source may have more later.
+    Idle,
+    /// The source is exhausted.
+    End,
+}
+
+pub trait Source {
+    /// Starts reading at `positions`, the ones a restored checkpoint
+    /// recorded; parts without one start where the source sees fit, e.g.
+    /// at its own committed position.
+    fn seek(&mut self, positions: &Positions) -> io::Result<()>;
+
+    /// The next record, waiting at most `timeout` for one.
+    fn fetch(&mut self, timeout: Duration) -> io::Result<Fetched>;
+
+    /// Tells the source that everything before `positions` is persisted.
+    fn commit(&mut self, positions: &Positions) -> io::Result<()>;
+}
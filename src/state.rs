@@ -0,0 +1,187 @@
+use crate::account::AccountsRepository;
+#[cfg(feature = "server")]
+use crate::actors::Outcome;
+use crate::engine::{Engine, RejectReason};
+use crate::expiry::HoldExpiry;
+use crate::metrics::EngineMetrics;
+use crate::retention::{AuditLog, Retention};
+use crate::transaction::{Transaction, TransactionLedger};
+use std::collections::{BTreeMap, HashMap};
+
+/// A ledger and an account repository owned together, for callers that
+/// apply transactions one at a time rather than borrowing both into an
+/// `Engine` for a whole batch.
+#[derive(Default)]
+pub struct State {
+    pub tx_ledger: TransactionLedger,
+    pub accounts: AccountsRepository,
+    last_tx_id: Option<u32>,
+    offset: u64,
+    input: Option<String>,
+    /// Where each replayable source resumes, see `source`.
+    positions: BTreeMap<String, u64>,
+    metrics: EngineMetrics,
+    hold_expiry: Option<HoldExpiry>,
+    retention: Option<(Retention, Box<dyn AuditLog>)>,
+}
+
+impl State {
+    pub fn new() -> State {
+        Default::default()
+    }
+
+    /// State restored from a checkpoint. Metrics start over from zero.
+    #[cfg(feature = "json")]
+    pub(crate) fn restored(
+        tx_ledger: TransactionLedger,
+        accounts: AccountsRepository,
+        offset: u64,
+        last_tx_id: Option<u32>,
+        input: Option<String>,
+    ) -> State {
+        State {
+            tx_ledger,
+            accounts,
+            last_tx_id,
+            offset,
+            input,
+            positions: BTreeMap::new(),
+            metrics: EngineMetrics::default(),
+            hold_expiry: None,
+            retention: None,
+        }
+    }
+
+    /// Closes disputes older than `policy` allows, by the system clock, see
+    /// `Engine::with_hold_expiry`.
+    pub fn set_hold_expiry(&mut self, policy: Option<HoldExpiry>) {
+        self.hold_expiry = policy;
+    }
+
+    pub fn hold_expiry(&self) -> Option<HoldExpiry> {
+        self.hold_expiry
+    }
+
+    /// Purges transactions whose dispute was settled longer ago than
+    /// `policy` allows, by the system clock, after exporting them to
+    /// `audit_log`, see `Engine::with_retention`.
+    pub fn set_retention<L: AuditLog + 'static>(&mut self, policy: Retention, audit_log: L) {
+        self.retention = Some((policy, Box::new(audit_log)));
+    }
+
+    pub fn apply(&mut self, tx: &Transaction) {
+        let _ = self.try_apply(tx);
+    }
+
+    /// Applies `tx` like `apply`, returning why if it was rejected.
+    pub fn try_apply(&mut self, tx: &Transaction) -> Result<(), RejectReason> {
+        let mut engine = Engine::new(&mut self.tx_ledger, &mut self.accounts);
+        if let Some(policy) = self.hold_expiry {
+            engine = engine.with_hold_expiry(policy);
+        }
+        if let Some((policy, audit_log)) = &mut self.retention {
+            engine = engine.with_retention(*policy, audit_log.as_mut());
+        }
+        let result = engine.apply(*tx).map(drop);
+        self.metrics.merge(engine.metrics());
+        self.advance(tx);
+        result
+    }
+
+    /// Counts `tx` as refused before it reached the engine. It is not
+    /// applied and does not advance the offset.
+    #[cfg(feature = "server")]
+    pub(crate) fn refuse(&mut self, tx: &Transaction, reason: RejectReason) {
+        self.metrics.record(tx.r#type(), Err(reason));
+    }
+
+    /// Counts `tx` as applied elsewhere, e.g. by a client's actor.
+    pub(crate) fn advance(&mut self, tx: &Transaction) {
+        self.last_tx_id = Some(tx.id());
+        self.offset += 1;
+    }
+
+    /// Takes over the ledger, accounts and metrics the actors of a router
+    /// ended with.
+    #[cfg(feature = "server")]
+    pub(crate) fn settle(&mut self, outcome: Outcome) {
+        self.tx_ledger = outcome.tx_ledger;
+        self.accounts = outcome.accounts;
+        self.metrics.merge(&outcome.metrics);
+    }
+
+    /// Counters accumulated over every transaction applied so far.
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    /// Number of transactions applied so far, including those covered by the
+    /// checkpoint this state was restored from.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Checks that every account is consistent with itself and the ledger:
+    /// available and held add up to total, nothing negative is held, and an
+    /// account that is not locked holds exactly its disputed transactions
+    /// and its withdrawals pending approval.
+    pub fn verify(&self) -> Result<(), String> {
+        const EPSILON: f64 = 1e-6;
+
+        let mut disputed: HashMap<u16, f64> = HashMap::new();
+        for tx in self
+            .tx_ledger
+            .iter()
+            .filter(|tx| tx.is_dispute() || tx.is_pending())
+        {
+            *disputed.entry(tx.account_id()).or_default() += tx.amount();
+        }
+        for account in self.accounts.sorted() {
+            let client = account.client_id();
+            let (available, held, total) = (
+                account.available_balance(),
+                account.held_balance(),
+                account.total_balance(),
+            );
+            if (available + held - total).abs() > EPSILON {
+                return Err(format!(
+                    "client {}: available {} + held {} != total {}",
+                    client, available, held, total
+                ));
+            }
+            if held < -EPSILON {
+                return Err(format!("client {}: negative held balance {}", client, held));
+            }
+            let expected = disputed.get(&client).copied().unwrap_or_default();
+            if !account.locked() && (held - expected).abs() > EPSILON {
+                return Err(format!(
+                    "client {}: held {} does not match disputed amount {}",
+                    client, held, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fingerprint of the input the checkpoint this state was restored from
+    /// was written for, if it recorded one.
+    pub fn input(&self) -> Option<&str> {
+        self.input.as_deref()
+    }
+
+    /// Where each part of a replayable source resumes after what was
+    /// applied, kept in checkpoints, see `source`.
+    pub fn positions(&self) -> &BTreeMap<String, u64> {
+        &self.positions
+    }
+
+    /// Records that `part` of a source resumes at `next`.
+    pub fn set_position(&mut self, part: &str, next: u64) {
+        self.positions.insert(part.to_string(), next);
+    }
+
+    /// Id of the most recently applied transaction.
+    pub fn last_tx_id(&self) -> Option<u32> {
+        self.last_tx_id
+    }
+}
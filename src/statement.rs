@@ -0,0 +1,271 @@
+//! Monthly account statements.
+//!
+//! A statement replays the log up to the end of the month and reads one
+//! client's balances before its first and after its last row in that month,
+//! listing every movement applied to the client in between. The input is
+//! taken to be chronological: a row without a timestamp happened at the time
+//! of the row before it (the epoch for rows before any timestamp).
+
+use crate::account::{Account, AccountsRepository};
+use crate::engine::Engine;
+use crate::fees::FeeSchedule;
+use crate::rounding::Rounding;
+use crate::timestamp::Month;
+use crate::transaction::{Transaction, TransactionLedger, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Balances {
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl Balances {
+    fn of(account: Option<&Account>) -> Balances {
+        account.map_or_else(Balances::default, |account| Balances {
+            available: account.available_balance(),
+            held: account.held_balance(),
+            total: account.total_balance(),
+            locked: account.locked(),
+        })
+    }
+}
+
+/// A movement applied to the client, with the balances right after it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Line {
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::timestamp::serialize")
+    )]
+    pub timestamp: u64,
+    pub tx: u32,
+    pub r#type: Type,
+    /// Funds moved; for a dispute, resolve or chargeback the amount of the
+    /// transaction it refers to.
+    pub amount: f64,
+    /// Fee taken alongside, see `fees`.
+    pub fee: f64,
+    pub balances: Balances,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DisputeActivity {
+    pub opened: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Statement {
+    pub client: u16,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_month"))]
+    pub month: Month,
+    pub opening: Balances,
+    pub lines: Vec<Line>,
+    pub disputes: DisputeActivity,
+    pub closing: Balances,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_month<S: serde::Serializer>(month: &Month, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(month)
+}
+
+/// `client`'s statement for `month` from the log of all transactions, as
+/// processed with `fees`.
+pub fn statement(
+    log: &[Transaction],
+    client: u16,
+    month: Month,
+    rounding: Rounding,
+    fees: Option<FeeSchedule>,
+) -> Statement {
+    let start = month.start().expect("months start after the epoch");
+    let end = month.end().expect("months end after the epoch");
+    let mut accounts = AccountsRepository::with_rounding(rounding);
+    let mut tx_ledger = TransactionLedger::new();
+    let mut engine = Engine::new(&mut tx_ledger, &mut accounts).with_journal();
+    if let Some(fees) = fees {
+        engine = engine.with_fees(fees);
+    }
+
+    let mut time = 0;
+    let mut opening = None;
+    let mut lines = Vec::new();
+    let mut disputes = DisputeActivity::default();
+    for tx in log {
+        time = tx.timestamp().unwrap_or(time);
+        if time >= end {
+            break;
+        }
+        if time >= start && opening.is_none() {
+            opening = Some(Balances::of(engine.accounts.get(client).as_deref()));
+        }
+        let posted = engine
+            .journal()
+            .map_or(0, |journal| journal.entries().len());
+        engine.process(std::slice::from_ref(tx));
+        let entries = engine
+            .journal()
+            .map_or(&[][..], |journal| journal.entries());
+        let (Some(entry), true) = (entries.get(posted), time >= start) else {
+            continue;
+        };
+        if tx.account_id() != client {
+            continue;
+        }
+        match tx.r#type() {
+            Type::Dispute => disputes.opened += 1,
+            Type::Resolve => disputes.resolved += 1,
+            Type::Chargeback => disputes.charged_back += 1,
+            Type::Deposit
+            | Type::Withdrawal
+            | Type::Bonus
+            | Type::Approve
+            | Type::Reject
+            | Type::Fee
+            | Type::Custom(_) => {}
+        }
+        lines.push(Line {
+            timestamp: time,
+            tx: tx.id(),
+            r#type: tx.r#type(),
+            amount: entry.amount,
+            fee: entries[posted + 1..]
+                .iter()
+                .filter(|entry| entry.is_fee())
+                .fold(0.0, |fee, entry| fee + entry.amount),
+            balances: Balances::of(engine.accounts.get(client).as_deref()),
+        });
+    }
+    let closing = Balances::of(engine.accounts.get(client).as_deref());
+    Statement {
+        client,
+        month,
+        opening: opening.unwrap_or(closing),
+        lines,
+        disputes,
+        closing,
+    }
+}
+
+/// One row of a statement flattened for CSV: the opening balance, every
+/// line, then the closing balance.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+pub struct Row {
+    kind: &'static str,
+    timestamp: Option<String>,
+    tx: Option<u32>,
+    r#type: Option<Type>,
+    amount: Option<f64>,
+    fee: Option<f64>,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+}
+
+#[cfg(feature = "serde")]
+impl Statement {
+    pub fn rows(&self) -> Vec<Row> {
+        let balance = |kind, balances: Balances| Row {
+            kind,
+            timestamp: None,
+            tx: None,
+            r#type: None,
+            amount: None,
+            fee: None,
+            available: balances.available,
+            held: balances.held,
+            total: balances.total,
+            locked: balances.locked,
+        };
+        let mut rows = vec![balance("opening", self.opening)];
+        rows.extend(self.lines.iter().map(|line| Row {
+            timestamp: Some(crate::timestamp::format(line.timestamp)),
+            tx: Some(line.tx),
+            r#type: Some(line.r#type),
+            amount: Some(line.amount),
+            fee: Some(line.fee),
+            ..balance("transaction", line.balances)
+        }));
+        rows.push(balance("closing", self.closing));
+        rows
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timestamp;
+
+    fn at(date: &str, tx: Transaction) -> Transaction {
+        tx.with_timestamp(timestamp::parse(date))
+    }
+
+    #[test]
+    fn june() {
+        let log = [
+            at("2024-05-20", Transaction::new(1, Type::Deposit, 1, 10.0)),
+            at("2024-06-02", Transaction::new(2, Type::Deposit, 1, 5.0)),
+            at("2024-06-03", Transaction::new(3, Type::Deposit, 2, 7.0)),
+            // No timestamp: happened on 2024-06-03 as well.
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+            at("2024-06-10", Transaction::new(4, Type::Withdrawal, 1, 50.0)),
+            at("2024-06-15", Transaction::new(1, Type::Resolve, 1, 0.0)),
+            at("2024-07-01", Transaction::new(5, Type::Withdrawal, 1, 1.0)),
+        ];
+        let statement = statement(&log, 1, "2024-06".parse().unwrap(), Rounding::HalfUp, None);
+        assert_eq!(statement.opening.total, 10.0);
+        assert_eq!(statement.closing.available, 15.0);
+        let lines: Vec<(u32, Type, f64)> = statement
+            .lines
+            .iter()
+            .map(|line| (line.tx, line.r#type, line.amount))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (2, Type::Deposit, 5.0),
+                (1, Type::Dispute, 10.0),
+                (1, Type::Resolve, 10.0),
+            ]
+        );
+        assert_eq!(
+            statement.lines[1].timestamp,
+            timestamp::parse("2024-06-03").unwrap()
+        );
+        assert_eq!(statement.lines[1].balances.held, 10.0);
+        assert_eq!(
+            statement.disputes,
+            DisputeActivity {
+                opened: 1,
+                resolved: 1,
+                charged_back: 0
+            }
+        );
+        #[cfg(feature = "serde")]
+        assert_eq!(statement.rows().len(), 5);
+    }
+
+    #[test]
+    fn quiet_month() {
+        let log = [at(
+            "2024-05-20",
+            Transaction::new(1, Type::Deposit, 1, 10.0),
+        )];
+        let statement = statement(&log, 1, "2024-06".parse().unwrap(), Rounding::HalfUp, None);
+        assert!(statement.lines.is_empty());
+        assert_eq!(statement.opening, statement.closing);
+        assert_eq!(statement.closing.total, 10.0);
+    }
+}
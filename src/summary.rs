@@ -0,0 +1,175 @@
+//! Aggregations of the journal for monitoring.
+
+use crate::journal::Entry;
+use crate::rounding::Rounding;
+use crate::transaction::{Label, Type};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Activity of one merchant across all clients.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MerchantSummary {
+    pub merchant: Label,
+    /// Deposits and withdrawals applied.
+    pub transactions: u64,
+    /// Sum of the amounts of those deposits and withdrawals.
+    pub volume: f64,
+    pub chargebacks: u64,
+}
+
+/// Activity of one transaction category across all clients.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CategorySummary {
+    pub category: Label,
+    /// Deposits and withdrawals applied.
+    pub transactions: u64,
+    /// Sum of the amounts of those deposits and withdrawals.
+    pub volume: f64,
+    pub disputes: u64,
+    /// Disputes per applied deposit or withdrawal.
+    pub dispute_rate: f64,
+}
+
+#[derive(Default)]
+struct Totals {
+    transactions: u64,
+    volume: f64,
+    disputes: u64,
+    chargebacks: u64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &Entry) {
+        match entry.r#type {
+            Type::Deposit | Type::Withdrawal => {
+                self.transactions += 1;
+                self.volume += entry.amount;
+            }
+            Type::Dispute => self.disputes += 1,
+            Type::Chargeback => self.chargebacks += 1,
+            Type::Resolve
+            | Type::Bonus
+            | Type::Approve
+            | Type::Reject
+            | Type::Fee
+            | Type::Custom(_) => {}
+        }
+    }
+
+    fn dispute_rate(&self) -> f64 {
+        match self.transactions {
+            0 => 0.0,
+            transactions => self.disputes as f64 / transactions as f64,
+        }
+    }
+}
+
+/// Totals per label picked by `key`, ordered by label. Entries without one,
+/// fees and bonuses are left out.
+fn totals_by<F>(entries: &[Entry], key: F) -> BTreeMap<Label, Totals>
+where
+    F: Fn(&Entry) -> Option<Label>,
+{
+    let mut totals: BTreeMap<Label, Totals> = BTreeMap::new();
+    for entry in entries {
+        if entry.is_fee() || entry.r#type == Type::Bonus {
+            continue;
+        }
+        if let Some(label) = key(entry) {
+            totals.entry(label).or_default().add(entry);
+        }
+    }
+    totals
+}
+
+/// Totals per merchant, ordered by merchant, with volumes rounded by
+/// `rounding`. Entries without a merchant are left out.
+pub fn by_merchant(entries: &[Entry], rounding: Rounding) -> Vec<MerchantSummary> {
+    totals_by(entries, |entry| entry.merchant)
+        .into_iter()
+        .map(|(merchant, totals)| MerchantSummary {
+            merchant,
+            transactions: totals.transactions,
+            volume: rounding.round(totals.volume),
+            chargebacks: totals.chargebacks,
+        })
+        .collect()
+}
+
+/// Totals per category, ordered by category, with volumes rounded by
+/// `rounding`. Entries without a category are left out.
+pub fn by_category(entries: &[Entry], rounding: Rounding) -> Vec<CategorySummary> {
+    totals_by(entries, |entry| entry.category)
+        .into_iter()
+        .map(|(category, totals)| CategorySummary {
+            category,
+            transactions: totals.transactions,
+            volume: rounding.round(totals.volume),
+            disputes: totals.disputes,
+            dispute_rate: totals.dispute_rate(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::journal::Journal;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn merchant_totals() {
+        let acme = Label::new("acme").ok();
+        let mut journal = Journal::new();
+        let deposit = Transaction::new(1, Type::Deposit, 1, 10.0).with_merchant(acme);
+        let withdrawal = Transaction::new(2, Type::Withdrawal, 1, 4.0).with_merchant(acme);
+        let other = Transaction::new(3, Type::Deposit, 2, 1.0);
+        journal.post(&deposit, &deposit, 10.0);
+        journal.post(&withdrawal, &withdrawal, 4.0);
+        journal.post(&other, &other, 1.0);
+        journal.post(&Transaction::new(1, Type::Dispute, 1, 0.0), &deposit, 10.0);
+        journal.post(
+            &Transaction::new(1, Type::Chargeback, 1, 0.0),
+            &deposit,
+            10.0,
+        );
+
+        let summary = by_merchant(journal.entries(), Rounding::HalfUp);
+        assert_eq!(
+            summary,
+            vec![MerchantSummary {
+                merchant: acme.unwrap(),
+                transactions: 2,
+                volume: 14.0,
+                chargebacks: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn category_dispute_rates() {
+        let payroll = Label::new("payroll").ok();
+        let gambling = Label::new("gambling").ok();
+        let mut journal = Journal::new();
+        for id in 1..=4 {
+            let deposit = Transaction::new(id, Type::Deposit, 1, 2.5).with_category(payroll);
+            journal.post(&deposit, &deposit, 2.5);
+        }
+        let bet = Transaction::new(5, Type::Withdrawal, 1, 1.0).with_category(gambling);
+        journal.post(&bet, &bet, 1.0);
+        journal.post(&Transaction::new(5, Type::Dispute, 1, 0.0), &bet, 1.0);
+        journal.post(&Transaction::new(5, Type::Resolve, 1, 0.0), &bet, 1.0);
+
+        let summary = by_category(journal.entries(), Rounding::HalfUp);
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].category, gambling.unwrap());
+        assert_eq!(summary[0].disputes, 1);
+        assert_eq!(summary[0].dispute_rate, 1.0);
+        assert_eq!(summary[1].category, payroll.unwrap());
+        assert_eq!(summary[1].volume, 10.0);
+        assert_eq!(summary[1].dispute_rate, 0.0);
+    }
+}
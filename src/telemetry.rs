@@ -0,0 +1,54 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+const ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Keeps the OTLP pipeline alive for the duration of a run and flushes
+/// pending spans when dropped.
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Installs a global subscriber exporting spans over OTLP/HTTP when
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Returns `None` otherwise, in
+    /// which case spans are compiled in but never recorded.
+    pub fn init() -> Result<Option<Telemetry>, Box<dyn std::error::Error>> {
+        let endpoint = match std::env::var(ENDPOINT_VAR) {
+            Ok(endpoint) => endpoint,
+            Err(..) => return Ok(None),
+        };
+
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()?;
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_PKG_NAME"),
+            )]))
+            .build();
+        let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .try_init()?;
+
+        Ok(Some(Telemetry { provider }))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            eprintln!("could not flush traces: {}", err);
+        }
+    }
+}
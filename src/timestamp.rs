@@ -0,0 +1,225 @@
+//! Transaction timestamps as seconds since the Unix epoch (UTC), and the
+//! calendar arithmetic needed to read them from input and group them by
+//! month without pulling in a date library.
+
+use std::fmt;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Parses Unix seconds (`1717200000`), a date (`2024-06-01`) or a UTC date
+/// and time (`2024-06-01T12:30:00Z`, the `Z` being optional).
+pub fn parse(s: &str) -> Option<u64> {
+    if let Ok(seconds) = s.parse() {
+        return Some(seconds);
+    }
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').unwrap_or(time))),
+        None => (s, None),
+    };
+    let mut fields = date.splitn(3, '-');
+    let year = fields.next()?.parse().ok()?;
+    let month = fields.next()?.parse().ok()?;
+    let day: u32 = fields.next()?.parse().ok()?;
+    if !(1..=days_in_month(year, month)?).contains(&day) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day)?.checked_mul(SECONDS_PER_DAY)?;
+    if let Some(time) = time {
+        let mut fields = time.splitn(3, ':');
+        let hours: u64 = fields.next()?.parse().ok()?;
+        let minutes: u64 = fields.next()?.parse().ok()?;
+        let secs: u64 = fields.next().map_or(Some(0), |s| s.parse().ok())?;
+        if hours > 23 || minutes > 59 || secs > 59 {
+            return None;
+        }
+        seconds += hours * 3600 + minutes * 60 + secs;
+    }
+    Some(seconds)
+}
+
+/// `2024-06-01T12:30:00Z`.
+pub fn format(seconds: u64) -> String {
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    let time = seconds % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Serializes Unix seconds as `format` does.
+#[cfg(feature = "serde")]
+pub fn serialize<S: serde::Serializer>(seconds: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format(*seconds))
+}
+
+/// Days between 1970-01-01 and the given date, `None` before 1970.
+fn days_from_civil(year: i32, month: u32, day: u32) -> Option<u64> {
+    // Howard Hinnant's days_from_civil, shifted so March starts the year.
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400) as u32;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era as i64 * 146_097 + day_of_era as i64 - 719_468;
+    u64::try_from(days).ok()
+}
+
+/// The date `days` after 1970-01-01, the inverse of `days_from_civil`.
+fn civil_from_days(days: u64) -> (i64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = (year_of_era + era * 400) as i64 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    Some(match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    })
+}
+
+/// A calendar month in UTC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Month {
+    year: i32,
+    month: u32,
+}
+
+impl Month {
+    pub fn new(year: i32, month: u32) -> Option<Month> {
+        if !(1..=12).contains(&month) {
+            return None;
+        }
+        let month = Month { year, month };
+        month.start()?;
+        month.end()?;
+        Some(month)
+    }
+
+    /// First second of the month.
+    pub fn start(self) -> Option<u64> {
+        days_from_civil(self.year, self.month, 1)?.checked_mul(SECONDS_PER_DAY)
+    }
+
+    /// First second of the following month, `None` if that is past the
+    /// last year there is.
+    pub fn end(self) -> Option<u64> {
+        let next = match self.month {
+            12 => Month {
+                year: self.year.checked_add(1)?,
+                month: 1,
+            },
+            month => Month {
+                year: self.year,
+                month: month + 1,
+            },
+        };
+        next.start()
+    }
+
+    pub fn contains(self, timestamp: u64) -> bool {
+        self.start().is_some_and(|start| start <= timestamp)
+            && self.end().is_some_and(|end| timestamp < end)
+    }
+}
+
+impl FromStr for Month {
+    type Err = String;
+
+    /// `YYYY-MM`, from 1970-01 on.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid month: {} (expected YYYY-MM)", s);
+        let (year, month) = s.split_once('-').ok_or_else(invalid)?;
+        let year = year.parse().map_err(|_| invalid())?;
+        let month = month.parse().map_err(|_| invalid())?;
+        Month::new(year, month).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(parse("1717200000"), Some(1_717_200_000));
+        assert_eq!(parse("1970-01-01"), Some(0));
+        assert_eq!(parse("2024-06-01"), Some(1_717_200_000));
+        assert_eq!(parse("2024-06-01T12:30:00Z"), Some(1_717_245_000));
+        assert_eq!(parse("2024-02-29 00:00"), Some(1_709_164_800));
+        assert_eq!(parse("2023-02-29"), None);
+        assert_eq!(parse("2024-06-01T24:00:00"), None);
+        assert_eq!(parse("1969-12-31"), None);
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn format_roundtrip() {
+        assert_eq!(format(0), "1970-01-01T00:00:00Z");
+        for s in [
+            "2024-02-29T23:59:59Z",
+            "2000-03-01T00:00:00Z",
+            "2024-12-31T12:30:05Z",
+        ] {
+            assert_eq!(format(parse(s).unwrap()), s);
+        }
+    }
+
+    #[test]
+    fn months() {
+        let june: Month = "2024-06".parse().unwrap();
+        assert_eq!(june.start(), parse("2024-06-01"));
+        assert_eq!(june.end(), parse("2024-07-01"));
+        assert!(june.contains(parse("2024-06-30T23:59:59").unwrap()));
+        assert!(!june.contains(parse("2024-07-01").unwrap()));
+        assert_eq!(
+            "2024-12".parse::<Month>().unwrap().end(),
+            parse("2025-01-01")
+        );
+        assert_eq!(june.to_string(), "2024-06");
+        assert!("2024-13".parse::<Month>().is_err());
+        assert!("June".parse::<Month>().is_err());
+        let last = Month {
+            year: i32::MAX,
+            month: 12,
+        };
+        assert_eq!(last.end(), None);
+        assert!(!last.contains(u64::MAX));
+        assert!(format!("{}-12", i32::MAX).parse::<Month>().is_err());
+        assert!(format!("{}-11", i32::MAX).parse::<Month>().is_ok());
+        assert_eq!(Month::new(i32::MIN, 1), None);
+    }
+}
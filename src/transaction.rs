@@ -1,48 +1,361 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::currency::Currency;
+use crate::money::Money;
+#[cfg(feature = "serde")]
+use crate::timestamp;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{hash_map, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-#[derive(Copy, Debug, Clone, PartialOrd, PartialEq, Deserialize)]
-#[serde(rename_all = "snake_case")]
+#[derive(Copy, Debug, Clone, PartialOrd, PartialEq)]
 pub enum Type {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Promotional credit such as cashback: added to the available funds like
+    /// a deposit but not open to disputes, see `Engine::with_disputable_bonuses`.
+    Bonus,
+    /// Completes a deposit or withdrawal held for approval, see `approval`.
+    Approve,
+    /// Turns down a deposit or withdrawal held for approval, undoing the hold.
+    Reject,
+    /// A chargeback fee passed on to the client. Only the engine posts these,
+    /// under the id of the chargeback, see `TransactionLedger::fees`.
+    Fee,
+    /// A type of the library user's own, see `Type::custom`.
+    Custom(Label),
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
-pub struct Transaction {
+impl Type {
+    pub const BUILT_IN: [Type; 9] = [
+        Type::Deposit,
+        Type::Withdrawal,
+        Type::Dispute,
+        Type::Resolve,
+        Type::Chargeback,
+        Type::Bonus,
+        Type::Approve,
+        Type::Reject,
+        Type::Fee,
+    ];
+
+    /// The transaction type of the library user's own called `name`.
+    /// Parsers skip rows of it as unknown unless configured with
+    /// `Parser::with_custom_type`, and engines hand it to the
+    /// `engine::Handler` given for it with `Engine::with_handler`.
+    /// Built-in names are not custom types.
+    pub fn custom(name: &str) -> Result<Type, String> {
+        if Type::BUILT_IN
+            .iter()
+            .any(|r#type| r#type.to_string() == name)
+        {
+            return Err(format!("{} is a built-in transaction type", name));
+        }
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            return Err(format!(
+                "invalid transaction type: {} (expected lowercase letters and underscores)",
+                name
+            ));
+        }
+        Ok(Type::Custom(Label::new(name)?))
+    }
+}
+
+/// The built-in types by name; custom ones are made with `Type::custom`.
+impl FromStr for Type {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(Type::Deposit),
+            "withdrawal" => Ok(Type::Withdrawal),
+            "dispute" => Ok(Type::Dispute),
+            "resolve" => Ok(Type::Resolve),
+            "chargeback" => Ok(Type::Chargeback),
+            "bonus" => Ok(Type::Bonus),
+            "approve" => Ok(Type::Approve),
+            "reject" => Ok(Type::Reject),
+            "fee" => Ok(Type::Fee),
+            _ => Err(format!("unknown transaction type: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Deposit => "deposit",
+            Type::Withdrawal => "withdrawal",
+            Type::Dispute => "dispute",
+            Type::Resolve => "resolve",
+            Type::Chargeback => "chargeback",
+            Type::Bonus => "bonus",
+            Type::Approve => "approve",
+            Type::Reject => "reject",
+            Type::Fee => "fee",
+            Type::Custom(name) => name.as_str(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Type {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A built-in type, or any other valid name as a custom type, so that
+/// persisted transactions of custom types read back. Parsers skip those
+/// they were not configured for.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Type {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Type;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transaction type")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Type, E> {
+                v.parse()
+                    .or_else(|_| Type::custom(v))
+                    .map_err(|_| E::custom(format!("unknown transaction type: {}", v)))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+/// A short free-form tag such as a merchant id or category, stored inline so that
+/// transactions stay `Copy`. At most `Label::CAPACITY` bytes, without commas
+/// or control characters.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Label {
+    len: u8,
+    bytes: [u8; Label::CAPACITY],
+}
+
+impl Label {
+    pub const CAPACITY: usize = 23;
+
+    pub fn new(label: &str) -> Result<Label, String> {
+        if label.len() > Label::CAPACITY {
+            return Err(format!(
+                "label longer than {} bytes: {}",
+                Label::CAPACITY,
+                label
+            ));
+        }
+        if label.chars().any(|c| c == ',' || c.is_control()) {
+            return Err(format!(
+                "label contains a comma or control character: {:?}",
+                label
+            ));
+        }
+        let mut bytes = [0; Label::CAPACITY];
+        bytes[..label.len()].copy_from_slice(label.as_bytes());
+        Ok(Label {
+            len: label.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).expect("labels are built from str")
+    }
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Label::new(s)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialOrd for Label {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Label {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Label {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Label {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Label;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string of at most {} bytes", Label::CAPACITY)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Label, E> {
+                Label::new(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+/// Where a deposit or withdrawal held for approval stands, see `approval`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Approval {
+    /// Its funds are held until an `approve` or `reject` arrives.
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Transaction<M = f64> {
     r#type: Type,
-    #[serde(rename(deserialize = "client"))]
+    #[cfg_attr(feature = "serde", serde(rename(deserialize = "client")))]
     account_id: u16,
-    #[serde(rename(deserialize = "tx"))]
+    #[cfg_attr(feature = "serde", serde(rename(deserialize = "tx")))]
     id: u32,
-    #[serde(default)]
-    amount: Option<f64>,
-    #[serde(skip_deserializing)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) amount: Option<M>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            alias = "counterparty",
+            deserialize_with = "deserialize_label"
+        )
+    )]
+    merchant: Option<Label>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "deserialize_label")
+    )]
+    category: Option<Label>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "deserialize_timestamp")
+    )]
+    timestamp: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    currency: Option<Currency>,
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
     is_dispute: bool,
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    is_charged_back: bool,
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    approval: Option<Approval>,
 }
 
-impl Transaction {
-    pub fn new(id: u32, r#type: Type, account_id: u16, amount: f64) -> Transaction {
+impl<M: Money> Transaction<M> {
+    pub fn new(id: u32, r#type: Type, account_id: u16, amount: M) -> Transaction<M> {
         Transaction {
             id,
             r#type,
             account_id,
             amount: Some(amount),
+            merchant: None,
+            category: None,
+            timestamp: None,
+            currency: None,
             is_dispute: false,
+            is_charged_back: false,
+            approval: None,
         }
     }
 
+    pub fn with_merchant(mut self, merchant: Option<Label>) -> Transaction<M> {
+        self.merchant = merchant;
+        self
+    }
+
+    /// Merchant or counterparty of a deposit or withdrawal, when the input
+    /// names one.
+    pub fn merchant(&self) -> Option<Label> {
+        self.merchant
+    }
+
+    pub fn with_category(mut self, category: Option<Label>) -> Transaction<M> {
+        self.category = category;
+        self
+    }
+
+    /// Category of a deposit or withdrawal such as payroll or refund, when
+    /// the input tags one.
+    pub fn category(&self) -> Option<Label> {
+        self.category
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Transaction<M> {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Seconds since the Unix epoch, when the input has a `timestamp` column.
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn with_currency(mut self, currency: Option<Currency>) -> Transaction<M> {
+        self.currency = currency;
+        self
+    }
+
+    /// ISO 4217 code of the amount, when the input has a `currency` column.
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
     pub fn r#type(&self) -> Type {
         self.r#type
     }
 
-    pub fn amount(&self) -> f64 {
+    pub fn amount(&self) -> M {
         self.amount.unwrap()
     }
 
+    /// The amount column, `None` when the row left it out.
+    pub fn optional_amount(&self) -> Option<M> {
+        self.amount
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
@@ -54,38 +367,362 @@ impl Transaction {
     pub fn is_dispute(&self) -> bool {
         self.is_dispute
     }
+
+    /// Whether a chargeback ended the dispute of this transaction, which
+    /// can then not be disputed again.
+    pub fn is_charged_back(&self) -> bool {
+        self.is_charged_back
+    }
+
+    /// Where the deposit or withdrawal stands if it was held for approval,
+    /// `None` for every other transaction.
+    pub fn approval(&self) -> Option<Approval> {
+        self.approval
+    }
+
+    /// Whether the transaction still waits for its `approve` or `reject`.
+    pub fn is_pending(&self) -> bool {
+        self.approval == Some(Approval::Pending)
+    }
 }
 
-pub struct TransactionLedger {
-    transactions: HashMap<u32, Transaction>,
+/// Accepts Unix seconds as a number or anything `timestamp::parse` reads,
+/// with an empty field meaning no timestamp.
+#[cfg(feature = "serde")]
+fn deserialize_timestamp<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Seconds(u64),
+        Text(String),
+    }
+
+    match Option::<Timestamp>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Timestamp::Seconds(seconds)) => Ok(Some(seconds)),
+        Some(Timestamp::Text(text)) if text.is_empty() => Ok(None),
+        Some(Timestamp::Text(text)) => timestamp::parse(&text)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {}", text))),
+    }
 }
-impl Default for TransactionLedger {
+
+/// Accepts any string as a label, cut to fit with a warning if it does not,
+/// so that an overlong merchant name costs the name rather than the row.
+#[cfg(feature = "serde")]
+fn deserialize_label<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Label>, D::Error> {
+    let Some(text) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    match Label::new(&text) {
+        Ok(label) if label.as_str().is_empty() => Ok(None),
+        Ok(label) => Ok(Some(label)),
+        Err(err) => {
+            let label = crate::bank::label(&text);
+            log::warn!(
+                "{}, keeping {:?}",
+                err,
+                label.as_ref().map_or("", Label::as_str)
+            );
+            Ok(label)
+        }
+    }
+}
+
+/// Every transaction seen so far by id, for duplicate detection and for
+/// disputes to find the transaction they refer to.
+///
+/// By default nothing is ever forgotten. With a window of N only the N most
+/// recently added ids are kept, and with a maximum age only those added
+/// within that long of the newest timestamp stored; a transaction without a
+/// timestamp counts as added at the newest one. An id dropped is no longer a
+/// duplicate and can no longer be disputed. Transactions under dispute or
+/// pending approval are never dropped, so their resolve, chargeback, approve
+/// or reject still finds them; they keep taking up room in the window until
+/// then, and are dropped in their turn once settled.
+pub struct TransactionLedger<M = f64> {
+    transactions: HashMap<u32, Transaction<M>>,
+    window: Option<usize>,
+    max_age: Option<Duration>,
+    /// Ids in the order they were added with the time they count as added
+    /// at, kept only with a window or a maximum age.
+    order: VecDeque<(u32, u64)>,
+    /// The newest timestamp stored, in Unix seconds.
+    latest: u64,
+    /// When each open dispute was raised, for those raised with a time.
+    disputed_at: HashMap<u32, SystemTime>,
+    disputes_by_age: BTreeSet<(SystemTime, u32)>,
+    /// When each settled dispute was resolved or charged back, for those
+    /// settled with a time.
+    settled_at: HashMap<u32, SystemTime>,
+    settlements_by_age: BTreeSet<(SystemTime, u32)>,
+    /// Ids of the transactions dropped by `purge`.
+    purged: HashSet<u32>,
+    /// Chargeback fees, kept apart since they share the chargeback's id.
+    fees: Vec<Transaction<M>>,
+}
+impl<M: Money> Default for TransactionLedger<M> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl TransactionLedger {
-    pub fn new() -> TransactionLedger {
+impl<M: Money> TransactionLedger<M> {
+    pub fn new() -> TransactionLedger<M> {
         TransactionLedger {
             transactions: Default::default(),
+            window: None,
+            max_age: None,
+            order: VecDeque::new(),
+            latest: 0,
+            disputed_at: HashMap::new(),
+            disputes_by_age: BTreeSet::new(),
+            settled_at: HashMap::new(),
+            settlements_by_age: BTreeSet::new(),
+            purged: HashSet::new(),
+            fees: Vec::new(),
         }
     }
-    pub fn append(&mut self, tx: &Transaction) {
-        self.transactions.entry(tx.id).or_insert(*tx);
+
+    /// A ledger that keeps only the last `capacity` ids.
+    pub fn with_window(capacity: usize) -> TransactionLedger<M> {
+        let mut ledger = TransactionLedger::new();
+        ledger.set_window(Some(capacity));
+        ledger
+    }
+
+    /// Changes the window, dropping whatever falls outside it. Transactions
+    /// already stored count as added in id order.
+    pub fn set_window(&mut self, capacity: Option<usize>) {
+        self.window = capacity;
+        self.reorder();
     }
 
-    pub fn get(&self, tx_id: u32) -> Option<&Transaction> {
+    pub fn window(&self) -> Option<usize> {
+        self.window
+    }
+
+    /// Changes the maximum age, dropping whatever is older. Transactions
+    /// already stored count as added in id order.
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        self.max_age = max_age;
+        self.reorder();
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    fn is_windowed(&self) -> bool {
+        self.window.is_some() || self.max_age.is_some()
+    }
+
+    /// Rebuilds the order of the stored transactions as that of their ids.
+    fn reorder(&mut self) {
+        self.order.clear();
+        if !self.is_windowed() {
+            return;
+        }
+        let mut ids: Vec<u32> = self.transactions.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let added = self.added_at(self.transactions[&id].timestamp);
+            self.order.push_back((id, added));
+        }
+        self.evict();
+    }
+
+    /// The time a transaction with `timestamp` counts as added at, moving
+    /// the newest timestamp on if it is newer.
+    fn added_at(&mut self, timestamp: Option<u64>) -> u64 {
+        self.latest = self.latest.max(timestamp.unwrap_or(0));
+        timestamp.unwrap_or(self.latest)
+    }
+
+    /// Stores `tx` under its id unless the id is taken or was purged.
+    pub fn append(&mut self, tx: &Transaction<M>) {
+        if self.purged.contains(&tx.id) {
+            return;
+        }
+        if let hash_map::Entry::Vacant(entry) = self.transactions.entry(tx.id) {
+            entry.insert(*tx);
+            if self.is_windowed() {
+                let added = self.added_at(tx.timestamp);
+                self.order.push_back((tx.id, added));
+                self.evict();
+            }
+        }
+    }
+
+    /// Drops the oldest ids beyond the window or older than the maximum
+    /// age, skipping disputed and pending ones. Those stay where they were
+    /// in the order, so that they are the next to go once settled.
+    fn evict(&mut self) {
+        let mut pinned = Vec::new();
+        while let Some(&(id, added)) = self.order.front() {
+            let full = self
+                .window
+                .is_some_and(|capacity| self.transactions.len() > capacity);
+            let expired = self
+                .max_age
+                .is_some_and(|max_age| added.saturating_add(max_age.as_secs()) < self.latest);
+            if !full && !expired {
+                break;
+            }
+            self.order.pop_front();
+            let tx = &self.transactions[&id];
+            if tx.is_dispute || tx.is_pending() {
+                pinned.push((id, added));
+            } else {
+                self.transactions.remove(&id);
+            }
+        }
+        for entry in pinned.into_iter().rev() {
+            self.order.push_front(entry);
+        }
+    }
+
+    /// Takes `tx_id` out of the ledger, e.g. to archive it, forgetting
+    /// when its dispute was raised or settled.
+    pub fn remove(&mut self, tx_id: u32) -> Option<Transaction<M>> {
+        let tx = self.transactions.remove(&tx_id)?;
+        if self.is_windowed() {
+            self.order.retain(|(id, _)| *id != tx_id);
+        }
+        self.forget_dispute_time(tx_id);
+        self.forget_settlement_time(tx_id);
+        Some(tx)
+    }
+
+    /// Drops `tx_id` for good, e.g. once its dispute details may no longer
+    /// be kept, but remembers the id so that a transaction reusing it is
+    /// still seen as a duplicate.
+    pub fn purge(&mut self, tx_id: u32) -> Option<Transaction<M>> {
+        self.purged.insert(tx_id);
+        self.remove(tx_id)
+    }
+
+    /// Whether `tx_id` was dropped by `purge`.
+    pub fn is_purged(&self, tx_id: u32) -> bool {
+        self.purged.contains(&tx_id)
+    }
+
+    /// Ids dropped by `purge`, in no particular order.
+    pub fn purged(&self) -> impl Iterator<Item = u32> + '_ {
+        self.purged.iter().copied()
+    }
+
+    /// Records a chargeback fee the engine posted. Fees are never dropped,
+    /// whatever the window.
+    pub fn append_fee(&mut self, fee: Transaction<M>) {
+        self.fees.push(fee);
+    }
+
+    /// Every chargeback fee posted, in the order posted.
+    pub fn fees(&self) -> &[Transaction<M>] {
+        &self.fees
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn get(&self, tx_id: u32) -> Option<&Transaction<M>> {
         self.transactions.get(&tx_id)
     }
 
+    /// Every stored transaction, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction<M>> {
+        self.transactions.values()
+    }
+
     pub fn dispute_tx(&mut self, tx_id: u32) {
         let tx = self.transactions.get_mut(&tx_id);
         tx.unwrap().is_dispute = true;
+        self.forget_settlement_time(tx_id);
     }
 
     pub fn undispute_tx(&mut self, tx_id: u32) {
         let tx = self.transactions.get_mut(&tx_id);
         tx.unwrap().is_dispute = false;
+        self.forget_dispute_time(tx_id);
+    }
+
+    /// Closes the dispute of `tx_id` for good.
+    pub fn charge_back_tx(&mut self, tx_id: u32) {
+        let tx = self.transactions.get_mut(&tx_id).unwrap();
+        tx.is_dispute = false;
+        tx.is_charged_back = true;
+        self.forget_dispute_time(tx_id);
+    }
+
+    /// Records where the withdrawal `tx_id` stands in its approval.
+    pub fn set_approval(&mut self, tx_id: u32, approval: Approval) {
+        let tx = self.transactions.get_mut(&tx_id).unwrap();
+        tx.approval = Some(approval);
+    }
+
+    /// Marks `tx_id` as disputed since `at`.
+    pub fn dispute_tx_at(&mut self, tx_id: u32, at: SystemTime) {
+        self.dispute_tx(tx_id);
+        self.disputed_at.insert(tx_id, at);
+        self.disputes_by_age.insert((at, tx_id));
+    }
+
+    /// When the open dispute of `tx_id` was raised, if that is known.
+    pub fn disputed_at(&self, tx_id: u32) -> Option<SystemTime> {
+        self.disputed_at.get(&tx_id).copied()
+    }
+
+    /// Ids of the disputes raised at or before `time`, oldest first.
+    pub fn disputed_since(&self, time: SystemTime) -> Vec<u32> {
+        self.disputes_by_age
+            .iter()
+            .take_while(|(at, _)| *at <= time)
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    /// Stops tracking the age of a dispute that can no longer expire, e.g.
+    /// because it was charged back.
+    pub fn forget_dispute_time(&mut self, tx_id: u32) {
+        if let Some(at) = self.disputed_at.remove(&tx_id) {
+            self.disputes_by_age.remove(&(at, tx_id));
+        }
+    }
+
+    /// Notes that the dispute of `tx_id` was settled at `at`.
+    pub fn settle_tx_at(&mut self, tx_id: u32, at: SystemTime) {
+        self.forget_settlement_time(tx_id);
+        self.settled_at.insert(tx_id, at);
+        self.settlements_by_age.insert((at, tx_id));
+    }
+
+    /// When the dispute of `tx_id` was last settled, if that is known.
+    pub fn settled_at(&self, tx_id: u32) -> Option<SystemTime> {
+        self.settled_at.get(&tx_id).copied()
+    }
+
+    /// Ids of the disputes settled at or before `time`, oldest first.
+    pub fn settled_since(&self, time: SystemTime) -> Vec<u32> {
+        self.settlements_by_age
+            .iter()
+            .take_while(|(at, _)| *at <= time)
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    pub fn forget_settlement_time(&mut self, tx_id: u32) {
+        if let Some(at) = self.settled_at.remove(&tx_id) {
+            self.settlements_by_age.remove(&(at, tx_id));
+        }
     }
 }
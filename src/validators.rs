@@ -0,0 +1,241 @@
+//! Checks a transaction has to pass before the engine applies it.
+//!
+//! The engine runs one `Validator` over every transaction, after screening
+//! and before its account is looked at, and rejects the transaction with the
+//! reason the validator returns. A `Chain` runs several in order and stops
+//! at the first that fails; besides the built-in ones below, anything that
+//! implements the trait can be pushed or inserted into it.
+
+use crate::engine::RejectReason;
+use crate::money::Money;
+use crate::transaction::{Transaction, Type};
+use std::collections::HashSet;
+
+pub trait Validator<M: Money = f64> {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason>;
+}
+
+impl<M: Money, F> Validator<M> for F
+where
+    F: Fn(&Transaction<M>) -> Result<(), RejectReason>,
+{
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        self(tx)
+    }
+}
+
+/// Validators run in order, the first failure being the result.
+pub struct Chain<M: Money = f64> {
+    validators: Vec<Box<dyn Validator<M> + Send + Sync>>,
+}
+
+impl<M: Money> Chain<M> {
+    pub fn new() -> Chain<M> {
+        Chain {
+            validators: Vec::new(),
+        }
+    }
+
+    /// Adds `validator` to the end of the chain.
+    pub fn with(mut self, validator: impl Validator<M> + Send + Sync + 'static) -> Chain<M> {
+        self.push(validator);
+        self
+    }
+
+    pub fn push(&mut self, validator: impl Validator<M> + Send + Sync + 'static) {
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Adds `validator` at `index`, so that it runs before the ones after it.
+    ///
+    /// Panics if `index` is greater than the length of the chain.
+    pub fn insert(&mut self, index: usize, validator: impl Validator<M> + Send + Sync + 'static) {
+        self.validators.insert(index, Box::new(validator));
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+}
+
+impl<M: Money> Default for Chain<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Money> Validator<M> for Chain<M> {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        self.validators
+            .iter()
+            .try_for_each(|validator| validator.validate(tx))
+    }
+}
+
+/// Rejects amounts outside `min..=max` as `AmountOutOfBounds`. Transactions
+/// without an amount, such as disputes, pass.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmountBounds {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl<M: Money> Validator<M> for AmountBounds {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        match tx.optional_amount().map(M::to_f64) {
+            Some(amount) if !(self.min..=self.max).contains(&amount) => {
+                Err(RejectReason::AmountOutOfBounds)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects amounts with more than `decimals` decimal places as
+/// `TooPrecise`, rather than rounding them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Precision {
+    pub decimals: i32,
+}
+
+impl<M: Money> Validator<M> for Precision {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        let Some(amount) = tx.optional_amount().map(M::to_f64) else {
+            return Ok(());
+        };
+        let scaled = amount * 10f64.powi(self.decimals);
+        match (scaled - scaled.round()).abs() <= 1e-6 * scaled.abs().max(1.0) {
+            true => Ok(()),
+            false => Err(RejectReason::TooPrecise),
+        }
+    }
+}
+
+/// Accepts only transactions of the given types, rejecting the others as
+/// `TypeNotAllowed`, e.g. to refuse bonuses from a source that must not
+/// send any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnownTypes {
+    types: Vec<Type>,
+}
+
+impl KnownTypes {
+    pub fn new(types: impl IntoIterator<Item = Type>) -> KnownTypes {
+        KnownTypes {
+            types: types.into_iter().collect(),
+        }
+    }
+}
+
+impl<M: Money> Validator<M> for KnownTypes {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        match self.types.contains(&tx.r#type()) {
+            true => Ok(()),
+            false => Err(RejectReason::TypeNotAllowed),
+        }
+    }
+}
+
+/// Accepts only transactions of the given clients, rejecting the others as
+/// `ClientNotAllowed`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllowedClients {
+    clients: HashSet<u16>,
+}
+
+impl AllowedClients {
+    pub fn new(clients: impl IntoIterator<Item = u16>) -> AllowedClients {
+        AllowedClients {
+            clients: clients.into_iter().collect(),
+        }
+    }
+}
+
+impl<M: Money> Validator<M> for AllowedClients {
+    fn validate(&self, tx: &Transaction<M>) -> Result<(), RejectReason> {
+        match self.clients.contains(&tx.account_id()) {
+            true => Ok(()),
+            false => Err(RejectReason::ClientNotAllowed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountsRepository;
+    use crate::engine::Engine;
+    use crate::transaction::TransactionLedger;
+
+    #[test]
+    fn built_in() {
+        let deposit = |amount| Transaction::new(1, Type::Deposit, 1, amount);
+        let bounds = AmountBounds {
+            min: 0.01,
+            max: 1000.0,
+        };
+        assert_eq!(bounds.validate(&deposit(1000.0)), Ok(()));
+        assert_eq!(
+            bounds.validate(&deposit(1000.5)),
+            Err(RejectReason::AmountOutOfBounds)
+        );
+        let precision = Precision { decimals: 2 };
+        assert_eq!(precision.validate(&deposit(10.25)), Ok(()));
+        assert_eq!(precision.validate(&deposit(1e9 + 0.07)), Ok(()));
+        assert_eq!(
+            precision.validate(&deposit(10.255)),
+            Err(RejectReason::TooPrecise)
+        );
+        let types = KnownTypes::new([Type::Deposit, Type::Withdrawal]);
+        assert_eq!(
+            types.validate(&Transaction::new(1, Type::Bonus, 1, 1.0)),
+            Err(RejectReason::TypeNotAllowed)
+        );
+        let clients = AllowedClients::new([1]);
+        assert_eq!(clients.validate(&deposit(1.0)), Ok(()));
+        assert_eq!(
+            clients.validate(&Transaction::new(1, Type::Deposit, 2, 1.0)),
+            Err(RejectReason::ClientNotAllowed)
+        );
+    }
+
+    #[test]
+    fn chain_in_engine() {
+        let mut chain = Chain::new()
+            .with(AmountBounds {
+                min: 0.0,
+                max: 100.0,
+            })
+            .with(Precision { decimals: 2 });
+        chain.insert(0, |tx: &Transaction| match tx.id() {
+            13 => Err(RejectReason::Invalid),
+            _ => Ok(()),
+        });
+        assert_eq!(chain.len(), 3);
+
+        let mut accounts = AccountsRepository::new();
+        let mut tx_ledger = TransactionLedger::new();
+        let mut engine = Engine::new(&mut tx_ledger, &mut accounts).with_validator(&chain);
+        engine.process(&[
+            Transaction::new(1, Type::Deposit, 1, 50.0),
+            Transaction::new(2, Type::Deposit, 1, 500.0),
+            Transaction::new(3, Type::Deposit, 1, 0.001),
+            Transaction::new(13, Type::Deposit, 1, 500.001),
+            Transaction::new(1, Type::Dispute, 1, 0.0),
+        ]);
+        let reasons: Vec<RejectReason> = engine.rejections().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::AmountOutOfBounds,
+                RejectReason::TooPrecise,
+                RejectReason::Invalid,
+            ]
+        );
+        assert_eq!(accounts.get(1).unwrap().held_balance(), 50.0);
+    }
+}
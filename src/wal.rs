@@ -0,0 +1,265 @@
+//! Write-ahead log of incoming transactions.
+//!
+//! Every transaction is appended as one
+//! `offset,type,client,tx,amount,merchant,category,timestamp,currency` line
+//! before it is applied, so whatever was accepted since the last checkpoint
+//! can be replayed after a crash. A line cut short by a crash mid-write is
+//! dropped on reading; a malformed line anywhere else is an error.
+//!
+//! The log starts with a `#version=N` line. Logs without one were written
+//! before the header existed and are read as version 1. Versions 1 and 2
+//! have no currency column, which reads as no currency; `migrate` stamps
+//! them with the current header.
+
+use crate::transaction::Transaction;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Format version written into new logs.
+pub const VERSION: u32 = 3;
+const HEADER: &str = "#version=";
+
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> io::Result<Wal> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut wal = Wal { file };
+        if wal.file.metadata()?.len() == 0 {
+            wal.write_header()?;
+        }
+        Ok(wal)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.file
+            .write_all(format!("{}{}\n", HEADER, VERSION).as_bytes())
+    }
+
+    /// Appends `tx` as the transaction applied at `offset` (1-based). The line
+    /// reaches the OS before this returns, so it survives the process
+    /// crashing but not necessarily the machine.
+    pub fn append(&mut self, offset: u64, tx: &Transaction) -> io::Result<()> {
+        let amount = tx
+            .optional_amount()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let merchant = tx.merchant().map(|m| m.to_string()).unwrap_or_default();
+        let category = tx.category().map(|c| c.to_string()).unwrap_or_default();
+        let timestamp = tx.timestamp().map(|t| t.to_string()).unwrap_or_default();
+        let currency = tx.currency().map(|c| c.to_string()).unwrap_or_default();
+        let line = format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            offset,
+            tx.r#type(),
+            tx.account_id(),
+            tx.id(),
+            amount,
+            merchant,
+            category,
+            timestamp,
+            currency
+        );
+        self.file.write_all(line.as_bytes())
+    }
+
+    /// Drops every entry, once a checkpoint covers them.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.write_header()
+    }
+
+    /// Every complete entry in the log at `path`, in order.
+    pub fn read(path: &Path) -> io::Result<Vec<(u64, Transaction)>> {
+        let Some(contents) = read_contents(path)? else {
+            return Ok(Vec::new());
+        };
+        version(&contents)?;
+        let complete = contents.rfind('\n').map_or("", |end| &contents[..end]);
+        complete
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.starts_with(HEADER))
+            .map(|(index, line)| {
+                parse_entry(line).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed wal entry on line {}: {:?}", index + 1, line),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Stamps the log at `path` with the current header, returning the version
+/// it had. Logs already at `VERSION` are left alone.
+pub fn migrate(path: &Path) -> io::Result<u32> {
+    let contents = read_contents(path)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no wal at this path"))?;
+    let from = version(&contents)?;
+    if from < VERSION {
+        let entries = match contents.strip_prefix(HEADER) {
+            Some(header) => header.split_once('\n').map_or("", |(_, entries)| entries),
+            None => &contents,
+        };
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        write!(file, "{}{}\n{}", HEADER, VERSION, entries)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+    }
+    Ok(from)
+}
+
+fn read_contents(path: &Path) -> io::Result<Option<String>> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut contents)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(Some(contents))
+}
+
+/// The format version of a log from its first line.
+fn version(contents: &str) -> io::Result<u32> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let Some(header) = contents
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix(HEADER))
+    else {
+        return Ok(1);
+    };
+    match header.parse() {
+        Ok(version) if (1..=VERSION).contains(&version) => Ok(version),
+        Ok(version) => Err(invalid(format!(
+            "wal version {} is newer than this release supports ({})",
+            version, VERSION
+        ))),
+        Err(_) => Err(invalid(format!("malformed wal header: {:?}", header))),
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(u64, Transaction)> {
+    let mut fields = line.split(',');
+    let offset = fields.next()?.parse().ok()?;
+    let r#type = fields.next()?.parse().ok()?;
+    let client = fields.next()?.parse().ok()?;
+    let id = fields.next()?.parse().ok()?;
+    let amount = match fields.next()? {
+        "" => None,
+        amount => Some(amount.parse().ok()?),
+    };
+    let merchant = match fields.next() {
+        None | Some("") => None,
+        Some(merchant) => Some(merchant.parse().ok()?),
+    };
+    let category = match fields.next() {
+        None | Some("") => None,
+        Some(category) => Some(category.parse().ok()?),
+    };
+    let timestamp = match fields.next() {
+        None | Some("") => None,
+        Some(timestamp) => Some(timestamp.parse().ok()?),
+    };
+    let currency = match fields.next() {
+        None | Some("") => None,
+        Some(currency) => Some(currency.parse().ok()?),
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    let mut tx = Transaction::new(id, r#type, client, 0.0)
+        .with_merchant(merchant)
+        .with_category(category)
+        .with_timestamp(timestamp)
+        .with_currency(currency);
+    tx.amount = amount;
+    Some((offset, tx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Type;
+
+    #[test]
+    fn append_read_truncate() {
+        let path = std::env::temp_dir().join(format!("fg-wal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(1, &Transaction::new(1, Type::Deposit, 3, 1.2345))
+            .unwrap();
+        wal.append(2, &Transaction::new(1, Type::Dispute, 3, 0.0))
+            .unwrap();
+        let jpy = Some("JPY".parse().unwrap());
+        wal.append(
+            3,
+            &Transaction::new(2, Type::Deposit, 3, 500.0).with_currency(jpy),
+        )
+        .unwrap();
+
+        // A torn final line from a crash mid-write is ignored.
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"4,withdr")
+            .unwrap();
+        let entries = Wal::read(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].0, 1);
+        assert_eq!(entries[0].1.amount(), 1.2345);
+        assert_eq!(entries[1].1.r#type(), Type::Dispute);
+        assert_eq!(entries[2].1.currency(), jpy);
+
+        wal.truncate().unwrap();
+        assert!(Wal::read(&path).unwrap().is_empty());
+        assert_eq!(migrate(&path).unwrap(), VERSION);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn versions() {
+        let path = std::env::temp_dir().join(format!("fg-wal-v1-{}.log", std::process::id()));
+        std::fs::write(&path, "1,deposit,1,1,2.0\n").unwrap();
+        assert_eq!(Wal::read(&path).unwrap().len(), 1);
+        assert_eq!(migrate(&path).unwrap(), 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "#version=3\n1,deposit,1,1,2.0\n");
+        assert_eq!(Wal::read(&path).unwrap().len(), 1);
+        assert_eq!(migrate(&path).unwrap(), VERSION);
+
+        std::fs::write(&path, "#version=2\n1,deposit,1,1,2.0,,,5\n").unwrap();
+        assert_eq!(migrate(&path).unwrap(), 2);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "#version=3\n1,deposit,1,1,2.0,,,5\n");
+
+        std::fs::write(&path, "#version=4\n1,deposit,1,1,2.0\n").unwrap();
+        assert!(Wal::read(&path).is_err());
+        assert!(migrate(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_entry() {
+        assert!(parse_entry("1,deposit,1,1,1.0").is_some());
+        let (_, tx) = parse_entry("1,deposit,1,1,1.0,acme").unwrap();
+        assert_eq!(tx.merchant().unwrap().as_str(), "acme");
+        let (_, tx) = parse_entry("1,deposit,1,1,1.0,,payroll").unwrap();
+        assert!(tx.merchant().is_none());
+        assert_eq!(tx.category().unwrap().as_str(), "payroll");
+        let (_, tx) = parse_entry("1,deposit,1,1,1.0,,,1717200000").unwrap();
+        assert_eq!(tx.timestamp(), Some(1_717_200_000));
+        let (_, tx) = parse_entry("1,deposit,1,1,1.0,,,,JPY").unwrap();
+        assert_eq!(tx.currency().unwrap().as_str(), "JPY");
+        assert!(parse_entry("1,deposit,1,1,1.0,,,,dollars").is_none());
+        assert!(parse_entry("1,deposit,1,1").is_none());
+        assert!(parse_entry("1,refund,1,1,1.0").is_none());
+    }
+}
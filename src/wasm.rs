@@ -0,0 +1,105 @@
+//! JavaScript API for the `wasm32-unknown-unknown` build (feature `wasm`).
+//! Only in-memory input is supported; there is no file IO in the browser.
+//!
+//! ```js
+//! const engine = new Engine();
+//! engine.processCsv("type,client,tx,amount\ndeposit,1,1,5.0\n");
+//! engine.account(1).available;
+//! ```
+
+use crate::account::Account;
+use crate::parser::Parser;
+use crate::state::State;
+use crate::transaction::{Transaction, Type};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct AccountView {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl From<&Account> for AccountView {
+    fn from(account: &Account) -> Self {
+        AccountView {
+            client: account.client_id(),
+            available: account.available_balance(),
+            held: account.held_balance(),
+            total: account.total_balance(),
+            locked: account.locked(),
+        }
+    }
+}
+
+#[wasm_bindgen(js_name = Engine)]
+#[derive(Default)]
+pub struct WasmEngine {
+    state: State,
+}
+
+#[wasm_bindgen(js_class = Engine)]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmEngine {
+        Default::default()
+    }
+
+    /// Applies a single transaction; `kind` is the CSV type name, e.g. `"dispute"`.
+    pub fn submit(
+        &mut self,
+        kind: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<f64>,
+    ) -> Result<(), JsError> {
+        let kind = kind.parse::<Type>().map_err(|err| JsError::new(&err))?;
+        self.state.apply(&Transaction::new(
+            tx,
+            kind,
+            client,
+            amount.unwrap_or_default(),
+        ));
+        Ok(())
+    }
+
+    /// Applies every row of a CSV document with a header row, as the CLI would.
+    #[wasm_bindgen(js_name = processCsv)]
+    pub fn process_csv(&mut self, csv: &str) -> Result<usize, JsError> {
+        let transactions = Parser::parse_reader(csv.as_bytes())?;
+        for tx in &transactions {
+            self.state.apply(tx);
+        }
+        Ok(transactions.len())
+    }
+
+    pub fn account(&self, client: u16) -> Option<AccountView> {
+        self.state
+            .accounts
+            .get(client)
+            .as_deref()
+            .map(AccountView::from)
+    }
+
+    /// All accounts ordered by client id.
+    pub fn snapshot(&self) -> Vec<AccountView> {
+        self.state
+            .accounts
+            .sorted()
+            .iter()
+            .map(AccountView::from)
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = snapshotCsv)]
+    pub fn snapshot_csv(&self) -> Result<String, JsError> {
+        let mut csv = Vec::new();
+        self.state
+            .accounts
+            .write_csv(&mut csv)
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(String::from_utf8(csv)?)
+    }
+}
@@ -0,0 +1,100 @@
+//! Excel workbooks as input.
+//!
+//! The transaction sheet is read like a CSV document: its first row is the
+//! header and every row after it goes through the same deserialization,
+//! checks and warnings as in `parser`, with lines counted as sheet rows.
+
+use crate::parser::{Parser, Tenants, Warning};
+use calamine::{Data, Reader, Xlsx, XlsxError};
+use csv::{Position, StringRecord};
+use std::io::{Read, Seek};
+
+/// Days between Excel's 1899-12-30 epoch and the Unix epoch.
+const UNIX_EPOCH_SERIAL: f64 = 25_569.0;
+
+/// Reads the `sheet` of the workbook, by default the one named
+/// `transactions` in any case, or the first one without such a sheet.
+pub fn parse_tenants_with_warnings<R: Read + Seek>(
+    reader: R,
+    sheet: Option<&str>,
+) -> Result<(Tenants, Vec<Warning>), XlsxError> {
+    let mut workbook = Xlsx::new(reader)?;
+    let names = workbook.sheet_names();
+    let name = match sheet {
+        Some(sheet) => sheet.to_string(),
+        None => names
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case("transactions"))
+            .or(names.first())
+            .cloned()
+            .ok_or_else(|| XlsxError::WorksheetNotFound("transactions".to_string()))?,
+    };
+    let range = workbook.worksheet_range(&name)?;
+    let first_row = range.start().map_or(0, |(row, _)| u64::from(row));
+    let mut rows = range.rows().enumerate().map(|(index, cells)| {
+        let mut record: StringRecord = cells.iter().map(cell).collect();
+        let mut position = Position::new();
+        position.set_line(first_row + index as u64 + 1);
+        record.set_position(Some(position));
+        record
+    });
+    let headers = rows.next().unwrap_or_default();
+    Ok(Parser::read_tenants(&headers, rows.map(Ok)))
+}
+
+/// A cell as it would read in a CSV export: numbers without a trailing
+/// `.0` and dates as Unix seconds.
+fn cell(data: &Data) -> String {
+    match data {
+        Data::DateTime(date) if date.is_datetime() => {
+            (((date.as_f64() - UNIX_EPOCH_SERIAL) * 86_400.0).round() as i64).to_string()
+        }
+        Data::String(value) => value.trim().to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::WarningKind;
+    use crate::transaction::Type;
+    use std::io::Cursor;
+
+    const WORKBOOK: &[u8] = include_bytes!("../tests/transactions.xlsx");
+
+    #[test]
+    fn transaction_sheet() {
+        let (tenants, warnings) = parse_tenants_with_warnings(Cursor::new(WORKBOOK), None).unwrap();
+        let parsed: Vec<(Type, u32, Option<f64>)> = tenants[&None]
+            .iter()
+            .map(|tx| (tx.r#type(), tx.id(), tx.optional_amount()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (Type::Deposit, 1, Some(5.5)),
+                (Type::Withdrawal, 2, Some(1.25)),
+                (Type::Dispute, 1, None),
+                (Type::Deposit, 4, Some(3.0)),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 5);
+        assert_eq!(
+            warnings[0].kind,
+            WarningKind::UnknownType("refund".to_string())
+        );
+
+        assert!(parse_tenants_with_warnings(Cursor::new(WORKBOOK), Some("Missing")).is_err());
+    }
+
+    #[test]
+    fn cells() {
+        assert_eq!(cell(&Data::Float(1.0)), "1");
+        assert_eq!(cell(&Data::Float(2.25)), "2.25");
+        assert_eq!(cell(&Data::Int(7)), "7");
+        assert_eq!(cell(&Data::String(" deposit ".to_string())), "deposit");
+        assert_eq!(cell(&Data::Empty), "");
+    }
+}